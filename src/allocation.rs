@@ -1,12 +1,14 @@
 use crate::assets::AssetClass;
 use crate::rebalance::AssetAllocation;
-use chrono::{Local, NaiveDate};
+use chrono::{Duration, Local, NaiveDate};
 use rust_decimal::Decimal;
+use serde::de::{Deserialize, Deserializer, IntoDeserializer};
+use std::collections::BTreeMap;
+use std::fmt;
 
-fn age_in_weeks(birthday: NaiveDate) -> i64 {
-    let today: NaiveDate = Local::now().date_naive();
-    assert!(birthday < today, "You were born in the future?");
-    (today - birthday).num_weeks()
+fn age_in_weeks_on(birthday: NaiveDate, day: NaiveDate) -> i64 {
+    assert!(birthday < day, "You were born in the future?");
+    (day - birthday).num_weeks()
 }
 
 /// Derive bond allocation from the "your age in bonds" principle.
@@ -26,10 +28,104 @@ fn age_in_weeks(birthday: NaiveDate) -> i64 {
 /// This function strikes a compromise, and gives allocations rounded to the week.
 ///
 pub fn bond_allocation(birthday: NaiveDate, from_years: u8) -> Decimal {
-    let age = Decimal::from(age_in_weeks(birthday)) / Decimal::from(52);
+    bond_allocation_with_precision(birthday, from_years, 2)
+}
+
+/// The most decimal places of a percentage that `bond_allocation_with_precision` will honor
+pub const MAX_BOND_PRECISION: u32 = 6;
+
+/// Derive bond allocation (see `bond_allocation`), with the percentage rounded to `precision` places.
+///
+/// A precision of 2 gives percentages like 45.17%, while 3 gives 45.172% for a smoother glide.
+pub fn bond_allocation_with_precision(
+    birthday: NaiveDate,
+    from_years: u8,
+    precision: u32,
+) -> Decimal {
+    bond_allocation_on(birthday, from_years, precision, Local::now().date_naive())
+}
+
+/// Derive bond allocation (see `bond_allocation_with_precision`) as of some other day
+pub fn bond_allocation_on(
+    birthday: NaiveDate,
+    from_years: u8,
+    precision: u32,
+    day: NaiveDate,
+) -> Decimal {
+    let age = Decimal::from(age_in_weeks_on(birthday, day)) / Decimal::from(52);
+    bond_allocation_at_age(age, from_years, precision)
+}
+
+/// The age from which we subtract to get the stock allocation, unless configured otherwise
+pub const DEFAULT_BOND_ALLOCATION_BASE: u8 = 120;
+
+/// International stocks' share of all stocks, unless configured otherwise (see `core_four`)
+pub const DEFAULT_INTL_RATIO: Decimal = Decimal::from_parts(40, 0, 0, false, 2);
+
+/// Years from now at which to show the target allocation (see `glide_path`)
+pub const GLIDE_PATH_YEARS: [i64; 4] = [0, 5, 10, 20];
+
+/// The target bond allocation at some point in the future
+#[derive(Debug, PartialEq, Eq)]
+pub struct GlidePoint {
+    pub years_from_now: i64,
+    pub age: i64,
+    pub ratio_bonds: Decimal,
+}
+
+impl fmt::Display for GlidePoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let when = match self.years_from_now {
+            0 => String::from("today"),
+            years => format!("in {:} years", years),
+        };
+        write!(
+            f,
+            "Age {:} ({:}): {:.2}% bonds, {:.2}% stocks",
+            self.age,
+            when,
+            self.ratio_bonds * Decimal::from(100),
+            (Decimal::from(1) - self.ratio_bonds) * Decimal::from(100)
+        )
+    }
+}
+
+/// How the bond allocation shifts as somebody ages (from `today`, out to `GLIDE_PATH_YEARS`)
+///
+/// `allocations_on` gives the target allocations as of some day (e.g. for a strategy).
+/// Bonds are read back from those allocations, since not all of them glide with age.
+pub fn glide_path<F>(birthday: NaiveDate, today: NaiveDate, allocations_on: F) -> Vec<GlidePoint>
+where
+    F: Fn(NaiveDate) -> Vec<AssetAllocation>,
+{
+    GLIDE_PATH_YEARS
+        .iter()
+        .map(|&years_from_now| {
+            // Ages are measured in 52-week years (see `bond_allocation`)
+            let day = today + Duration::weeks(52 * years_from_now);
+            let ratio_bonds = allocations_on(day)
+                .iter()
+                .filter(|a| a.asset_class == AssetClass::USBonds)
+                .map(|a| a.target_ratio)
+                .sum();
+            GlidePoint {
+                years_from_now,
+                age: age_in_weeks_on(birthday, day) / 52,
+                ratio_bonds,
+            }
+        })
+        .collect()
+}
+
+fn bond_allocation_at_age(age: Decimal, from_years: u8, precision: u32) -> Decimal {
+    assert!(
+        precision <= MAX_BOND_PRECISION,
+        "Precision cannot exceed {:} decimal places",
+        MAX_BOND_PRECISION
+    );
 
     let mut stock_allocation = Decimal::from(from_years) - age;
-    stock_allocation = stock_allocation.round_dp(2);
+    stock_allocation = stock_allocation.round_dp(precision);
     let scale = &stock_allocation.scale();
     stock_allocation.set_scale(scale + 2).unwrap(); // Convert to an actual ratio
 
@@ -69,38 +165,386 @@ pub fn bond_allocation(birthday: NaiveDate, from_years: u8) -> Decimal {
 ///      - 49%  Giant Cap
 ///      - 36%  Largo Cap
 ///      - 15%  Mid Cap
+///
 /// For simplicity, just say that any US Total Market fund is 75% large cap.
 /// Accordingly, for a 50/50 split of small+mid vs. large+giant,
 /// I want $1 in VSMAX for every $2 in VTSAX.
 ///
 pub fn core_four(ratio_bonds: Decimal) -> Vec<AssetAllocation> {
+    core_four_with_intl(ratio_bonds, DEFAULT_INTL_RATIO)
+}
+
+/// Return a "Core Four" allocation, with international stocks at some fraction of all stocks.
+///
+/// Rather than a fixed 40%, some investors weight international stocks by their share of global
+/// market cap (roughly 40-45% ex-US, but it drifts over time). Whatever remains of the stock
+/// allocation is split between US Total, US Small/Mid, and REIT in the same 33:17:10 proportions
+/// as the classic strategy.
+pub fn core_four_with_intl(ratio_bonds: Decimal, ratio_intl: Decimal) -> Vec<AssetAllocation> {
     let one: Decimal = 1.into();
 
     assert!(!ratio_bonds.is_sign_negative(), "Ratio must be positive");
     assert!(ratio_bonds <= one, "Ratio cannot exceed 100%");
+    assert!(!ratio_intl.is_sign_negative(), "Ratio must be positive");
+    assert!(ratio_intl <= one, "Ratio cannot exceed 100%");
 
     let ratio_stocks: Decimal = one - ratio_bonds;
 
+    // Of the 60% that isn't international in the classic strategy: 33% US, 17% small, 10% REIT
+    // (Multiply before dividing, so that the classic 40% split stays exact)
+    let ratio_domestic = one - ratio_intl;
+    let us_total = Decimal::from(33) * ratio_domestic / Decimal::from(60) * ratio_stocks;
+    let us_small = Decimal::from(17) * ratio_domestic / Decimal::from(60) * ratio_stocks;
+    let intl = ratio_intl * ratio_stocks;
+    // Take REIT as the remainder, guaranteeing that all ratios sum to exactly 100%
+    let reit = ratio_stocks - us_total - us_small - intl;
+
     vec![
         // Allocate ratio of bonds
         AssetAllocation::new(AssetClass::USBonds, ratio_bonds),
         // Split remaining funds between US Total, US Small/Mid, International, and REIT
-        AssetAllocation::new(AssetClass::USTotal, Decimal::new(33, 2) * ratio_stocks),
-        AssetAllocation::new(AssetClass::USSmall, Decimal::new(17, 2) * ratio_stocks),
-        AssetAllocation::new(AssetClass::IntlStocks, Decimal::new(40, 2) * ratio_stocks),
-        AssetAllocation::new(AssetClass::REIT, Decimal::new(10, 2) * ratio_stocks),
+        AssetAllocation::new(AssetClass::USTotal, us_total),
+        AssetAllocation::new(AssetClass::USSmall, us_small),
+        AssetAllocation::new(AssetClass::IntlStocks, intl),
+        AssetAllocation::new(AssetClass::REIT, reit),
     ]
 }
 
+/// Return a Bogleheads ["Three-Fund" portfolio][three-fund]: US stocks, international, & bonds.
+///
+/// `ratio_intl` is international stocks' share of the stock allocation (the rest is US Total).
+///
+/// [three-fund]: https://www.bogleheads.org/wiki/Three-fund_portfolio
+pub fn three_fund(ratio_bonds: Decimal, ratio_intl: Decimal) -> Vec<AssetAllocation> {
+    let one: Decimal = 1.into();
+
+    assert!(!ratio_bonds.is_sign_negative(), "Ratio must be positive");
+    assert!(ratio_bonds <= one, "Ratio cannot exceed 100%");
+    assert!(!ratio_intl.is_sign_negative(), "Ratio must be positive");
+    assert!(ratio_intl <= one, "Ratio cannot exceed 100%");
+
+    let ratio_stocks: Decimal = one - ratio_bonds;
+    let intl = ratio_intl * ratio_stocks;
+    vec![
+        AssetAllocation::new(AssetClass::USBonds, ratio_bonds),
+        AssetAllocation::new(AssetClass::USTotal, ratio_stocks - intl),
+        AssetAllocation::new(AssetClass::IntlStocks, intl),
+    ]
+}
+
+/// Target ratios given explicitly by asset class (the `[allocation]` section of `config.toml`)
+pub type ExplicitAllocation = BTreeMap<AssetClass, Decimal>;
+
+#[derive(Serialize)]
+struct AllocationSection {
+    /// TOML keys must be strings, so asset classes are keyed by name (e.g. "USBonds")
+    allocation: BTreeMap<String, Decimal>,
+}
+
+/// Render target allocations as an `[allocation]` section, to be pasted into `config.toml`
+///
+/// Ratios are written as strings, so that they're read back exactly (still summing to 100%).
+pub fn to_toml(allocations: &[AssetAllocation]) -> String {
+    let allocation = allocations
+        .iter()
+        .map(|a| {
+            let name = toml::Value::try_from(&a.asset_class)
+                .expect("Asset classes serialize as strings")
+                .as_str()
+                .map(String::from)
+                .expect("Asset classes serialize as strings");
+            (name, a.target_ratio)
+        })
+        .collect();
+    toml::to_string(&AllocationSection { allocation })
+        .expect("Allocations can always be serialized")
+}
+
+/// Read a section keyed by asset class, e.g. `[allocation]`
+///
+/// TOML keys are always strings, so enums need a hand.
+pub fn deserialize_by_class<'de, D, V>(
+    deserializer: D,
+) -> Result<Option<BTreeMap<AssetClass, V>>, D::Error>
+where
+    D: Deserializer<'de>,
+    V: Deserialize<'de>,
+{
+    let by_name: Option<BTreeMap<String, V>> = Option::deserialize(deserializer)?;
+    by_name
+        .map(|by_name| {
+            by_name
+                .into_iter()
+                .map(|(name, value)| {
+                    let asset_class = AssetClass::deserialize(name.into_deserializer())?;
+                    Ok((asset_class, value))
+                })
+                .collect()
+        })
+        .transpose()
+}
+
+/// Target allocations, exactly as given
+pub fn from_explicit(allocation: &ExplicitAllocation) -> Vec<AssetAllocation> {
+    allocation
+        .iter()
+        .map(|(asset_class, ratio)| AssetAllocation::new(asset_class.clone(), *ratio))
+        .collect()
+}
+
+/// Bill Schultheis' ["Coffeehouse" portfolio][lazy]: 40% bonds, the rest spread over six equal
+/// stock sleeves (the large cap & large value sleeves are both US Total; small & small value both
+/// US Small/Mid).
+///
+/// [lazy]: https://www.bogleheads.org/wiki/Lazy_portfolios
+const COFFEEHOUSE: &[(AssetClass, i64)] = &[
+    (AssetClass::USBonds, 40),
+    (AssetClass::USTotal, 20),
+    (AssetClass::USSmall, 20),
+    (AssetClass::IntlStocks, 10),
+    (AssetClass::REIT, 10),
+];
+
+/// David Swensen's lazy portfolio, from "Unconventional Success" (TIPS & Treasuries are bonds;
+/// developed & emerging markets are international stocks).
+const SWENSEN: &[(AssetClass, i64)] = &[
+    (AssetClass::USBonds, 30),
+    (AssetClass::USTotal, 30),
+    (AssetClass::IntlStocks, 20),
+    (AssetClass::REIT, 20),
+];
+
+/// A single total world stock fund, held as its US & international parts (by rough market cap)
+const TOTAL_WORLD: &[(AssetClass, i64)] =
+    &[(AssetClass::USTotal, 60), (AssetClass::IntlStocks, 40)];
+
+/// Target allocations from a preset's percentages
+fn from_preset(preset: &[(AssetClass, i64)]) -> Vec<AssetAllocation> {
+    preset
+        .iter()
+        .map(|(asset_class, percent)| {
+            AssetAllocation::new(asset_class.clone(), Decimal::new(*percent, 2))
+        })
+        .collect()
+}
+
+/// A named "lazy portfolio" strategy, from which target allocations are derived
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LazyPortfolio {
+    /// US Total, international, & bonds (with 120 minus your age in stocks)
+    ThreeFund,
+    /// See `core_four` (with 120 minus your age in stocks)
+    #[default]
+    CoreFour,
+    /// The "Core Four," but with the classic (more conservative) rule: your age in bonds
+    AgeInBonds,
+    /// See `COFFEEHOUSE` (fixed ratios, regardless of age)
+    Coffeehouse,
+    /// See `SWENSEN` (fixed ratios, regardless of age)
+    Swensen,
+    /// See `TOTAL_WORLD` (all stocks, regardless of age)
+    TotalWorld,
+}
+
+impl fmt::Display for LazyPortfolio {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            LazyPortfolio::ThreeFund => "Three-fund",
+            LazyPortfolio::CoreFour => "Core Four",
+            LazyPortfolio::AgeInBonds => "Age in bonds",
+            LazyPortfolio::Coffeehouse => "Coffeehouse",
+            LazyPortfolio::Swensen => "Swensen",
+            LazyPortfolio::TotalWorld => "Total world",
+        };
+        write!(f, "{:}", name)
+    }
+}
+
+impl LazyPortfolio {
+    pub fn all() -> [LazyPortfolio; 6] {
+        [
+            LazyPortfolio::ThreeFund,
+            LazyPortfolio::CoreFour,
+            LazyPortfolio::AgeInBonds,
+            LazyPortfolio::Coffeehouse,
+            LazyPortfolio::Swensen,
+            LazyPortfolio::TotalWorld,
+        ]
+    }
+
+    /// Fixed percentages by asset class, for strategies that don't depend on age
+    fn preset(&self) -> Option<&'static [(AssetClass, i64)]> {
+        match self {
+            LazyPortfolio::Coffeehouse => Some(COFFEEHOUSE),
+            LazyPortfolio::Swensen => Some(SWENSEN),
+            LazyPortfolio::TotalWorld => Some(TOTAL_WORLD),
+            LazyPortfolio::ThreeFund | LazyPortfolio::CoreFour | LazyPortfolio::AgeInBonds => None,
+        }
+    }
+
+    /// The age from which we subtract in order to get the stock allocation (see `bond_allocation`)
+    ///
+    /// Most strategies subtract from the configured `base` (e.g. 120), but owning your age in
+    /// bonds always means 100. Presets ignore the resulting bond ratio, so for them this makes
+    /// no difference.
+    pub fn from_years(&self, base: u8) -> u8 {
+        match self {
+            LazyPortfolio::AgeInBonds => 100,
+            _ => base,
+        }
+    }
+
+    /// Target allocations for the given bond ratio (`ratio_intl` is intl's share of stocks)
+    ///
+    /// Presets have fixed ratios, so neither argument applies to them.
+    pub fn allocations(&self, ratio_bonds: Decimal, ratio_intl: Decimal) -> Vec<AssetAllocation> {
+        if let Some(preset) = self.preset() {
+            return from_preset(preset);
+        }
+        match self {
+            LazyPortfolio::ThreeFund => three_fund(ratio_bonds, ratio_intl),
+            _ => core_four_with_intl(ratio_bonds, ratio_intl),
+        }
+    }
+
+    /// Target allocations for somebody with the given birthday
+    pub fn allocations_for(
+        &self,
+        birthday: NaiveDate,
+        precision: u32,
+        base: u8,
+        ratio_intl: Decimal,
+    ) -> Vec<AssetAllocation> {
+        let ratio_bonds =
+            bond_allocation_with_precision(birthday, self.from_years(base), precision);
+        self.allocations(ratio_bonds, ratio_intl)
+    }
+
+    /// Target allocations for somebody with the given birthday, as of some other day
+    pub fn allocations_on(
+        &self,
+        birthday: NaiveDate,
+        precision: u32,
+        base: u8,
+        ratio_intl: Decimal,
+        day: NaiveDate,
+    ) -> Vec<AssetAllocation> {
+        let ratio_bonds = bond_allocation_on(birthday, self.from_years(base), precision, day);
+        self.allocations(ratio_bonds, ratio_intl)
+    }
+}
+
+/// Re-divide the US stock sleeve so there's `small_per_total` in US Small/Mid per $1 in US Total.
+///
+/// The classic strategy's 33:17 split is roughly 1:2 (see `core_four`); a 1:1 tilt instead
+/// holds as much small+mid cap as total market, while 1:3 tilts towards large cap.
+/// The combined US stock allocation is unchanged (as are strategies without a small cap sleeve).
+pub fn tilt_small(
+    allocations: Vec<AssetAllocation>,
+    small_per_total: Decimal,
+) -> Vec<AssetAllocation> {
+    assert!(
+        !small_per_total.is_sign_negative(),
+        "Ratio must be positive"
+    );
+    if !allocations
+        .iter()
+        .any(|a| a.asset_class == AssetClass::USSmall)
+    {
+        return allocations;
+    }
+
+    let sleeve: Decimal = allocations
+        .iter()
+        .filter(|a| a.asset_class == AssetClass::USTotal || a.asset_class == AssetClass::USSmall)
+        .map(|a| a.target_ratio)
+        .sum();
+    let us_total = sleeve / (Decimal::from(1) + small_per_total);
+    // Take small as the remainder, so that the sleeve's total is exactly preserved
+    let us_small = sleeve - us_total;
+
+    allocations
+        .into_iter()
+        .map(|allocation| match allocation.asset_class {
+            AssetClass::USTotal => AssetAllocation::new(AssetClass::USTotal, us_total),
+            AssetClass::USSmall => AssetAllocation::new(AssetClass::USSmall, us_small),
+            _ => allocation,
+        })
+        .collect()
+}
+
+/// Divide a strategy's REIT sleeve between US and international REITs.
+///
+/// `ratio_intl` is the fraction of the REIT sleeve that goes to international REITs.
+/// Strategies without a REIT allocation are returned unchanged.
+pub fn split_reit(allocations: Vec<AssetAllocation>, ratio_intl: Decimal) -> Vec<AssetAllocation> {
+    let one: Decimal = 1.into();
+    assert!(!ratio_intl.is_sign_negative(), "Ratio must be positive");
+    assert!(ratio_intl <= one, "Ratio cannot exceed 100%");
+
+    let mut split = Vec::new();
+    for allocation in allocations.into_iter() {
+        if allocation.asset_class == AssetClass::REIT {
+            let reit = allocation.target_ratio;
+            split.push(AssetAllocation::new(
+                AssetClass::USREIT,
+                reit * (one - ratio_intl),
+            ));
+            split.push(AssetAllocation::new(
+                AssetClass::IntlREIT,
+                reit * ratio_intl,
+            ));
+        } else {
+            split.push(allocation);
+        }
+    }
+    split
+}
+
+/// Set aside a fixed share of the portfolio for a target-date fund (`AssetClass::Target`).
+///
+/// Target-date funds hold their own (changing) mix of stocks & bonds, so they don't fit any one
+/// sleeve of a strategy. Instead, they're given `ratio_target` of the portfolio outright, and
+/// the strategy's other allocations are scaled down proportionally to fill the rest.
+pub fn include_target_date(
+    allocations: Vec<AssetAllocation>,
+    ratio_target: Decimal,
+) -> Vec<AssetAllocation> {
+    let one: Decimal = 1.into();
+    assert!(!ratio_target.is_sign_negative(), "Ratio must be positive");
+    assert!(ratio_target <= one, "Ratio cannot exceed 100%");
+
+    let total: Decimal = allocations.iter().map(|a| a.target_ratio).sum();
+    let mut scaled: Vec<AssetAllocation> = allocations
+        .into_iter()
+        .map(|allocation| {
+            AssetAllocation::new(
+                allocation.asset_class,
+                allocation.target_ratio * (one - ratio_target),
+            )
+        })
+        .collect();
+    // Take the target-date fund as the remainder, so that the overall total is exactly preserved
+    let scaled_total: Decimal = scaled.iter().map(|a| a.target_ratio).sum();
+    scaled.push(AssetAllocation::new(
+        AssetClass::Target,
+        total - scaled_total,
+    ));
+    scaled
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::assets::Asset;
+    use crate::rebalance::Portfolio;
 
     #[test]
     #[should_panic(expected = "You were born in the future?")]
     fn test_future_birthday() {
         let birthday = NaiveDate::from_ymd_opt(2095, 6, 14).unwrap();
-        age_in_weeks(birthday);
+        age_in_weeks_on(birthday, NaiveDate::from_ymd_opt(2024, 1, 1).unwrap());
     }
 
     #[test]
@@ -115,6 +559,37 @@ mod tests {
         assert_eq!(bond_allocation(birthday, 130), Decimal::from(0));
     }
 
+    #[test]
+    fn test_bond_allocation_precision() {
+        let age = Decimal::new(451766, 4);
+        let two_places = bond_allocation_at_age(age, 120, 2);
+        let three_places = bond_allocation_at_age(age, 120, 3);
+        // 120 - 45.1766 = 74.8234% in stocks
+        assert_eq!(two_places, Decimal::new(2518, 4));
+        assert_eq!(three_places, Decimal::new(25177, 5));
+
+        for ratio_bonds in [two_places, three_places] {
+            let total: Decimal = core_four(ratio_bonds).iter().map(|a| a.target_ratio).sum();
+            assert_eq!(total, 1.into());
+        }
+    }
+
+    #[test]
+    fn test_bond_allocation_default_precision() {
+        let birthday = NaiveDate::from_ymd_opt(1985, 1, 1).unwrap();
+        assert_eq!(
+            bond_allocation(birthday, 120),
+            bond_allocation_with_precision(birthday, 120, 2)
+        );
+        assert!(bond_allocation_with_precision(birthday, 120, 3).scale() <= 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "Precision cannot exceed 6 decimal places")]
+    fn test_bond_allocation_excessive_precision() {
+        bond_allocation_at_age(Decimal::from(45), 120, 7);
+    }
+
     #[test]
     #[should_panic(expected = "Ratio must be positive")]
     fn test_negative_ratio() {
@@ -156,6 +631,205 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_core_four_weighted_by_market_cap() {
+        let allocations = core_four_with_intl(0.into(), Decimal::new(45, 2));
+        assert_eq!(
+            allocations[3],
+            AssetAllocation::new(AssetClass::IntlStocks, Decimal::new(45, 2))
+        );
+        // The 55% left over is split 33:17:10 between US Total, US Small, and REIT
+        assert_eq!(
+            allocations[1],
+            AssetAllocation::new(AssetClass::USTotal, Decimal::new(3025, 4))
+        );
+        assert_eq!(
+            allocations[2].target_ratio.round_dp(6),
+            Decimal::new(155833, 6)
+        );
+        assert_eq!(
+            allocations[4].target_ratio.round_dp(6),
+            Decimal::new(91667, 6)
+        );
+
+        let total: Decimal = allocations.iter().map(|a| a.target_ratio).sum();
+        assert_eq!(total, 1.into());
+    }
+
+    #[test]
+    fn test_core_four_market_cap_with_bonds() {
+        let allocations = core_four_with_intl(Decimal::new(20, 2), Decimal::new(45, 2));
+        assert_eq!(allocations[3].target_ratio, Decimal::new(36, 2));
+        assert_eq!(allocations[1].target_ratio, Decimal::new(242, 3));
+        let total: Decimal = allocations.iter().map(|a| a.target_ratio).sum();
+        assert_eq!(total, 1.into());
+    }
+
+    #[test]
+    fn test_three_fund() {
+        assert_eq!(
+            three_fund(Decimal::new(20, 2), Decimal::new(40, 2)),
+            vec![
+                AssetAllocation::new(AssetClass::USBonds, Decimal::new(20, 2)),
+                AssetAllocation::new(AssetClass::USTotal, Decimal::new(48, 2)),
+                AssetAllocation::new(AssetClass::IntlStocks, Decimal::new(32, 2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lazy_portfolio_dispatch() {
+        let ratio_bonds = Decimal::new(20, 2);
+        let ratio_intl = Decimal::new(40, 2);
+        assert_eq!(
+            LazyPortfolio::CoreFour.allocations(ratio_bonds, ratio_intl),
+            core_four(ratio_bonds)
+        );
+        assert_eq!(
+            LazyPortfolio::ThreeFund.allocations(ratio_bonds, ratio_intl),
+            three_fund(ratio_bonds, ratio_intl)
+        );
+        // Owning your age in bonds is more conservative than 120 minus your age in stocks
+        let birthday = NaiveDate::from_ymd_opt(1985, 1, 1).unwrap();
+        assert!(
+            LazyPortfolio::AgeInBonds.allocations_for(birthday, 2, 120, ratio_intl)[0].target_ratio
+                > LazyPortfolio::CoreFour.allocations_for(birthday, 2, 120, ratio_intl)[0]
+                    .target_ratio
+        );
+        // A lower base holds more in bonds (though owning your age in bonds always uses 100)
+        let bonds = |strategy: LazyPortfolio, base| {
+            strategy.allocations_for(birthday, 2, base, ratio_intl)[0].target_ratio
+        };
+        assert!(bonds(LazyPortfolio::CoreFour, 110) > bonds(LazyPortfolio::CoreFour, 120));
+        assert_eq!(
+            bonds(LazyPortfolio::AgeInBonds, 110),
+            bonds(LazyPortfolio::AgeInBonds, 120)
+        );
+    }
+
+    #[test]
+    fn test_glide_path_grows_more_conservative() {
+        let birthday = NaiveDate::from_ymd_opt(1985, 1, 1).unwrap();
+        let today = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let path_for = |strategy: LazyPortfolio| {
+            glide_path(birthday, today, |day| {
+                strategy.allocations_on(birthday, 2, 120, DEFAULT_INTL_RATIO, day)
+            })
+        };
+        let glide_path = path_for(LazyPortfolio::CoreFour);
+
+        let ages: Vec<i64> = glide_path.iter().map(|point| point.age).collect();
+        assert_eq!(ages, vec![39, 44, 49, 59]);
+        for pair in glide_path.windows(2) {
+            assert!(pair[0].ratio_bonds < pair[1].ratio_bonds);
+        }
+        assert_eq!(
+            glide_path[0].to_string(),
+            "Age 39 (today): 19.12% bonds, 80.88% stocks"
+        );
+        assert_eq!(
+            glide_path[3].to_string(),
+            "Age 59 (in 20 years): 39.12% bonds, 60.88% stocks"
+        );
+
+        // Fixed presets don't glide at all
+        let swensen = path_for(LazyPortfolio::Swensen);
+        assert!(swensen
+            .iter()
+            .all(|point| point.ratio_bonds == Decimal::new(30, 2)));
+    }
+
+    #[test]
+    fn test_swensen_preset() {
+        let birthday = NaiveDate::from_ymd_opt(1985, 1, 1).unwrap();
+        let allocations =
+            LazyPortfolio::Swensen.allocations_for(birthday, 2, 120, Decimal::new(40, 2));
+        assert_eq!(
+            allocations,
+            vec![
+                AssetAllocation::new(AssetClass::USBonds, Decimal::new(30, 2)),
+                AssetAllocation::new(AssetClass::USTotal, Decimal::new(30, 2)),
+                AssetAllocation::new(AssetClass::IntlStocks, Decimal::new(20, 2)),
+                AssetAllocation::new(AssetClass::REIT, Decimal::new(20, 2)),
+            ]
+        );
+        let total: Decimal = allocations.iter().map(|a| a.target_ratio).sum();
+        assert_eq!(total, 1.into());
+    }
+
+    #[test]
+    fn test_presets_sum_to_100_percent() {
+        for strategy in LazyPortfolio::all().iter().filter(|s| s.preset().is_some()) {
+            let allocations = strategy.allocations(Decimal::new(20, 2), Decimal::new(40, 2));
+            let total: Decimal = allocations.iter().map(|a| a.target_ratio).sum();
+            assert_eq!(total, 1.into(), "{:} should sum to 100%", strategy);
+        }
+    }
+
+    #[test]
+    fn test_equal_small_tilt() {
+        let tilted = tilt_small(core_four(0.into()), 1.into());
+        assert_eq!(
+            tilted,
+            vec![
+                AssetAllocation::new(AssetClass::USBonds, 0.into()),
+                AssetAllocation::new(AssetClass::USTotal, Decimal::new(25, 2)),
+                AssetAllocation::new(AssetClass::USSmall, Decimal::new(25, 2)),
+                AssetAllocation::new(AssetClass::IntlStocks, Decimal::new(40, 2)),
+                AssetAllocation::new(AssetClass::REIT, Decimal::new(10, 2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_large_cap_tilt() {
+        let tilted = tilt_small(
+            core_four(Decimal::new(20, 2)),
+            Decimal::from(1) / Decimal::from(3),
+        );
+        // The US sleeve (40%) is split 3:1 between US Total and US Small
+        assert_eq!(tilted[1].target_ratio.round_dp(6), Decimal::new(3, 1));
+        assert_eq!(tilted[2].target_ratio.round_dp(6), Decimal::new(1, 1));
+        assert_eq!(
+            tilted[1].target_ratio + tilted[2].target_ratio,
+            Decimal::new(40, 2)
+        );
+        let total: Decimal = tilted.iter().map(|a| a.target_ratio).sum();
+        assert_eq!(total, 1.into());
+    }
+
+    #[test]
+    fn test_split_reit() {
+        let split = split_reit(core_four(0.into()), Decimal::new(30, 2));
+        assert_eq!(
+            split,
+            vec![
+                AssetAllocation::new(AssetClass::USBonds, 0.into()),
+                AssetAllocation::new(AssetClass::USTotal, Decimal::new(33, 2)),
+                AssetAllocation::new(AssetClass::USSmall, Decimal::new(17, 2)),
+                AssetAllocation::new(AssetClass::IntlStocks, Decimal::new(40, 2)),
+                AssetAllocation::new(AssetClass::USREIT, Decimal::new(7, 2)),
+                AssetAllocation::new(AssetClass::IntlREIT, Decimal::new(3, 2)),
+            ]
+        );
+        let reit_total: Decimal = split
+            .iter()
+            .filter(|a| {
+                a.asset_class == AssetClass::USREIT || a.asset_class == AssetClass::IntlREIT
+            })
+            .map(|a| a.target_ratio)
+            .sum();
+        assert_eq!(reit_total, Decimal::new(10, 2));
+        let total: Decimal = split.iter().map(|a| a.target_ratio).sum();
+        assert_eq!(total, 1.into());
+    }
+
+    #[test]
+    #[should_panic(expected = "Ratio cannot exceed 100%")]
+    fn test_split_reit_exceeds_one_hundred_percent() {
+        split_reit(core_four(0.into()), 2.into());
+    }
+
     #[test]
     fn test_core_four_middle_aged() {
         assert_eq!(
@@ -169,4 +843,51 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_include_target_date() {
+        let allocations = include_target_date(core_four(Decimal::new(20, 2)), Decimal::new(50, 2));
+        assert_eq!(
+            allocations,
+            vec![
+                AssetAllocation::new(AssetClass::USBonds, Decimal::new(10, 2)),
+                AssetAllocation::new(AssetClass::USTotal, Decimal::new(132, 3)),
+                AssetAllocation::new(AssetClass::USSmall, Decimal::new(68, 3)),
+                AssetAllocation::new(AssetClass::IntlStocks, Decimal::new(16, 2)),
+                AssetAllocation::new(AssetClass::REIT, Decimal::new(4, 2)),
+                AssetAllocation::new(AssetClass::Target, Decimal::new(50, 2)),
+            ]
+        );
+        let total: Decimal = allocations.iter().map(|a| a.target_ratio).sum();
+        assert_eq!(total, 1.into());
+    }
+
+    fn target_fund() -> Asset {
+        Asset::new(
+            String::from("Vanguard Target Retirement 2050 Fund"),
+            Some(String::from("VFIFX")),
+            250.into(),
+            AssetClass::Target,
+            None,
+            None,
+            None,
+        )
+    }
+
+    #[test]
+    fn test_target_date_holdings_are_kept() {
+        // Without a Target allocation, the holding has nowhere to go
+        let without = Portfolio::from_assets(core_four(0.into()), vec![target_fund()]);
+        assert_eq!(without.current_value(), 0.into());
+
+        let allocations = include_target_date(core_four(0.into()), Decimal::new(25, 2));
+        let with = Portfolio::from_assets(allocations, vec![target_fund()]);
+        assert_eq!(with.current_value(), 250.into());
+        let target = with
+            .allocations()
+            .iter()
+            .find(|a| a.asset_class == AssetClass::Target)
+            .unwrap();
+        assert_eq!(target.assets().len(), 1);
+    }
 }
@@ -0,0 +1,168 @@
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::assets::AssetClass;
+use crate::decutil;
+use crate::rebalance::Portfolio;
+
+/// How the portfolio changed over a calendar year, and why
+#[derive(Debug, PartialEq, Eq)]
+pub struct AnnualReport {
+    pub year: i32,
+    /// Value of each asset class at the end of the previous year
+    pub starting: BTreeMap<AssetClass, Decimal>,
+    /// Value of each asset class at the end of the year (or today, for the current year)
+    pub ending: BTreeMap<AssetClass, Decimal>,
+    /// Money put into investments over the year, less any taken out
+    pub contributions: Decimal,
+}
+
+/// The days bounding a year's report: the end of the previous year, and the end of this one
+///
+/// A year still in progress ends today.
+pub fn year_bounds(year: i32, today: NaiveDate) -> (NaiveDate, NaiveDate) {
+    let start = NaiveDate::from_ymd_opt(year - 1, 12, 31).unwrap();
+    let end = NaiveDate::from_ymd_opt(year, 12, 31).unwrap();
+    (start, end.min(today))
+}
+
+fn values_by_class(portfolio: &Portfolio) -> BTreeMap<AssetClass, Decimal> {
+    portfolio
+        .allocations()
+        .iter()
+        .map(|allocation| (allocation.asset_class.clone(), allocation.current_value()))
+        .collect()
+}
+
+fn ratio(value: Decimal, total: Decimal) -> Decimal {
+    if total == 0.into() {
+        0.into()
+    } else {
+        value / total
+    }
+}
+
+impl AnnualReport {
+    pub fn new(
+        year: i32,
+        starting: &Portfolio,
+        ending: &Portfolio,
+        contributions: Decimal,
+    ) -> AnnualReport {
+        AnnualReport {
+            year,
+            starting: values_by_class(starting),
+            ending: values_by_class(ending),
+            contributions,
+        }
+    }
+
+    pub fn starting_total(&self) -> Decimal {
+        self.starting.values().sum()
+    }
+
+    pub fn ending_total(&self) -> Decimal {
+        self.ending.values().sum()
+    }
+
+    /// Whatever change in value isn't explained by contributions came from the market
+    pub fn market_growth(&self) -> Decimal {
+        self.ending_total() - self.starting_total() - self.contributions
+    }
+}
+
+impl fmt::Display for AnnualReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (starting_total, ending_total) = (self.starting_total(), self.ending_total());
+        let zero = Decimal::from(0);
+        writeln!(f, "{:} in review:", self.year)?;
+
+        let mut asset_classes: Vec<&AssetClass> = self.starting.keys().collect();
+        asset_classes.extend(
+            self.ending
+                .keys()
+                .filter(|c| !self.starting.contains_key(*c)),
+        );
+        asset_classes.sort();
+        for asset_class in asset_classes {
+            let start = *self.starting.get(asset_class).unwrap_or(&zero);
+            let end = *self.ending.get(asset_class).unwrap_or(&zero);
+            writeln!(
+                f,
+                " - {:}: {:} ({:.2}%) -> {:} ({:.2}%)",
+                asset_class,
+                decutil::format_dollars(&start),
+                ratio(start, starting_total) * Decimal::from(100),
+                decutil::format_dollars(&end),
+                ratio(end, ending_total) * Decimal::from(100),
+            )?;
+        }
+        writeln!(
+            f,
+            "Portfolio total: {:} -> {:}",
+            decutil::format_dollars(&starting_total),
+            decutil::format_dollars(&ending_total)
+        )?;
+        let signed = |amount: Decimal| {
+            let sign = if amount.is_sign_negative() { "-" } else { "" };
+            format!("{:}{:}", sign, decutil::format_dollars(&amount.abs()))
+        };
+        writeln!(f, " - Net contributions: {:}", signed(self.contributions))?;
+        write!(f, " - Market growth: {:}", signed(self.market_growth()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rebalance::sixty_forty_portfolio;
+
+    fn ymd(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn test_contributions_and_growth() {
+        let end_of_2022 = sixty_forty_portfolio(6_000.into(), 4_000.into());
+        let end_of_2023 = sixty_forty_portfolio(7_500.into(), 5_000.into());
+        let report = AnnualReport::new(2023, &end_of_2022, &end_of_2023, 1_500.into());
+
+        assert_eq!(report.starting_total(), Decimal::from(10_000));
+        assert_eq!(report.ending_total(), Decimal::from(12_500));
+        // $2,500 more, $1,500 of which was contributed
+        assert_eq!(report.market_growth(), Decimal::from(1_000));
+        assert_eq!(
+            report.to_string(),
+            "2023 in review:\n \
+             - US bonds: $4,000 (40.00%) -> $5,000 (40.00%)\n \
+             - US total market: $6,000 (60.00%) -> $7,500 (60.00%)\n\
+             Portfolio total: $10,000 -> $12,500\n \
+             - Net contributions: $1,500\n \
+             - Market growth: $1,000"
+        );
+    }
+
+    #[test]
+    fn test_market_losses() {
+        let start = sixty_forty_portfolio(10_000.into(), 0.into());
+        let end = sixty_forty_portfolio(9_000.into(), 0.into());
+        let report = AnnualReport::new(2022, &start, &end, 500.into());
+        assert_eq!(report.market_growth(), Decimal::from(-1_500));
+        assert!(report.to_string().ends_with("Market growth: -$1,500"));
+    }
+
+    #[test]
+    fn test_year_bounds() {
+        assert_eq!(
+            year_bounds(2023, ymd(2024, 3, 1)),
+            (ymd(2022, 12, 31), ymd(2023, 12, 31))
+        );
+        // The current year runs through today
+        assert_eq!(
+            year_bounds(2024, ymd(2024, 3, 1)),
+            (ymd(2023, 12, 31), ymd(2024, 3, 1))
+        );
+    }
+}
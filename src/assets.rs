@@ -2,9 +2,12 @@ use chrono::{DateTime, Local};
 use rust_decimal::Decimal;
 use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::error::Error;
 use std::fmt;
 use std::io;
+use std::iter::FromIterator;
+use std::str::FromStr;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct UnclassifiedAssetError {
@@ -17,6 +20,10 @@ impl UnclassifiedAssetError {
             fund_name: fund_name.to_string(),
         }
     }
+
+    pub fn fund_name(&self) -> &str {
+        &self.fund_name
+    }
 }
 
 impl fmt::Display for UnclassifiedAssetError {
@@ -31,7 +38,48 @@ impl Error for UnclassifiedAssetError {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingPriceError {
+    symbol: String,
+}
+
+impl fmt::Display for MissingPriceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "no price given for {:}", self.symbol)
+    }
+}
+
+impl Error for MissingPriceError {}
+
 #[derive(Debug, PartialEq, Eq)]
+pub enum HoldingError {
+    Unclassified(UnclassifiedAssetError),
+    MissingPrice(MissingPriceError),
+}
+
+impl fmt::Display for HoldingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HoldingError::Unclassified(e) => write!(f, "{:}", e),
+            HoldingError::MissingPrice(e) => write!(f, "{:}", e),
+        }
+    }
+}
+
+impl Error for HoldingError {}
+
+impl From<UnclassifiedAssetError> for HoldingError {
+    fn from(e: UnclassifiedAssetError) -> Self {
+        HoldingError::Unclassified(e)
+    }
+}
+impl From<MissingPriceError> for HoldingError {
+    fn from(e: MissingPriceError) -> Self {
+        HoldingError::MissingPrice(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Asset {
     pub name: String,
     pub symbol: Option<String>,
@@ -41,8 +89,17 @@ pub struct Asset {
     quantity: Option<Decimal>,
     last_price: Option<Decimal>,
     price_obtained: Option<DateTime<Local>>,
+    /// A user's annotation, e.g. "taxable, don't sell"
+    pub note: Option<String>,
+    /// For bond funds, the (effective) duration in years, if given
+    pub duration: Option<Decimal>,
+    /// Decimal places of shares to show (the quantity itself is never rounded)
+    share_precision: u32,
 }
 
+/// Decimal places of shares to show by default (e.g. 123.4568 shares)
+pub const DEFAULT_SHARE_PRECISION: u32 = 4;
+
 impl Asset {
     pub fn new(
         name: String,
@@ -61,12 +118,30 @@ impl Asset {
             quantity,
             last_price,
             price_obtained,
+            note: None,
+            duration: None,
+            share_precision: DEFAULT_SHARE_PRECISION,
         }
     }
 }
 
 impl Asset {
-    fn price_is_dated(&self) -> bool {
+    /// Number of shares held (if known)
+    pub fn quantity(&self) -> Option<Decimal> {
+        self.quantity
+    }
+
+    /// Show this many decimal places of shares
+    pub fn set_share_precision(&mut self, share_precision: u32) {
+        self.share_precision = share_precision;
+    }
+
+    /// Most recent price per share (if known)
+    pub fn last_price(&self) -> Option<Decimal> {
+        self.last_price
+    }
+
+    pub fn price_is_dated(&self) -> bool {
         match self.price_obtained {
             Some(then) => (Local::now() - then).num_weeks() > 1,
             None => false,
@@ -93,7 +168,7 @@ impl PartialOrd for Asset {
 impl fmt::Display for Asset {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut descriptor = match (self.quantity, self.last_price) {
-            (Some(q), Some(p)) => format!("{:} x ${:.2}", q, p),
+            (Some(q), Some(p)) => format!("{:} x ${:.2}", q.round_dp(self.share_precision), p),
             (_, _) => String::from("unknown price & quantity"),
         };
         if self.price_is_dated() {
@@ -105,11 +180,122 @@ impl fmt::Display for Asset {
             Some(symbol) => format!("{:} ({:})", symbol, self.name),
             None => self.name.clone(),
         };
-        write!(f, "{:}: ${:.2} ({:})", label, self.value, descriptor)
+        write!(f, "{:}: ${:.2} ({:})", label, self.value, descriptor)?;
+        if let Some(note) = &self.note {
+            write!(f, " [{:}]", note)?;
+        }
+        Ok(())
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// A holding which could be converted to a cheaper share class of the same fund
+#[derive(Debug, PartialEq, Eq)]
+pub struct ShareClassUpgrade {
+    pub from_symbol: String,
+    pub to_symbol: String,
+    pub value: Decimal,
+}
+
+impl fmt::Display for ShareClassUpgrade {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Convert {:} to {:} to lower fees (${:.2})",
+            self.from_symbol, self.to_symbol, self.value
+        )
+    }
+}
+
+impl Asset {
+    /// Attach any note given for this asset, keyed by its symbol (or failing that, its name)
+    pub fn annotate(&mut self, notes: &HashMap<String, String>) {
+        let note = self
+            .symbol
+            .as_ref()
+            .and_then(|symbol| notes.get(symbol))
+            .or_else(|| notes.get(&self.name));
+        if let Some(note) = note {
+            self.note = Some(note.clone());
+        }
+    }
+
+    /// Attach the duration given for this asset's symbol (if any)
+    pub fn set_duration(&mut self, durations: &HashMap<String, Decimal>) {
+        if let Some(duration) = self.symbol.as_ref().and_then(|s| durations.get(s)) {
+            self.duration = Some(*duration);
+        }
+    }
+
+    /// If a cheaper share class is known for this asset, describe the upgrade.
+    pub fn share_class_upgrade(
+        &self,
+        upgrades: &HashMap<String, String>,
+    ) -> Option<ShareClassUpgrade> {
+        let symbol = self.symbol.as_ref()?;
+        upgrades.get(symbol).map(|to_symbol| ShareClassUpgrade {
+            from_symbol: symbol.clone(),
+            to_symbol: to_symbol.clone(),
+            value: self.value,
+        })
+    }
+}
+
+/// A quantity of some commodity, known without reference to any accounting file
+///
+/// Library users can describe their holdings directly, then value them with
+/// `value_holdings` (supplying prices and classifications of their own).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Holding {
+    pub name: String,
+    pub symbol: String,
+    pub quantity: Decimal,
+}
+
+impl Holding {
+    pub fn new(name: &str, symbol: &str, quantity: Decimal) -> Holding {
+        Holding {
+            name: name.to_string(),
+            symbol: symbol.to_string(),
+            quantity,
+        }
+    }
+}
+
+/// Whether the fund is an I Bond (their made-up tickers live in the "Series I" namespace)
+pub fn is_series_i(fund_name: &str) -> bool {
+    fund_name.starts_with("Series I ")
+}
+
+/// Classify & value each holding, using the last known price (by symbol)
+pub fn value_holdings(
+    holdings: &[Holding],
+    classifications: &AssetClassifications,
+    prices: &HashMap<String, Decimal>,
+) -> Result<Vec<Asset>, HoldingError> {
+    let mut assets = Vec::new();
+    for holding in holdings {
+        let asset_class = classifications.classify(&holding.symbol)?;
+        let price = prices
+            .get(&holding.symbol)
+            .ok_or_else(|| MissingPriceError {
+                symbol: holding.symbol.clone(),
+            })?;
+        assets.push(Asset::new(
+            holding.name.clone(),
+            Some(holding.symbol.clone()),
+            holding.quantity * price,
+            asset_class.to_owned(),
+            Some(holding.quantity),
+            Some(*price),
+            None,
+        ));
+    }
+    Ok(assets)
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String")]
 pub enum AssetClass {
     USBonds,
     USTotal,
@@ -117,6 +303,8 @@ pub enum AssetClass {
     IntlBonds,
     IntlStocks,
     REIT,
+    USREIT,
+    IntlREIT,
     Target,
     Cash,
 }
@@ -130,6 +318,8 @@ impl fmt::Display for AssetClass {
             AssetClass::IntlBonds => "International bonds",
             AssetClass::IntlStocks => "International stocks",
             AssetClass::REIT => "REIT",
+            AssetClass::USREIT => "US REIT",
+            AssetClass::IntlREIT => "International REIT",
             AssetClass::Target => "Target",
             AssetClass::Cash => "Cash",
         };
@@ -137,6 +327,66 @@ impl fmt::Display for AssetClass {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownAssetClassError {
+    name: String,
+}
+
+impl fmt::Display for UnknownAssetClassError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let valid: Vec<String> = AssetClass::ALL
+            .iter()
+            .map(|asset_class| format!("{:?}", asset_class))
+            .collect();
+        write!(
+            f,
+            "unknown asset class '{:}' (expected one of {:})",
+            self.name,
+            valid.join(", ")
+        )
+    }
+}
+
+impl Error for UnknownAssetClassError {}
+
+impl AssetClass {
+    pub const ALL: [AssetClass; 10] = [
+        AssetClass::USBonds,
+        AssetClass::USTotal,
+        AssetClass::USSmall,
+        AssetClass::IntlBonds,
+        AssetClass::IntlStocks,
+        AssetClass::REIT,
+        AssetClass::USREIT,
+        AssetClass::IntlREIT,
+        AssetClass::Target,
+        AssetClass::Cash,
+    ];
+}
+
+impl FromStr for AssetClass {
+    type Err = UnknownAssetClassError;
+
+    /// Accept either the variant name (`USTotal`) or its display name (`US total market`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        AssetClass::ALL
+            .iter()
+            .find(|asset_class| format!("{:?}", asset_class) == s || asset_class.to_string() == s)
+            .cloned()
+            .ok_or_else(|| UnknownAssetClassError {
+                name: s.to_string(),
+            })
+    }
+}
+
+impl TryFrom<String> for AssetClass {
+    type Error = UnknownAssetClassError;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        name.parse()
+    }
+}
+
 /// This struct is used in 'data/classified.csv' to map from ticker names to asset classes
 #[derive(Debug, Deserialize, Serialize)]
 struct AssetClassMapping {
@@ -146,16 +396,40 @@ struct AssetClassMapping {
 
 pub struct AssetClassifications {
     mapping: HashMap<String, AssetClass>,
+    /// Where to count any fund lacking a classification (rather than failing)
+    default: Option<AssetClass>,
+}
+
+impl Default for AssetClassifications {
+    fn default() -> Self {
+        AssetClassifications::new()
+    }
+}
+
+impl FromIterator<(String, AssetClass)> for AssetClassifications {
+    fn from_iter<I: IntoIterator<Item = (String, AssetClass)>>(iter: I) -> Self {
+        let mut asset_classifications = AssetClassifications::new();
+        for (name, asset_class) in iter {
+            asset_classifications.add(name, asset_class);
+        }
+        asset_classifications
+    }
 }
 
 impl AssetClassifications {
     pub fn new() -> AssetClassifications {
         AssetClassifications {
             mapping: HashMap::new(),
+            default: None,
         }
     }
 
-    fn add(&mut self, name: String, asset_class: AssetClass) {
+    /// Count funds lacking a classification as this asset class (see `unclassified_warning`)
+    pub fn set_default(&mut self, asset_class: AssetClass) {
+        self.default = Some(asset_class);
+    }
+
+    pub fn add(&mut self, name: String, asset_class: AssetClass) {
         self.mapping.insert(name, asset_class);
     }
 
@@ -177,13 +451,27 @@ impl AssetClassifications {
 
     pub fn classify(&self, fund_name: &str) -> Result<&AssetClass, UnclassifiedAssetError> {
         // Special case -- no need to classify *every* fund as a bond...
-        if fund_name.starts_with("Series I ") {
+        if is_series_i(fund_name) {
             return Ok(&AssetClass::USBonds);
         }
         self.mapping
             .get(fund_name)
+            .or(self.default.as_ref())
             .ok_or_else(|| UnclassifiedAssetError::new(fund_name))
     }
+
+    /// Warn that a fund lacks a classification, if it's only classified by the default
+    pub fn unclassified_warning(&self, fund_name: &str) -> Option<String> {
+        if is_series_i(fund_name) || self.mapping.contains_key(fund_name) {
+            return None;
+        }
+        self.default.as_ref().map(|default| {
+            format!(
+                "{:} is not classified; counting it as {:}",
+                fund_name, default
+            )
+        })
+    }
 }
 
 #[cfg(test)]
@@ -203,6 +491,24 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_default_asset_class() {
+        let mut ac: AssetClassifications = vec![(String::from("VBTLX"), AssetClass::USBonds)]
+            .into_iter()
+            .collect();
+        ac.set_default(AssetClass::USTotal);
+
+        assert_eq!(ac.classify("ZZZZZ"), Ok(&AssetClass::USTotal));
+        assert_eq!(
+            ac.unclassified_warning("ZZZZZ"),
+            Some(String::from(
+                "ZZZZZ is not classified; counting it as US total market"
+            ))
+        );
+        assert_eq!(ac.classify("VBTLX"), Ok(&AssetClass::USBonds));
+        assert_eq!(ac.unclassified_warning("VBTLX"), None);
+    }
+
     #[test]
     fn test_serializing_from_csv() {
         let data = "ticker_name,asset_class\nVTSAX,USTotal\nVFIAX,USTotal";
@@ -224,6 +530,75 @@ mod tests {
         );
     }
 
+    fn vtsax(value: Decimal) -> Asset {
+        Asset::new(
+            String::from("Vanguard Total Stock Market Index Fund Admiral Shares"),
+            Some(String::from("VTSAX")),
+            value,
+            AssetClass::USTotal,
+            Some(value),
+            Some(1.into()),
+            None,
+        )
+    }
+
+    #[test]
+    fn test_display_rounds_shares() {
+        let mut asset = Asset::new(
+            String::from("Vanguard Total Stock Market Index Fund Admiral Shares"),
+            Some(String::from("VTSAX")),
+            Decimal::new(12_346, 2),
+            AssetClass::USTotal,
+            Some(Decimal::new(123_456_789, 6)),
+            Some(1.into()),
+            None,
+        );
+        assert_eq!(
+            asset.to_string(),
+            "VTSAX (Vanguard Total Stock Market Index Fund Admiral Shares): $123.46 \
+             (123.4568 x $1.00)"
+        );
+        asset.set_share_precision(2);
+        assert!(asset.to_string().contains("(123.46 x $1.00)"));
+
+        // Only the display is rounded, not the quantity itself
+        assert_eq!(asset.quantity(), Some(Decimal::new(123_456_789, 6)));
+    }
+
+    #[test]
+    fn test_display_with_note() {
+        let mut notes = HashMap::new();
+        notes.insert(String::from("VTSAX"), String::from("taxable, don't sell"));
+
+        let mut annotated = vtsax(100.into());
+        annotated.annotate(&notes);
+        assert_eq!(
+            annotated.to_string(),
+            "VTSAX (Vanguard Total Stock Market Index Fund Admiral Shares): $100.00 \
+             (100 x $1.00) [taxable, don't sell]"
+        );
+
+        let mut plain = vtsax(100.into());
+        plain.annotate(&HashMap::new());
+        assert_eq!(
+            plain.to_string(),
+            "VTSAX (Vanguard Total Stock Market Index Fund Admiral Shares): $100.00 \
+             (100 x $1.00)"
+        );
+    }
+
+    #[test]
+    fn test_note_by_account_name() {
+        let mut notes = HashMap::new();
+        notes.insert(
+            String::from("Vanguard Total Stock Market Index Fund Admiral Shares"),
+            String::from("employer match locked"),
+        );
+        let mut asset = vtsax(100.into());
+        asset.annotate(&notes);
+        assert_eq!(asset.note, Some(String::from("employer match locked")));
+    }
+
     /// If this fails, it is likely because one of the asset class names was changed!
     #[test]
     fn test_all_asset_classes() {
@@ -235,11 +610,75 @@ CCCCC,IntlBonds
 DDDDD,IntlStocks
 EEEEE,REIT
 FFFFF,Target
-GGGGG,Cash";
+GGGGG,Cash
+HHHHH,USREIT
+IIIII,IntlREIT";
         let rdr = csv::Reader::from_reader(data.as_bytes());
         AssetClassifications::from_reader(rdr).expect("All asset types are parseable");
     }
 
+    #[test]
+    fn test_value_holdings_in_memory() {
+        let ac: AssetClassifications = vec![(String::from("VTSAX"), AssetClass::USTotal)]
+            .into_iter()
+            .collect();
+        let mut prices = HashMap::new();
+        prices.insert(String::from("VTSAX"), Decimal::new(10025, 2));
+
+        let holdings = vec![Holding::new("Total Stock", "VTSAX", 10.into())];
+        let assets = value_holdings(&holdings, &ac, &prices).unwrap();
+        assert_eq!(assets[0].value, Decimal::new(100250, 2));
+        assert_eq!(assets[0].asset_class, AssetClass::USTotal);
+
+        let unpriced = vec![Holding::new("Total Stock", "VTSAX", 10.into())];
+        assert_eq!(
+            value_holdings(&unpriced, &ac, &HashMap::new()),
+            Err(HoldingError::MissingPrice(MissingPriceError {
+                symbol: String::from("VTSAX")
+            }))
+        );
+    }
+
+    #[test]
+    fn test_share_class_upgrade() {
+        let mut upgrades = HashMap::new();
+        upgrades.insert(String::from("VTSMX"), String::from("VTSAX"));
+
+        let investor_shares = Asset::new(
+            String::from("Vanguard Total Stock Market Index Fund Investor Shares"),
+            Some(String::from("VTSMX")),
+            2500.into(),
+            AssetClass::USTotal,
+            None,
+            None,
+            None,
+        );
+        let upgrade = investor_shares.share_class_upgrade(&upgrades).unwrap();
+        assert_eq!(
+            upgrade,
+            ShareClassUpgrade {
+                from_symbol: String::from("VTSMX"),
+                to_symbol: String::from("VTSAX"),
+                value: 2500.into(),
+            }
+        );
+        assert_eq!(
+            format!("{}", upgrade),
+            "Convert VTSMX to VTSAX to lower fees ($2500.00)"
+        );
+
+        let admiral_shares = Asset::new(
+            String::from("Vanguard Total Stock Market Index Fund Admiral Shares"),
+            Some(String::from("VTSAX")),
+            2500.into(),
+            AssetClass::USTotal,
+            None,
+            None,
+            None,
+        );
+        assert_eq!(admiral_shares.share_class_upgrade(&upgrades), None);
+    }
+
     #[test]
     fn included_file_can_be_parsed() {
         AssetClassifications::from_csv("data/classified.csv").expect("File can be parsed!");
@@ -295,4 +734,30 @@ GGGGG,Cash";
             "VTABX (Vanguard Total Intl Bd Idx Admiral): $10392.00 (800 x $12.99)"
         );
     }
+
+    #[test]
+    fn test_parse_asset_class() {
+        assert_eq!("USTotal".parse(), Ok(AssetClass::USTotal));
+        assert_eq!("US total market".parse(), Ok(AssetClass::USTotal));
+        assert_eq!("International REIT".parse(), Ok(AssetClass::IntlREIT));
+        for asset_class in AssetClass::ALL.iter() {
+            assert_eq!(asset_class.to_string().parse(), Ok(asset_class.clone()));
+        }
+
+        let err = "USStocks".parse::<AssetClass>().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unknown asset class 'USStocks' (expected one of USBonds, USTotal, USSmall, \
+             IntlBonds, IntlStocks, REIT, USREIT, IntlREIT, Target, Cash)"
+        );
+    }
+
+    #[test]
+    fn test_csv_accepts_display_names() {
+        let data = "ticker_name,asset_class\nVTSAX,US total market\nVBTLX,USBonds\n";
+        let classifications =
+            AssetClassifications::from_reader(csv::Reader::from_reader(data.as_bytes())).unwrap();
+        assert_eq!(classifications.classify("VTSAX"), Ok(&AssetClass::USTotal));
+        assert_eq!(classifications.classify("VBTLX"), Ok(&AssetClass::USBonds));
+    }
 }
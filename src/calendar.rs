@@ -0,0 +1,178 @@
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+
+/// Which regularly scheduled market holidays to observe
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HolidaySchedule {
+    /// The New York Stock Exchange's holidays
+    #[default]
+    US,
+    /// No holidays at all (only weekends are closed); list any in `closures`
+    None,
+}
+
+/// The days on which a market trades (and so on which new quotes can be expected)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct TradingCalendar {
+    #[serde(default)]
+    pub holidays: HolidaySchedule,
+    /// Other days on which the market is closed (e.g. a national day of mourning)
+    #[serde(default)]
+    pub closures: Vec<NaiveDate>,
+}
+
+/// Easter Sunday in the Gregorian calendar (the "anonymous" algorithm)
+fn easter(year: i32) -> NaiveDate {
+    let a = year % 19;
+    let b = year / 100;
+    let c = year % 100;
+    let d = b / 4;
+    let e = b % 4;
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let i = c / 4;
+    let k = c % 4;
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let month = (h + l - 7 * m + 114) / 31;
+    let day = (h + l - 7 * m + 114) % 31 + 1;
+    NaiveDate::from_ymd_opt(year, month as u32, day as u32).unwrap()
+}
+
+fn nth_weekday(year: i32, month: u32, weekday: Weekday, n: u8) -> NaiveDate {
+    NaiveDate::from_weekday_of_month_opt(year, month, weekday, n).unwrap()
+}
+
+fn last_weekday(year: i32, month: u32, weekday: Weekday) -> NaiveDate {
+    NaiveDate::from_weekday_of_month_opt(year, month, weekday, 5)
+        .unwrap_or_else(|| nth_weekday(year, month, weekday, 4))
+}
+
+/// A fixed-date holiday falling on a weekend is observed on the nearest weekday
+fn observed(date: NaiveDate) -> NaiveDate {
+    match date.weekday() {
+        Weekday::Sat => date - Duration::days(1),
+        Weekday::Sun => date + Duration::days(1),
+        _ => date,
+    }
+}
+
+/// Every day the NYSE is closed for a holiday in the given year
+fn us_holidays(year: i32) -> Vec<NaiveDate> {
+    let fixed = |month, day| NaiveDate::from_ymd_opt(year, month, day).unwrap();
+    let mut holidays = vec![
+        nth_weekday(year, 1, Weekday::Mon, 3), // Martin Luther King Jr. Day
+        nth_weekday(year, 2, Weekday::Mon, 3), // Washington's Birthday
+        easter(year) - Duration::days(2),      // Good Friday
+        last_weekday(year, 5, Weekday::Mon),   // Memorial Day
+        observed(fixed(7, 4)),                 // Independence Day
+        nth_weekday(year, 9, Weekday::Mon, 1), // Labor Day
+        nth_weekday(year, 11, Weekday::Thu, 4), // Thanksgiving
+        observed(fixed(12, 25)),               // Christmas
+    ];
+    // When New Year's Day is a Saturday, the NYSE stays open the Friday before (year end)
+    if fixed(1, 1).weekday() != Weekday::Sat {
+        holidays.push(observed(fixed(1, 1)));
+    }
+    if year >= 2022 {
+        holidays.push(observed(fixed(6, 19))); // Juneteenth
+    }
+    holidays
+}
+
+impl TradingCalendar {
+    pub fn is_trading_day(&self, date: NaiveDate) -> bool {
+        if let Weekday::Sat | Weekday::Sun = date.weekday() {
+            return false;
+        }
+        if self.closures.contains(&date) {
+            return false;
+        }
+        match self.holidays {
+            HolidaySchedule::US => !us_holidays(date.year()).contains(&date),
+            HolidaySchedule::None => true,
+        }
+    }
+
+    /// Return the most recent trading day on or before the given date
+    pub fn most_recent_trading_day(&self, date: NaiveDate) -> NaiveDate {
+        let mut day = date;
+        while !self.is_trading_day(day) {
+            day -= Duration::days(1);
+        }
+        day
+    }
+
+    /// Return the last trading day strictly before the given date
+    pub fn previous_trading_day(&self, date: NaiveDate) -> NaiveDate {
+        self.most_recent_trading_day(date - Duration::days(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ymd(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn test_easter() {
+        assert_eq!(easter(2024), ymd(2024, 3, 31));
+        assert_eq!(easter(2025), ymd(2025, 4, 20));
+    }
+
+    #[test]
+    fn test_most_recent_trading_day_over_weekend() {
+        let calendar = TradingCalendar::default();
+        let friday = ymd(2024, 1, 12);
+        assert_eq!(calendar.most_recent_trading_day(friday), friday);
+        assert_eq!(calendar.most_recent_trading_day(ymd(2024, 1, 13)), friday);
+        assert_eq!(calendar.most_recent_trading_day(ymd(2024, 1, 14)), friday);
+    }
+
+    #[test]
+    fn test_most_recent_trading_day_over_holiday() {
+        let calendar = TradingCalendar::default();
+        // Martin Luther King Jr. Day makes for a long weekend
+        assert!(!calendar.is_trading_day(ymd(2024, 1, 15)));
+        assert_eq!(
+            calendar.most_recent_trading_day(ymd(2024, 1, 15)),
+            ymd(2024, 1, 12)
+        );
+        assert_eq!(
+            calendar.previous_trading_day(ymd(2024, 1, 16)),
+            ymd(2024, 1, 12)
+        );
+
+        // Good Friday
+        assert_eq!(
+            calendar.most_recent_trading_day(ymd(2024, 3, 29)),
+            ymd(2024, 3, 28)
+        );
+        // Christmas on a Sunday is observed Monday
+        assert_eq!(
+            calendar.most_recent_trading_day(ymd(2022, 12, 26)),
+            ymd(2022, 12, 23)
+        );
+        // New Year's Day on a Saturday isn't observed on the Friday before
+        assert!(calendar.is_trading_day(ymd(2021, 12, 31)));
+    }
+
+    #[test]
+    fn test_other_markets() {
+        let calendar = TradingCalendar {
+            holidays: HolidaySchedule::None,
+            closures: vec![ymd(2024, 1, 26)],
+        };
+        // US holidays don't apply...
+        assert!(calendar.is_trading_day(ymd(2024, 1, 15)));
+        // ...but listed closures do
+        assert_eq!(
+            calendar.most_recent_trading_day(ymd(2024, 1, 28)),
+            ymd(2024, 1, 25)
+        );
+    }
+}
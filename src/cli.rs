@@ -0,0 +1,404 @@
+use crate::currency::{self, AmountError};
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+/// How to report errors
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// A plain message, for people
+    #[default]
+    Text,
+    /// A JSON object, for scripts
+    Json,
+}
+
+/// Options given on the command line
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Args {
+    /// Write fetched prices without asking for confirmation first
+    pub assume_yes: bool,
+    /// Contribute whatever brings the portfolio to this total (instead of asking how much)
+    pub top_up_to: Option<Decimal>,
+    /// Contribute this much (negative to withdraw), instead of asking how much
+    pub contribution: Option<Decimal>,
+    /// Write a Markdown summary of the rebalance to this path
+    pub receipt_path: Option<String>,
+    /// Show what each lazy portfolio strategy would recommend, rather than rebalancing
+    pub compare_strategies: bool,
+    /// Skip the retirement projection, going straight to the rebalance
+    pub no_projection: bool,
+    /// Just fetch & write new prices, then exit (no portfolio, projection, or rebalance)
+    pub update_prices_only: bool,
+    /// Write holdings to this path as a CSV that Personal Capital / Empower can import, then exit
+    pub aggregator_csv_path: Option<String>,
+    /// Write target allocations to this path as an `[allocation]` config section, then exit
+    pub allocation_toml_path: Option<String>,
+    /// List commodities whose prices can't be written in whole cents without loss, then exit
+    pub check_price_precision: bool,
+    /// Show how the target allocation shifts with age for somebody born on this day, then exit
+    pub glide_path_birthday: Option<NaiveDate>,
+    /// Report how the portfolio changed over this calendar year, then exit
+    pub annual_report_year: Option<i32>,
+    /// How to report errors
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArgumentError {
+    message: String,
+}
+
+impl ArgumentError {
+    pub fn new(message: String) -> ArgumentError {
+        ArgumentError { message }
+    }
+}
+
+impl fmt::Display for ArgumentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:}", self.message)
+    }
+}
+
+impl Error for ArgumentError {}
+
+impl Args {
+    /// Parse arguments (excluding the program name)
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Args, ArgumentError> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_ref() {
+                "-y" | "--yes" => parsed.assume_yes = true,
+                "--compare-strategies" => parsed.compare_strategies = true,
+                "--no-projection" => parsed.no_projection = true,
+                "--update-prices-only" => parsed.update_prices_only = true,
+                "--check-price-precision" => parsed.check_price_precision = true,
+                "--top-up-to" => {
+                    parsed.top_up_to = Some(positive_amount(&arg, args.next())?);
+                }
+                "--contribute" | "--withdraw" => {
+                    if parsed.contribution.is_some() {
+                        return Err(ArgumentError::new(String::from(
+                            "Give only one of --contribute or --withdraw",
+                        )));
+                    }
+                    let amount = positive_amount(&arg, args.next())?;
+                    parsed.contribution = Some(if arg == "--withdraw" { -amount } else { amount });
+                }
+                "--receipt" => {
+                    let path = args
+                        .next()
+                        .ok_or_else(|| ArgumentError::new(format!("Missing path for {:}", arg)))?;
+                    parsed.receipt_path = Some(path);
+                }
+                "--format" => {
+                    let format = args.next().ok_or_else(|| {
+                        ArgumentError::new(format!("Missing format for {:}", arg))
+                    })?;
+                    parsed.format = match format.as_ref() {
+                        "text" => OutputFormat::Text,
+                        "json" => OutputFormat::Json,
+                        _ => {
+                            return Err(ArgumentError::new(format!(
+                                "Invalid format for {:}: {:} (expected text or json)",
+                                arg, format
+                            )))
+                        }
+                    };
+                }
+                "--export-aggregator-csv" => {
+                    let path = args
+                        .next()
+                        .ok_or_else(|| ArgumentError::new(format!("Missing path for {:}", arg)))?;
+                    parsed.aggregator_csv_path = Some(path);
+                }
+                "--export-allocation" => {
+                    let path = args
+                        .next()
+                        .ok_or_else(|| ArgumentError::new(format!("Missing path for {:}", arg)))?;
+                    parsed.allocation_toml_path = Some(path);
+                }
+                "--glide-path" => {
+                    let birthday = args.next().ok_or_else(|| {
+                        ArgumentError::new(format!("Missing birthday for {:}", arg))
+                    })?;
+                    let birthday =
+                        NaiveDate::parse_from_str(&birthday, "%Y-%m-%d").map_err(|_| {
+                            ArgumentError::new(format!(
+                                "Invalid birthday for {:}: {:} (expected YYYY-MM-DD)",
+                                arg, birthday
+                            ))
+                        })?;
+                    parsed.glide_path_birthday = Some(birthday);
+                }
+                "--annual-report" => {
+                    let year = args
+                        .next()
+                        .ok_or_else(|| ArgumentError::new(format!("Missing year for {:}", arg)))?;
+                    let year = year.parse().map_err(|_| {
+                        ArgumentError::new(format!("Invalid year for {:}: {:}", arg, year))
+                    })?;
+                    parsed.annual_report_year = Some(year);
+                }
+                _ => return Err(ArgumentError::new(format!("Unknown argument: {:}", arg))),
+            }
+        }
+        if parsed.top_up_to.is_some() && parsed.contribution.is_some() {
+            return Err(ArgumentError::new(String::from(
+                "Give only one of --top-up-to, --contribute, or --withdraw",
+            )));
+        }
+        Ok(parsed)
+    }
+}
+
+/// Parse the (dollar) amount given for `arg`, which must be above zero
+///
+/// Exchange rates come from the config, so other currencies are only accepted at the prompt.
+fn positive_amount(arg: &str, amount: Option<String>) -> Result<Decimal, ArgumentError> {
+    let amount =
+        amount.ok_or_else(|| ArgumentError::new(format!("Missing amount for {:}", arg)))?;
+    match currency::parse_amount(&amount, &HashMap::new()) {
+        Ok(parsed) if parsed > Decimal::ZERO => Ok(parsed),
+        Ok(_) => Err(ArgumentError::new(format!(
+            "Invalid amount for {:}: {:} (must be above zero)",
+            arg, amount
+        ))),
+        Err(AmountError::UnknownCurrency(code)) => Err(ArgumentError::new(format!(
+            "Invalid amount for {:}: {:} (only {:} is accepted here; enter {:} amounts at the prompt)",
+            arg,
+            amount,
+            currency::BASE_CURRENCY,
+            code
+        ))),
+        Err(e) => Err(ArgumentError::new(format!(
+            "Invalid amount for {:}: {:}",
+            arg, e
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, ArgumentError> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_no_arguments() {
+        assert_eq!(parse(&[]), Ok(Args::default()));
+    }
+
+    #[test]
+    fn test_assume_yes() {
+        assert!(parse(&["--yes"]).unwrap().assume_yes);
+        assert!(parse(&["-y"]).unwrap().assume_yes);
+    }
+
+    #[test]
+    fn test_top_up_to() {
+        assert_eq!(
+            parse(&["--top-up-to", "500000"]).unwrap().top_up_to,
+            Some(Decimal::from(500_000))
+        );
+        assert_eq!(
+            format!("{}", parse(&["--top-up-to"]).unwrap_err()),
+            "Missing amount for --top-up-to"
+        );
+        assert_eq!(
+            format!("{}", parse(&["--top-up-to", "lots"]).unwrap_err()),
+            "Invalid amount for --top-up-to: Not a number: 'lots'"
+        );
+        assert_eq!(
+            parse(&["--top-up-to", "$500,000"]).unwrap().top_up_to,
+            Some(Decimal::from(500_000))
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                parse(&["--top-up-to", "500000", "--contribute", "100"]).unwrap_err()
+            ),
+            "Give only one of --top-up-to, --contribute, or --withdraw"
+        );
+    }
+
+    #[test]
+    fn test_contribute_or_withdraw() {
+        assert_eq!(parse(&[]).unwrap().contribution, None);
+        assert_eq!(
+            parse(&["--contribute", "1000"]).unwrap().contribution,
+            Some(Decimal::from(1000))
+        );
+        assert_eq!(
+            parse(&["--withdraw", "250.50"]).unwrap().contribution,
+            Some(Decimal::new(-25050, 2))
+        );
+        assert_eq!(
+            format!("{}", parse(&["--contribute", "lots"]).unwrap_err()),
+            "Invalid amount for --contribute: Not a number: 'lots'"
+        );
+        assert_eq!(
+            format!("{}", parse(&["--withdraw", "-100"]).unwrap_err()),
+            "Invalid amount for --withdraw: -100 (must be above zero)"
+        );
+        assert_eq!(
+            format!("{}", parse(&["--contribute", "0"]).unwrap_err()),
+            "Invalid amount for --contribute: 0 (must be above zero)"
+        );
+        assert_eq!(
+            parse(&["--contribute", "$1,000"]).unwrap().contribution,
+            Some(Decimal::from(1000))
+        );
+        assert_eq!(
+            parse(&["--contribute", "1000 usd"]).unwrap().contribution,
+            Some(Decimal::from(1000))
+        );
+        assert_eq!(
+            format!("{}", parse(&["--contribute", "1000 GBP"]).unwrap_err()),
+            "Invalid amount for --contribute: 1000 GBP (only USD is accepted here; enter GBP amounts at the prompt)"
+        );
+        assert_eq!(
+            format!("{}", parse(&["--withdraw"]).unwrap_err()),
+            "Missing amount for --withdraw"
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                parse(&["--contribute", "100", "--withdraw", "100"]).unwrap_err()
+            ),
+            "Give only one of --contribute or --withdraw"
+        );
+    }
+
+    #[test]
+    fn test_receipt() {
+        assert_eq!(
+            parse(&["--receipt", "receipt.md"]).unwrap().receipt_path,
+            Some(String::from("receipt.md"))
+        );
+        assert_eq!(
+            format!("{}", parse(&["--receipt"]).unwrap_err()),
+            "Missing path for --receipt"
+        );
+    }
+
+    #[test]
+    fn test_compare_strategies() {
+        assert!(!parse(&[]).unwrap().compare_strategies);
+        assert!(parse(&["--compare-strategies"]).unwrap().compare_strategies);
+    }
+
+    #[test]
+    fn test_no_projection() {
+        assert!(!parse(&[]).unwrap().no_projection);
+        assert!(parse(&["--no-projection"]).unwrap().no_projection);
+    }
+
+    #[test]
+    fn test_update_prices_only() {
+        let args = parse(&["--update-prices-only", "--yes"]).unwrap();
+        assert!(args.update_prices_only);
+        assert!(args.assume_yes);
+    }
+
+    #[test]
+    fn test_check_price_precision() {
+        assert!(!parse(&[]).unwrap().check_price_precision);
+        assert!(
+            parse(&["--check-price-precision"])
+                .unwrap()
+                .check_price_precision
+        );
+    }
+
+    #[test]
+    fn test_export_aggregator_csv() {
+        assert_eq!(
+            parse(&["--export-aggregator-csv", "holdings.csv"])
+                .unwrap()
+                .aggregator_csv_path,
+            Some(String::from("holdings.csv"))
+        );
+        assert_eq!(
+            format!("{}", parse(&["--export-aggregator-csv"]).unwrap_err()),
+            "Missing path for --export-aggregator-csv"
+        );
+    }
+
+    #[test]
+    fn test_export_allocation() {
+        assert_eq!(
+            parse(&["--export-allocation", "allocation.toml"])
+                .unwrap()
+                .allocation_toml_path,
+            Some(String::from("allocation.toml"))
+        );
+        assert_eq!(
+            format!("{}", parse(&["--export-allocation"]).unwrap_err()),
+            "Missing path for --export-allocation"
+        );
+    }
+
+    #[test]
+    fn test_glide_path() {
+        assert_eq!(
+            parse(&["--glide-path", "1985-01-01"])
+                .unwrap()
+                .glide_path_birthday,
+            NaiveDate::from_ymd_opt(1985, 1, 1)
+        );
+        assert_eq!(
+            format!("{}", parse(&["--glide-path"]).unwrap_err()),
+            "Missing birthday for --glide-path"
+        );
+        assert_eq!(
+            format!("{}", parse(&["--glide-path", "01/01/1985"]).unwrap_err()),
+            "Invalid birthday for --glide-path: 01/01/1985 (expected YYYY-MM-DD)"
+        );
+    }
+
+    #[test]
+    fn test_annual_report() {
+        assert_eq!(
+            parse(&["--annual-report", "2023"])
+                .unwrap()
+                .annual_report_year,
+            Some(2023)
+        );
+        assert_eq!(
+            format!("{}", parse(&["--annual-report"]).unwrap_err()),
+            "Missing year for --annual-report"
+        );
+        assert_eq!(
+            format!("{}", parse(&["--annual-report", "last"]).unwrap_err()),
+            "Invalid year for --annual-report: last"
+        );
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(parse(&[]).unwrap().format, OutputFormat::Text);
+        assert_eq!(
+            parse(&["--format", "json"]).unwrap().format,
+            OutputFormat::Json
+        );
+        assert_eq!(
+            format!("{}", parse(&["--format", "yaml"]).unwrap_err()),
+            "Invalid format for --format: yaml (expected text or json)"
+        );
+    }
+
+    #[test]
+    fn test_unknown_argument() {
+        assert_eq!(
+            format!("{}", parse(&["--frobnicate"]).unwrap_err()),
+            "Unknown argument: --frobnicate"
+        );
+    }
+}
@@ -0,0 +1,192 @@
+use rust_decimal::Decimal;
+
+use crate::allocation::LazyPortfolio;
+use crate::assets::{Asset, AssetClass};
+use crate::rebalance::{self, AssetAllocation, ContributionPlan, Portfolio};
+
+/// What one strategy would have us do with the same holdings & contribution
+pub struct StrategyComparison {
+    pub strategy: LazyPortfolio,
+    pub plan: ContributionPlan,
+}
+
+/// Allocate the same contribution across the same holdings, under each strategy
+///
+/// `assets` should be every holding, since each strategy targets its own asset classes.
+/// `allocations_for` gives a strategy's target allocations (with any tilts applied).
+pub fn compare_strategies<F>(
+    strategies: &[LazyPortfolio],
+    allocations_for: F,
+    assets: &[Asset],
+    contribution: Decimal,
+) -> Vec<StrategyComparison>
+where
+    F: Fn(LazyPortfolio) -> Vec<AssetAllocation>,
+{
+    strategies
+        .iter()
+        .map(|strategy| {
+            let portfolio = Portfolio::from_assets(allocations_for(*strategy), assets.to_vec());
+            StrategyComparison {
+                strategy: *strategy,
+                plan: rebalance::optimally_allocate(portfolio, contribution).contribution_plan(),
+            }
+        })
+        .collect()
+}
+
+/// Tabulate each strategy's target ratio & recommended contribution, one row per asset class
+pub fn render_table(comparisons: &[StrategyComparison]) -> String {
+    let mut asset_classes: Vec<AssetClass> = Vec::new();
+    for comparison in comparisons {
+        for planned in comparison.plan.contributions.iter() {
+            if !asset_classes.contains(&planned.asset_class) {
+                asset_classes.push(planned.asset_class.clone());
+            }
+        }
+    }
+
+    let mut header = format!("{:<24}", "Asset class");
+    for comparison in comparisons {
+        header.push_str(&format!(" | {:>20}", comparison.strategy.to_string()));
+    }
+    let mut lines = vec![header];
+
+    for asset_class in asset_classes {
+        let mut line = format!("{:<24}", asset_class.to_string());
+        for comparison in comparisons {
+            let cell = match comparison
+                .plan
+                .contributions
+                .iter()
+                .find(|planned| planned.asset_class == asset_class)
+            {
+                Some(planned) => format!(
+                    "{:.2}% ${:.2}",
+                    planned.target_ratio * Decimal::from(100),
+                    planned.amount.round_dp(2)
+                ),
+                None => String::from("-"),
+            };
+            line.push_str(&format!(" | {:>20}", cell));
+        }
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocation;
+    use chrono::NaiveDate;
+
+    fn holdings() -> Vec<Asset> {
+        vec![
+            Asset::new(
+                String::from("Vanguard Total Stock Market Index Fund Admiral Shares"),
+                Some(String::from("VTSAX")),
+                600.into(),
+                AssetClass::USTotal,
+                None,
+                None,
+                None,
+            ),
+            Asset::new(
+                String::from("Vanguard Total Bond Market Index Fund Admiral Shares"),
+                Some(String::from("VBTLX")),
+                400.into(),
+                AssetClass::USBonds,
+                None,
+                None,
+                None,
+            ),
+        ]
+    }
+
+    fn target(comparison: &StrategyComparison, asset_class: AssetClass) -> Option<Decimal> {
+        comparison
+            .plan
+            .contributions
+            .iter()
+            .find(|planned| planned.asset_class == asset_class)
+            .map(|planned| planned.target_ratio)
+    }
+
+    #[test]
+    fn test_compare_three_fund_and_core_four() {
+        let birthday = NaiveDate::from_ymd_opt(1985, 1, 1).unwrap();
+        let comparisons = compare_strategies(
+            &[LazyPortfolio::ThreeFund, LazyPortfolio::CoreFour],
+            |strategy| strategy.allocations_for(birthday, 2, 120, Decimal::new(40, 2)),
+            &holdings(),
+            500.into(),
+        );
+        let (three_fund, core_four) = (&comparisons[0], &comparisons[1]);
+
+        // Same bond allocation & international share, but the US sleeve is divided differently
+        assert_eq!(
+            target(three_fund, AssetClass::USBonds),
+            target(core_four, AssetClass::USBonds)
+        );
+        assert_eq!(
+            target(three_fund, AssetClass::IntlStocks),
+            target(core_four, AssetClass::IntlStocks)
+        );
+        assert!(target(three_fund, AssetClass::USTotal) > target(core_four, AssetClass::USTotal));
+        assert_eq!(target(three_fund, AssetClass::REIT), None);
+        assert!(target(core_four, AssetClass::REIT).is_some());
+
+        for comparison in comparisons.iter() {
+            assert_eq!(comparison.plan.starting_total, Decimal::from(1000));
+            assert_eq!(
+                comparison.plan.ending_total().round_dp(2),
+                Decimal::from(1500)
+            );
+        }
+
+        let table = render_table(&comparisons);
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[0].contains("Three-fund"));
+        assert!(lines[0].contains("Core Four"));
+        // REIT is absent from the three-fund portfolio
+        let reit = lines.iter().find(|line| line.starts_with("REIT")).unwrap();
+        assert!(reit.contains(" | "));
+        assert!(reit.split(" | ").nth(1).unwrap().trim() == "-");
+    }
+
+    #[test]
+    fn test_holdings_outside_one_strategy_count_for_another() {
+        let birthday = NaiveDate::from_ymd_opt(1985, 1, 1).unwrap();
+        let mut assets = holdings();
+        assets.push(Asset::new(
+            String::from("Vanguard Real Estate Index Fund Admiral Shares"),
+            Some(String::from("VGSLX")),
+            100.into(),
+            AssetClass::REIT,
+            None,
+            None,
+            None,
+        ));
+        let comparisons = compare_strategies(
+            &[LazyPortfolio::ThreeFund, LazyPortfolio::CoreFour],
+            |strategy| {
+                let derived = strategy.allocations_for(birthday, 2, 120, Decimal::new(40, 2));
+                allocation::tilt_small(derived, 1.into())
+            },
+            &assets,
+            500.into(),
+        );
+        let (three_fund, core_four) = (&comparisons[0], &comparisons[1]);
+
+        // The three-fund portfolio has no REIT sleeve, but the Core Four still counts the REIT
+        assert_eq!(three_fund.plan.starting_total, Decimal::from(1000));
+        assert_eq!(core_four.plan.starting_total, Decimal::from(1100));
+
+        // Tilts apply to each strategy, just as they would when rebalancing
+        assert_eq!(
+            target(core_four, AssetClass::USTotal),
+            target(core_four, AssetClass::USSmall)
+        );
+    }
+}
@@ -20,18 +20,155 @@ fn banking_years(earlier_date: NaiveDate, later_date: NaiveDate) -> f64 {
     (full_days as f64) / 365.25
 }
 
-/// Compound the principal, with a given APY, from now until the end date
-pub fn compound(principal: Decimal, apy: f64, end_date: NaiveDate) -> Decimal {
-    let multiplier = (apy + 1.0).powf(years_until(end_date));
+fn grow(principal: Decimal, multiplier: f64) -> Decimal {
     let dollars = principal.to_f64().unwrap() * multiplier; // Fractional dollars
     let cents = (dollars * 100.0) as i64;
     Decimal::new(cents, 2)
 }
 
-/// Identify an annual income that can be safely maintained in perpetuity
-pub fn safe_withdrawal_income(principal: Decimal) -> Decimal {
-    let safe_withdrawal_rate = Decimal::new(4, 2);
-    principal * safe_withdrawal_rate
+/// Compound the principal, with a given APY, from now until the end date
+pub fn compound(principal: Decimal, apy: f64, end_date: NaiveDate) -> Decimal {
+    grow(principal, (apy + 1.0).powf(years_until(end_date)))
+}
+
+/// Compound the principal at one APY for the first few years, then at another until the end date
+///
+/// If the end date comes first, only the early APY ever applies.
+pub fn compound_two_regime(
+    principal: Decimal,
+    early_apy: f64,
+    early_years: f64,
+    late_apy: f64,
+    end_date: NaiveDate,
+) -> Decimal {
+    let years = years_until(end_date);
+    let early = early_years.min(years);
+    let late = years - early;
+    grow(
+        principal,
+        (early_apy + 1.0).powf(early) * (late_apy + 1.0).powf(late),
+    )
+}
+
+/// What one dollar deposited at the end of each year grows to after `years` (an annuity)
+fn annuity_multiplier(apy: f64, years: f64) -> f64 {
+    if apy == 0.0 {
+        return years;
+    }
+    ((apy + 1.0).powf(years) - 1.0) / apy
+}
+
+/// Compound a yearly contribution, with a given APY, from now until the end date
+pub fn compound_contributions(annual: Decimal, apy: f64, end_date: NaiveDate) -> Decimal {
+    grow(annual, annuity_multiplier(apy, years_until(end_date)))
+}
+
+/// Compound a yearly contribution at one APY for the first few years, then at another
+pub fn compound_contributions_two_regime(
+    annual: Decimal,
+    early_apy: f64,
+    early_years: f64,
+    late_apy: f64,
+    end_date: NaiveDate,
+) -> Decimal {
+    let years = years_until(end_date);
+    let early = early_years.min(years);
+    let late = years - early;
+    // Early deposits keep growing (at the late APY) once the early regime ends
+    grow(
+        annual,
+        annuity_multiplier(early_apy, early) * (late_apy + 1.0).powf(late)
+            + annuity_multiplier(late_apy, late),
+    )
+}
+
+/// Return how many years of growth it takes for the principal to reach a target total
+///
+/// A principal already at (or beyond) the target needs zero years.
+/// Without growth (or without any principal to grow), the target is never reached.
+fn years_to_target(principal: Decimal, apy: f64, target_total: Decimal) -> Option<f64> {
+    if principal >= target_total {
+        return Some(0.0);
+    }
+    if apy <= 0.0 || principal <= 0.into() {
+        return None;
+    }
+    let ratio = target_total.to_f64().unwrap() / principal.to_f64().unwrap();
+    Some(ratio.ln() / (apy + 1.0).ln())
+}
+
+/// Return how many fewer years it takes to reach a target by adding a windfall today
+///
+/// If the windfall brings the principal to the target, the entire wait is saved.
+/// If the principal alone would never reach the target, there's no wait to compare against.
+pub fn years_saved(
+    principal: Decimal,
+    windfall: Decimal,
+    apy: f64,
+    target_total: Decimal,
+) -> Option<f64> {
+    let without_windfall = years_to_target(principal, apy, target_total)?;
+    let with_windfall = years_to_target(principal + windfall, apy, target_total)?;
+    Some(without_windfall - with_windfall)
+}
+
+/// Return how many years of withdrawals (at the end of each year) it takes to exhaust a balance
+///
+/// If growth covers every withdrawal, the balance is never exhausted.
+fn years_until_depleted(balance: Decimal, annual_withdrawal: Decimal, apy: f64) -> Option<f64> {
+    let balance = balance.to_f64().unwrap();
+    let withdrawal = annual_withdrawal.to_f64().unwrap();
+    let growth = balance * apy;
+    if withdrawal <= growth || withdrawal <= 0.0 {
+        return None;
+    }
+    if apy == 0.0 {
+        return Some(balance / withdrawal);
+    }
+    // Solve for t: balance * (1 + apy)^t = withdrawal * ((1 + apy)^t - 1) / apy
+    Some((withdrawal / (withdrawal - growth)).ln() / (apy + 1.0).ln())
+}
+
+/// Return the day on which withdrawals (starting from `start`) would exhaust the balance
+///
+/// Returns `None` if withdrawals are sustainable (or would last past any representable date).
+pub fn depletion_date(
+    start: NaiveDate,
+    balance: Decimal,
+    annual_withdrawal: Decimal,
+    apy: f64,
+) -> Option<NaiveDate> {
+    let years = years_until_depleted(balance, annual_withdrawal, apy)?;
+    let days = chrono::Duration::try_days((years * 365.25).round() as i64)?;
+    start.checked_add_signed(days)
+}
+
+/// The flat rate used for projections (unless configured), regardless of how the portfolio is invested
+pub const SAFE_WITHDRAWAL_RATE: Decimal = Decimal::from_parts(4, 0, 0, false, 2);
+
+/// A sustainable withdrawal rate for a portfolio entirely in bonds
+pub const CONSERVATIVE_SWR: Decimal = Decimal::from_parts(30, 0, 0, false, 3);
+
+/// A sustainable withdrawal rate for a portfolio entirely in stocks
+pub const AGGRESSIVE_SWR: Decimal = Decimal::from_parts(45, 0, 0, false, 3);
+
+/// Identify an annual income that can be safely maintained in perpetuity, at the given rate
+pub fn safe_withdrawal_income(principal: Decimal, rate: Decimal) -> Decimal {
+    principal * rate
+}
+
+/// Break an annual income down by month (rounded to the cent), for budgeting
+pub fn monthly_income(annual_income: Decimal) -> Decimal {
+    (annual_income / Decimal::from(12)).round_dp(2)
+}
+
+/// Suggest a withdrawal rate suited to the portfolio's share of stocks (0 to 1)
+///
+/// Bonds return less than stocks over the long run, so a bond-heavy portfolio
+/// sustains a lower rate. Rates are interpolated linearly between the two extremes.
+pub fn suggested_swr(stock_ratio: Decimal) -> Decimal {
+    let stock_ratio = stock_ratio.max(Decimal::from(0)).min(Decimal::from(1));
+    CONSERVATIVE_SWR + (AGGRESSIVE_SWR - CONSERVATIVE_SWR) * stock_ratio
 }
 
 #[cfg(test)]
@@ -55,10 +192,172 @@ mod tests {
         //assert_eq!(total, Decimal::new(112517280, 2));
     }
 
+    #[test]
+    fn test_two_regime_compounding() {
+        let principal = Decimal::from(100_000);
+        let end_date = Local::now().date_naive() + chrono::Duration::days(365 * 30);
+        let flat = compound(principal, 0.07, end_date);
+
+        // A decade of 3% returns before reverting to 7% is worth less than 7% throughout
+        let declining = compound_two_regime(principal, 0.03, 10.0, 0.07, end_date);
+        assert!(declining > principal);
+        assert!(declining < flat);
+
+        // With the same return in both regimes, it's just flat compounding
+        assert_eq!(
+            compound_two_regime(principal, 0.07, 10.0, 0.07, end_date),
+            flat
+        );
+        // An early regime outlasting the horizon is all that applies
+        assert_eq!(
+            compound_two_regime(principal, 0.03, 50.0, 0.07, end_date),
+            compound(principal, 0.03, end_date)
+        );
+    }
+
+    #[test]
+    fn test_contributions_compound() {
+        // $1 a year for 10 years at 7% grows to about $13.82; without growth, it's just $10
+        assert!((annuity_multiplier(0.07, 10.0) - 13.8164).abs() < 0.0001);
+        assert_eq!(annuity_multiplier(0.0, 10.0), 10.0);
+
+        let annual = Decimal::from(10_000);
+        let end_date = Local::now().date_naive() + chrono::Duration::days(365 * 30);
+        let flat = compound_contributions(annual, 0.07, end_date);
+        assert!(flat > annual * Decimal::from(29));
+        assert_eq!(
+            compound_contributions_two_regime(annual, 0.07, 10.0, 0.07, end_date),
+            flat
+        );
+        assert!(compound_contributions_two_regime(annual, 0.03, 10.0, 0.07, end_date) < flat);
+    }
+
+    #[test]
+    fn test_years_to_target() {
+        let years = years_to_target(100_000.into(), 0.07, 1_000_000.into()).unwrap();
+        assert!((years - 34.03).abs() < 0.01);
+        assert_eq!(
+            years_to_target(1_000_000.into(), 0.07, 1_000_000.into()),
+            Some(0.0)
+        );
+    }
+
+    #[test]
+    fn test_targets_unreachable_without_growth() {
+        assert_eq!(years_to_target(100_000.into(), 0.0, 1_000_000.into()), None);
+        assert_eq!(
+            years_to_target(100_000.into(), -0.02, 1_000_000.into()),
+            None
+        );
+        assert_eq!(years_to_target(0.into(), 0.07, 1_000_000.into()), None);
+        // Even without growth, a principal already at the target is there
+        assert_eq!(
+            years_to_target(1_000_000.into(), 0.0, 1_000_000.into()),
+            Some(0.0)
+        );
+        assert_eq!(
+            years_saved(100_000.into(), 50_000.into(), 0.0, 1_000_000.into()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_windfall_saves_years() {
+        let without_windfall = years_to_target(100_000.into(), 0.07, 1_000_000.into()).unwrap();
+        let with_windfall = years_to_target(150_000.into(), 0.07, 1_000_000.into()).unwrap();
+        assert!(with_windfall < without_windfall);
+
+        let saved = years_saved(100_000.into(), 50_000.into(), 0.07, 1_000_000.into()).unwrap();
+        assert!(saved > 0.0);
+        assert!((saved - (without_windfall - with_windfall)).abs() < 1e-9);
+        assert!((saved - 5.99).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_windfall_exceeding_target() {
+        let saved = years_saved(100_000.into(), 2_000_000.into(), 0.07, 1_000_000.into());
+        let remaining = years_to_target(100_000.into(), 0.07, 1_000_000.into());
+        assert_eq!(saved, remaining);
+    }
+
+    #[test]
+    fn test_depletion_date() {
+        // Without growth, $10,000 a year exhausts $100,000 in exactly ten years
+        let start = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+        assert_eq!(
+            depletion_date(start, 100_000.into(), 10_000.into(), 0.0),
+            NaiveDate::from_ymd_opt(2030, 1, 1)
+        );
+
+        // At 5%, half of each withdrawal is covered by growth: ln(2) / ln(1.05) years
+        let years = years_until_depleted(100_000.into(), 10_000.into(), 0.05).unwrap();
+        assert!((years - 14.2067).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_depletion_beyond_the_calendar() {
+        // A million years of withdrawals ends after the last representable date
+        let start = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+        let balance = Decimal::from(1_000_000_000);
+        assert_eq!(depletion_date(start, balance, 1_000.into(), 0.0), None);
+        // ...and a trillion years is more days than a `Duration` can hold
+        assert_eq!(
+            depletion_date(start, balance, Decimal::new(1, 3), 0.0),
+            None
+        );
+    }
+
+    #[test]
+    fn test_sustainable_withdrawals_never_deplete() {
+        // Withdrawing no more than the growth leaves the balance intact forever
+        let start = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+        assert_eq!(
+            depletion_date(start, 1_000_000.into(), 40_000.into(), 0.05),
+            None
+        );
+        assert_eq!(
+            depletion_date(start, 1_000_000.into(), 50_000.into(), 0.05),
+            None
+        );
+        assert_eq!(depletion_date(start, 1_000_000.into(), 0.into(), 0.0), None);
+        assert!(depletion_date(start, 1_000_000.into(), 50_001.into(), 0.05).is_some());
+    }
+
     #[test]
     fn test_swr() {
-        assert_eq!(safe_withdrawal_income(1_000_000.into()), 40_000.into());
-        assert_eq!(safe_withdrawal_income(2_000_000.into()), 80_000.into());
-        assert_eq!(safe_withdrawal_income(3_000_000.into()), 120_000.into());
+        let rate = SAFE_WITHDRAWAL_RATE;
+        assert_eq!(
+            safe_withdrawal_income(1_000_000.into(), rate),
+            40_000.into()
+        );
+        assert_eq!(
+            safe_withdrawal_income(2_000_000.into(), rate),
+            80_000.into()
+        );
+        assert_eq!(
+            safe_withdrawal_income(3_000_000.into(), rate),
+            120_000.into()
+        );
+
+        // A more cautious 3.25%
+        let cautious = Decimal::new(325, 4);
+        assert_eq!(
+            safe_withdrawal_income(1_000_000.into(), cautious),
+            32_500.into()
+        );
+    }
+
+    #[test]
+    fn test_suggested_swr() {
+        let all_bonds = suggested_swr(0.into());
+        let all_stocks = suggested_swr(1.into());
+        assert!(all_bonds < all_stocks);
+        assert_eq!(all_bonds, CONSERVATIVE_SWR);
+        assert_eq!(all_stocks, AGGRESSIVE_SWR);
+
+        // A 60/40 portfolio lands in between
+        assert_eq!(suggested_swr(Decimal::new(6, 1)), Decimal::new(39, 3));
+        // Ratios are clamped to the possible range
+        assert_eq!(suggested_swr(Decimal::new(12, 1)), AGGRESSIVE_SWR);
     }
 }
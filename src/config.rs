@@ -1,11 +1,57 @@
 use serde_derive::Deserialize;
 
-use chrono::NaiveDate;
+use crate::allocation::{
+    self, ExplicitAllocation, GlidePoint, LazyPortfolio, DEFAULT_BOND_ALLOCATION_BASE,
+    DEFAULT_INTL_RATIO, MAX_BOND_PRECISION,
+};
+use crate::assets::AssetClass;
+use crate::calendar::TradingCalendar;
+use crate::compounding::SAFE_WITHDRAWAL_RATE;
+use crate::dateutil;
+use crate::exchange::{AlphaVantageRates, CachedRates, ExchangeRateProvider};
+use crate::projection::{AnnualContributions, GrowthModel};
+use crate::quote::{
+    AlphaVantage, FinanceQuoteCommand, QuoteProvider, UpdateCadence, DEFAULT_QUOTE_CONCURRENCY,
+};
+use crate::rebalance::{
+    AssetAllocation, Band, DisplayOrder, DriftThresholds, LinkedClasses, Objective, SweepAccount,
+};
+use crate::stats::IncomeSign;
+use chrono::{NaiveDate, NaiveTime};
+use chrono_tz::Tz;
+use rusqlite::OpenFlags;
+use rust_decimal::Decimal;
+use serde::de::{Deserializer, Error};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::fs;
 
+/// Why a config file couldn't be used
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file isn't valid TOML, or has an invalid setting
+    Parse(toml::de::Error),
+    /// Settings which are valid alone, but can't be used together
+    Conflict(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Parse(e) => write!(f, "Invalid config: {:}", e),
+            ConfigError::Conflict(message) => write!(f, "Invalid config: {:}", message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
 #[derive(Deserialize)]
 struct User {
     birthday: String, // YYYY-MM-DD
+    /// Zone in which to interpret quotes' trading days, e.g. "US/Eastern" (default: system local)
+    #[serde(default)]
+    timezone: Option<Tz>,
 }
 
 impl User {
@@ -19,20 +65,463 @@ pub struct GnuCash {
     pub path_to_book: String,
     pub file_format: String,
     pub update_prices: bool,
+    /// Don't write fetched quotes whose trading day is already several days old
+    #[serde(default)]
+    pub skip_stale_quotes: bool,
+    /// If the book has no USD currency, create it (so that a first price may be written)
+    #[serde(default)]
+    pub create_missing_currency: bool,
+    /// Time of day at which to record quotes, e.g. '16:00:00' for the close (default: noon)
+    pub quote_time: Option<NaiveTime>,
+    /// Only count transactions posted on or before this date (YYYY-MM-DD)
+    pub balance_as_of: Option<NaiveDate>,
+    /// Only count splits that have been cleared or reconciled (to match broker statements)
+    #[serde(default)]
+    pub reconciled_only: bool,
+    /// Which prices (by source & type) to trust when reading a SQLite book
+    #[serde(default)]
+    pub price_sources: PriceSources,
+    /// Whether income is recorded as 'negative' (the double-entry convention) or 'positive'
+    #[serde(default)]
+    pub income_sign: IncomeSign,
+    /// Fetch exchange rates from AlphaVantage for prices given in other currencies
+    /// (rather than using those under `[exchange_rates]`)
+    #[serde(default)]
+    pub fetch_exchange_rates: bool,
+    /// Fetch quotes by running this Finance::Quote command (e.g. `['gnc-fq-dump', 'vanguard']`)
+    /// rather than from AlphaVantage; each symbol is given as a final argument
+    #[serde(default, deserialize_with = "deserialize_quote_command")]
+    pub quote_command: Option<FinanceQuoteCommand>,
+    /// Most quotes to fetch at once (default: 4), to stay within the provider's rate limits
+    #[serde(default, deserialize_with = "deserialize_concurrency")]
+    pub quote_concurrency: Option<usize>,
+}
+
+/// Preferences among the prices recorded in a book's price database
+///
+/// GnuCash records where each price came from (`source`, e.g. 'user:price' for prices
+/// entered by hand, 'Finance::Quote' for fetched ones) and its `type` ('last', 'nav', ...).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct PriceSources {
+    /// Of several prices on the same day, use the one from this source
+    pub prefer: Option<String>,
+    /// Ignore prices of these types entirely
+    #[serde(default)]
+    pub exclude_types: Vec<String>,
+}
+
+/// A price set by hand, for holdings without a public quote (e.g. private stock, I-Bonds)
+///
+/// It stands until the book records a real price newer than `as_of`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct PriceOverride {
+    pub price: Decimal,
+    pub as_of: NaiveDate,
+}
+
+/// Tweaks to the "Core Four" strategy used to derive target allocations
+#[derive(Deserialize, Default)]
+pub struct Strategy {
+    /// Which lazy portfolio to follow: "three_fund", "core_four" (default), "age_in_bonds",
+    /// or a fixed preset ("coffeehouse", "swensen", or "total_world")
+    #[serde(default)]
+    pub name: LazyPortfolio,
+    /// International stocks' share of global market cap, used in place of the classic 40%
+    #[serde(default, deserialize_with = "deserialize_intl_market_cap")]
+    pub intl_market_cap: Option<Decimal>,
+    /// If given, split the REIT sleeve into US & international REIT (this fraction international)
+    /// (Funds should then be classified as `USREIT` or `IntlREIT`, rather than `REIT`)
+    #[serde(default, deserialize_with = "deserialize_intl_reit_ratio")]
+    pub intl_reit_ratio: Option<Decimal>,
+    /// Dollars in US Small/Mid per dollar in US Total (default: roughly 1:2, i.e. 17:33)
+    #[serde(default, deserialize_with = "deserialize_small_per_total")]
+    pub small_per_total: Option<Decimal>,
+    /// Decimal places to keep in the bond percentage (default: 2, e.g. 45.17%)
+    #[serde(default, deserialize_with = "deserialize_bond_precision")]
+    pub bond_precision: Option<u32>,
+    /// Share of the portfolio held in a target-date fund (classified as `Target`)
+    #[serde(default, deserialize_with = "deserialize_target_date_ratio")]
+    pub target_date_ratio: Option<Decimal>,
+    /// Flag any asset class holding more than this many dollars above its target
+    pub overweight_dollar_threshold: Option<Decimal>,
+    /// Warn when uninvested cash exceeds this share of the portfolio
+    pub cash_drag_threshold: Option<Decimal>,
+    /// What to optimize for: "min_deviation" (default) or "min_trades"
+    #[serde(default)]
+    pub objective: Objective,
+    /// Trade in no more than this many asset classes per rebalance
+    #[serde(default, deserialize_with = "deserialize_max_trades")]
+    pub max_trades: Option<usize>,
+    /// Refuse withdrawals leaving any asset class below this fraction of its target (e.g. 0.5)
+    pub withdrawal_floor: Option<Decimal>,
+    /// Invest this fraction of the `Cash` asset class along with each contribution (e.g. 1.0)
+    #[serde(default, deserialize_with = "deserialize_cash_sweep")]
+    pub cash_sweep: Option<Decimal>,
+    /// Accounts to fill with contributions, highest priority first (e.g. 401k, then Roth IRA)
+    #[serde(default)]
+    pub sweep_order: Vec<SweepAccount>,
+    /// Asset classes which should never be sold to fund a withdrawal
+    #[serde(default)]
+    pub locked_classes: Vec<AssetClass>,
+    /// Two asset classes to hold in a fixed ratio, balanced against others as if they were one
+    #[serde(default, deserialize_with = "deserialize_linked_classes")]
+    pub linked_classes: Option<LinkedClasses>,
+}
+
+/// Options controlling how output is formatted
+#[derive(Deserialize, Default)]
+pub struct Display {
+    /// Fixed width for dollar columns in the retirement table (default: fit the widest value)
+    pub retirement_column_width: Option<usize>,
+    /// Skip the retirement projection entirely (as with `--no-projection`)
+    #[serde(default)]
+    pub hide_projection: bool,
+    /// Show safe withdrawal income by month, as well as by year
+    #[serde(default)]
+    pub monthly_income: bool,
+    /// Show each asset class's share of the contribution, alongside its dollar amount
+    #[serde(default)]
+    pub contribution_percentages: bool,
+    /// Skip the retirement projection until the portfolio is worth at least this much
+    pub projection_minimum: Option<Decimal>,
+    /// Adjust contributions so that the per-fund amounts (in whole cents) sum exactly
+    #[serde(default)]
+    pub reconcile_pennies: bool,
+    /// Save each fund's value here, to report the largest changes since the previous run
+    pub snapshot_path: Option<String>,
+    /// Relative deviations beyond which an asset class shows minor or major drift
+    #[serde(default)]
+    pub drift_thresholds: DriftThresholds,
+    /// Order in which to list asset classes: "value", "alphabetical", or { custom = [...] }
+    #[serde(default)]
+    pub asset_class_order: DisplayOrder,
+    /// Decimal places of shares to show for each holding (default: 4)
+    pub share_precision: Option<u32>,
+}
+
+/// Expected real (inflation-adjusted) returns, for projecting the portfolio's worth
+#[derive(Deserialize, Default)]
+pub struct Growth {
+    /// Long-run annual return (default: 7%)
+    #[serde(default, deserialize_with = "deserialize_return")]
+    pub apy: Option<f64>,
+    /// A different return expected over the next several years, before reverting to `apy`
+    #[serde(default, deserialize_with = "deserialize_return")]
+    pub early_apy: Option<f64>,
+    /// How many years `early_apy` applies
+    pub early_years: Option<f64>,
+    /// Composite (fixed plus inflation) rate at which I Bonds grow, instead of `apy`
+    #[serde(default, deserialize_with = "deserialize_return")]
+    pub ibond_rate: Option<f64>,
+}
+
+/// Assumptions about retirement itself
+#[derive(Deserialize, Default)]
+pub struct Planning {
+    /// Fraction of the portfolio which may be withdrawn each year (default: 4%)
+    #[serde(default, deserialize_with = "deserialize_withdrawal_rate")]
+    pub safe_withdrawal_rate: Option<Decimal>,
+    /// Long-run annual real return, for projections (same as `apy` under `[growth]`)
+    #[serde(default, deserialize_with = "deserialize_return")]
+    pub expected_real_return: Option<f64>,
+    /// Hold this number minus your age in stocks (default: 120; 100 is "age in bonds")
+    #[serde(default, deserialize_with = "deserialize_bond_allocation_base")]
+    pub bond_allocation_base: Option<u8>,
+    /// Dollars to be contributed each year until retirement, for projections
+    pub annual_contribution: Option<Decimal>,
+}
+
+/// Refuse withdrawal rates that can't be a fraction of the portfolio (e.g. `4` for 4%)
+fn deserialize_withdrawal_rate<'de, D>(deserializer: D) -> Result<Option<Decimal>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let rate: Option<Decimal> = serde::Deserialize::deserialize(deserializer)?;
+    match rate {
+        Some(rate) if rate <= Decimal::from(0) || rate > Decimal::from(1) => {
+            Err(D::Error::custom(format!(
+                "safe_withdrawal_rate must be above 0 and at most 1, not {:}",
+                rate
+            )))
+        }
+        _ => Ok(rate),
+    }
+}
+
+/// Refuse bases giving absurd allocations (e.g. mostly bonds in one's twenties)
+fn deserialize_bond_allocation_base<'de, D>(deserializer: D) -> Result<Option<u8>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let base: Option<i64> = serde::Deserialize::deserialize(deserializer)?;
+    match base {
+        Some(base) if !(80..=150).contains(&base) => Err(D::Error::custom(format!(
+            "bond_allocation_base must be between 80 and 150, not {:}",
+            base
+        ))),
+        _ => Ok(base.map(|base| base as u8)),
+    }
+}
+
+/// Ratios only carry so many decimal places
+fn deserialize_bond_precision<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let precision: Option<u32> = serde::Deserialize::deserialize(deserializer)?;
+    match precision {
+        Some(precision) if precision > MAX_BOND_PRECISION => Err(D::Error::custom(format!(
+            "bond_precision must be at most {:}, not {:}",
+            MAX_BOND_PRECISION, precision
+        ))),
+        _ => Ok(precision),
+    }
+}
+
+/// At least one quote must be fetched at a time
+fn deserialize_concurrency<'de, D>(deserializer: D) -> Result<Option<usize>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let concurrency: Option<usize> = serde::Deserialize::deserialize(deserializer)?;
+    match concurrency {
+        Some(0) => Err(D::Error::custom("quote_concurrency must be at least 1")),
+        _ => Ok(concurrency),
+    }
+}
+
+/// Refuse fractions outside of 0 to 1 (e.g. `50` meaning 50%)
+fn check_fraction<E: Error>(name: &str, fraction: Option<Decimal>) -> Result<Option<Decimal>, E> {
+    match fraction {
+        Some(fraction) if fraction < 0.into() || fraction > 1.into() => Err(E::custom(format!(
+            "{:} must be between 0 and 1, not {:}",
+            name, fraction
+        ))),
+        _ => Ok(fraction),
+    }
+}
+
+fn deserialize_cash_sweep<'de, D>(deserializer: D) -> Result<Option<Decimal>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    check_fraction("cash_sweep", serde::Deserialize::deserialize(deserializer)?)
+}
+
+fn deserialize_intl_market_cap<'de, D>(deserializer: D) -> Result<Option<Decimal>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    check_fraction(
+        "intl_market_cap",
+        serde::Deserialize::deserialize(deserializer)?,
+    )
+}
+
+fn deserialize_intl_reit_ratio<'de, D>(deserializer: D) -> Result<Option<Decimal>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    check_fraction(
+        "intl_reit_ratio",
+        serde::Deserialize::deserialize(deserializer)?,
+    )
+}
+
+fn deserialize_target_date_ratio<'de, D>(deserializer: D) -> Result<Option<Decimal>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    check_fraction(
+        "target_date_ratio",
+        serde::Deserialize::deserialize(deserializer)?,
+    )
+}
+
+/// Holding a negative amount of small cap per dollar of total market makes no sense
+fn deserialize_small_per_total<'de, D>(deserializer: D) -> Result<Option<Decimal>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let ratio: Option<Decimal> = serde::Deserialize::deserialize(deserializer)?;
+    match ratio {
+        Some(ratio) if ratio.is_sign_negative() => Err(D::Error::custom(format!(
+            "small_per_total cannot be negative, not {:}",
+            ratio
+        ))),
+        _ => Ok(ratio),
+    }
+}
+
+/// Rebalancing can't happen without trading in at least one asset class
+fn deserialize_max_trades<'de, D>(deserializer: D) -> Result<Option<usize>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let max_trades: Option<usize> = serde::Deserialize::deserialize(deserializer)?;
+    match max_trades {
+        Some(0) => Err(D::Error::custom("max_trades must be at least 1")),
+        _ => Ok(max_trades),
+    }
+}
+
+/// A quote command needs at least a program to run
+fn deserialize_quote_command<'de, D>(
+    deserializer: D,
+) -> Result<Option<FinanceQuoteCommand>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let command: Option<Vec<String>> = serde::Deserialize::deserialize(deserializer)?;
+    command
+        .map(|args| FinanceQuoteCommand::new(&args).map_err(D::Error::custom))
+        .transpose()
+}
+
+/// A band's minimum can't exceed its maximum
+fn deserialize_bands<'de, D>(
+    deserializer: D,
+) -> Result<Option<BTreeMap<AssetClass, Band>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let bands: Option<BTreeMap<AssetClass, Band>> = allocation::deserialize_by_class(deserializer)?;
+    let inverted = bands.iter().flatten().find(|(_, band)| band.min > band.max);
+    match inverted {
+        Some((asset_class, band)) => Err(D::Error::custom(format!(
+            "band minimum for {:?} ({:}) cannot exceed its maximum ({:})",
+            asset_class, band.min, band.max
+        ))),
+        None => Ok(bands),
+    }
+}
+
+/// Explicit target ratios must account for the whole portfolio
+fn deserialize_explicit_allocation<'de, D>(
+    deserializer: D,
+) -> Result<Option<ExplicitAllocation>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let explicit: Option<ExplicitAllocation> = allocation::deserialize_by_class(deserializer)?;
+    match explicit {
+        Some(ref ratios) if ratios.values().sum::<Decimal>() != 1.into() => {
+            Err(D::Error::custom(format!(
+                "allocation ratios must sum to 1, not {:}",
+                ratios.values().sum::<Decimal>().normalize()
+            )))
+        }
+        _ => Ok(explicit),
+    }
+}
+
+/// Linked classes must be held in a positive ratio (or one would hold nothing at all)
+fn deserialize_linked_classes<'de, D>(deserializer: D) -> Result<Option<LinkedClasses>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let link: Option<LinkedClasses> = serde::Deserialize::deserialize(deserializer)?;
+    match link {
+        Some(ref link) if link.ratio <= 0.into() => Err(D::Error::custom(format!(
+            "linked_classes ratio must be above 0, not {:}",
+            link.ratio
+        ))),
+        _ => Ok(link),
+    }
+}
+
+/// Funds can only be bought in positive increments (e.g. 1 for whole shares)
+fn deserialize_increments<'de, D>(deserializer: D) -> Result<HashMap<String, Decimal>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let increments: HashMap<String, Decimal> = serde::Deserialize::deserialize(deserializer)?;
+    match increments
+        .iter()
+        .find(|(_, increment)| **increment <= 0.into())
+    {
+        Some((symbol, increment)) => Err(D::Error::custom(format!(
+            "share increment for {:} must be above 0, not {:}",
+            symbol, increment
+        ))),
+        None => Ok(increments),
+    }
+}
+
+/// Refuse returns which would break compounding (losing everything, or worse, each year)
+fn deserialize_return<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let apy: Option<f64> = serde::Deserialize::deserialize(deserializer)?;
+    match apy {
+        Some(apy) if !apy.is_finite() || apy <= -1.0 => Err(D::Error::custom(format!(
+            "annual returns must be finite and above -1, not {:}",
+            apy
+        ))),
+        _ => Ok(apy),
+    }
 }
 
 #[derive(Deserialize)]
 pub struct Config {
     user: User,
     pub gnucash: GnuCash,
+    #[serde(default)]
+    pub strategy: Strategy,
+    #[serde(default)]
+    pub display: Display,
+    #[serde(default)]
+    pub growth: Growth,
+    #[serde(default)]
+    pub planning: Planning,
+    /// Target ratios by asset class, used in place of those derived from `strategy`
+    #[serde(default, deserialize_with = "deserialize_explicit_allocation")]
+    pub allocation: Option<ExplicitAllocation>,
+    /// Count funds missing from `data/classified.csv` as this asset class, rather than failing
+    pub default_asset_class: Option<AssetClass>,
+    /// Acceptable range of ratios by asset class, outside of which a class is rebalanced
+    #[serde(default, deserialize_with = "deserialize_bands")]
+    pub bands: Option<BTreeMap<AssetClass, Band>>,
+    /// Map from a fund's ticker to a cheaper share class of the same fund (e.g. VTSMX -> VTSAX)
+    #[serde(default)]
+    pub share_class_upgrades: HashMap<String, String>,
+    /// How often to fetch quotes for specific funds, keyed by symbol (default: daily)
+    #[serde(default)]
+    pub quote_cadence: HashMap<String, UpdateCadence>,
+    /// Days on which the market trades, for judging whether prices are current (default: US)
+    #[serde(default)]
+    pub trading_calendar: TradingCalendar,
+    /// Notes to show alongside holdings, keyed by symbol or account name
+    #[serde(default)]
+    pub notes: HashMap<String, String>,
+    /// Duration (in years) of bond funds, keyed by symbol, for judging interest rate sensitivity
+    #[serde(default)]
+    pub durations: HashMap<String, Decimal>,
+    /// The most each type of account (e.g. IRA) may receive per year, for projections
+    #[serde(default)]
+    pub contribution_limits: BTreeMap<String, Decimal>,
+    /// Smallest number of shares each fund may be bought in, keyed by symbol (default: dollars)
+    #[serde(default, deserialize_with = "deserialize_increments")]
+    pub share_increments: HashMap<String, Decimal>,
+    /// Currency in which AlphaVantage quotes each symbol, where not USD (e.g. `VUSA.LON = 'GBP'`)
+    #[serde(default)]
+    pub quote_currencies: HashMap<String, String>,
+    /// Value of one unit of a foreign currency in US dollars (for contributions like "1000 GBP")
+    #[serde(default)]
+    pub exchange_rates: HashMap<String, Decimal>,
+    /// Manually set prices, keyed by commodity symbol
+    #[serde(default)]
+    pub price_overrides: HashMap<String, PriceOverride>,
 }
 
-impl Config {
+impl Default for Config {
     /// Return default settings for use with the sample data
-    pub fn default() -> Config {
+    fn default() -> Config {
         Config {
             user: User {
                 birthday: String::from("1985-01-01"),
+                timezone: None,
             },
             gnucash: GnuCash {
                 path_to_book: String::from("example/sqlite3.gnucash"),
@@ -40,14 +529,174 @@ impl Config {
                 // This requires GnuCash to be installed.
                 // So that people can demo with *just* Rust, assume it's off by default.
                 update_prices: false,
+                skip_stale_quotes: false,
+                create_missing_currency: false,
+                quote_time: None,
+                balance_as_of: None,
+                reconciled_only: false,
+                price_sources: PriceSources::default(),
+                income_sign: IncomeSign::Negative,
+                fetch_exchange_rates: false,
+                quote_command: None,
+                quote_concurrency: None,
             },
+            strategy: Strategy::default(),
+            display: Display::default(),
+            growth: Growth::default(),
+            planning: Planning::default(),
+            allocation: None,
+            default_asset_class: None,
+            bands: None,
+            share_class_upgrades: HashMap::new(),
+            quote_cadence: HashMap::new(),
+            trading_calendar: TradingCalendar::default(),
+            notes: HashMap::new(),
+            durations: HashMap::new(),
+            contribution_limits: BTreeMap::new(),
+            share_increments: HashMap::new(),
+            quote_currencies: HashMap::new(),
+            exchange_rates: HashMap::new(),
+            price_overrides: HashMap::new(),
         }
     }
+}
 
+impl Config {
     pub fn user_birthday(&self) -> NaiveDate {
         self.user.birthday()
     }
 
+    pub fn user_timezone(&self) -> Option<Tz> {
+        self.user.timezone
+    }
+
+    pub fn quote_time(&self) -> NaiveTime {
+        self.gnucash.quote_time.unwrap_or_else(dateutil::noon)
+    }
+
+    /// Target allocations under `strategy` as of `day`, with the configured tilts applied
+    ///
+    /// (An explicit `[allocation]` is ignored, so that strategies can be compared)
+    pub fn strategy_allocations(
+        &self,
+        strategy: LazyPortfolio,
+        birthday: NaiveDate,
+        day: NaiveDate,
+    ) -> Vec<AssetAllocation> {
+        let mut derived = strategy.allocations_on(
+            birthday,
+            self.strategy.bond_precision.unwrap_or(2),
+            self.bond_allocation_base(),
+            self.strategy.intl_market_cap.unwrap_or(DEFAULT_INTL_RATIO),
+            day,
+        );
+        if let Some(small_per_total) = self.strategy.small_per_total {
+            derived = allocation::tilt_small(derived, small_per_total);
+        }
+        if let Some(ratio_intl) = self.strategy.intl_reit_ratio {
+            derived = allocation::split_reit(derived, ratio_intl);
+        }
+        if let Some(ratio_target) = self.strategy.target_date_ratio {
+            derived = allocation::include_target_date(derived, ratio_target);
+        }
+        derived
+    }
+
+    /// Target allocations as of `day`: exactly as given under `[allocation]`, if present,
+    /// otherwise derived from the configured strategy
+    pub fn target_allocations(&self, birthday: NaiveDate, day: NaiveDate) -> Vec<AssetAllocation> {
+        match &self.allocation {
+            Some(explicit) => allocation::from_explicit(explicit),
+            None => self.strategy_allocations(self.strategy.name, birthday, day),
+        }
+    }
+
+    /// How the target allocation (see `target_allocations`) shifts as somebody ages
+    pub fn glide_path(&self, birthday: NaiveDate, today: NaiveDate) -> Vec<GlidePoint> {
+        allocation::glide_path(birthday, today, |day| {
+            self.target_allocations(birthday, day)
+        })
+    }
+
+    /// Returns to assume when projecting the portfolio's worth at retirement
+    pub fn growth_model(&self) -> GrowthModel {
+        let apy = self
+            .planning
+            .expected_real_return
+            .or(self.growth.apy)
+            .unwrap_or(0.07);
+        match (self.growth.early_apy, self.growth.early_years) {
+            (Some(early_apy), Some(early_years)) => GrowthModel::TwoRegime {
+                early_apy,
+                early_years,
+                late_apy: apy,
+            },
+            _ => GrowthModel::Flat(apy),
+        }
+    }
+
+    /// The rate at which to project I Bonds, if they shouldn't grow like the rest
+    pub fn ibond_rate(&self) -> Option<f64> {
+        self.growth.ibond_rate
+    }
+
+    /// Fraction of the portfolio to assume may be withdrawn each year in retirement
+    pub fn safe_withdrawal_rate(&self) -> Decimal {
+        self.planning
+            .safe_withdrawal_rate
+            .unwrap_or(SAFE_WITHDRAWAL_RATE)
+    }
+
+    /// Yearly contributions to assume when projecting (capped by any limits)
+    pub fn annual_contributions(&self) -> AnnualContributions {
+        AnnualContributions {
+            planned: self.planning.annual_contribution.unwrap_or_default(),
+            limits: self.contribution_limits.clone(),
+        }
+    }
+
+    /// The age from which to subtract one's own age, giving the share of stocks
+    pub fn bond_allocation_base(&self) -> u8 {
+        self.planning
+            .bond_allocation_base
+            .unwrap_or(DEFAULT_BOND_ALLOCATION_BASE)
+    }
+
+    /// Where to fetch the latest quotes from
+    pub fn quote_provider(&self) -> Box<dyn QuoteProvider> {
+        match &self.gnucash.quote_command {
+            Some(command) => Box::new(command.clone()),
+            None => Box::new(AlphaVantage {
+                currencies: self.quote_currencies.clone(),
+            }),
+        }
+    }
+
+    /// Where to find exchange rates, for valuing holdings priced in other currencies
+    pub fn exchange_rate_provider(&self) -> Box<dyn ExchangeRateProvider> {
+        if self.gnucash.fetch_exchange_rates {
+            Box::new(CachedRates::new(AlphaVantageRates {}))
+        } else {
+            Box::new(self.exchange_rates.clone())
+        }
+    }
+
+    /// Most quotes to fetch at once
+    pub fn quote_concurrency(&self) -> usize {
+        self.gnucash
+            .quote_concurrency
+            .unwrap_or(DEFAULT_QUOTE_CONCURRENCY)
+    }
+
+    /// How to open a SQLite book: read-only, unless we mean to write prices to it
+    pub fn sqlite_open_flags(&self) -> OpenFlags {
+        if self.gnucash.update_prices {
+            OpenFlags::SQLITE_OPEN_READ_WRITE
+        } else {
+            OpenFlags::SQLITE_OPEN_READ_ONLY
+        }
+    }
+
     /// Return a Config from file, or default settings if not present
     ///
     /// See `example_config.toml` for a sample configuration:
@@ -55,32 +704,105 @@ impl Config {
     /// ```toml
     /// [user]
     /// birthday = '1971-06-14'
+    /// timezone = 'US/Eastern'
     ///
     /// [gnucash]
     /// path_to_book = '/path/to/database.gnucash'
     /// file_format = 'sqlite3'
+    ///
+    /// [strategy]
+    /// intl_reit_ratio = 0.3
+    ///
+    /// [share_class_upgrades]
+    /// VTSMX = 'VTSAX'
+    ///
+    /// [quote_cadence]
+    /// VMFXX = 'weekly'
+    ///
+    /// [notes]
+    /// VTSAX = 'taxable, do not sell'
+    ///
+    /// [exchange_rates]
+    /// GBP = 1.27
+    ///
+    /// [price_overrides]
+    /// IBOND = { price = 25.40, as_of = '2024-01-01' }
     /// ```
-    pub fn from_file(path: &str) -> Config {
-        let config_toml = match fs::read_to_string(path) {
-            Ok(file) => file,
-            Err(_) => {
-                println!("Using default example configuration. Write to config.toml for real use.");
-                return Config::default();
-            }
-        };
+    pub fn from_file(path: &str) -> Result<Config, ConfigError> {
+        Ok(Config::read_file(path)?.unwrap_or_default())
+    }
+
+    /// Return a Config from file, or `None` if there's no file to read
+    pub fn read_file(path: &str) -> Result<Option<Config>, ConfigError> {
+        match fs::read_to_string(path) {
+            Ok(config_toml) => Config::parse(&config_toml).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Parse a Config from TOML, rejecting settings which conflict with one another
+    pub fn parse(config_toml: &str) -> Result<Config, ConfigError> {
+        let conf: Config = toml::from_str(config_toml).map_err(ConfigError::Parse)?;
+        conf.check_rebalancing_options()?;
+        conf.check_growth_options()?;
+        Ok(conf)
+    }
+
+    /// `expected_real_return` is another name for `apy`, so only one may be given
+    fn check_growth_options(&self) -> Result<(), ConfigError> {
+        if self.growth.apy.is_some() && self.planning.expected_real_return.is_some() {
+            return Err(ConfigError::Conflict(String::from(
+                "set either apy (under [growth]) or expected_real_return (under [planning]), not both",
+            )));
+        }
+        Ok(())
+    }
 
-        toml::from_str(&config_toml).unwrap()
+    /// Rebalancing options each pick a different optimizer, so at most one may be given
+    fn check_rebalancing_options(&self) -> Result<(), ConfigError> {
+        let given: Vec<&str> = [
+            ("bands", self.bands.is_some()),
+            ("cash_sweep", self.strategy.cash_sweep.is_some()),
+            ("max_trades", self.strategy.max_trades.is_some()),
+            ("linked_classes", self.strategy.linked_classes.is_some()),
+            ("objective", self.strategy.objective != Objective::default()),
+        ]
+        .iter()
+        .filter(|(_, is_given)| *is_given)
+        .map(|(name, _)| *name)
+        .collect();
+        if given.len() > 1 {
+            return Err(ConfigError::Conflict(format!(
+                "only one of bands, cash_sweep, max_trades, linked_classes, or objective may be set (found {:})",
+                given.join(", ")
+            )));
+        }
+        Ok(())
+    }
+
+    /// Warn that default settings are in use (and what they are), lest they pass for real ones
+    pub fn describe_defaults(&self) -> String {
+        format!(
+            "WARNING: No config.toml found, so using the example configuration:\n \
+             - birthday: {:}\n \
+             - book: {:} ({:})\n\
+             Target allocations depend on your birthday! Write to config.toml for real use.",
+            self.user.birthday, self.gnucash.path_to_book, self.gnucash.file_format
+        )
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::calendar::HolidaySchedule;
+    use crate::rebalance::AssetAllocation;
 
     #[test]
     fn test_parses_birthday() {
         let user = User {
             birthday: String::from("1962-12-31"),
+            timezone: None,
         };
         assert_eq!(
             user.birthday(),
@@ -90,22 +812,664 @@ mod tests {
 
     #[test]
     fn test_parse_from_toml() {
-        let conf = Config::from_file("example_config.toml");
+        let conf = Config::from_file("example_config.toml").unwrap();
         assert_eq!(
             conf.user_birthday(),
             NaiveDate::from_ymd_opt(1972, 7, 12).unwrap()
         );
         assert_eq!(&conf.gnucash.path_to_book, "/home/linus/sqlite3.gnucash");
         assert_eq!(&conf.gnucash.file_format, "sqlite3");
-        assert_eq!(conf.gnucash.update_prices, true);
+        assert!(conf.gnucash.update_prices);
+        assert_eq!(conf.sqlite_open_flags(), OpenFlags::SQLITE_OPEN_READ_WRITE);
+    }
+
+    #[test]
+    fn test_parse_optional_sections() {
+        let conf: Config = toml::from_str(
+            "default_asset_class = 'USTotal'
+
+             [user]
+             birthday = '1972-07-12'
+             timezone = 'US/Eastern'
+
+             [gnucash]
+             path_to_book = '/home/linus/sqlite3.gnucash'
+             file_format = 'sqlite3'
+             update_prices = false
+             create_missing_currency = true
+             quote_time = '16:00:00'
+             price_sources = { prefer = 'user:price', exclude_types = ['nav'] }
+             quote_command = ['gnc-fq-dump', 'vanguard']
+             quote_concurrency = 2
+             balance_as_of = '2023-12-31'
+             reconciled_only = true
+             fetch_exchange_rates = true
+             income_sign = 'positive'
+
+             [strategy]
+             name = 'three_fund'
+             intl_market_cap = 0.42
+             intl_reit_ratio = 0.25
+             small_per_total = 1.0
+             bond_precision = 3
+             target_date_ratio = 0.3
+             overweight_dollar_threshold = 5000
+             cash_drag_threshold = 0.05
+             objective = 'min_trades'
+             max_trades = 3
+             withdrawal_floor = 0.5
+             cash_sweep = 1.0
+             sweep_order = [{ account = '401k', room = 5000 }, { account = 'Roth IRA' }]
+             locked_classes = ['USBonds']
+             linked_classes = { primary = 'USTotal', secondary = 'USSmall', ratio = 2 }
+
+             [display]
+             retirement_column_width = 12
+             hide_projection = true
+             projection_minimum = 1000
+             monthly_income = true
+             contribution_percentages = true
+             snapshot_path = '.last_run.json'
+             drift_thresholds = { minor = 0.03, major = 0.10 }
+             reconcile_pennies = true
+             share_precision = 3
+             asset_class_order = { custom = ['USBonds', 'USTotal'] }
+
+             [growth]
+             apy = 0.06
+             early_apy = 0.03
+             early_years = 10
+             ibond_rate = 0.043
+
+             [planning]
+             safe_withdrawal_rate = 0.035
+             bond_allocation_base = 110
+             annual_contribution = 30000
+
+             [contribution_limits]
+             IRA = 7000
+
+             [bands]
+             USTotal = { min = 0.50, max = 0.60 }
+
+             [share_class_upgrades]
+             VTSMX = 'VTSAX'
+
+             [quote_cadence]
+             VMFXX = 'weekly'
+
+             [trading_calendar]
+             holidays = 'none'
+             closures = ['2024-01-26']
+
+             [notes]
+             VTSAX = 'employer match locked'
+
+             [durations]
+             VBTLX = 6.1
+
+             [share_increments]
+             VTI = 1
+
+             [quote_currencies]
+             'VUSA.LON' = 'GBP'
+
+             [exchange_rates]
+             GBP = 1.27
+
+             [price_overrides]
+             IBOND = { price = 25.40, as_of = '2024-01-01' }",
+        )
+        .unwrap();
+        assert_eq!(
+            conf.share_class_upgrades.get("VTSMX"),
+            Some(&String::from("VTSAX"))
+        );
+        assert_eq!(
+            conf.quote_cadence.get("VMFXX"),
+            Some(&UpdateCadence::Weekly)
+        );
+        assert_eq!(
+            conf.trading_calendar,
+            TradingCalendar {
+                holidays: HolidaySchedule::None,
+                closures: vec![NaiveDate::from_ymd_opt(2024, 1, 26).unwrap()],
+            }
+        );
+        assert!(conf.gnucash.create_missing_currency);
+        assert!(conf.gnucash.reconciled_only);
+        assert!(conf.gnucash.fetch_exchange_rates);
+        assert_eq!(conf.gnucash.income_sign, IncomeSign::Positive);
+        assert_eq!(
+            conf.gnucash.balance_as_of,
+            Some(NaiveDate::from_ymd_opt(2023, 12, 31).unwrap())
+        );
+        assert_eq!(
+            conf.notes.get("VTSAX"),
+            Some(&String::from("employer match locked"))
+        );
+        assert_eq!(conf.durations.get("VBTLX"), Some(&Decimal::new(61, 1)));
+        assert_eq!(conf.share_increments.get("VTI"), Some(&Decimal::from(1)));
+        assert_eq!(conf.default_asset_class, Some(AssetClass::USTotal));
+        assert_eq!(conf.safe_withdrawal_rate(), Decimal::new(35, 3));
+        assert_eq!(conf.bond_allocation_base(), 110);
+        assert_eq!(conf.annual_contributions().planned, Decimal::from(30_000));
+        assert_eq!(
+            conf.annual_contributions().effective(),
+            Decimal::from(7_000)
+        );
+        assert_eq!(
+            conf.bands.as_ref().unwrap().get(&AssetClass::USTotal),
+            Some(&Band {
+                min: Decimal::new(50, 2),
+                max: Decimal::new(60, 2),
+            })
+        );
+        assert_eq!(conf.exchange_rates.get("GBP"), Some(&Decimal::new(127, 2)));
+        assert_eq!(
+            conf.quote_currencies.get("VUSA.LON"),
+            Some(&String::from("GBP"))
+        );
+        assert_eq!(
+            conf.price_overrides.get("IBOND"),
+            Some(&PriceOverride {
+                price: Decimal::new(2540, 2),
+                as_of: NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(),
+            })
+        );
+        assert_eq!(conf.user_timezone(), Some(Tz::US__Eastern));
+        // A quote's trading day is stored as noon Eastern, whatever the machine's zone
+        let noon = dateutil::localize_at_noon_in("2024-01-10", conf.user_timezone()).unwrap();
+        assert_eq!(dateutil::datetime_for_sqlite(noon), "2024-01-10 17:00:00");
+        // ...but quotes are recorded at the market close
+        let close =
+            dateutil::localize_at_time_in("2024-01-10", conf.quote_time(), conf.user_timezone())
+                .unwrap();
+        assert_eq!(dateutil::datetime_for_sqlite(close), "2024-01-10 21:00:00");
+        assert_eq!(
+            conf.gnucash.price_sources,
+            PriceSources {
+                prefer: Some(String::from("user:price")),
+                exclude_types: vec![String::from("nav")],
+            }
+        );
+        assert_eq!(
+            conf.gnucash.quote_command,
+            Some(FinanceQuoteCommand {
+                program: String::from("gnc-fq-dump"),
+                args: vec![String::from("vanguard")],
+            })
+        );
+        assert_eq!(conf.quote_concurrency(), 2);
+        assert_eq!(conf.strategy.name, LazyPortfolio::ThreeFund);
+        assert_eq!(conf.strategy.intl_market_cap, Some(Decimal::new(42, 2)));
+        assert_eq!(conf.strategy.intl_reit_ratio, Some(Decimal::new(25, 2)));
+        assert_eq!(conf.strategy.small_per_total, Some(Decimal::from(1)));
+        assert_eq!(conf.strategy.bond_precision, Some(3));
+        assert_eq!(conf.strategy.target_date_ratio, Some(Decimal::new(3, 1)));
+        assert_eq!(
+            conf.strategy.overweight_dollar_threshold,
+            Some(Decimal::from(5000))
+        );
+        assert_eq!(conf.strategy.cash_drag_threshold, Some(Decimal::new(5, 2)));
+        assert_eq!(conf.strategy.objective, Objective::MinTrades);
+        assert_eq!(conf.strategy.max_trades, Some(3));
+        assert_eq!(conf.strategy.withdrawal_floor, Some(Decimal::new(5, 1)));
+        assert_eq!(conf.strategy.cash_sweep, Some(Decimal::new(10, 1)));
+        assert_eq!(
+            conf.strategy.sweep_order,
+            vec![
+                SweepAccount {
+                    account: String::from("401k"),
+                    room: Some(Decimal::from(5000)),
+                },
+                SweepAccount {
+                    account: String::from("Roth IRA"),
+                    room: None,
+                },
+            ]
+        );
+        assert_eq!(conf.strategy.locked_classes, vec![AssetClass::USBonds]);
+        assert_eq!(
+            conf.strategy.linked_classes,
+            Some(LinkedClasses {
+                primary: AssetClass::USTotal,
+                secondary: AssetClass::USSmall,
+                ratio: 2.into(),
+            })
+        );
+        assert_eq!(conf.display.retirement_column_width, Some(12));
+        assert!(conf.display.reconcile_pennies);
+        assert_eq!(conf.display.share_precision, Some(3));
+        assert!(conf.display.hide_projection);
+        assert_eq!(conf.display.projection_minimum, Some(Decimal::from(1_000)));
+        assert!(conf.display.monthly_income);
+        assert!(conf.display.contribution_percentages);
+        assert_eq!(
+            conf.display.snapshot_path,
+            Some(String::from(".last_run.json"))
+        );
+        assert_eq!(
+            conf.growth_model(),
+            GrowthModel::TwoRegime {
+                early_apy: 0.03,
+                early_years: 10.0,
+                late_apy: 0.06,
+            }
+        );
+        assert_eq!(conf.ibond_rate(), Some(0.043));
+        assert_eq!(
+            conf.display.drift_thresholds,
+            DriftThresholds {
+                minor: Decimal::new(3, 2),
+                major: Decimal::new(10, 2),
+            }
+        );
+        assert_eq!(
+            conf.display.asset_class_order,
+            DisplayOrder::Custom(vec![AssetClass::USBonds, AssetClass::USTotal])
+        );
+    }
+
+    #[test]
+    fn test_out_of_range_withdrawal_rate() {
+        for rate in &["-0.04", "4", "0"] {
+            let planning = format!("[planning]\nsafe_withdrawal_rate = {:}\n", rate);
+            let err = with_sections(&planning).err().unwrap();
+            assert!(
+                err.to_string()
+                    .contains("safe_withdrawal_rate must be above 0 and at most 1"),
+                "{:}",
+                err
+            );
+        }
+    }
+
+    fn with_sections(sections: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(&format!(
+            "[user]\nbirthday = '1985-01-01'\n\n[gnucash]\npath_to_book = 'book.gnucash'\nfile_format = 'sqlite3'\nupdate_prices = false\n\n{:}",
+            sections
+        ))
+    }
+
+    #[test]
+    fn test_quote_concurrency_must_be_positive() {
+        let gnucash = "[user]\nbirthday = '1985-01-01'\n\n[gnucash]\npath_to_book = 'book.gnucash'\nfile_format = 'sqlite3'\nupdate_prices = true\nquote_concurrency = 0\n";
+        let err = toml::from_str::<Config>(gnucash).err().unwrap();
+        assert!(
+            err.to_string()
+                .contains("quote_concurrency must be at least 1"),
+            "{:}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_bond_precision_is_bounded() {
+        let err = with_sections("[strategy]\nbond_precision = 7\n")
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string()
+                .contains("bond_precision must be at most 6, not 7"),
+            "{:}",
+            err
+        );
+        assert!(with_sections("[strategy]\nbond_precision = 6\n").is_ok());
+    }
+
+    #[test]
+    fn test_max_trades_must_be_positive() {
+        let err = with_sections("[strategy]\nmax_trades = 0\n").err().unwrap();
+        assert!(
+            err.to_string().contains("max_trades must be at least 1"),
+            "{:}",
+            err
+        );
+        assert!(with_sections("[strategy]\nmax_trades = 1\n").is_ok());
+    }
+
+    #[test]
+    fn test_intl_market_cap_must_be_a_fraction() {
+        let err = with_sections("[strategy]\nintl_market_cap = 40\n")
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string()
+                .contains("intl_market_cap must be between 0 and 1, not 40"),
+            "{:}",
+            err
+        );
+        assert!(with_sections("[strategy]\nintl_market_cap = 0.4\n").is_ok());
+    }
+
+    #[test]
+    fn test_intl_reit_ratio_must_be_a_fraction() {
+        let err = with_sections("[strategy]\nintl_reit_ratio = 1.2\n")
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string()
+                .contains("intl_reit_ratio must be between 0 and 1, not 1.2"),
+            "{:}",
+            err
+        );
+        assert!(with_sections("[strategy]\nintl_reit_ratio = 0.3\n").is_ok());
+    }
+
+    #[test]
+    fn test_target_date_ratio_must_be_a_fraction() {
+        let err = with_sections("[strategy]\ntarget_date_ratio = -0.5\n")
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string()
+                .contains("target_date_ratio must be between 0 and 1, not -0.5"),
+            "{:}",
+            err
+        );
+        assert!(with_sections("[strategy]\ntarget_date_ratio = 1\n").is_ok());
+    }
+
+    #[test]
+    fn test_small_per_total_cannot_be_negative() {
+        let err = with_sections("[strategy]\nsmall_per_total = -0.5\n")
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string()
+                .contains("small_per_total cannot be negative, not -0.5"),
+            "{:}",
+            err
+        );
+        // A heavy tilt (more small cap than total market) is allowed
+        assert!(with_sections("[strategy]\nsmall_per_total = 2\n").is_ok());
+    }
+
+    #[test]
+    fn test_cash_sweep_must_be_a_fraction() {
+        for sweep in &["-0.1", "1.5", "100"] {
+            let strategy = format!("[strategy]\ncash_sweep = {:}\n", sweep);
+            let err = with_sections(&strategy).err().unwrap();
+            assert!(
+                err.to_string()
+                    .contains("cash_sweep must be between 0 and 1"),
+                "{:}",
+                err
+            );
+        }
+        assert!(with_sections("[strategy]\ncash_sweep = 0.5\n").is_ok());
+    }
+
+    #[test]
+    fn test_rebalancing_options_conflict() {
+        let base = "[user]\nbirthday = '1985-01-01'\n\n[gnucash]\npath_to_book = 'book.gnucash'\nfile_format = 'sqlite3'\nupdate_prices = false\n";
+        assert!(Config::parse(&format!("{:}\n[strategy]\nmax_trades = 2\n", base)).is_ok());
+
+        let err = Config::parse(&format!(
+            "{:}\n[strategy]\nmax_trades = 2\ncash_sweep = 1.0\n\n[bands]\nUSTotal = {{ min = 0.5, max = 0.6 }}\n",
+            base
+        ))
+        .err()
+        .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "Invalid config: only one of bands, cash_sweep, max_trades, linked_classes, or objective may be set (found bands, cash_sweep, max_trades)"
+        );
+    }
+
+    #[test]
+    fn test_apy_conflicts_with_expected_real_return() {
+        let base = "[user]\nbirthday = '1985-01-01'\n\n[gnucash]\npath_to_book = 'book.gnucash'\nfile_format = 'sqlite3'\nupdate_prices = false\n";
+        let err = Config::parse(&format!(
+            "{:}\n[planning]\nexpected_real_return = 0.05\n\n[growth]\napy = 0.06\n",
+            base
+        ))
+        .err()
+        .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "Invalid config: set either apy (under [growth]) or expected_real_return (under [planning]), not both"
+        );
+    }
+
+    #[test]
+    fn test_quote_command_must_name_a_program() {
+        for command in ["[]", "['']"] {
+            let gnucash = format!("[user]\nbirthday = '1985-01-01'\n\n[gnucash]\npath_to_book = 'book.gnucash'\nfile_format = 'sqlite3'\nupdate_prices = true\nquote_command = {:}\n", command);
+            let err = toml::from_str::<Config>(&gnucash).err().unwrap();
+            assert!(
+                err.to_string()
+                    .contains("quote_command must name a program to run"),
+                "{:}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_band_minimum_cannot_exceed_maximum() {
+        let err = with_sections("[bands]\nUSTotal = { min = 0.6, max = 0.5 }\n")
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string()
+                .contains("band minimum for USTotal (0.6) cannot exceed its maximum (0.5)"),
+            "{:}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_linked_classes_ratio_must_be_positive() {
+        for ratio in &["0", "-2"] {
+            let err = with_sections(&format!(
+                "[strategy]\nlinked_classes = {{ primary = 'USTotal', secondary = 'USSmall', ratio = {:} }}\n",
+                ratio
+            ))
+            .err()
+            .unwrap();
+            assert!(
+                err.to_string()
+                    .contains("linked_classes ratio must be above 0"),
+                "{:}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_share_increments_must_be_positive() {
+        let err = with_sections("[share_increments]\nVTI = 0\n")
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string()
+                .contains("share increment for VTI must be above 0, not 0"),
+            "{:}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_out_of_range_bond_allocation_base() {
+        for base in &["79", "151", "-120", "1000"] {
+            let planning = format!("[planning]\nbond_allocation_base = {:}\n", base);
+            let err = with_sections(&planning).err().unwrap();
+            assert!(
+                err.to_string()
+                    .contains("bond_allocation_base must be between 80 and 150"),
+                "{:}",
+                err
+            );
+        }
+        let conf = with_sections("[planning]\nbond_allocation_base = 100\n").unwrap();
+        assert_eq!(conf.bond_allocation_base(), 100);
+    }
+
+    #[test]
+    fn test_explicit_allocation_must_sum_to_one() {
+        let err = with_sections("[allocation]\nUSBonds = 0.25\nUSTotal = 0.70\n")
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string()
+                .contains("allocation ratios must sum to 1, not 0.95"),
+            "{:}",
+            err
+        );
+        let conf = with_sections("[allocation]\nUSBonds = 0.25\nUSTotal = 0.75\n").unwrap();
+        assert_eq!(conf.allocation.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_expected_real_return() {
+        let conf = with_sections("[planning]\nexpected_real_return = 0.05\n").unwrap();
+        assert_eq!(conf.growth_model(), GrowthModel::Flat(0.05));
+
+        // It stands in for `apy`, while any early returns still apply
+        let conf = with_sections(
+            "[planning]\nexpected_real_return = 0.05\n\n[growth]\nearly_apy = 0.03\nearly_years = 10\n",
+        )
+        .unwrap();
+        assert_eq!(
+            conf.growth_model(),
+            GrowthModel::TwoRegime {
+                early_apy: 0.03,
+                early_years: 10.0,
+                late_apy: 0.05,
+            }
+        );
+
+        for section in &[
+            "[planning]\nexpected_real_return = -1.0\n",
+            "[planning]\nexpected_real_return = inf\n",
+            "[growth]\napy = nan\n",
+            "[growth]\nearly_apy = -1.5\n",
+        ] {
+            let err = with_sections(section).err().unwrap();
+            assert!(
+                err.to_string()
+                    .contains("annual returns must be finite and above -1"),
+                "{:}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_exported_allocation_round_trips() {
+        // Tilting gives ratios with many decimal places (which must still sum to exactly 100%)
+        let derived = crate::allocation::tilt_small(
+            LazyPortfolio::CoreFour.allocations(Decimal::new(2815, 4), Decimal::new(4, 1)),
+            Decimal::new(1, 0) / Decimal::from(3),
+        );
+        let conf = with_sections(&crate::allocation::to_toml(&derived)).unwrap();
+
+        let ratios = |allocations: Vec<AssetAllocation>| -> HashMap<AssetClass, Decimal> {
+            allocations
+                .into_iter()
+                .map(|a| (a.asset_class, a.target_ratio))
+                .collect()
+        };
+        let explicit = crate::allocation::from_explicit(&conf.allocation.unwrap());
+        let total: Decimal = explicit.iter().map(|a| a.target_ratio).sum();
+        assert_eq!(total, Decimal::from(1));
+        assert_eq!(ratios(explicit), ratios(derived));
+    }
+
+    #[test]
+    fn test_glide_path_follows_configured_targets() {
+        let birthday = NaiveDate::from_ymd_opt(1985, 1, 1).unwrap();
+        let today = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let bonds = |conf: &Config| -> Vec<Decimal> {
+            conf.glide_path(birthday, today)
+                .iter()
+                .map(|point| point.ratio_bonds)
+                .collect()
+        };
+        let plain = bonds(&with_sections("").unwrap());
+
+        // Part of the portfolio is held in a target-date fund, so less is in bond funds
+        let with_target_date =
+            bonds(&with_sections("[strategy]\ntarget_date_ratio = 0.5\n").unwrap());
+        for (target_date, plain) in with_target_date.iter().zip(plain.iter()) {
+            assert_eq!(*target_date, plain / Decimal::from(2));
+        }
+
+        // An explicit allocation doesn't change with age
+        let explicit = with_sections("[allocation]\nUSBonds = 0.25\nUSTotal = 0.75\n").unwrap();
+        assert_eq!(bonds(&explicit), vec![Decimal::new(25, 2); 4]);
+    }
+
+    #[test]
+    fn test_describe_defaults() {
+        assert!(Config::read_file("/tmp/definitely_does_not_exist.toml")
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            Config::default().describe_defaults(),
+            "WARNING: No config.toml found, so using the example configuration:\n \
+             - birthday: 1985-01-01\n \
+             - book: example/sqlite3.gnucash (sqlite3)\n\
+             Target allocations depend on your birthday! Write to config.toml for real use."
+        );
     }
 
     #[test]
     fn test_fallback_to_default_settings() {
-        let conf = Config::from_file("/tmp/definitely_does_not_exist.toml");
+        let conf = Config::from_file("/tmp/definitely_does_not_exist.toml").unwrap();
         assert_eq!(&conf.user.birthday, "1985-01-01");
+        assert_eq!(conf.user_timezone(), None);
+        assert_eq!(conf.quote_time(), dateutil::noon());
         assert_eq!(&conf.gnucash.path_to_book, "example/sqlite3.gnucash");
         assert_eq!(&conf.gnucash.file_format, "sqlite3");
-        assert_eq!(conf.gnucash.update_prices, false);
+        assert!(!conf.gnucash.update_prices);
+        assert!(!conf.gnucash.fetch_exchange_rates);
+        assert_eq!(conf.gnucash.income_sign, IncomeSign::Negative);
+        assert_eq!(conf.safe_withdrawal_rate(), Decimal::new(4, 2));
+        assert_eq!(conf.bond_allocation_base(), 120);
+        assert_eq!(conf.annual_contributions(), AnnualContributions::default());
+        assert_eq!(conf.sqlite_open_flags(), OpenFlags::SQLITE_OPEN_READ_ONLY);
+        assert!(!conf.gnucash.create_missing_currency);
+        assert!(!conf.gnucash.reconciled_only);
+        assert_eq!(conf.gnucash.balance_as_of, None);
+        assert_eq!(conf.gnucash.price_sources, PriceSources::default());
+        assert_eq!(conf.gnucash.quote_command, None);
+        assert_eq!(conf.quote_concurrency(), 4);
+        assert_eq!(conf.trading_calendar.holidays, HolidaySchedule::US);
+        assert_eq!(conf.display.snapshot_path, None);
+        assert_eq!(conf.display.share_precision, None);
+        assert_eq!(conf.display.projection_minimum, None);
+        assert!(!conf.display.monthly_income);
+        assert!(!conf.display.contribution_percentages);
+        assert_eq!(conf.growth_model(), GrowthModel::Flat(0.07));
+        assert_eq!(conf.ibond_rate(), None);
+        assert!(conf.share_class_upgrades.is_empty());
+        assert!(conf.notes.is_empty());
+        assert!(conf.durations.is_empty());
+        assert!(conf.share_increments.is_empty());
+        assert!(conf.exchange_rates.is_empty());
+        assert!(conf.quote_currencies.is_empty());
+        assert!(conf.price_overrides.is_empty());
+        assert_eq!(conf.strategy.name, LazyPortfolio::CoreFour);
+        assert_eq!(conf.strategy.intl_market_cap, None);
+        assert_eq!(conf.strategy.intl_reit_ratio, None);
+        assert_eq!(conf.strategy.small_per_total, None);
+        assert_eq!(conf.strategy.bond_precision, None);
+        assert_eq!(conf.strategy.target_date_ratio, None);
+        assert_eq!(conf.strategy.overweight_dollar_threshold, None);
+        assert_eq!(conf.strategy.cash_drag_threshold, None);
+        assert_eq!(conf.strategy.objective, Objective::MinDeviation);
+        assert_eq!(conf.strategy.max_trades, None);
+        assert_eq!(conf.strategy.withdrawal_floor, None);
+        assert_eq!(conf.strategy.cash_sweep, None);
+        assert_eq!(conf.allocation, None);
+        assert_eq!(conf.bands, None);
+        assert_eq!(conf.default_asset_class, None);
+        assert!(conf.strategy.sweep_order.is_empty());
+        assert!(conf.strategy.locked_classes.is_empty());
+        assert_eq!(conf.strategy.linked_classes, None);
+        assert_eq!(conf.display.drift_thresholds, DriftThresholds::default());
     }
 }
@@ -0,0 +1,141 @@
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// The currency in which the portfolio (and all prices) are denominated
+pub const BASE_CURRENCY: &str = "USD";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AmountError {
+    InvalidNumber(String),
+    UnknownCurrency(String),
+}
+
+impl fmt::Display for AmountError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AmountError::InvalidNumber(given) => write!(f, "Not a number: '{:}'", given),
+            AmountError::UnknownCurrency(code) => {
+                write!(f, "No exchange rate configured for {:}", code)
+            }
+        }
+    }
+}
+
+impl Error for AmountError {}
+
+/// Parse an amount of money (e.g. "1000", "$1,000" or "1000 GBP"), converting it to the base currency.
+///
+/// Amounts without a currency are assumed to already be in the base currency.
+/// `rates` gives the value of one unit of each foreign currency, in the base currency.
+pub fn parse_amount(input: &str, rates: &HashMap<String, Decimal>) -> Result<Decimal, AmountError> {
+    let mut parts = input.split_whitespace();
+    let number = parts.next().unwrap_or("");
+    let (sign, number) = match number.strip_prefix('-') {
+        Some(unsigned) => ("-", unsigned),
+        None => ("", number),
+    };
+    let digits = number.strip_prefix('$').unwrap_or(number).replace(',', "");
+    let amount = Decimal::from_str(&format!("{:}{:}", sign, digits))
+        .map_err(|_| AmountError::InvalidNumber(input.to_string()))?;
+
+    let code = match (parts.next(), parts.next()) {
+        (None, _) => return Ok(amount),
+        (Some(code), None) => code.to_uppercase(),
+        (Some(_), Some(_)) => return Err(AmountError::InvalidNumber(input.to_string())),
+    };
+    if code == BASE_CURRENCY {
+        return Ok(amount);
+    }
+    match rates.get(&code) {
+        Some(rate) => Ok(amount * rate),
+        None => Err(AmountError::UnknownCurrency(code)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::{Asset, AssetClass};
+    use crate::rebalance::{self, AssetAllocation, Portfolio};
+
+    fn rates() -> HashMap<String, Decimal> {
+        let mut rates = HashMap::new();
+        rates.insert(String::from("GBP"), Decimal::new(125, 2));
+        rates
+    }
+
+    #[test]
+    fn test_base_currency() {
+        assert_eq!(parse_amount("1000", &rates()), Ok(Decimal::from(1000)));
+        assert_eq!(
+            parse_amount(" 1000 usd\n", &rates()),
+            Ok(Decimal::from(1000))
+        );
+        assert_eq!(
+            parse_amount("-250.50", &rates()),
+            Ok(Decimal::new(-25050, 2))
+        );
+    }
+
+    #[test]
+    fn test_dollar_signs_and_commas() {
+        assert_eq!(parse_amount("$1,000", &rates()), Ok(Decimal::from(1000)));
+        assert_eq!(
+            parse_amount("-$1,250.50", &rates()),
+            Ok(Decimal::new(-125050, 2))
+        );
+        assert_eq!(parse_amount("1,000 GBP", &rates()), Ok(Decimal::from(1250)));
+        assert_eq!(
+            parse_amount("$", &rates()),
+            Err(AmountError::InvalidNumber(String::from("$")))
+        );
+    }
+
+    #[test]
+    fn test_invalid_amounts() {
+        assert_eq!(
+            parse_amount("lots", &rates()),
+            Err(AmountError::InvalidNumber(String::from("lots")))
+        );
+        assert_eq!(
+            parse_amount("1000 GBP please", &rates()),
+            Err(AmountError::InvalidNumber(String::from("1000 GBP please")))
+        );
+        assert_eq!(
+            parse_amount("1000 EUR", &rates()).unwrap_err().to_string(),
+            "No exchange rate configured for EUR"
+        );
+    }
+
+    #[test]
+    fn test_converted_contribution_is_allocated() {
+        let contribution = parse_amount("1000 GBP", &rates()).unwrap();
+        assert_eq!(contribution, Decimal::from(1250));
+
+        let mut stocks = AssetAllocation::new(AssetClass::USTotal, Decimal::new(50, 2));
+        let bonds = AssetAllocation::new(AssetClass::USBonds, Decimal::new(50, 2));
+        stocks.add_asset(Asset::new(
+            String::from("Vanguard Total Stock Market Index Fund Admiral Shares"),
+            Some(String::from("VTSAX")),
+            750.into(),
+            AssetClass::USTotal,
+            None,
+            None,
+            None,
+        ));
+        let portfolio = Portfolio::new(vec![stocks, bonds]);
+
+        let plan = rebalance::optimally_allocate(portfolio, contribution).contribution_plan();
+        assert_eq!(plan.contribution, Decimal::from(1250));
+        assert_eq!(plan.ending_total(), Decimal::from(2000));
+        let bonds = plan
+            .contributions
+            .iter()
+            .find(|planned| planned.asset_class == AssetClass::USBonds)
+            .unwrap();
+        assert_eq!(bonds.amount, Decimal::from(1000));
+    }
+}
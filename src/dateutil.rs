@@ -1,4 +1,8 @@
-use chrono::{DateTime, Local, LocalResult, NaiveDateTime, ParseResult, TimeZone, Utc};
+use chrono::{
+    DateTime, Datelike, Duration, Local, LocalResult, NaiveDate, NaiveDateTime, NaiveTime,
+    ParseResult, TimeZone, Utc,
+};
+use chrono_tz::Tz;
 
 static GNUCASH_DT_FORMAT: &str = "%Y-%m-%d %H:%M:%S %z";
 static GNUCASH_NO_DT_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
@@ -7,10 +11,43 @@ static GNUCASH_NO_DT_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
  * Attach noon, local time to a naive YMD date.
  */
 pub fn localize_at_noon(ymd: &str) -> LocalResult<DateTime<Local>> {
-    let noon: String = format!("{:}T12:00:00", ymd);
-    let naive = NaiveDateTime::parse_from_str(&noon, "%Y-%m-%dT%H:%M:%S").unwrap();
+    localize_at_noon_in(ymd, None)
+}
+
+/**
+ * Attach noon in the given zone (or the system's local zone, if none) to a naive YMD date.
+ *
+ * The result is always expressed in local time, but represents the same instant.
+ * Naming a zone keeps stored datetimes the same regardless of where we happen to run.
+ */
+pub fn localize_at_noon_in(ymd: &str, zone: Option<Tz>) -> LocalResult<DateTime<Local>> {
+    localize_at_time_in(ymd, noon(), zone)
+}
 
-    Local.from_local_datetime(&naive)
+/// Noon, the time of day at which Finance::Quote (and so GnuCash) records a trading day's quote
+pub fn noon() -> NaiveTime {
+    NaiveTime::from_hms_opt(12, 0, 0).unwrap()
+}
+
+/**
+ * Attach a time of day in the given zone (or the system's local zone) to a naive YMD date.
+ *
+ * See `localize_at_noon_in` -- some prefer the market close (e.g. 16:00 Eastern) instead.
+ */
+pub fn localize_at_time_in(
+    ymd: &str,
+    time: NaiveTime,
+    zone: Option<Tz>,
+) -> LocalResult<DateTime<Local>> {
+    let date = NaiveDate::parse_from_str(ymd, "%Y-%m-%d").unwrap();
+    let naive = date.and_time(time);
+
+    match zone {
+        Some(tz) => tz
+            .from_local_datetime(&naive)
+            .map(|dt| dt.with_timezone(&Local)),
+        None => Local.from_local_datetime(&naive),
+    }
 }
 
 // In XML, datetimes are given with local TZ explicitly in them!
@@ -30,3 +67,141 @@ pub fn datetime_for_sqlite(dt: DateTime<Local>) -> String {
     let utc_dt: DateTime<Utc> = dt.into();
     utc_dt.format(GNUCASH_NO_DT_FORMAT).to_string()
 }
+
+/// The first instant of a day in the given zone
+///
+/// Where DST begins at midnight (e.g. Brazil, until 2019), the day starts once clocks jump ahead.
+fn start_of_day<Z: TimeZone>(zone: &Z, date: NaiveDate) -> DateTime<Utc> {
+    let midnight = date.and_time(NaiveTime::MIN);
+    (0..24 * 60)
+        .find_map(|minutes| {
+            zone.from_local_datetime(&(midnight + Duration::minutes(minutes)))
+                .earliest()
+        })
+        .expect("Every day has some valid local time")
+        .with_timezone(&Utc)
+}
+
+/// The start of a day in the given zone, and of the day after, as SQLite datetimes (UTC)
+fn day_bounds_in<Z: TimeZone>(zone: &Z, date: NaiveDate) -> (String, String) {
+    let start_of = |date| {
+        start_of_day(zone, date)
+            .format(GNUCASH_NO_DT_FORMAT)
+            .to_string()
+    };
+    (start_of(date), start_of(date.succ_opt().unwrap()))
+}
+
+/// The start of the local day, and of the day after, as SQLite datetimes (UTC)
+pub fn day_bounds_for_sqlite(date: NaiveDate) -> (String, String) {
+    day_bounds_in(&Local, date)
+}
+
+/// The anniversary of a date (e.g. a birthday) in the given year
+///
+/// Feb 29 only exists in leap years; in other years, the anniversary falls on Feb 28.
+pub fn safe_anniversary(date: NaiveDate, year: i32) -> NaiveDate {
+    date.with_year(year)
+        .or_else(|| NaiveDate::from_ymd_opt(year, 2, 28))
+        .unwrap()
+}
+
+/// Whole years elapsed from `start` until `end` (e.g. one's age on a given day)
+///
+/// A year is only complete once its anniversary (see `safe_anniversary`) has been reached.
+pub fn full_years_between(start: NaiveDate, end: NaiveDate) -> i64 {
+    let years = i64::from(end.year() - start.year());
+    if end < safe_anniversary(start, end.year()) {
+        years - 1
+    } else {
+        years
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_market_close_eastern_stored_as_utc() {
+        let close = NaiveTime::from_hms_opt(16, 0, 0).unwrap();
+        let winter = localize_at_time_in("2024-01-10", close, Some(Tz::US__Eastern)).unwrap();
+        assert_eq!(datetime_for_sqlite(winter), "2024-01-10 21:00:00");
+        assert_eq!(
+            localize_at_time_in("2024-01-10", noon(), None),
+            localize_at_noon("2024-01-10")
+        );
+    }
+
+    #[test]
+    fn test_noon_eastern_stored_as_utc() {
+        let winter = localize_at_noon_in("2024-01-10", Some(Tz::US__Eastern)).unwrap();
+        assert_eq!(datetime_for_sqlite(winter), "2024-01-10 17:00:00");
+
+        // Daylight saving time applies in the summer
+        let summer = localize_at_noon_in("2024-07-10", Some(Tz::US__Eastern)).unwrap();
+        assert_eq!(datetime_for_sqlite(summer), "2024-07-10 16:00:00");
+    }
+
+    #[test]
+    fn test_round_trip_through_sqlite() {
+        let noon = localize_at_noon_in("2024-01-10", Some(Tz::UTC)).unwrap();
+        assert_eq!(datetime_for_sqlite(noon), "2024-01-10 12:00:00");
+        assert_eq!(utc_to_datetime("2024-01-10 12:00:00"), noon);
+    }
+
+    #[test]
+    fn test_day_bounds() {
+        let day = NaiveDate::from_ymd_opt(2024, 1, 10).unwrap();
+        assert_eq!(
+            day_bounds_in(&Tz::US__Eastern, day),
+            (
+                String::from("2024-01-10 05:00:00"),
+                String::from("2024-01-11 05:00:00")
+            )
+        );
+    }
+
+    #[test]
+    fn test_day_bounds_when_dst_starts_at_midnight() {
+        // São Paulo skipped from midnight to 1am (-02:00) on Nov 4, 2018
+        let day = NaiveDate::from_ymd_opt(2018, 11, 4).unwrap();
+        assert_eq!(
+            day_bounds_in(&Tz::America__Sao_Paulo, day),
+            (
+                String::from("2018-11-04 03:00:00"),
+                String::from("2018-11-05 02:00:00")
+            )
+        );
+    }
+
+    #[test]
+    fn test_full_years_between() {
+        let ymd = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let birthday = ymd(1985, 12, 15);
+
+        // Born in December, one is still a year younger than the years suggest in January
+        assert_eq!(full_years_between(birthday, ymd(2024, 1, 10)), 38);
+
+        // A year only completes on the birthday itself
+        assert_eq!(full_years_between(birthday, ymd(2024, 12, 14)), 38);
+        assert_eq!(full_years_between(birthday, ymd(2024, 12, 15)), 39);
+        assert_eq!(full_years_between(birthday, ymd(2024, 12, 31)), 39);
+    }
+
+    #[test]
+    fn test_leap_day_anniversary() {
+        let ymd = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let birthday = ymd(2000, 2, 29);
+
+        assert_eq!(safe_anniversary(birthday, 2023), ymd(2023, 2, 28));
+        assert_eq!(safe_anniversary(birthday, 2024), ymd(2024, 2, 29));
+        assert_eq!(safe_anniversary(ymd(1985, 12, 15), 2023), ymd(2023, 12, 15));
+
+        // Ages tick over on that same anniversary
+        assert_eq!(full_years_between(birthday, ymd(2023, 2, 27)), 22);
+        assert_eq!(full_years_between(birthday, ymd(2023, 2, 28)), 23);
+        assert_eq!(full_years_between(birthday, ymd(2024, 2, 28)), 23);
+        assert_eq!(full_years_between(birthday, ymd(2024, 2, 29)), 24);
+    }
+}
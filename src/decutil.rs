@@ -7,8 +7,21 @@ use std::str::FromStr;
 pub enum InvalidRatioError {
     InvalidDecimal(rust_decimal::Error),
     IncompleteRatio(IncompleteRatioError),
+    NonInteger(NonIntegerError),
 }
 
+impl fmt::Display for InvalidRatioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InvalidRatioError::InvalidDecimal(e) => write!(f, "{:}", e),
+            InvalidRatioError::IncompleteRatio(e) => write!(f, "{:}", e),
+            InvalidRatioError::NonInteger(e) => write!(f, "{:}", e),
+        }
+    }
+}
+
+impl std::error::Error for InvalidRatioError {}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct IncompleteRatioError {
     fraction: String,
@@ -37,6 +50,14 @@ pub fn format_dollars(quantity: &Decimal) -> String {
     format!("${:}", formatted)
 }
 
+/// Format the quantity as USD (see `format_dollars`), rounded to the cent
+pub fn format_dollars_and_cents(quantity: &Decimal) -> String {
+    let rounded = quantity.round_dp(2);
+    let dollars = rounded.trunc();
+    let cents = ((rounded - dollars) * Decimal::from(100)).abs();
+    format!("{:}.{:0>2}", format_dollars(&dollars), cents.normalize())
+}
+
 impl IncompleteRatioError {
     fn new(fraction: &str) -> IncompleteRatioError {
         IncompleteRatioError {
@@ -51,6 +72,28 @@ impl fmt::Display for IncompleteRatioError {
     }
 }
 
+/// GnuCash fractions are always integer/integer (never e.g. "1.5e3/100")
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NonIntegerError {
+    component: &'static str,
+    value: String,
+}
+
+impl NonIntegerError {
+    fn new(component: &'static str, value: &str) -> NonIntegerError {
+        NonIntegerError {
+            component,
+            value: value.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for NonIntegerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:} '{:}' is not an integer", self.component, self.value)
+    }
+}
+
 impl From<rust_decimal::Error> for InvalidRatioError {
     fn from(e: rust_decimal::Error) -> Self {
         InvalidRatioError::InvalidDecimal(e)
@@ -61,6 +104,23 @@ impl From<IncompleteRatioError> for InvalidRatioError {
         InvalidRatioError::IncompleteRatio(e)
     }
 }
+impl From<NonIntegerError> for InvalidRatioError {
+    fn from(e: NonIntegerError) -> Self {
+        InvalidRatioError::NonInteger(e)
+    }
+}
+
+/// Reject anything other than an (optionally negative) run of digits.
+///
+/// Empty components are left for `Decimal::from_str` to reject.
+fn check_integer(component: &'static str, value: &str) -> Result<(), NonIntegerError> {
+    let digits = value.strip_prefix('-').unwrap_or(value);
+    if value.is_empty() || digits.chars().all(|c| c.is_ascii_digit()) {
+        Ok(())
+    } else {
+        Err(NonIntegerError::new(component, value))
+    }
+}
 
 pub fn frac_to_quantity(fraction: &str) -> Result<Decimal, InvalidRatioError> {
     let mut components = fraction.split('/');
@@ -71,6 +131,9 @@ pub fn frac_to_quantity(fraction: &str) -> Result<Decimal, InvalidRatioError> {
         .next()
         .ok_or_else(|| IncompleteRatioError::new(fraction))?;
 
+    check_integer("numerator", numerator)?;
+    check_integer("denominator", denominator)?;
+
     let dec_numerator = Decimal::from_str(numerator)?;
     let dec_denominator = Decimal::from_str(denominator)?;
     Ok(dec_numerator / dec_denominator)
@@ -81,6 +144,14 @@ pub fn price_to_cents(quantity: &Decimal) -> Option<u64> {
     rounded_to_whole_cents.to_u64()
 }
 
+/// Whether a price would change if written in whole cents (see `price_to_cents`)
+pub fn loses_precision_in_cents(price: &Decimal) -> bool {
+    match price_to_cents(price) {
+        Some(cents) => Decimal::from(cents) != price * Decimal::from(100),
+        None => true,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -137,11 +208,24 @@ mod tests {
         assert_raises_err("1/");
     }
 
+    #[test]
+    fn test_scientific_notation_numerator() {
+        let error = frac_to_quantity("1.5e3/100").err().unwrap();
+        assert_eq!(error.to_string(), "numerator '1.5e3' is not an integer");
+    }
+
+    #[test]
+    fn test_non_numeric_denominator() {
+        let error = frac_to_quantity("150/one").err().unwrap();
+        assert_eq!(error.to_string(), "denominator 'one' is not an integer");
+    }
+
     #[test]
     fn test_frac_to_quantity() {
         assert_eq!(frac_to_quantity("1/2").unwrap(), Decimal::new(50, 2));
         assert_eq!(frac_to_quantity("3/4").unwrap(), Decimal::new(75, 2));
         assert_eq!(frac_to_quantity("0/213481143").unwrap(), Decimal::new(0, 0));
+        assert_eq!(frac_to_quantity("-1/4").unwrap(), Decimal::new(-25, 2));
     }
 
     #[test]
@@ -150,6 +234,20 @@ mod tests {
         frac_to_quantity("1/0").unwrap();
     }
 
+    #[test]
+    fn test_dollars_and_cents() {
+        assert_eq!(
+            format_dollars_and_cents(&Decimal::new(333_333_333, 5)),
+            "$3,333.33"
+        );
+        assert_eq!(format_dollars_and_cents(&Decimal::from(25)), "$25.00");
+        assert_eq!(format_dollars_and_cents(&Decimal::new(1_005, 2)), "$10.05");
+        assert_eq!(
+            format_dollars_and_cents(&Decimal::new(99_999, 3)),
+            "$100.00"
+        );
+    }
+
     #[test]
     fn test_price_to_cents() {
         assert_eq!(price_to_cents(&Decimal::new(3525, 2)), Some(3525));
@@ -163,6 +261,14 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_loses_precision_in_cents() {
+        assert!(!loses_precision_in_cents(&Decimal::new(3525, 2)));
+        // Trailing zeroes beyond the cents place are no loss
+        assert!(!loses_precision_in_cents(&Decimal::new(352_500, 4)));
+        assert!(loses_precision_in_cents(&Decimal::new(83_912, 4)));
+    }
+
     #[test]
     fn test_negative_prices() {
         assert_eq!(price_to_cents(&Decimal::new(-1, 0)), None);
@@ -0,0 +1,189 @@
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fmt;
+
+use crate::currency::BASE_CURRENCY;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExchangeRateError {
+    pub currency: String,
+}
+
+impl fmt::Display for ExchangeRateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "No exchange rate available for {:}", self.currency)
+    }
+}
+
+impl Error for ExchangeRateError {}
+
+/// A source of exchange rates, for valuing holdings priced in other currencies
+pub trait ExchangeRateProvider {
+    /// The value of one unit of `currency` in the base currency, as of `day`
+    fn rate(&self, currency: &str, day: NaiveDate) -> Result<Decimal, ExchangeRateError>;
+}
+
+/// Rates given in `[exchange_rates]`, which hold regardless of the day
+impl ExchangeRateProvider for HashMap<String, Decimal> {
+    fn rate(&self, currency: &str, _day: NaiveDate) -> Result<Decimal, ExchangeRateError> {
+        self.get(currency)
+            .copied()
+            .ok_or_else(|| ExchangeRateError {
+                currency: currency.to_string(),
+            })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct JsonExchangeRate {
+    #[serde(rename = "1. From_Currency Code")]
+    pub from_currency: String,
+
+    #[serde(rename = "3. To_Currency Code")]
+    pub to_currency: String,
+
+    #[serde(rename = "5. Exchange Rate")]
+    pub rate: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct RealtimeJsonExchangeRate {
+    #[serde(rename = "Realtime Currency Exchange Rate")]
+    pub exchange_rate: JsonExchangeRate,
+}
+
+/// Exchange rates from AlphaVantage (requires `ALPHAVANTAGE_API_KEY` to be set)
+///
+/// AlphaVantage only offers the latest rate, so the day asked for is ignored.
+pub struct AlphaVantageRates {}
+
+impl ExchangeRateProvider for AlphaVantageRates {
+    fn rate(&self, currency: &str, _day: NaiveDate) -> Result<Decimal, ExchangeRateError> {
+        let error = || ExchangeRateError {
+            currency: currency.to_string(),
+        };
+        let api_key = env::var("ALPHAVANTAGE_API_KEY").map_err(|_| error())?;
+
+        let url: String = format!(
+            "https://www.alphavantage.co/query?function=CURRENCY_EXCHANGE_RATE&from_currency={:}&to_currency={:}&apikey={:}",
+            currency, BASE_CURRENCY, api_key,
+        );
+        let body = reqwest::blocking::get(url)
+            .and_then(|response| response.text())
+            .map_err(|_| error())?;
+        let parsed: RealtimeJsonExchangeRate = serde_json::from_str(&body).map_err(|_| error())?;
+        Ok(parsed.exchange_rate.rate)
+    }
+}
+
+/// Remember each day's rates, so that every currency is fetched at most once a day
+pub struct CachedRates<P: ExchangeRateProvider> {
+    provider: P,
+    cache: RefCell<HashMap<(String, NaiveDate), Decimal>>,
+}
+
+impl<P: ExchangeRateProvider> CachedRates<P> {
+    pub fn new(provider: P) -> CachedRates<P> {
+        CachedRates {
+            provider,
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+impl<P: ExchangeRateProvider> ExchangeRateProvider for CachedRates<P> {
+    fn rate(&self, currency: &str, day: NaiveDate) -> Result<Decimal, ExchangeRateError> {
+        let key = (currency.to_string(), day);
+        if let Some(rate) = self.cache.borrow().get(&key) {
+            return Ok(*rate);
+        }
+        let rate = self.provider.rate(currency, day)?;
+        self.cache.borrow_mut().insert(key, rate);
+        Ok(rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    /// Hands out a fixed rate, counting how often it's asked
+    struct CountingRates {
+        fetches: Cell<usize>,
+    }
+
+    impl ExchangeRateProvider for CountingRates {
+        fn rate(&self, _currency: &str, _day: NaiveDate) -> Result<Decimal, ExchangeRateError> {
+            self.fetches.set(self.fetches.get() + 1);
+            Ok(Decimal::new(127, 2))
+        }
+    }
+
+    fn ymd(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let data = r#"{
+            "Realtime Currency Exchange Rate": {
+                "1. From_Currency Code": "GBP",
+                "2. From_Currency Name": "British Pound Sterling",
+                "3. To_Currency Code": "USD",
+                "4. To_Currency Name": "United States Dollar",
+                "5. Exchange Rate": "1.27140000",
+                "6. Last Refreshed": "2023-12-28 21:55:01",
+                "7. Time Zone": "UTC",
+                "8. Bid Price": "1.27136000",
+                "9. Ask Price": "1.27144000"
+            }
+        }"#;
+        let parsed: RealtimeJsonExchangeRate = serde_json::from_str(data).unwrap();
+
+        assert_eq!(
+            parsed,
+            RealtimeJsonExchangeRate {
+                exchange_rate: JsonExchangeRate {
+                    from_currency: "GBP".into(),
+                    to_currency: "USD".into(),
+                    rate: Decimal::new(127140000, 8),
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn test_cached_rate_is_not_fetched_again() {
+        let cached = CachedRates::new(CountingRates {
+            fetches: Cell::new(0),
+        });
+        let day = ymd(2023, 12, 28);
+
+        assert_eq!(cached.rate("GBP", day), Ok(Decimal::new(127, 2)));
+        assert_eq!(cached.rate("GBP", day), Ok(Decimal::new(127, 2)));
+        assert_eq!(cached.provider.fetches.get(), 1);
+
+        // Another currency, or another day, needs its own rate
+        cached.rate("EUR", day).unwrap();
+        cached.rate("GBP", ymd(2023, 12, 29)).unwrap();
+        assert_eq!(cached.provider.fetches.get(), 3);
+    }
+
+    #[test]
+    fn test_configured_rates() {
+        let mut configured = HashMap::new();
+        configured.insert(String::from("GBP"), Decimal::new(125, 2));
+        let day = ymd(2023, 12, 28);
+
+        assert_eq!(configured.rate("GBP", day), Ok(Decimal::new(125, 2)));
+        assert_eq!(
+            configured.rate("EUR", day).unwrap_err().to_string(),
+            "No exchange rate available for EUR"
+        );
+    }
+}
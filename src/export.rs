@@ -0,0 +1,75 @@
+use rust_decimal::Decimal;
+use std::error::Error;
+use std::fs;
+
+use crate::assets::Asset;
+
+/// Column headers expected by Personal Capital / Empower when importing holdings
+const AGGREGATOR_HEADERS: [&str; 5] = ["Account", "Ticker", "Shares", "Price", "Value"];
+
+/// Aggregators reject rows without a ticker, so holdings lacking one get this instead
+pub const MISSING_TICKER: &str = "N/A";
+
+fn optional_amount(amount: Option<Decimal>) -> String {
+    amount.map(|amount| amount.to_string()).unwrap_or_default()
+}
+
+/// Render holdings as a CSV in the layout that Personal Capital / Empower can import
+pub fn render_aggregator_csv(assets: &[Asset]) -> Result<String, Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(vec![]);
+    wtr.write_record(AGGREGATOR_HEADERS)?;
+    for asset in assets {
+        wtr.write_record([
+            asset.name.clone(),
+            asset
+                .symbol
+                .clone()
+                .unwrap_or_else(|| String::from(MISSING_TICKER)),
+            optional_amount(asset.quantity()),
+            optional_amount(asset.last_price().map(|price| price.round_dp(2))),
+            asset.value.round_dp(2).to_string(),
+        ])?;
+    }
+    Ok(String::from_utf8(wtr.into_inner()?)?)
+}
+
+pub fn write_aggregator_csv(path: &str, assets: &[Asset]) -> Result<(), Box<dyn Error>> {
+    fs::write(path, render_aggregator_csv(assets)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::AssetClass;
+
+    #[test]
+    fn test_header_and_rows() {
+        let assets = vec![
+            Asset::new(
+                String::from("Roth IRA"),
+                Some(String::from("VTSAX")),
+                Decimal::new(1234567, 2),
+                AssetClass::USTotal,
+                Some(Decimal::new(1234567, 4)),
+                Some(Decimal::new(10000, 2)),
+                None,
+            ),
+            Asset::new(
+                String::from("Savings"),
+                None,
+                Decimal::new(50000, 2),
+                AssetClass::Cash,
+                None,
+                None,
+                None,
+            ),
+        ];
+        let csv = render_aggregator_csv(&assets).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "Account,Ticker,Shares,Price,Value");
+        assert_eq!(lines[1], "Roth IRA,VTSAX,123.4567,100.00,12345.67");
+        assert_eq!(lines[2], "Savings,N/A,,,500.00");
+        assert_eq!(lines.len(), 3);
+    }
+}
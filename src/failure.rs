@@ -0,0 +1,67 @@
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::cli::OutputFormat;
+
+/// An error that stops the program, described for people or (with `--format json`) for scripts
+#[derive(Debug, PartialEq, Eq)]
+pub struct Failure {
+    /// A short, stable identifier for the kind of error (e.g. "unclassified_asset")
+    pub kind: &'static str,
+    pub message: String,
+    /// Details a script might act on (e.g. which symbol wasn't classified)
+    pub context: BTreeMap<String, String>,
+}
+
+impl Failure {
+    pub fn new<E: fmt::Display>(kind: &'static str, error: E) -> Failure {
+        Failure {
+            kind,
+            message: error.to_string(),
+            context: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_context<V: ToString>(mut self, key: &str, value: V) -> Failure {
+        self.context.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn to_json(&self) -> String {
+        json!({
+            "error": {
+                "kind": self.kind,
+                "message": self.message,
+                "context": self.context,
+            }
+        })
+        .to_string()
+    }
+
+    pub fn render(&self, format: OutputFormat) -> String {
+        match format {
+            OutputFormat::Text => self.message.clone(),
+            OutputFormat::Json => self.to_json(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let failure = Failure::new("unclassified_asset", "asset ZZZZZ not classified")
+            .with_context("symbol", "ZZZZZ");
+        assert_eq!(
+            failure.render(OutputFormat::Text),
+            "asset ZZZZZ not classified"
+        );
+        assert_eq!(
+            failure.render(OutputFormat::Json),
+            r#"{"error":{"context":{"symbol":"ZZZZZ"},"kind":"unclassified_asset","message":"asset ZZZZZ not classified"}}"#
+        );
+    }
+}
@@ -1,27 +1,34 @@
-use chrono::{DateTime, Datelike, Local};
+use chrono::{DateTime, Local, NaiveDate};
+use flate2::read::GzDecoder;
 use quick_xml::events::Event;
 use quick_xml::Reader;
-use rusqlite::{params, Connection, NO_PARAMS};
+use rusqlite::types::Type;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension, NO_PARAMS};
 use rust_decimal::Decimal;
 use std::collections::HashMap;
-use std::convert::Into;
+use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
-use std::io::BufReader;
+use std::io::{BufReader, SeekFrom};
 
 use crate::assets;
-use crate::config::Config;
+use crate::calendar::TradingCalendar;
+use crate::config::{Config, PriceOverride, PriceSources};
 use crate::dateutil;
 use crate::decutil;
+use crate::exchange::{ExchangeRateError, ExchangeRateProvider};
+use crate::health::PortfolioHealth;
 use crate::quote;
 use crate::rebalance::{AssetAllocation, Portfolio};
 
-trait GnucashFromXML {
-    fn from_xml(_: &mut Reader<BufReader<File>>) -> Self;
-}
+/// XML books may be read straight from disk, or through a gzip decoder
+type XmlReader = Reader<BufReader<Box<dyn Read>>>;
 
-trait GnucashFromSqlite {
-    fn from_sqlite(_: &Connection, conf: &Config) -> Self;
+/// The first two bytes of any gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+trait GnucashFromXML {
+    fn from_xml(_: &mut XmlReader) -> Self;
 }
 
 #[derive(Debug)]
@@ -29,8 +36,116 @@ pub struct CommodityError {
     pub commodity_id: String,
 }
 
+/// Why a price couldn't be written to the book
+#[derive(Debug)]
+pub enum PriceWriteError {
+    /// The commodity (or currency) has no GUID by which to refer to it
+    Commodity(CommodityError),
+    Sqlite(rusqlite::Error),
+}
+
+impl From<CommodityError> for PriceWriteError {
+    fn from(e: CommodityError) -> PriceWriteError {
+        PriceWriteError::Commodity(e)
+    }
+}
+
+impl From<rusqlite::Error> for PriceWriteError {
+    fn from(e: rusqlite::Error) -> PriceWriteError {
+        PriceWriteError::Sqlite(e)
+    }
+}
+
+impl fmt::Display for PriceWriteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PriceWriteError::Commodity(e) => write!(f, "no GUID for {:}", e.commodity_id),
+            PriceWriteError::Sqlite(e) => write!(f, "{:}", e),
+        }
+    }
+}
+
+/// Why the book couldn't be read
+#[derive(Debug)]
+pub enum BookError {
+    Io(std::io::Error),
+    Sqlite(rusqlite::Error),
+    Csv(csv::Error),
+    ExchangeRate(ExchangeRateError),
+    /// A `file_format` other than sqlite3, xml, or csv
+    UnsupportedFormat(String),
+}
+
+impl From<std::io::Error> for BookError {
+    fn from(e: std::io::Error) -> BookError {
+        BookError::Io(e)
+    }
+}
+
+impl From<rusqlite::Error> for BookError {
+    fn from(e: rusqlite::Error) -> BookError {
+        BookError::Sqlite(e)
+    }
+}
+
+impl From<csv::Error> for BookError {
+    fn from(e: csv::Error) -> BookError {
+        BookError::Csv(e)
+    }
+}
+
+impl From<ExchangeRateError> for BookError {
+    fn from(e: ExchangeRateError) -> BookError {
+        BookError::ExchangeRate(e)
+    }
+}
+
+impl fmt::Display for BookError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BookError::Io(e) => write!(f, "{:}", e),
+            BookError::Sqlite(e) => write!(f, "{:}", e),
+            BookError::Csv(e) => write!(f, "{:}", e),
+            BookError::ExchangeRate(e) => write!(f, "{:}", e),
+            BookError::UnsupportedFormat(format) => write!(
+                f,
+                "Unsupported file format '{:}' (expected sqlite3, xml, or csv)",
+                format
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BookError {}
+
+/// The book holds investments, but has no prices with which to value them
+#[derive(Debug, PartialEq, Eq)]
+pub struct NoPricesError {
+    /// Whether we already tried fetching quotes
+    pub fetched_quotes: bool,
+}
+
+impl fmt::Display for NoPricesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.fetched_quotes {
+            write!(
+                f,
+                "No prices available, even after fetching quotes; seed prices in GnuCash"
+            )
+        } else {
+            write!(
+                f,
+                "No prices available; enable update_prices or seed prices"
+            )
+        }
+    }
+}
+
+impl std::error::Error for NoPricesError {}
+
+/// A commodity's value in another commodity (usually a currency) at some moment
 #[derive(Debug)]
-struct Price {
+pub struct Price {
     from_commodity: Commodity,
     to_commodity: Commodity,
     value: Decimal,
@@ -38,17 +153,47 @@ struct Price {
 }
 
 impl Price {
-    fn is_in_usd(&self) -> bool {
-        match &self.to_commodity.space {
-            Some(space) => space == "CURRENCY" && self.to_commodity.id == "USD",
-            None => false,
+    #[cfg(test)]
+    fn new(
+        from_commodity: Commodity,
+        to_commodity: Commodity,
+        value: Decimal,
+        time: DateTime<Local>,
+    ) -> Price {
+        Price {
+            from_commodity,
+            to_commodity,
+            value,
+            time,
         }
     }
 
+    fn is_in_usd(&self) -> bool {
+        self.is_in_currency() && self.to_commodity.id == "USD"
+    }
+
+    fn is_in_currency(&self) -> bool {
+        self.to_commodity.space.as_deref() == Some("CURRENCY")
+    }
+
     fn commodity_name(&self) -> &str {
         self.from_commodity.id.as_ref()
     }
 
+    /// A price as of the quote, for a commodity which has never been priced before
+    fn first_from_quote(
+        from_commodity: Commodity,
+        to_commodity: Commodity,
+        q: &quote::Quote,
+    ) -> Price {
+        Price {
+            from_commodity,
+            to_commodity,
+            value: q.last,
+            time: q.time,
+        }
+    }
+
     fn at_new_quoted_value(&self, q: &quote::Quote) -> Price {
         Price {
             from_commodity: self.from_commodity.clone(),
@@ -70,7 +215,7 @@ impl Price {
 }
 
 impl GnucashFromXML for Price {
-    fn from_xml(reader: &mut Reader<BufReader<File>>) -> Price {
+    fn from_xml(reader: &mut XmlReader) -> Price {
         let mut buf = Vec::new();
 
         let mut maybe_from_commodity = None;
@@ -121,9 +266,56 @@ impl GnucashFromXML for Price {
     }
 }
 
+/// A newly fetched quote which differs from the last known price of its commodity
+#[derive(Debug)]
+pub struct PriceUpdate {
+    pub commodity: Commodity,
+    pub old_value: Option<Decimal>,
+    pub quote: quote::Quote,
+}
+
+impl fmt::Display for PriceUpdate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let old_value = match self.old_value {
+            Some(value) => value.to_string(),
+            None => String::from("(none)"),
+        };
+        write!(
+            f,
+            "{:}: {:} -> {:} ({:})",
+            self.commodity.id,
+            old_value,
+            self.quote.last,
+            self.quote.time.date_naive().format("%Y-%m-%d")
+        )
+    }
+}
+
+/// A commodity's latest price, which can't be written in whole cents without losing precision
+#[derive(Debug, PartialEq, Eq)]
+pub struct LossyPrice {
+    pub symbol: String,
+    pub value: Decimal,
+}
+
+impl fmt::Display for LossyPrice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:}: {:} (would be written as {:.2})",
+            self.symbol,
+            self.value,
+            self.value.round_dp(2)
+        )
+    }
+}
+
 #[derive(Debug)]
 struct PriceDatabase {
     last_price_by_commodity: HashMap<String, Price>,
+    sources: PriceSources,
+    /// Ignore prices from after this day (for valuing holdings in the past)
+    through: Option<NaiveDate>,
 }
 
 pub fn new_uuid() -> String {
@@ -138,16 +330,24 @@ impl PriceDatabase {
         let last_price_by_commodity: HashMap<String, Price> = HashMap::new();
         PriceDatabase {
             last_price_by_commodity,
+            sources: PriceSources::default(),
+            through: None,
         }
     }
 
     // TODO: Update the database in-place by using mut self
+    /// Write a price from the quote, replacing any price previously written on the same day
+    ///
+    /// This makes writes safe to retry: if updating commodities is interrupted and then
+    /// re-run, we won't insert duplicate prices for the same commodity & date
+    /// (even if the time of day given to quotes has since changed).
+    /// Returns the new price, or `None` if an existing price was replaced instead.
     pub fn write_price_from_quote(
         &self,
         conn: &Connection,
         q: &quote::Quote,
         old_price: &Price,
-    ) -> Result<Price, CommodityError> {
+    ) -> Result<Option<Price>, PriceWriteError> {
         let new_price = old_price.at_new_quoted_value(q);
         let new_price_uuid = new_uuid();
 
@@ -156,21 +356,49 @@ impl PriceDatabase {
         let commodity_guid: String = match &new_price.from_commodity.guid {
             Some(guid) => guid.clone(),
             None => {
-                return Err(CommodityError {
+                return Err(PriceWriteError::from(CommodityError {
                     commodity_id: new_price.from_commodity.id.clone(),
-                })
+                }))
             }
         };
         let currency_guid: String = match &new_price.to_commodity.guid {
             Some(guid) => guid.clone(),
             None => {
-                return Err(CommodityError {
+                return Err(PriceWriteError::from(CommodityError {
                     commodity_id: new_price.to_commodity.id.clone(),
-                })
+                }))
             }
         };
 
         let cents: u64 = decutil::price_to_cents(&new_price.value).unwrap();
+        let date = dateutil::datetime_for_sqlite(new_price.time);
+        let (day_start, next_day_start) =
+            dateutil::day_bounds_for_sqlite(new_price.time.date_naive());
+
+        let num_updated = conn.execute(
+            "UPDATE prices
+                SET value_num = ?1,
+                    value_denom = ?2,
+                    date = ?3
+              WHERE commodity_guid = ?4
+                AND currency_guid = ?5
+                AND date >= ?6
+                AND date < ?7
+                AND source = ?8",
+            params![
+                &cents.to_string(),
+                "100",
+                &date,
+                &commodity_guid,
+                &currency_guid,
+                &day_start,
+                &next_day_start,
+                "Finance::Quote",
+            ],
+        )?;
+        if num_updated > 0 {
+            return Ok(None);
+        }
 
         conn.execute(
             "INSERT INTO prices (
@@ -191,16 +419,15 @@ impl PriceDatabase {
                 &new_price_uuid,
                 &commodity_guid,
                 &currency_guid,
-                &dateutil::datetime_for_sqlite(new_price.time),
+                &date,
                 "Finance::Quote",
                 "last",
                 &cents.to_string(),
                 "100",
             ],
-        )
-        .unwrap();
+        )?;
 
-        Ok(new_price)
+        Ok(Some(new_price))
     }
 
     fn read_price(&mut self, price: Price) {
@@ -213,44 +440,76 @@ impl PriceDatabase {
         self.last_price_by_commodity.insert(name, price);
     }
 
+    /// The commodity in which prices are given (US dollars), as recorded in the book if possible
+    fn usd(&self) -> Commodity {
+        self.last_price_by_commodity
+            .values()
+            .find(|price| price.is_in_usd())
+            .map(|price| price.to_commodity.clone())
+            .unwrap_or_else(|| {
+                Commodity::new(
+                    None,
+                    String::from("USD"),
+                    Some(String::from("CURRENCY")),
+                    Some(String::from("US Dollar")),
+                )
+            })
+    }
+
+    /// Restate any prices given in other currencies in US dollars, at the rate on their day
+    fn convert_to_usd(
+        &mut self,
+        rates: &dyn ExchangeRateProvider,
+    ) -> Result<(), ExchangeRateError> {
+        let usd = self.usd();
+        for price in self.last_price_by_commodity.values_mut() {
+            if price.is_in_usd() {
+                continue;
+            }
+            let rate = rates.rate(&price.to_commodity.id, price.time.date_naive())?;
+            price.value *= rate;
+            price.to_commodity = usd.clone();
+        }
+        Ok(())
+    }
+
     fn last_commodity_price(&self, commodity: &Commodity) -> Option<&Price> {
         self.last_price_by_commodity.get(&commodity.id)
     }
 
     fn last_price_for(&self, account: &Account) -> Option<&Price> {
         match &account.commodity {
-            Some(commodity) => self.last_commodity_price(&commodity),
+            Some(commodity) => self.last_commodity_price(commodity),
             None => panic!("Can't fetch last price of an account without a commodity"),
         }
     }
 
+    /// Read the latest price for each commodity, honoring any preferred sources & excluded types
+    ///
+    /// Of two prices on the same day, one from the preferred source wins (even if it's earlier).
     fn populate_from_sqlite(&mut self, conn: &Connection) -> rusqlite::Result<()> {
         let mut stmt = conn.prepare(
-            "-- NOTE: This query uses a quirk of SQLite that does not comply with the SQL standard
-                      -- (SQLite lets you `GROUP BY` columns, then select non-aggregate columns)
-                      -- It's handy here, but it may not be portable to other SQL implementations
-                      SELECT -- Fraction which forms the actual price
-                             p.value_num, p.value_denom,
-
-                             -- Last known price date
-                             max(p.date),
-
-                             -- Commodity for which the price is being quoted
-                             from_c.guid, from_c.mnemonic, from_c.namespace, from_c.fullname,
-
-                             -- Commodity in which the price is defined (generally a currency)
-                             to_c.guid, to_c.mnemonic, to_c.namespace, to_c.fullname
-                        FROM prices p
-                             JOIN commodities from_c ON p.commodity_guid = from_c.guid
-                             JOIN commodities to_c   ON p.currency_guid = to_c.guid
-                       WHERE from_c.namespace IN ('FUND', 'Series I')
-                       GROUP BY p.commodity_guid;",
+            "SELECT -- Fraction which forms the actual price
+                    p.value_num, p.value_denom,
+
+                    p.date,
+
+                    -- Commodity for which the price is being quoted
+                    from_c.guid, from_c.mnemonic, from_c.namespace, from_c.fullname,
+
+                    -- Commodity in which the price is defined (generally a currency)
+                    to_c.guid, to_c.mnemonic, to_c.namespace, to_c.fullname,
+
+                    -- Where the price came from (e.g. 'user:price'), and its type (e.g. 'last')
+                    p.source, p.type
+               FROM prices p
+                    JOIN commodities from_c ON p.commodity_guid = from_c.guid
+                    JOIN commodities to_c   ON p.currency_guid = to_c.guid
+              WHERE from_c.namespace IN ('FUND', 'Series I');",
         )?;
 
         let price_iter = stmt.query_map(NO_PARAMS, |row| {
-            let num: i64 = row.get(0)?;
-            let denom: i64 = row.get(1)?;
-            let value: Decimal = Decimal::from(num) / Decimal::from(denom);
+            let value = fraction_from_row(row, 0, 1)?;
 
             let dt: String = row.get(2)?;
 
@@ -270,15 +529,43 @@ impl PriceDatabase {
                     row.get(10)?,
                 ),
             };
-            Ok(price)
+            let source: Option<String> = row.get(11)?;
+            let price_type: Option<String> = row.get(12)?;
+            Ok((price, source, price_type))
         })?;
-        for price in price_iter {
-            self.read_price(price.unwrap());
+
+        // Rank prices by day, then by source, then by time of day
+        let mut best_by_commodity: HashMap<String, (Price, bool)> = HashMap::new();
+        for row in price_iter {
+            let (price, source, price_type) = row?;
+            if let Some(through) = self.through {
+                if price.time.date_naive() > through {
+                    continue;
+                }
+            }
+            if let Some(price_type) = price_type {
+                if self.sources.exclude_types.contains(&price_type) {
+                    continue;
+                }
+            }
+            let preferred = self.sources.prefer.is_some() && source == self.sources.prefer;
+            let rank =
+                |price: &Price, preferred: bool| (price.time.date_naive(), preferred, price.time);
+            let name = String::from(price.commodity_name());
+            if let Some((best, best_preferred)) = best_by_commodity.get(&name) {
+                if rank(&price, preferred) < rank(best, *best_preferred) {
+                    continue;
+                }
+            }
+            best_by_commodity.insert(name, (price, preferred));
+        }
+        for (_, (price, _)) in best_by_commodity {
+            self.read_price(price);
         }
         Ok(())
     }
 
-    fn populate_from_xml(&mut self, reader: &mut Reader<BufReader<File>>) {
+    fn populate_from_xml(&mut self, reader: &mut XmlReader) {
         let mut buf = Vec::new();
 
         loop {
@@ -286,7 +573,7 @@ impl PriceDatabase {
                 Ok(Event::Start(ref e)) => {
                     if let b"price" = e.name() {
                         let price = Price::from_xml(reader);
-                        if !&price.is_in_usd() {
+                        if !&price.is_in_currency() {
                             continue;
                         }
                         self.read_price(price);
@@ -305,7 +592,7 @@ impl PriceDatabase {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Commodity {
     pub guid: Option<String>, // a UUID lowercased with no hypens, absent from XML
     pub id: String,           // "VTSAX"
@@ -342,7 +629,7 @@ impl Commodity {
 }
 
 impl GnucashFromXML for Commodity {
-    fn from_xml(reader: &mut Reader<BufReader<File>>) -> Commodity {
+    fn from_xml(reader: &mut XmlReader) -> Commodity {
         let mut buf = Vec::new();
 
         let mut space = None;
@@ -415,12 +702,14 @@ struct LazySplit {
     value_fraction: Result<String, quick_xml::Error>,
     quantity_fraction: Result<String, quick_xml::Error>,
     account: String, // guid
+    /// Whether the split has been cleared or reconciled
+    reconciled: bool,
 }
 
 impl GenericSplit for LazySplit {
     fn get_quantity(&self) -> Decimal {
         match &self.quantity_fraction {
-            Ok(frac) => decutil::frac_to_quantity(&frac).unwrap(),
+            Ok(frac) => decutil::frac_to_quantity(frac).unwrap(),
             Err(_) => panic!("Error parsing quantity"),
         }
     }
@@ -428,33 +717,38 @@ impl GenericSplit for LazySplit {
     #[allow(dead_code)]
     fn get_value(&self) -> Decimal {
         match &self.value_fraction {
-            Ok(frac) => decutil::frac_to_quantity(&frac).unwrap(),
+            Ok(frac) => decutil::frac_to_quantity(frac).unwrap(),
             Err(_) => panic!("Error parsing value"),
         }
     }
 }
 
-impl Into<ComputedSplit> for LazySplit {
-    fn into(self) -> ComputedSplit {
+impl From<LazySplit> for ComputedSplit {
+    fn from(split: LazySplit) -> ComputedSplit {
         ComputedSplit {
-            value: self.get_value(),
-            quantity: self.get_quantity(),
-            account: self.account,
+            value: split.get_value(),
+            quantity: split.get_quantity(),
+            account: split.account,
         }
     }
 }
 
 impl GnucashFromXML for LazySplit {
-    fn from_xml(reader: &mut Reader<BufReader<File>>) -> LazySplit {
+    fn from_xml(reader: &mut XmlReader) -> LazySplit {
         let mut buf = Vec::new();
 
         let mut value_fraction = None;
         let mut quantity_fraction = None;
         let mut account = None;
+        let mut reconciled = false;
 
         loop {
             match reader.read_event(&mut buf) {
                 Ok(Event::Start(ref e)) => match e.name() {
+                    b"split:reconciled-state" => {
+                        let state = reader.read_text(e.name(), &mut Vec::new()).unwrap();
+                        reconciled = state == "c" || state == "y";
+                    }
                     b"split:value" => {
                         value_fraction = Some(reader.read_text(e.name(), &mut Vec::new()));
                     }
@@ -482,6 +776,7 @@ impl GnucashFromXML for LazySplit {
                 value_fraction,
                 quantity_fraction,
                 account,
+                reconciled,
             },
             (_, _, _) => panic!("Must have value, quantity, and account in a split"),
         }
@@ -501,12 +796,11 @@ struct Transaction {
 }
 
 impl Transaction {
-    #[allow(dead_code)]
     fn date_posted(&self) -> DateTime<Local> {
         dateutil::localize_from_dt_with_tz(&self.date_posted_string).unwrap()
     }
 
-    fn parse_splits(reader: &mut Reader<BufReader<File>>) -> Vec<Split> {
+    fn parse_splits(reader: &mut XmlReader) -> Vec<Split> {
         let mut splits: Vec<Split> = Vec::new();
         let mut buf = Vec::new();
 
@@ -533,7 +827,7 @@ impl Transaction {
         splits
     }
 
-    fn parse_date_posted(reader: &mut Reader<BufReader<File>>) -> String {
+    fn parse_date_posted(reader: &mut XmlReader) -> String {
         let mut buf = Vec::new();
 
         let mut found_ts = None;
@@ -564,7 +858,7 @@ impl Transaction {
 }
 
 impl GnucashFromXML for Transaction {
-    fn from_xml(reader: &mut Reader<BufReader<File>>) -> Transaction {
+    fn from_xml(reader: &mut XmlReader) -> Transaction {
         let mut buf = Vec::new();
 
         let mut name: String = String::from("");
@@ -613,6 +907,46 @@ impl GnucashFromXML for Transaction {
     }
 }
 
+static SPLITS_QUERY: &str = "SELECT account_guid,
+                                   value_num, value_denom,
+                                   quantity_num, quantity_denom
+                              FROM splits";
+
+/// Read a GnuCash `num`/`denom` pair of columns as a single quantity
+///
+/// Huge denominators (with correspondingly huge numerators) can overflow `i64`;
+/// such values are read as text instead, which `Decimal` can represent.
+pub(crate) fn fraction_from_row(
+    row: &rusqlite::Row,
+    num_index: usize,
+    denom_index: usize,
+) -> rusqlite::Result<Decimal> {
+    if let (Ok(num), Ok(denom)) = (row.get::<_, i64>(num_index), row.get::<_, i64>(denom_index)) {
+        return Ok(Decimal::from(num) / Decimal::from(denom));
+    }
+
+    let as_text = |index: usize| {
+        row.get::<_, i64>(index)
+            .map(|integer| integer.to_string())
+            .or_else(|_| row.get::<_, String>(index))
+    };
+    let fraction = format!("{:}/{:}", as_text(num_index)?, as_text(denom_index)?);
+    decutil::frac_to_quantity(&fraction)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(num_index, Type::Text, Box::new(e)))
+}
+
+fn computed_split_from_row(row: &rusqlite::Row) -> rusqlite::Result<ComputedSplit> {
+    let account: String = row.get(0)?;
+    let value = fraction_from_row(row, 1, 2)?;
+    let quantity = fraction_from_row(row, 3, 4)?;
+
+    Ok(ComputedSplit {
+        value,
+        quantity,
+        account,
+    })
+}
+
 struct Account {
     guid: String,
     name: String,
@@ -635,34 +969,12 @@ impl Account {
         }
     }
 
+    /// Read just this account's splits (see `Book::read_splits_from_sqlite` for the batched version)
+    #[cfg(test)]
     fn read_splits_from_sqlite(&mut self, conn: &Connection) -> rusqlite::Result<()> {
-        let mut stmt = conn.prepare(
-            "SELECT account_guid,
-                    value_num, value_denom,
-                    quantity_num, quantity_denom
-               FROM splits
-              WHERE account_guid = $1
-              ",
-        )?;
-
-        let splits = stmt.query_map([&self.guid].iter(), |row| {
-            let account: String = row.get(0)?;
-
-            let value_num: i64 = row.get(1)?;
-            let value_denom: i64 = row.get(2)?;
-            let value: Decimal = Decimal::from(value_num) / Decimal::from(value_denom);
+        let mut stmt = conn.prepare(&format!("{:} WHERE account_guid = $1", SPLITS_QUERY))?;
 
-            let quantity_num: i64 = row.get(3)?;
-            let quantity_denom: i64 = row.get(4)?;
-            let quantity: Decimal = Decimal::from(quantity_num) / Decimal::from(quantity_denom);
-
-            let split = ComputedSplit {
-                value,
-                quantity,
-                account,
-            };
-            Ok(split)
-        })?;
+        let splits = stmt.query_map([&self.guid].iter(), computed_split_from_row)?;
 
         self.splits = splits
             .map(|split| Split::Computed(split.unwrap()))
@@ -677,6 +989,18 @@ impl Account {
         false
     }
 
+    /// An account holding the given quantity of its commodity (in a single split)
+    fn holding(name: &str, commodity: Commodity, quantity: Decimal) -> Account {
+        let guid = new_uuid();
+        let mut account = Account::new(guid.clone(), String::from(name), Some(commodity));
+        account.add_split(Split::Computed(ComputedSplit {
+            value: 0.into(),
+            quantity,
+            account: guid,
+        }));
+        account
+    }
+
     fn add_split(&mut self, split: Split) {
         self.splits.push(split);
     }
@@ -707,7 +1031,7 @@ impl Account {
 }
 
 impl GnucashFromXML for Account {
-    fn from_xml(mut reader: &mut Reader<BufReader<File>>) -> Account {
+    fn from_xml(reader: &mut XmlReader) -> Account {
         let mut buf = Vec::new();
 
         let mut guid: String = String::from("");
@@ -725,7 +1049,7 @@ impl GnucashFromXML for Account {
                         name = reader.read_text(e.name(), &mut Vec::new()).unwrap();
                     }
                     b"act:commodity" => {
-                        commodity = Some(Commodity::from_xml(&mut reader));
+                        commodity = Some(Commodity::from_xml(reader));
                     }
                     _ => (),
                 },
@@ -748,6 +1072,15 @@ impl GnucashFromXML for Account {
     }
 }
 
+/// One row of a holdings spreadsheet, for those who don't track investments in GnuCash
+#[derive(Debug, Deserialize)]
+struct HoldingRecord {
+    account: String,
+    symbol: String,
+    quantity: Decimal,
+    price: Decimal,
+}
+
 pub struct Book {
     pricedb: PriceDatabase,
     account_by_guid: HashMap<String, Account>,
@@ -761,27 +1094,141 @@ impl Book {
         }
     }
 
-    pub fn from_config(conf: &Config) -> Book {
+    pub fn from_config(conf: &Config) -> Result<Book, BookError> {
         let path = &conf.gnucash.path_to_book;
-        if conf.gnucash.file_format == "sqlite3" {
-            Book::from_sqlite_file(path, conf)
+        let as_of = conf.gnucash.balance_as_of;
+        let mut book = if conf.gnucash.file_format == "sqlite3" {
+            Book::from_sqlite_file_as_of(
+                path,
+                conf.sqlite_open_flags(),
+                as_of,
+                conf.gnucash.reconciled_only,
+                &conf.gnucash.price_sources,
+            )?
         } else if conf.gnucash.file_format == "xml" {
-            Book::from_xml_file(path)
+            Book::from_xml_file_as_of(path, as_of, conf.gnucash.reconciled_only)?
+        } else if conf.gnucash.file_format == "csv" {
+            Book::from_csv_file(path)?
         } else {
-            panic!("Other file formats not supported at this time");
+            return Err(BookError::UnsupportedFormat(
+                conf.gnucash.file_format.clone(),
+            ));
+        };
+        book.apply_price_overrides(&conf.price_overrides);
+        Ok(book)
+    }
+
+    /// Use manually set prices, except where the book has a newer price of its own
+    pub fn apply_price_overrides(&mut self, overrides: &HashMap<String, PriceOverride>) {
+        let usd = self.pricedb.usd();
+        for (symbol, price_override) in overrides {
+            let from_commodity = self
+                .account_by_guid
+                .values()
+                .filter_map(|account| account.commodity.as_ref())
+                .find(|commodity| &commodity.id == symbol)
+                .cloned()
+                .unwrap_or_else(|| {
+                    Commodity::new(None, symbol.clone(), Some(String::from("FUND")), None)
+                });
+            let ymd = price_override.as_of.format("%Y-%m-%d").to_string();
+            self.pricedb.read_price(Price {
+                from_commodity,
+                to_commodity: usd.clone(),
+                value: price_override.price,
+                time: dateutil::localize_at_noon(&ymd).unwrap(),
+            });
         }
     }
 
-    pub fn from_sqlite_file(filename: &str, conf: &Config) -> Book {
-        let conn = Connection::open(filename).expect("Could not open file");
-        Book::from_sqlite(&conn, conf)
+    pub fn from_sqlite_file(filename: &str, flags: OpenFlags) -> rusqlite::Result<Book> {
+        let conn = Connection::open_with_flags(filename, flags)?;
+        Book::from_sqlite(&conn)
+    }
+
+    fn from_sqlite_file_as_of(
+        filename: &str,
+        flags: OpenFlags,
+        as_of: Option<NaiveDate>,
+        reconciled_only: bool,
+        price_sources: &PriceSources,
+    ) -> rusqlite::Result<Book> {
+        let conn = Connection::open_with_flags(filename, flags)?;
+        Book::from_sqlite_as_of(&conn, as_of, reconciled_only, price_sources)
+    }
+
+    /// Read the book as it stood at the end of the given day (transactions & prices alike)
+    ///
+    /// Prices in other currencies are converted to US dollars, just as for the current book.
+    pub fn from_sqlite_file_on(
+        filename: &str,
+        flags: OpenFlags,
+        date: NaiveDate,
+        reconciled_only: bool,
+        price_sources: &PriceSources,
+        rates: &dyn ExchangeRateProvider,
+    ) -> Result<Book, BookError> {
+        let conn = Connection::open_with_flags(filename, flags)?;
+        Book::from_sqlite_on(&conn, date, reconciled_only, price_sources, rates)
+    }
+
+    /// Read holdings from a CSV with `account`, `symbol`, `quantity` & `price` columns
+    ///
+    /// There's no price history in such a file, so its prices are taken to be current.
+    pub fn from_csv_file(filename: &str) -> csv::Result<Book> {
+        Book::from_csv_reader(csv::Reader::from_path(filename)?)
+    }
+
+    fn from_csv_reader<R: Read>(mut rdr: csv::Reader<R>) -> csv::Result<Book> {
+        let mut book = Book::new();
+        let usd = book.pricedb.usd();
+        let now = Local::now();
+        for result in rdr.deserialize() {
+            let record: HoldingRecord = result?;
+            let commodity = Commodity::new(None, record.symbol, Some(String::from("FUND")), None);
+            book.pricedb.read_price(Price {
+                from_commodity: commodity.clone(),
+                to_commodity: usd.clone(),
+                value: record.price,
+                time: now,
+            });
+            book.add_investment(Account::holding(
+                &record.account,
+                commodity,
+                record.quantity,
+            ));
+        }
+        Ok(book)
     }
 
     #[allow(dead_code)]
-    pub fn from_xml_file(filename: &str) -> Book {
-        println!("This can be sluggish on larger XML files. Consider SQLite format instead!");
-        let mut reader = Reader::from_file(filename).unwrap();
-        Book::from_xml(&mut reader)
+    pub fn from_xml_file(filename: &str) -> std::io::Result<Book> {
+        Book::from_xml_file_as_of(filename, None, false)
+    }
+
+    fn from_xml_file_as_of(
+        filename: &str,
+        as_of: Option<NaiveDate>,
+        reconciled_only: bool,
+    ) -> std::io::Result<Book> {
+        eprintln!("This can be sluggish on larger XML files. Consider SQLite format instead!");
+        let mut reader = Book::open_xml(filename)?;
+        Ok(Book::from_xml_as_of(&mut reader, as_of, reconciled_only))
+    }
+
+    /// Open an XML book, decompressing it if gzipped (as GnuCash saves them by default)
+    fn open_xml(filename: &str) -> std::io::Result<XmlReader> {
+        let mut file = File::open(filename)?;
+        let mut magic = [0; 2];
+        let is_gzipped = file.read(&mut magic)? == magic.len() && magic == GZIP_MAGIC;
+        file.seek(SeekFrom::Start(0))?;
+
+        let source: Box<dyn Read> = if is_gzipped {
+            Box::new(GzDecoder::new(file))
+        } else {
+            Box::new(file)
+        };
+        Ok(Reader::from_reader(BufReader::new(source)))
     }
 
     fn add_split(&mut self, split: Split) {
@@ -798,10 +1245,71 @@ impl Book {
         self.account_by_guid.insert(account.guid.clone(), account);
     }
 
-    /// Return all investment holdings worth more than $0
-    fn holdings(&self, asset_classifications: assets::AssetClassifications) -> Vec<assets::Asset> {
+    /// Build a book directly from accounts & prices (no XML or SQLite needed)
+    #[cfg(test)]
+    fn with_holdings(accounts: Vec<Account>, prices: Vec<Price>) -> Book {
+        let mut book = Book::new();
+        for account in accounts {
+            book.add_investment(account);
+        }
+        for price in prices {
+            book.pricedb.read_price(price);
+        }
+        book
+    }
+
+    /// Value holdings priced in other currencies in US dollars instead
+    ///
+    /// Only currencies actually used by prices are looked up, so a book priced entirely in
+    /// US dollars never needs an exchange rate.
+    pub fn convert_prices(
+        &mut self,
+        rates: &dyn ExchangeRateProvider,
+    ) -> Result<(), ExchangeRateError> {
+        self.pricedb.convert_to_usd(rates)
+    }
+
+    /// Confirm that there are prices with which to value holdings
+    ///
+    /// A newly created book may have accounts, but no prices at all.
+    /// (If `update_prices` is enabled, quotes for every held commodity are fetched beforehand)
+    pub fn check_prices(&self, fetched_quotes: bool) -> Result<(), NoPricesError> {
+        if self.account_by_guid.is_empty() || !self.pricedb.last_price_by_commodity.is_empty() {
+            return Ok(());
+        }
+        Err(NoPricesError { fetched_quotes })
+    }
+
+    /// Latest prices that can't be written in whole cents (as `write_price_from_quote` does)
+    ///
+    /// Any such commodity would lose precision once its next price is fetched & written.
+    pub fn lossy_prices(&self) -> Vec<LossyPrice> {
+        let mut lossy: Vec<LossyPrice> = self
+            .pricedb
+            .last_price_by_commodity
+            .values()
+            .filter(|price| decutil::loses_precision_in_cents(&price.value))
+            .map(|price| LossyPrice {
+                symbol: String::from(price.commodity_name()),
+                value: price.value,
+            })
+            .collect();
+        lossy.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+        lossy
+    }
+
+    /// Value every non-empty account, setting aside any whose commodity lacks a classification
+    fn classified_holdings(
+        &self,
+        asset_classifications: &assets::AssetClassifications,
+    ) -> (Vec<assets::Asset>, Vec<assets::UnclassifiedAssetError>) {
         let mut non_zero_holdings = Vec::new();
+        let mut unclassified = Vec::new();
         for account in self.account_by_guid.values() {
+            // An account may be priced only once it holds shares (e.g. when valued as of the past)
+            if account.current_quantity() == 0.into() {
+                continue;
+            }
             let last_price = self
                 .pricedb
                 .last_price_for(account)
@@ -813,27 +1321,29 @@ impl Book {
                 continue;
             }
 
-            let symbol: Option<String> = match &account.commodity {
-                Some(commodity) => Some(commodity.id.to_owned()),
-                None => None,
-            };
+            let symbol: Option<String> = account
+                .commodity
+                .as_ref()
+                .map(|commodity| commodity.id.to_owned());
 
             if let Some(commodity) = &account.commodity {
-                let asset_class = asset_classifications.classify(&commodity.id).unwrap();
-                non_zero_holdings.push(assets::Asset::new(
-                    account.name.to_owned(),
-                    symbol,
-                    value,
-                    asset_class.to_owned(),
-                    Some(account.current_quantity()),
-                    Some(last_price.value),
-                    Some(last_price.time),
-                ));
+                match asset_classifications.classify(&commodity.id) {
+                    Ok(asset_class) => non_zero_holdings.push(assets::Asset::new(
+                        account.name.to_owned(),
+                        symbol,
+                        value,
+                        asset_class.to_owned(),
+                        Some(account.current_quantity()),
+                        Some(last_price.value),
+                        Some(last_price.time),
+                    )),
+                    Err(e) => unclassified.push(e),
+                }
             } else {
                 panic!("Account lacks a commodity! This should not happen");
             }
         }
-        non_zero_holdings
+        (non_zero_holdings, unclassified)
     }
 
     pub fn portfolio_status(
@@ -841,33 +1351,105 @@ impl Book {
         asset_classifications: assets::AssetClassifications,
         ideal_allocations: Vec<AssetAllocation>,
     ) -> Portfolio {
-        let mut by_asset_class: HashMap<assets::AssetClass, AssetAllocation> = HashMap::new();
-        for allocation in ideal_allocations.into_iter() {
-            by_asset_class.insert(allocation.asset_class.clone(), allocation);
+        self.try_portfolio_status(&asset_classifications, ideal_allocations)
+            .unwrap_or_else(|e| panic!("{:}", e))
+    }
+
+    /// Value & classify every holding (whether or not a strategy targets its asset class)
+    ///
+    /// Fails on the first holding which lacks a classification.
+    pub fn try_classified_holdings(
+        &self,
+        asset_classifications: &assets::AssetClassifications,
+    ) -> Result<Vec<assets::Asset>, assets::UnclassifiedAssetError> {
+        let (holdings, mut unclassified) = self.classified_holdings(asset_classifications);
+        if !unclassified.is_empty() {
+            return Err(unclassified.swap_remove(0));
         }
+        Ok(holdings)
+    }
 
-        for asset in self.holdings(asset_classifications) {
-            // We ignore asset types not included in allocation
-            if let Some(allocation) = by_asset_class.get_mut(&asset.asset_class) {
-                allocation.add_asset(asset);
-            }
+    /// Build the portfolio, failing on the first holding which lacks a classification
+    pub fn try_portfolio_status(
+        &self,
+        asset_classifications: &assets::AssetClassifications,
+        ideal_allocations: Vec<AssetAllocation>,
+    ) -> Result<Portfolio, assets::UnclassifiedAssetError> {
+        let holdings = self.try_classified_holdings(asset_classifications)?;
+        Ok(Portfolio::from_assets(ideal_allocations, holdings))
+    }
+
+    /// Summarize the book's holdings, counting (rather than failing on) unclassified assets
+    pub fn portfolio_health(
+        &self,
+        asset_classifications: &assets::AssetClassifications,
+        ideal_allocations: Vec<AssetAllocation>,
+    ) -> PortfolioHealth {
+        let (holdings, unclassified) = self.classified_holdings(asset_classifications);
+        Portfolio::from_assets(ideal_allocations, holdings).health(unclassified.len())
+    }
+
+    /// Look up the USD currency commodity, creating it if missing (and we're allowed to)
+    ///
+    /// A fresh book may lack USD entirely, yet every price must be quoted in some currency.
+    /// Creating it is opt-in, since it's a write that users may not expect.
+    pub fn usd_currency(
+        conn: &Connection,
+        create_if_missing: bool,
+    ) -> rusqlite::Result<Option<Commodity>> {
+        let existing = conn
+            .query_row(
+                "SELECT guid, mnemonic, namespace, fullname
+                   FROM commodities
+                  WHERE namespace = 'CURRENCY'
+                    AND mnemonic = 'USD'",
+                NO_PARAMS,
+                |row| {
+                    Ok(Commodity::new(
+                        Some(row.get(0)?),
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                    ))
+                },
+            )
+            .optional()?;
+        if existing.is_some() || !create_if_missing {
+            return Ok(existing);
         }
-        Portfolio::new(by_asset_class.into_iter().map(|(_, v)| v).collect())
+
+        // Match the row GnuCash itself writes for USD
+        let usd = Commodity::new(
+            Some(new_uuid()),
+            String::from("USD"),
+            Some(String::from("CURRENCY")),
+            Some(String::from("US Dollar")),
+        );
+        conn.execute(
+            "INSERT INTO commodities (
+                   guid, namespace, mnemonic, fullname, cusip,
+                   fraction, quote_flag, quote_source, quote_tz
+                 )
+                 VALUES (?1, 'CURRENCY', 'USD', 'US Dollar', '840', 100, 1, 'currency', '')",
+            params![usd.guid],
+        )?;
+        Ok(Some(usd))
     }
 
-    fn alphavantage_commodities(conn: &Connection) -> rusqlite::Result<Vec<Commodity>> {
+    /// Funds flagged for quotes from the given source (e.g. 'alphavantage')
+    fn quotable_commodities(conn: &Connection, source: &str) -> rusqlite::Result<Vec<Commodity>> {
         let mut stmt = conn
             .prepare(
                 "SELECT guid, mnemonic, namespace, fullname
                    FROM commodities
                   WHERE namespace = 'FUND'
                     AND quote_flag
-                    AND quote_source = 'alphavantage'
+                    AND quote_source = ?1
                   ",
             )
             .expect("Invalid SQL");
 
-        let commodities = stmt.query_map(NO_PARAMS, |row| {
+        let commodities = stmt.query_map([source], |row| {
             Ok(Commodity::new(
                 Some(row.get(0)?),
                 row.get(1)?,
@@ -879,46 +1461,52 @@ impl Book {
         Ok(commodities.map(|ret| ret.unwrap()).collect())
     }
 
-    fn commodities_needing_quotes(&self, conn: &Connection) -> Vec<Commodity> {
-        let now = Local::now();
+    fn commodities_needing_quotes(&self, conn: &Connection, conf: &Config) -> Vec<Commodity> {
+        self.commodities_due_for_quotes(
+            Book::quotable_commodities(conn, conf.quote_provider().quote_source()).unwrap(),
+            Local::now(),
+            &conf.quote_cadence,
+            &conf.trading_calendar,
+        )
+    }
 
+    /// Identify which commodities are due for a new quote (the least recently priced first)
+    ///
+    /// Commodities are updated daily, unless given a different cadence (keyed by symbol).
+    fn commodities_due_for_quotes(
+        &self,
+        commodities: Vec<Commodity>,
+        now: DateTime<Local>,
+        cadences: &HashMap<String, quote::UpdateCadence>,
+        calendar: &TradingCalendar,
+    ) -> Vec<Commodity> {
         struct PriceAndCommodity<'a> {
             price: Option<&'a Price>,
             commodity: Commodity,
         }
 
-        let mut commodities_and_prices: Vec<PriceAndCommodity> =
-            Book::alphavantage_commodities(conn)
-                .unwrap()
-                .into_iter()
-                .map(|commodity| PriceAndCommodity {
-                    price: self.pricedb.last_commodity_price(&commodity),
-                    commodity,
-                })
-                .filter(|cap| {
-                    match cap.price {
-                        Some(price) => {
-                            let days = (now - price.time).num_days().abs();
-                            // println!("Days without quote for {:}: {:}", cap.commodity.id, days);
-                            match now.weekday() {
-                                // (If it's currently the weekend, last Friday's fetch will do)
-                                chrono::Weekday::Sat => days > 1,
-                                chrono::Weekday::Sun => days > 2,
-                                // On weekdays, settle for yesterday's quotes.
-                                // (AlphaVantage's free API isn't always the most current)
-                                _ => days > 1,
-                            }
-                        }
-                        // If no price was found, we definitely need a new quote.
-                        None => true,
+        let mut commodities_and_prices: Vec<PriceAndCommodity> = commodities
+            .into_iter()
+            .map(|commodity| PriceAndCommodity {
+                price: self.pricedb.last_commodity_price(&commodity),
+                commodity,
+            })
+            .filter(|cap| {
+                match cap.price {
+                    Some(price) => {
+                        let cadence = cadences.get(&cap.commodity.id).copied().unwrap_or_default();
+                        cadence.is_due(price.time, now, calendar)
                     }
-                })
-                .collect();
+                    // If no price was found, we definitely need a new quote.
+                    None => true,
+                }
+            })
+            .collect();
 
         // Commodities with the oldest date will come first
         commodities_and_prices.sort_by_key(|cap| match cap.price {
             Some(price) => price.time.date_naive(),
-            // Because we can't currently handle them, put commodities missing prices last
+            // Put commodities missing prices last
             None => now.date_naive(),
         });
         commodities_and_prices
@@ -927,106 +1515,345 @@ impl Book {
             .collect()
     }
 
-    // TODO: Run these requests in parallel.
-    fn update_price_if_needed(
+    /// Fetch the latest quote for every commodity that's due for one (several at a time)
+    ///
+    /// Commodities whose quote fails to fetch are skipped.
+    /// Progress is reported to `out`, since this can be slow.
+    fn fetch_quotes(
         &self,
         conn: &Connection,
-        commodity: &Commodity,
-    ) -> Result<Option<Price>, quote::FinanceQuoteError> {
-        let last_price = self.pricedb.last_commodity_price(commodity);
-
+        conf: &Config,
+        out: &mut dyn Write,
+    ) -> Vec<(Commodity, quote::Quote)> {
+        let commodities = self.commodities_needing_quotes(conn, conf);
         // Output what's happening, since this can be slow.
-        print!("Fetching latest price for {:}", commodity.id);
-        if let Some(price) = last_price {
-            print!(": {:}", price.value);
-        }
-        std::io::stdout().flush().ok();
-
-        let last_quote = match quote::FinanceQuote::fetch_quote(commodity) {
-            Ok(quote) => {
-                println!(
-                    " --> {:} ({:})",
-                    quote.last,
-                    quote.time.date_naive().format("%Y-%m-%d")
-                );
-                quote
-            }
-            Err(e) => {
-                println!("  ERROR!");
-                return Err(e);
+        if !commodities.is_empty() {
+            let _ = writeln!(
+                out,
+                "Fetching latest prices for {:} commodities",
+                commodities.len()
+            );
+        }
+        let fetched = quote::fetch_quotes(
+            conf.quote_provider().as_ref(),
+            &commodities,
+            conf.user_timezone(),
+            conf.quote_time(),
+            conf.quote_concurrency(),
+        );
+
+        // Quotes are reported (and written) one at a time, in order
+        let mut quotes = Vec::new();
+        for (commodity, fetched_quote) in commodities.into_iter().zip(fetched) {
+            let _ = write!(out, "Latest price for {:}", commodity.id);
+            if let Some(price) = self.pricedb.last_commodity_price(&commodity) {
+                let _ = write!(out, ": {:}", price.value);
             }
-        };
 
-        let updated_price: Option<Price> = match last_price {
-            Some(price) => {
-                if price.should_update_with_quote(&last_quote) {
-                    self.pricedb
-                        .write_price_from_quote(conn, &last_quote, &price)
-                        .ok()
-                } else {
-                    None
+            let last_quote = match fetched_quote {
+                Ok(quote) => {
+                    let _ = writeln!(
+                        out,
+                        " --> {:} ({:})",
+                        quote.last,
+                        quote.time.date_naive().format("%Y-%m-%d")
+                    );
+                    quote
+                }
+                Err(e) => {
+                    let _ = writeln!(out, "  ERROR!");
+                    let _ = writeln!(out, "{:}; skipping it", e);
+                    continue;
+                }
+            };
+
+            if last_quote.is_stale(Local::now(), quote::MAX_QUOTE_AGE_DAYS) {
+                let _ = writeln!(
+                    out,
+                    "   Warning: latest quote for {:} is from {:}",
+                    commodity.id,
+                    last_quote.time.date_naive().format("%Y-%m-%d")
+                );
+                if conf.gnucash.skip_stale_quotes {
+                    continue;
                 }
             }
-            // TODO: When there's no known last price, we should be able to get the `to_commodity`
-            // (which is just USD) and write the first price to the database.
-            // However, since we lack the commodity UUID, we can't write.
-            // For now, the best workaround for new commodities is to fetch once in Gnucash.
-            None => {
-                println!("Currently not able to write first price on new commodities");
-                None
-            }
-        };
+            quotes.push((commodity, last_quote));
+        }
+        quotes
+    }
 
-        Ok(updated_price)
+    /// Identify which quotes have information not yet recorded in the price database
+    fn price_updates(
+        &self,
+        quotes: Vec<(Commodity, quote::Quote)>,
+        out: &mut dyn Write,
+    ) -> Vec<PriceUpdate> {
+        quotes
+            .into_iter()
+            .filter_map(|(commodity, quote)| {
+                let last_price = self.pricedb.last_commodity_price(&commodity);
+                match last_price {
+                    // Writing the quote as-is would record it in the wrong currency
+                    Some(price) if price.to_commodity.id != quote.currency => {
+                        let _ = writeln!(
+                            out,
+                            "Skipping quote for {:}: quoted in {:}, but priced in {:}",
+                            commodity.id, quote.currency, price.to_commodity.id
+                        );
+                        None
+                    }
+                    Some(price) if !price.should_update_with_quote(&quote) => None,
+                    _ => Some(PriceUpdate {
+                        old_value: last_price.map(|price| price.value),
+                        commodity,
+                        quote,
+                    }),
+                }
+            })
+            .collect()
     }
-    fn update_commodities(
+
+    /// Fetch quotes, returning the prices that would change (without writing anything)
+    ///
+    /// Progress is reported to `out` (e.g. stderr, to keep stdout free for a JSON report).
+    pub fn preview_price_updates(
         &self,
         conn: &Connection,
-    ) -> Result<Vec<Price>, quote::FinanceQuoteError> {
+        conf: &Config,
+        out: &mut dyn Write,
+    ) -> Vec<PriceUpdate> {
+        let quotes = self.fetch_quotes(conn, conf, out);
+        self.price_updates(quotes, out)
+    }
+
+    /// Write each update as a new price, returning the prices written
+    ///
+    /// Updates which fail, are skipped, or only replace an existing price aren't returned.
+    ///
+    /// Commodities without any price yet get a first price in USD
+    /// (created only if `create_missing_currency` allows it).
+    fn write_price_updates(
+        &mut self,
+        conn: &Connection,
+        updates: &[PriceUpdate],
+        create_missing_currency: bool,
+        out: &mut dyn Write,
+    ) -> rusqlite::Result<Vec<Price>> {
+        let any_unpriced = updates.iter().any(|update| {
+            self.pricedb
+                .last_commodity_price(&update.commodity)
+                .is_none()
+        });
+        let usd = if any_unpriced {
+            Book::usd_currency(conn, create_missing_currency)?
+        } else {
+            None
+        };
+
         let mut new_prices = Vec::new();
-        for commodity in self.commodities_needing_quotes(conn).iter() {
-            if let Some(price) = self.update_price_if_needed(conn, &commodity)? {
-                new_prices.push(price);
+        let mut replaced_any = false;
+        for update in updates {
+            let written = match (self.pricedb.last_commodity_price(&update.commodity), &usd) {
+                (Some(price), _) => self
+                    .pricedb
+                    .write_price_from_quote(conn, &update.quote, price),
+                (None, Some(usd)) if update.quote.currency == usd.id => {
+                    let first_price = Price::first_from_quote(
+                        update.commodity.clone(),
+                        usd.clone(),
+                        &update.quote,
+                    );
+                    self.pricedb
+                        .write_price_from_quote(conn, &update.quote, &first_price)
+                }
+                (None, Some(_)) => {
+                    let _ = writeln!(
+                        out,
+                        "Skipping first price for {:}: quoted in {:}, not USD",
+                        update.commodity.id, update.quote.currency
+                    );
+                    continue;
+                }
+                (None, None) => {
+                    let _ = writeln!(
+                        out,
+                        "Cannot write a first price for {:}: the book has no USD currency (set create_missing_currency to add it)",
+                        update.commodity.id
+                    );
+                    continue;
+                }
+            };
+            match written {
+                Ok(Some(new_price)) => new_prices.push(new_price),
+                Ok(None) => replaced_any = true,
+                Err(e) => {
+                    let _ = writeln!(
+                        out,
+                        "Could not write price for {:}: {:}",
+                        update.commodity.id, e
+                    );
+                }
             }
         }
+
+        if replaced_any || !new_prices.is_empty() {
+            // Currently, must re-populate from database to get the most current prices!
+            // TODO: `write_price_from_quote()` should update the PriceDatabase in-place
+            self.pricedb.populate_from_sqlite(conn)?;
+        }
         Ok(new_prices)
     }
 
-    fn get_accounts(conn: &Connection, namespace: &str) -> Vec<Account> {
-        let mut stmt = conn
-            .prepare(
-                "SELECT a.guid, a.name,
-                        -- Commodity for the account
-                        c.guid, c.mnemonic, c.namespace, c.fullname
-                   FROM accounts a
-                        JOIN commodities c ON a.commodity_guid = c.guid
-                  WHERE c.namespace = $1
-                  ",
-            )
-            .expect("Invalid SQL");
+    /// Fetch the latest quotes, writing new prices to the book if `confirm` approves them.
+    ///
+    /// Returns the new prices which were written (none, if declined).
+    /// Progress is reported to `out`. Only supported for SQLite books.
+    pub fn update_prices<F>(
+        &mut self,
+        conf: &Config,
+        out: &mut dyn Write,
+        confirm: F,
+    ) -> rusqlite::Result<Vec<Price>>
+    where
+        F: FnOnce(&[PriceUpdate]) -> bool,
+    {
+        let conn = Connection::open(&conf.gnucash.path_to_book)?;
+        let updates = self.preview_price_updates(&conn, conf, out);
+        if updates.is_empty() || !confirm(&updates) {
+            return Ok(Vec::new());
+        }
+        self.write_price_updates(&conn, &updates, conf.gnucash.create_missing_currency, out)
+    }
+
+    fn get_accounts(conn: &Connection, namespace: &str) -> rusqlite::Result<Vec<Account>> {
+        let mut stmt = conn.prepare(
+            "SELECT a.guid, a.name,
+                    -- Commodity for the account
+                    c.guid, c.mnemonic, c.namespace, c.fullname
+               FROM accounts a
+                    JOIN commodities c ON a.commodity_guid = c.guid
+              WHERE c.namespace = $1
+              ",
+        )?;
 
-        stmt.query_map([namespace], |row| {
+        let accounts = stmt.query_map([namespace], |row| {
             let account_guid = row.get(0)?;
             let account_name = row.get(1)?;
             let commodity =
                 Commodity::new(Some(row.get(2)?), row.get(3)?, row.get(4)?, row.get(5)?);
 
             Ok(Account::new(account_guid, account_name, Some(commodity)))
-        })
-        .unwrap()
-        .map(|ret| ret.unwrap())
-        .collect()
+        })?;
+        accounts.collect()
+    }
+
+    /// Read the splits for all given accounts in a single query
+    ///
+    /// (Querying once per account is needlessly slow on large books)
+    /// If given a date, only splits from transactions posted on or before that date are read.
+    /// If `reconciled_only`, splits not yet cleared or reconciled are skipped.
+    fn read_splits_from_sqlite(
+        conn: &Connection,
+        accounts: &mut [Account],
+        as_of: Option<NaiveDate>,
+        reconciled_only: bool,
+    ) -> rusqlite::Result<()> {
+        if accounts.is_empty() {
+            return Ok(());
+        }
+        let placeholders: Vec<String> = (1..=accounts.len()).map(|i| format!("?{:}", i)).collect();
+        let mut params: Vec<String> = accounts.iter().map(|a| a.guid.clone()).collect();
+
+        let mut join = "";
+        let mut conditions = vec![format!("account_guid IN ({:})", placeholders.join(", "))];
+        if let Some(date) = as_of {
+            params.push(date.format("%Y-%m-%d").to_string());
+            join = " JOIN transactions ON splits.tx_guid = transactions.guid";
+            conditions.push(format!(
+                "date(transactions.post_date) <= ?{:}",
+                params.len()
+            ));
+        }
+        if reconciled_only {
+            // GnuCash marks splits 'n' (new), 'c' (cleared), 'y' (reconciled), or 'v' (voided)
+            conditions.push(String::from("splits.reconcile_state IN ('c', 'y')"));
+        }
+        let query = format!(
+            "{:}{:} WHERE {:}",
+            SPLITS_QUERY,
+            join,
+            conditions.join(" AND ")
+        );
+        let mut stmt = conn.prepare(&query)?;
+
+        let mut splits_by_account: HashMap<String, Vec<Split>> = HashMap::new();
+        for split in stmt.query_map(params, computed_split_from_row)? {
+            let split = split?;
+            splits_by_account
+                .entry(split.account.clone())
+                .or_default()
+                .push(Split::Computed(split));
+        }
+
+        for account in accounts.iter_mut() {
+            account.splits = splits_by_account.remove(&account.guid).unwrap_or_default();
+        }
+        Ok(())
     }
 }
 
-impl GnucashFromSqlite for Book {
-    fn from_sqlite(conn: &Connection, conf: &Config) -> Book {
+impl Book {
+    fn from_sqlite(conn: &Connection) -> rusqlite::Result<Book> {
+        Book::from_sqlite_as_of(conn, None, false, &PriceSources::default())
+    }
+
+    /// Read the book, counting only transactions posted on or before `as_of` (if given)
+    ///
+    /// On very large books, skipping recent transactions can cut down on the splits to sum.
+    /// (Prices are unaffected: the latest known price is always used)
+    ///
+    /// If `reconciled_only`, splits which haven't been cleared or reconciled are skipped too,
+    /// so that holdings match those on broker statements.
+    fn from_sqlite_as_of(
+        conn: &Connection,
+        as_of: Option<NaiveDate>,
+        reconciled_only: bool,
+        price_sources: &PriceSources,
+    ) -> rusqlite::Result<Book> {
+        let mut pricedb = PriceDatabase::new();
+        pricedb.sources = price_sources.clone();
+        Book::from_sqlite_with_prices(conn, as_of, reconciled_only, pricedb)
+    }
+
+    /// Read the book as of the given day, valuing holdings at the prices known that day
+    fn from_sqlite_on(
+        conn: &Connection,
+        date: NaiveDate,
+        reconciled_only: bool,
+        price_sources: &PriceSources,
+        rates: &dyn ExchangeRateProvider,
+    ) -> Result<Book, BookError> {
+        let mut pricedb = PriceDatabase::new();
+        pricedb.sources = price_sources.clone();
+        pricedb.through = Some(date);
+        let mut book = Book::from_sqlite_with_prices(conn, Some(date), reconciled_only, pricedb)?;
+        book.convert_prices(rates)?;
+        Ok(book)
+    }
+
+    fn from_sqlite_with_prices(
+        conn: &Connection,
+        as_of: Option<NaiveDate>,
+        reconciled_only: bool,
+        pricedb: PriceDatabase,
+    ) -> rusqlite::Result<Book> {
         let mut book = Book::new();
+        book.pricedb = pricedb;
 
-        for mut account in Book::get_accounts(conn, "FUND") {
+        let mut accounts = Book::get_accounts(conn, "FUND")?;
+        for account in accounts.iter() {
             assert!(account.is_investment());
-            account.read_splits_from_sqlite(conn).unwrap();
-            book.add_investment(account);
         }
 
         // I Bonds are an interesting case -- they should count as bounds in any
@@ -1036,33 +1863,33 @@ impl GnucashFromSqlite for Book {
         // To get around all this, I make up ticker names for my I Bonds, then
         // just use the Price Editor to input the values from TreasuryDirect.gov
         // (every ~year or so, since interest rates are adjusted twice yearly).
-        for mut account in Book::get_accounts(conn, "Series I") {
-            account.read_splits_from_sqlite(conn).unwrap();
+        accounts.extend(Book::get_accounts(conn, "Series I")?);
+
+        Book::read_splits_from_sqlite(conn, &mut accounts, as_of, reconciled_only)?;
+        for account in accounts {
             book.add_investment(account);
         }
 
-        book.pricedb.populate_from_sqlite(conn).unwrap();
-        if conf.gnucash.update_prices {
-            match book.update_commodities(conn) {
-                Ok(updated_commodities) => {
-                    if !updated_commodities.is_empty() {
-                        // Currently, must re-populate from database to get the most current prices!
-                        // TODO: `write_price_from_quote()` should update the PriceDatabase in-place
-                        book.pricedb.populate_from_sqlite(conn).unwrap();
-                    }
-                }
-                Err(e) => println!(
-                    "Failed to fetch price for {:}, continuing without updating other prices",
-                    e.symbol
-                ),
-            };
-        }
-        book
+        book.pricedb.populate_from_sqlite(conn)?;
+        Ok(book)
     }
 }
 
 impl GnucashFromXML for Book {
-    fn from_xml(reader: &mut Reader<BufReader<File>>) -> Book {
+    fn from_xml(reader: &mut XmlReader) -> Book {
+        Book::from_xml_as_of(reader, None, false)
+    }
+}
+
+impl Book {
+    /// Read the book, counting only transactions posted on or before `as_of` (if given)
+    ///
+    /// If `reconciled_only`, splits which haven't been cleared or reconciled are skipped too.
+    fn from_xml_as_of(
+        reader: &mut XmlReader,
+        as_of: Option<NaiveDate>,
+        reconciled_only: bool,
+    ) -> Book {
         let mut book = Book::new();
 
         let mut buf = Vec::new();
@@ -1089,7 +1916,17 @@ impl GnucashFromXML for Book {
                         // By the time we've reached this section, we've parsed all accounts.
                         b"gnc:transaction" => {
                             let transaction = Transaction::from_xml(reader);
+                            if let Some(date) = as_of {
+                                if transaction.date_posted().date_naive() > date {
+                                    continue;
+                                }
+                            }
                             for split in transaction.splits.into_iter() {
+                                if reconciled_only
+                                    && matches!(split, Split::Lazy(ref lazy) if !lazy.reconciled)
+                                {
+                                    continue;
+                                }
                                 book.add_split(split);
                             }
                         }
@@ -1108,3 +1945,1102 @@ impl GnucashFromXML for Book {
         book
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::AssetClass;
+    use chrono::NaiveTime;
+
+    /// Create an in-memory database with (a subset of) the tables GnuCash uses
+    fn empty_book() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE commodities(
+                guid text(32) PRIMARY KEY NOT NULL,
+                namespace text(2048) NOT NULL,
+                mnemonic text(2048) NOT NULL,
+                fullname text(2048),
+                cusip text(2048),
+                fraction integer NOT NULL,
+                quote_flag integer NOT NULL,
+                quote_source text(2048),
+                quote_tz text(2048)
+            );
+            CREATE TABLE prices(
+                guid text(32) PRIMARY KEY NOT NULL,
+                commodity_guid text(32) NOT NULL,
+                currency_guid text(32) NOT NULL,
+                date text(19) NOT NULL,
+                source text(2048),
+                type text(2048),
+                value_num bigint NOT NULL,
+                value_denom bigint NOT NULL
+            );",
+        )
+        .unwrap();
+        conn
+    }
+
+    /// Add investment accounts (one per fund), each with many small purchases
+    ///
+    /// Only the tables & columns read by `Book::from_sqlite` are populated.
+    fn add_investment_accounts(conn: &Connection, num_accounts: usize, splits_per_account: usize) {
+        conn.execute_batch(
+            "CREATE TABLE accounts(
+                guid text(32) PRIMARY KEY NOT NULL,
+                name text(2048) NOT NULL,
+                account_type text(2048) NOT NULL,
+                commodity_guid text(32),
+                commodity_scu integer NOT NULL,
+                non_std_scu integer NOT NULL,
+                parent_guid text(32),
+                code text(2048),
+                description text(2048),
+                hidden integer,
+                placeholder integer
+            );
+            CREATE TABLE splits(
+                guid text(32) PRIMARY KEY NOT NULL,
+                tx_guid text(32) NOT NULL,
+                account_guid text(32) NOT NULL,
+                memo text(2048) NOT NULL,
+                action text(2048) NOT NULL,
+                reconcile_state text(1) NOT NULL,
+                reconcile_date text(19),
+                value_num bigint NOT NULL,
+                value_denom bigint NOT NULL,
+                quantity_num bigint NOT NULL,
+                quantity_denom bigint NOT NULL,
+                lot_guid text(32)
+            );
+            CREATE INDEX splits_account_guid_index ON splits(account_guid);
+            BEGIN;",
+        )
+        .unwrap();
+
+        for i in 0..num_accounts {
+            let commodity = fund(&format!("FUND{:}", i));
+            let account_guid = new_uuid();
+            conn.execute(
+                "INSERT INTO commodities
+                     VALUES (?1, 'FUND', ?2, ?2, '', 10000, 1, 'alphavantage', '')",
+                params![commodity.guid, commodity.id],
+            )
+            .unwrap();
+            conn.execute(
+                "INSERT INTO accounts
+                     VALUES (?1, ?2, 'MUTUAL', ?3, 10000, 0, NULL, '', '', 0, 0)",
+                params![account_guid, commodity.id, commodity.guid],
+            )
+            .unwrap();
+            for _ in 0..splits_per_account {
+                // Each purchase is $25.00 for 1.5 shares
+                conn.execute(
+                    "INSERT INTO splits
+                         VALUES (?1, ?2, ?3, '', '', 'n', NULL, 2500, 100, 15000, 10000, NULL)",
+                    params![new_uuid(), new_uuid(), account_guid],
+                )
+                .unwrap();
+            }
+        }
+        conn.execute_batch("COMMIT;").unwrap();
+    }
+
+    #[test]
+    #[ignore = "timing-dependent; run with --ignored when changing how books load"]
+    fn test_loading_large_book_is_fast() {
+        let conn = empty_book();
+        add_investment_accounts(&conn, 40, 250);
+
+        let start = std::time::Instant::now();
+        let book = Book::from_sqlite(&conn).unwrap();
+        let elapsed = start.elapsed();
+
+        assert_eq!(book.account_by_guid.len(), 40);
+        for account in book.account_by_guid.values() {
+            assert_eq!(account.current_quantity(), Decimal::from(375));
+        }
+        // Generous enough for slow CI machines & debug builds, but not for O(n^2) behavior
+        assert!(
+            elapsed < std::time::Duration::from_secs(2),
+            "Loading 10,000 splits took {:?}",
+            elapsed
+        );
+    }
+
+    #[test]
+    fn test_fractions_overflowing_i64() {
+        let conn = Connection::open_in_memory().unwrap();
+        // Untyped columns keep oversized integers as text (rather than lossy floats)
+        conn.execute_batch(
+            "CREATE TABLE splits(account_guid, value_num, value_denom, quantity_num, quantity_denom);
+             INSERT INTO splits
+                  VALUES ('big', '250000000000000000000', '10000000000000000000',
+                          '15000000000000000000000', '10000000000000000000000');
+             INSERT INTO splits VALUES ('small', 2500, 100, 15000, 10000);",
+        )
+        .unwrap();
+        assert!(conn
+            .query_row(
+                "SELECT value_num FROM splits WHERE account_guid = 'big'",
+                NO_PARAMS,
+                |row| row.get::<_, i64>(0)
+            )
+            .is_err());
+
+        let mut stmt = conn.prepare(SPLITS_QUERY).unwrap();
+        let splits: Vec<ComputedSplit> = stmt
+            .query_map(NO_PARAMS, computed_split_from_row)
+            .unwrap()
+            .map(|split| split.unwrap())
+            .collect();
+        for split in splits {
+            assert_eq!(split.value, Decimal::from(25), "{:}", split.account);
+            assert_eq!(split.quantity, Decimal::new(15, 1), "{:}", split.account);
+        }
+    }
+
+    #[test]
+    fn test_batched_splits_match_per_account_splits() {
+        let conn = empty_book();
+        add_investment_accounts(&conn, 5, 20);
+        // One account has no splits at all
+        conn.execute(
+            "INSERT INTO accounts
+                 VALUES ('emptyaccount', 'Empty', 'MUTUAL', ?1, 10000, 0, NULL, '', '', 0, 0)",
+            params![fund("FUND0").guid],
+        )
+        .unwrap();
+
+        let mut batched = Book::get_accounts(&conn, "FUND").unwrap();
+        assert_eq!(batched.len(), 6);
+        Book::read_splits_from_sqlite(&conn, &mut batched, None, false).unwrap();
+
+        for account in batched.iter() {
+            let mut single = Account::new(account.guid.clone(), account.name.clone(), None);
+            single.read_splits_from_sqlite(&conn).unwrap();
+            assert_eq!(account.splits.len(), single.splits.len());
+            assert_eq!(account.current_quantity(), single.current_quantity());
+        }
+    }
+
+    #[test]
+    fn test_balance_as_of_ignores_later_splits() {
+        let conn = empty_book();
+        add_investment_accounts(&conn, 1, 0);
+        conn.execute_batch(
+            "CREATE TABLE transactions(
+                guid text(32) PRIMARY KEY NOT NULL,
+                currency_guid text(32) NOT NULL,
+                num text(2048) NOT NULL,
+                post_date text(19),
+                enter_date text(19),
+                description text(2048)
+            );",
+        )
+        .unwrap();
+        let account_guid: String = conn
+            .query_row("SELECT guid FROM accounts", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        for (tx_guid, post_date, shares) in &[
+            ("december", "2023-12-29 10:59:00", 10),
+            ("newyearseve", "2023-12-31 10:59:00", 5),
+            ("january", "2024-01-02 10:59:00", 2),
+        ] {
+            conn.execute(
+                "INSERT INTO transactions VALUES (?1, ?2, '', ?3, ?3, '')",
+                params![tx_guid, usd().guid, post_date],
+            )
+            .unwrap();
+            conn.execute(
+                "INSERT INTO splits
+                     VALUES (?1, ?2, ?3, '', '', 'n', NULL, 100, 1, ?4, 1, NULL)",
+                params![new_uuid(), tx_guid, account_guid, shares],
+            )
+            .unwrap();
+        }
+
+        let quantity = |book: &Book| book.account_by_guid[&account_guid].current_quantity();
+        let as_of = NaiveDate::from_ymd_opt(2023, 12, 31).unwrap();
+        assert_eq!(
+            quantity(
+                &Book::from_sqlite_as_of(&conn, Some(as_of), false, &PriceSources::default())
+                    .unwrap()
+            ),
+            Decimal::from(15)
+        );
+        assert_eq!(
+            quantity(
+                &Book::from_sqlite_as_of(&conn, None, false, &PriceSources::default()).unwrap()
+            ),
+            Decimal::from(17)
+        );
+    }
+
+    #[test]
+    fn test_reconciled_only_skips_uncleared_splits() {
+        let conn = empty_book();
+        add_investment_accounts(&conn, 1, 0);
+        let account_guid: String = conn
+            .query_row("SELECT guid FROM accounts", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        for (reconcile_state, shares) in &[("y", 10), ("n", 3)] {
+            conn.execute(
+                "INSERT INTO splits
+                     VALUES (?1, 'tx', ?2, '', '', ?3, NULL, 100, 1, ?4, 1, NULL)",
+                params![new_uuid(), account_guid, reconcile_state, shares],
+            )
+            .unwrap();
+        }
+
+        let quantity = |reconciled_only: bool| {
+            let book =
+                Book::from_sqlite_as_of(&conn, None, reconciled_only, &PriceSources::default())
+                    .unwrap();
+            book.account_by_guid[&account_guid].current_quantity()
+        };
+        assert_eq!(quantity(false), Decimal::from(13));
+        assert_eq!(quantity(true), Decimal::from(10));
+    }
+
+    fn fund(symbol: &str) -> Commodity {
+        Commodity::new(
+            Some(format!("{:0>32}", symbol.to_lowercase())),
+            String::from(symbol),
+            Some(String::from("FUND")),
+            None,
+        )
+    }
+
+    fn usd() -> Commodity {
+        Commodity::new(
+            Some(String::from("094c20b1856d400ba0b04ef086baa814")),
+            String::from("USD"),
+            Some(String::from("CURRENCY")),
+            Some(String::from("US Dollar")),
+        )
+    }
+
+    fn quote(symbol: &str, ymd: &str, last: Decimal) -> quote::Quote {
+        quote::Quote {
+            symbol: String::from(symbol),
+            time: dateutil::localize_at_noon(ymd).unwrap(),
+            last,
+            currency: String::from("USD"),
+        }
+    }
+
+    fn price(symbol: &str, ymd: &str, value: Decimal) -> Price {
+        Price::new(
+            fund(symbol),
+            usd(),
+            value,
+            dateutil::localize_at_noon(ymd).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_sub_cent_prices_are_lossy() {
+        let book = Book::with_holdings(
+            vec![],
+            vec![
+                price("VTSAX", "2023-12-27", Decimal::new(10_012, 2)),
+                price("VSMAX", "2023-12-26", Decimal::new(99_999, 3)),
+                price("VSMAX", "2023-12-27", Decimal::new(10_000, 2)),
+                price("VBTLX", "2023-12-27", Decimal::new(83_912, 4)),
+            ],
+        );
+        let lossy = book.lossy_prices();
+        // Only the latest price matters (VSMAX was once priced at a fraction of a cent)
+        assert_eq!(
+            lossy,
+            vec![LossyPrice {
+                symbol: String::from("VBTLX"),
+                value: Decimal::new(83_912, 4),
+            }]
+        );
+        assert_eq!(
+            lossy[0].to_string(),
+            "VBTLX: 8.3912 (would be written as 8.39)"
+        );
+    }
+
+    #[test]
+    fn test_should_update_when_quote_is_newer() {
+        let last = price("VTSAX", "2023-12-27", Decimal::new(10000, 2));
+        // Even if the value is unchanged, a new trading day is worth recording
+        assert!(last.should_update_with_quote(&quote(
+            "VTSAX",
+            "2023-12-28",
+            Decimal::new(10000, 2)
+        )));
+        assert!(last.should_update_with_quote(&quote(
+            "VTSAX",
+            "2023-12-28",
+            Decimal::new(10125, 2)
+        )));
+    }
+
+    #[test]
+    fn test_should_update_when_value_changes() {
+        let last = price("VTSAX", "2023-12-28", Decimal::new(10000, 2));
+        assert!(last.should_update_with_quote(&quote(
+            "VTSAX",
+            "2023-12-28",
+            Decimal::new(10012, 2)
+        )));
+        // Even an older quote is recorded if its value differs (GnuCash picks which to use)
+        assert!(last.should_update_with_quote(&quote(
+            "VTSAX",
+            "2023-12-27",
+            Decimal::new(9950, 2)
+        )));
+    }
+
+    #[test]
+    fn test_should_not_update_with_known_price() {
+        let last = price("VTSAX", "2023-12-28", Decimal::new(10000, 2));
+        assert!(!last.should_update_with_quote(&quote(
+            "VTSAX",
+            "2023-12-28",
+            Decimal::new(10000, 2)
+        )));
+        assert!(!last.should_update_with_quote(&quote(
+            "VTSAX",
+            "2023-12-27",
+            Decimal::new(10000, 2)
+        )));
+    }
+
+    #[test]
+    fn test_holdings_without_a_database() {
+        let book = Book::with_holdings(
+            vec![
+                Account::holding("Roth IRA", fund("VTSAX"), Decimal::new(15, 1)),
+                Account::holding("Old 401k", fund("VBTLX"), 0.into()),
+            ],
+            vec![
+                price("VTSAX", "2023-12-27", Decimal::new(9900, 2)),
+                price("VTSAX", "2023-12-28", Decimal::new(10000, 2)),
+                price("VBTLX", "2023-12-28", Decimal::new(1050, 2)),
+            ],
+        );
+        let mut classifications = assets::AssetClassifications::new();
+        classifications.add(String::from("VTSAX"), assets::AssetClass::USTotal);
+        classifications.add(String::from("VBTLX"), assets::AssetClass::USBonds);
+
+        // Empty accounts are ignored; others are valued at their latest price
+        let (holdings, unclassified) = book.classified_holdings(&classifications);
+        assert!(unclassified.is_empty());
+        assert_eq!(holdings.len(), 1);
+        assert_eq!(holdings[0].name, "Roth IRA");
+        assert_eq!(holdings[0].value, Decimal::from(150));
+    }
+
+    fn count_prices(conn: &Connection) -> i64 {
+        conn.query_row("SELECT count(*) FROM prices", NO_PARAMS, |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_usd_currency_is_found() {
+        let conn = empty_book();
+        conn.execute(
+            "INSERT INTO commodities
+                 VALUES (?1, 'CURRENCY', 'USD', 'US Dollar', '840', 100, 1, 'currency', '')",
+            params![usd().guid],
+        )
+        .unwrap();
+        assert_eq!(Book::usd_currency(&conn, false).unwrap(), Some(usd()));
+    }
+
+    #[test]
+    fn test_usd_currency_is_not_created_unless_configured() {
+        let conn = empty_book();
+        assert_eq!(Book::usd_currency(&conn, false).unwrap(), None);
+        let num_commodities: i64 = conn
+            .query_row("SELECT count(*) FROM commodities", NO_PARAMS, |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(num_commodities, 0);
+    }
+
+    #[test]
+    fn test_missing_usd_currency_is_created() {
+        let conn = empty_book();
+        let usd = Book::usd_currency(&conn, true).unwrap().unwrap();
+        assert_eq!(usd.id, "USD");
+        assert_eq!(usd.space, Some(String::from("CURRENCY")));
+
+        // Subsequent lookups find the same row, rather than creating another
+        assert_eq!(Book::usd_currency(&conn, true).unwrap(), Some(usd.clone()));
+
+        // A price can now be written against the new currency
+        let first_price = Price {
+            from_commodity: fund("VTSAX"),
+            to_commodity: usd.clone(),
+            value: 0.into(),
+            time: dateutil::localize_at_noon("2023-12-27").unwrap(),
+        };
+        PriceDatabase::new()
+            .write_price_from_quote(
+                &conn,
+                &quote("VTSAX", "2023-12-28", Decimal::new(10125, 2)),
+                &first_price,
+            )
+            .unwrap();
+        let currency_guid: String = conn
+            .query_row("SELECT currency_guid FROM prices", NO_PARAMS, |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(Some(currency_guid), usd.guid);
+    }
+
+    fn price_override(price: Decimal, ymd: &str) -> PriceOverride {
+        PriceOverride {
+            price,
+            as_of: NaiveDate::parse_from_str(ymd, "%Y-%m-%d").unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_newer_price_override_wins() {
+        let mut book = Book::new();
+        book.pricedb
+            .read_price(price("IBOND", "2024-01-09", Decimal::new(2500, 2)));
+
+        let mut overrides = HashMap::new();
+        overrides.insert(
+            String::from("IBOND"),
+            price_override(Decimal::new(2540, 2), "2024-01-10"),
+        );
+        book.apply_price_overrides(&overrides);
+
+        let last = book.pricedb.last_commodity_price(&fund("IBOND")).unwrap();
+        assert_eq!(last.value, Decimal::new(2540, 2));
+        assert_eq!(last.to_commodity, usd());
+    }
+
+    #[test]
+    fn test_fresher_stored_price_beats_override() {
+        let mut book = Book::new();
+        book.pricedb
+            .read_price(price("IBOND", "2024-01-10", Decimal::new(2560, 2)));
+
+        let mut overrides = HashMap::new();
+        overrides.insert(
+            String::from("IBOND"),
+            price_override(Decimal::new(2540, 2), "2024-01-01"),
+        );
+        book.apply_price_overrides(&overrides);
+
+        let last = book.pricedb.last_commodity_price(&fund("IBOND")).unwrap();
+        assert_eq!(last.value, Decimal::new(2560, 2));
+    }
+
+    #[test]
+    fn test_book_without_any_prices() {
+        let conn = empty_book();
+        add_investment_accounts(&conn, 2, 1);
+        let book = Book::from_sqlite(&conn).unwrap();
+
+        let error = book.check_prices(false).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No prices available; enable update_prices or seed prices"
+        );
+        assert!(book.check_prices(true).is_err());
+
+        // An entirely empty book has nothing to value
+        assert_eq!(Book::new().check_prices(false), Ok(()));
+    }
+
+    #[test]
+    fn test_writing_same_quote_twice_is_idempotent() {
+        let conn = empty_book();
+        let pricedb = PriceDatabase::new();
+        let old_price = price("VTSAX", "2023-12-27", Decimal::new(10000, 2));
+        let q = quote("VTSAX", "2023-12-28", Decimal::new(10125, 2));
+
+        assert!(pricedb
+            .write_price_from_quote(&conn, &q, &old_price)
+            .unwrap()
+            .is_some());
+        assert!(pricedb
+            .write_price_from_quote(&conn, &q, &old_price)
+            .unwrap()
+            .is_none());
+        assert_eq!(count_prices(&conn), 1);
+
+        // A corrected quote for the same day replaces the earlier value
+        let corrected = quote("VTSAX", "2023-12-28", Decimal::new(10150, 2));
+        pricedb
+            .write_price_from_quote(&conn, &corrected, &old_price)
+            .unwrap();
+        assert_eq!(count_prices(&conn), 1);
+        let value_num: i64 = conn
+            .query_row("SELECT value_num FROM prices", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(value_num, 10150);
+
+        // Likewise, a quote for the same day at a different time of day
+        let at_close = quote::Quote {
+            time: dateutil::localize_at_time_in(
+                "2023-12-28",
+                NaiveTime::from_hms_opt(16, 0, 0).unwrap(),
+                None,
+            )
+            .unwrap(),
+            ..quote("VTSAX", "2023-12-28", Decimal::new(10175, 2))
+        };
+        assert!(pricedb
+            .write_price_from_quote(&conn, &at_close, &old_price)
+            .unwrap()
+            .is_none());
+        assert_eq!(count_prices(&conn), 1);
+
+        // A quote for a later day is a new price
+        let next_day = quote("VTSAX", "2023-12-29", Decimal::new(10200, 2));
+        pricedb
+            .write_price_from_quote(&conn, &next_day, &old_price)
+            .unwrap();
+        assert_eq!(count_prices(&conn), 2);
+    }
+
+    #[test]
+    fn test_database_errors_are_returned() {
+        // No prices table to write to
+        let conn = Connection::open_in_memory().unwrap();
+        let old_price = price("VTSAX", "2023-12-27", Decimal::new(10000, 2));
+        let q = quote("VTSAX", "2023-12-28", Decimal::new(10125, 2));
+        assert!(matches!(
+            PriceDatabase::new().write_price_from_quote(&conn, &q, &old_price),
+            Err(PriceWriteError::Sqlite(_))
+        ));
+    }
+
+    #[test]
+    fn test_preview_lists_only_changed_prices() {
+        let mut book = Book::new();
+        book.pricedb
+            .read_price(price("VTSAX", "2023-12-28", Decimal::new(10000, 2)));
+        book.pricedb
+            .read_price(price("VBTLX", "2023-12-27", Decimal::new(1050, 2)));
+        book.pricedb
+            .read_price(price("VTIAX", "2023-12-28", Decimal::new(3000, 2)));
+
+        let updates = book.price_updates(
+            vec![
+                // Same day, same price: nothing new.
+                (
+                    fund("VTSAX"),
+                    quote("VTSAX", "2023-12-28", Decimal::new(10000, 2)),
+                ),
+                // Same price, but on a newer day.
+                (
+                    fund("VBTLX"),
+                    quote("VBTLX", "2023-12-28", Decimal::new(1050, 2)),
+                ),
+                // Same day, but with a corrected price.
+                (
+                    fund("VTIAX"),
+                    quote("VTIAX", "2023-12-28", Decimal::new(3012, 2)),
+                ),
+            ],
+            &mut std::io::sink(),
+        );
+        let described: Vec<String> = updates.iter().map(|u| format!("{}", u)).collect();
+        assert_eq!(
+            described,
+            vec![
+                "VBTLX: 10.50 -> 10.50 (2023-12-28)",
+                "VTIAX: 30.00 -> 30.12 (2023-12-28)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_preview_skips_quotes_in_another_currency() {
+        let mut book = Book::new();
+        book.pricedb
+            .read_price(price("VUSA", "2023-12-27", Decimal::new(8000, 2)));
+
+        let mut in_pounds = quote("VUSA", "2023-12-28", Decimal::new(6500, 2));
+        in_pounds.currency = String::from("GBP");
+        let mut out = Vec::new();
+        assert!(book
+            .price_updates(vec![(fund("VUSA"), in_pounds)], &mut out)
+            .is_empty());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Skipping quote for VUSA: quoted in GBP, but priced in USD\n"
+        );
+    }
+
+    #[test]
+    fn test_preview_includes_commodities_without_prices() {
+        let book = Book::new();
+        let updates = book.price_updates(
+            vec![(
+                fund("VSMAX"),
+                quote("VSMAX", "2023-12-28", Decimal::new(10460, 2)),
+            )],
+            &mut std::io::sink(),
+        );
+        assert_eq!(updates.len(), 1);
+        assert_eq!(
+            format!("{}", updates[0]),
+            "VSMAX: (none) -> 104.60 (2023-12-28)"
+        );
+    }
+
+    #[test]
+    fn test_first_price_written_for_new_commodity() {
+        let conn = empty_book();
+        let vsmax = fund("VSMAX");
+        conn.execute(
+            "INSERT INTO commodities VALUES (?1, 'FUND', 'VSMAX', '', '', 10000, 1, 'alphavantage', '')",
+            params![vsmax.guid],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO commodities
+                 VALUES (?1, 'CURRENCY', 'USD', 'US Dollar', '840', 100, 1, 'currency', '')",
+            params![usd().guid],
+        )
+        .unwrap();
+
+        let mut book = Book::new();
+        let updates = book.price_updates(
+            vec![(
+                vsmax.clone(),
+                quote("VSMAX", "2023-12-28", Decimal::new(10460, 2)),
+            )],
+            &mut std::io::sink(),
+        );
+        let written = book
+            .write_price_updates(&conn, &updates, false, &mut std::io::sink())
+            .unwrap();
+        assert_eq!(written.len(), 1);
+        assert_eq!(written[0].to_commodity, usd());
+        assert_eq!(count_prices(&conn), 1);
+
+        let price = book.pricedb.last_commodity_price(&vsmax).unwrap();
+        assert_eq!(price.value, Decimal::new(10460, 2));
+        assert_eq!(price.to_commodity.guid, usd().guid);
+
+        // Retrying the same updates replaces the price, rather than writing a new one
+        assert!(book
+            .write_price_updates(&conn, &updates, false, &mut std::io::sink())
+            .unwrap()
+            .is_empty());
+        assert_eq!(count_prices(&conn), 1);
+    }
+
+    #[test]
+    fn test_commodities_quoted_by_source() {
+        let conn = empty_book();
+        for (symbol, source) in [("VTSAX", "alphavantage"), ("VWIAX", "vanguard")] {
+            conn.execute(
+                "INSERT INTO commodities VALUES (?1, 'FUND', ?2, '', '', 10000, 1, ?3, '')",
+                params![fund(symbol).guid, symbol, source],
+            )
+            .unwrap();
+        }
+        let symbols = |source| -> Vec<String> {
+            Book::quotable_commodities(&conn, source)
+                .unwrap()
+                .into_iter()
+                .map(|commodity| commodity.id)
+                .collect()
+        };
+        assert_eq!(symbols("alphavantage"), vec!["VTSAX"]);
+        assert_eq!(symbols("vanguard"), vec!["VWIAX"]);
+        assert!(symbols("yahoo_json").is_empty());
+    }
+
+    #[test]
+    fn test_first_price_requires_usd() {
+        let conn = empty_book();
+        let mut book = Book::new();
+        let updates = book.price_updates(
+            vec![(
+                fund("VSMAX"),
+                quote("VSMAX", "2023-12-28", Decimal::new(10460, 2)),
+            )],
+            &mut std::io::sink(),
+        );
+        assert!(book
+            .write_price_updates(&conn, &updates, false, &mut std::io::sink())
+            .unwrap()
+            .is_empty());
+        assert_eq!(count_prices(&conn), 0);
+
+        // Once allowed to create USD, the first price can be written
+        assert_eq!(
+            book.write_price_updates(&conn, &updates, true, &mut std::io::sink())
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(count_prices(&conn), 1);
+    }
+
+    #[test]
+    fn test_failure_to_find_usd_is_an_error() {
+        // No tables at all, so USD can't even be looked up
+        let conn = Connection::open_in_memory().unwrap();
+        let mut book = Book::new();
+        let updates = book.price_updates(
+            vec![(
+                fund("VSMAX"),
+                quote("VSMAX", "2023-12-28", Decimal::new(10460, 2)),
+            )],
+            &mut std::io::sink(),
+        );
+        assert!(book
+            .write_price_updates(&conn, &updates, true, &mut std::io::sink())
+            .is_err());
+    }
+
+    #[test]
+    fn test_commodities_due_by_cadence() {
+        let mut book = Book::new();
+        book.pricedb
+            .read_price(price("VTSAX", "2024-01-08", Decimal::new(10000, 2)));
+        book.pricedb
+            .read_price(price("VMFXX", "2024-01-05", Decimal::from(1)));
+        book.pricedb
+            .read_price(price("VBTLX", "2024-01-02", Decimal::new(1050, 2)));
+
+        let mut cadences = HashMap::new();
+        cadences.insert(String::from("VMFXX"), quote::UpdateCadence::Weekly);
+        cadences.insert(String::from("VBTLX"), quote::UpdateCadence::Weekly);
+
+        let wednesday = dateutil::localize_at_noon("2024-01-10").unwrap();
+        let due: Vec<String> = book
+            .commodities_due_for_quotes(
+                vec![fund("VTSAX"), fund("VMFXX"), fund("VBTLX"), fund("VSMAX")],
+                wednesday,
+                &cadences,
+                &TradingCalendar::default(),
+            )
+            .into_iter()
+            .map(|commodity| commodity.id)
+            .collect();
+        // The weekly fund priced last Friday isn't due, but the one from last Tuesday is.
+        // The fund without any price comes last.
+        assert_eq!(due, vec!["VBTLX", "VTSAX", "VSMAX"]);
+    }
+
+    /// A book with a hand-entered price & a fetched price for VTSAX on the same day
+    fn book_with_manual_and_fetched_prices() -> Connection {
+        let conn = empty_book();
+        let vtsax = fund("VTSAX");
+        let usd = usd();
+        conn.execute(
+            "INSERT INTO commodities VALUES (?1, 'FUND', 'VTSAX', '', '', 10000, 1, '', '')",
+            params![vtsax.guid],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO commodities VALUES (?1, 'CURRENCY', 'USD', '', '', 100, 0, '', '')",
+            params![usd.guid],
+        )
+        .unwrap();
+        let prices = [
+            ("2024-01-10 15:00:00", "user:price", "unknown", 10050),
+            ("2024-01-10 21:00:00", "Finance::Quote", "last", 10125),
+            ("2024-01-09 21:00:00", "Finance::Quote", "nav", 10000),
+        ];
+        for (date, source, price_type, cents) in prices.iter() {
+            conn.execute(
+                "INSERT INTO prices VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, 100)",
+                params![
+                    new_uuid(),
+                    vtsax.guid,
+                    usd.guid,
+                    date,
+                    source,
+                    price_type,
+                    cents
+                ],
+            )
+            .unwrap();
+        }
+        conn
+    }
+
+    fn last_vtsax_price(conn: &Connection, sources: PriceSources) -> Decimal {
+        let mut pricedb = PriceDatabase::new();
+        pricedb.sources = sources;
+        pricedb.populate_from_sqlite(conn).unwrap();
+        pricedb.last_commodity_price(&fund("VTSAX")).unwrap().value
+    }
+
+    #[test]
+    fn test_prices_through_a_past_day() {
+        let conn = book_with_manual_and_fetched_prices();
+        let mut pricedb = PriceDatabase::new();
+        pricedb.through = Some(NaiveDate::from_ymd_opt(2024, 1, 9).unwrap());
+        pricedb.populate_from_sqlite(&conn).unwrap();
+        assert_eq!(
+            pricedb.last_commodity_price(&fund("VTSAX")).unwrap().value,
+            Decimal::from(100)
+        );
+    }
+
+    #[test]
+    fn test_latest_price_wins_by_default() {
+        let conn = book_with_manual_and_fetched_prices();
+        assert_eq!(
+            last_vtsax_price(&conn, PriceSources::default()),
+            Decimal::new(10125, 2)
+        );
+    }
+
+    #[test]
+    fn test_preferred_source_wins_on_same_day() {
+        let conn = book_with_manual_and_fetched_prices();
+        let sources = PriceSources {
+            prefer: Some(String::from("user:price")),
+            exclude_types: vec![],
+        };
+        assert_eq!(last_vtsax_price(&conn, sources), Decimal::new(10050, 2));
+    }
+
+    #[test]
+    fn test_excluded_price_types_are_ignored() {
+        let conn = book_with_manual_and_fetched_prices();
+        let sources = PriceSources {
+            prefer: None,
+            exclude_types: vec![String::from("last"), String::from("unknown")],
+        };
+        // Only the (older) NAV remains
+        assert_eq!(last_vtsax_price(&conn, sources), Decimal::new(10000, 2));
+    }
+
+    #[test]
+    fn test_unreadable_books_are_errors() {
+        let book_error = |path: &str, file_format: &str| {
+            let mut conf = Config::default();
+            conf.gnucash.path_to_book = String::from(path);
+            conf.gnucash.file_format = String::from(file_format);
+            Book::from_config(&conf).err().unwrap()
+        };
+        assert!(matches!(
+            book_error("example/missing.gnucash", "xml"),
+            BookError::Io(_)
+        ));
+        assert!(matches!(
+            book_error("example/missing.csv", "csv"),
+            BookError::Csv(_)
+        ));
+        assert!(matches!(
+            book_error("README.md", "sqlite3"),
+            BookError::Sqlite(_)
+        ));
+        assert_eq!(
+            book_error("example/sqlite3.gnucash", "ledger").to_string(),
+            "Unsupported file format 'ledger' (expected sqlite3, xml, or csv)"
+        );
+    }
+
+    #[test]
+    fn test_portfolio_from_holdings_csv() {
+        let data = "\
+account,symbol,quantity,price
+Roth IRA,VTSAX,10,100.50
+Roth IRA,VTIAX,20,30
+401(k),VBTLX,40,10.25
+";
+        let book = Book::from_csv_reader(csv::Reader::from_reader(data.as_bytes())).unwrap();
+        assert_eq!(book.check_prices(false), Ok(()));
+
+        let classifications: assets::AssetClassifications = vec![
+            (String::from("VTSAX"), AssetClass::USTotal),
+            (String::from("VTIAX"), AssetClass::IntlStocks),
+            (String::from("VBTLX"), AssetClass::USBonds),
+        ]
+        .into_iter()
+        .collect();
+        let portfolio = book.portfolio_status(
+            classifications,
+            vec![
+                AssetAllocation::new(AssetClass::USTotal, Decimal::new(50, 2)),
+                AssetAllocation::new(AssetClass::IntlStocks, Decimal::new(30, 2)),
+                AssetAllocation::new(AssetClass::USBonds, Decimal::new(20, 2)),
+            ],
+        );
+        assert_eq!(portfolio.current_value(), Decimal::new(2015, 0));
+        let by_class: HashMap<AssetClass, Decimal> = portfolio
+            .allocations()
+            .iter()
+            .map(|allocation| (allocation.asset_class.clone(), allocation.current_value()))
+            .collect();
+        assert_eq!(by_class[&AssetClass::USTotal], Decimal::new(1005, 0));
+        assert_eq!(by_class[&AssetClass::IntlStocks], Decimal::new(600, 0));
+        assert_eq!(by_class[&AssetClass::USBonds], Decimal::new(410, 0));
+    }
+
+    #[test]
+    fn test_gzipped_xml_book() {
+        let path = std::env::temp_dir().join(format!("gzipped_{:}.gnucash", std::process::id()));
+        let mut encoder = flate2::write::GzEncoder::new(
+            File::create(&path).unwrap(),
+            flate2::Compression::default(),
+        );
+        encoder
+            .write_all(&std::fs::read("example/xml.gnucash").unwrap())
+            .unwrap();
+        encoder.finish().unwrap();
+
+        let plain = Book::from_xml_file("example/xml.gnucash").unwrap();
+        let gzipped = Book::from_xml_file(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(!plain.account_by_guid.is_empty());
+        assert_eq!(gzipped.account_by_guid.len(), plain.account_by_guid.len());
+        for (guid, account) in &plain.account_by_guid {
+            assert_eq!(
+                gzipped.account_by_guid[guid].current_quantity(),
+                account.current_quantity()
+            );
+        }
+    }
+
+    #[test]
+    fn test_xml_reconciled_only() {
+        let read = |xml: &str, reconciled_only: bool| {
+            let source: Box<dyn Read> = Box::new(std::io::Cursor::new(xml.to_string()));
+            let mut reader = Reader::from_reader(BufReader::new(source));
+            Book::from_xml_as_of(&mut reader, None, reconciled_only)
+        };
+        let xml = std::fs::read_to_string("example/xml.gnucash").unwrap();
+        let plain = read(&xml, false);
+        assert!(plain
+            .account_by_guid
+            .values()
+            .any(|account| account.current_quantity() != 0.into()));
+
+        // Every split in the example book is new (neither cleared nor reconciled)
+        let unreconciled = read(&xml, true);
+        assert!(unreconciled
+            .account_by_guid
+            .values()
+            .all(|account| account.current_quantity() == 0.into()));
+
+        let cleared = xml.replace("<split:reconciled-state>n<", "<split:reconciled-state>c<");
+        let reconciled = read(&cleared, true);
+        for (guid, account) in &plain.account_by_guid {
+            assert_eq!(
+                reconciled.account_by_guid[guid].current_quantity(),
+                account.current_quantity()
+            );
+        }
+    }
+
+    #[test]
+    fn test_foreign_prices_are_converted() {
+        let gbp = Commodity::new(
+            Some(String::from("gbp00000000000000000000000000000")),
+            String::from("GBP"),
+            Some(String::from("CURRENCY")),
+            Some(String::from("British Pound")),
+        );
+        let mut book = Book::with_holdings(
+            vec![
+                Account::holding("ISA", fund("VWRL"), Decimal::from(10)),
+                Account::holding("Roth IRA", fund("VTSAX"), Decimal::from(2)),
+            ],
+            vec![
+                Price::new(
+                    fund("VWRL"),
+                    gbp,
+                    Decimal::from(80),
+                    dateutil::localize_at_noon("2023-12-28").unwrap(),
+                ),
+                price("VTSAX", "2023-12-28", Decimal::from(100)),
+            ],
+        );
+        let mut classifications = assets::AssetClassifications::new();
+        classifications.add(String::from("VWRL"), assets::AssetClass::IntlStocks);
+        classifications.add(String::from("VTSAX"), assets::AssetClass::USTotal);
+
+        let mut rates = HashMap::new();
+        assert_eq!(
+            book.convert_prices(&rates).unwrap_err().currency,
+            String::from("GBP")
+        );
+        rates.insert(String::from("GBP"), Decimal::new(125, 2));
+        book.convert_prices(&rates).unwrap();
+
+        let (mut holdings, _) = book.classified_holdings(&classifications);
+        holdings.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(holdings[0].name, "ISA");
+        assert_eq!(holdings[0].value, Decimal::from(1000));
+        assert_eq!(holdings[1].name, "Roth IRA");
+        assert_eq!(holdings[1].value, Decimal::from(200));
+    }
+
+    #[test]
+    fn test_foreign_prices_are_converted_in_the_past() {
+        let conn = empty_book();
+        add_investment_accounts(&conn, 1, 0);
+        conn.execute_batch(
+            "CREATE TABLE transactions(
+                guid text(32) PRIMARY KEY NOT NULL,
+                currency_guid text(32) NOT NULL,
+                num text(2048) NOT NULL,
+                post_date text(19),
+                enter_date text(19),
+                description text(2048)
+            );
+            INSERT INTO commodities
+                 VALUES ('gbp00000000000000000000000000000', 'CURRENCY', 'GBP', 'British Pound',
+                         '826', 100, 1, 'currency', '');",
+        )
+        .unwrap();
+        let account_guid: String = conn
+            .query_row("SELECT guid FROM accounts", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        conn.execute(
+            "INSERT INTO transactions VALUES ('buy', ?1, '', '2023-06-01 10:59:00', '', '')",
+            params!["gbp00000000000000000000000000000"],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO splits VALUES ('s1', 'buy', ?1, '', '', 'n', NULL, 800, 1, 10, 1, NULL)",
+            params![account_guid],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO prices
+                 VALUES (?1, ?2, 'gbp00000000000000000000000000000', '2023-12-28 21:00:00',
+                         'Finance::Quote', 'last', 8000, 100)",
+            params![new_uuid(), fund("FUND0").guid],
+        )
+        .unwrap();
+        let mut classifications = assets::AssetClassifications::new();
+        classifications.add(String::from("FUND0"), assets::AssetClass::IntlStocks);
+        let end_of_year = NaiveDate::from_ymd_opt(2023, 12, 31).unwrap();
+
+        let mut rates = HashMap::new();
+        let missing =
+            Book::from_sqlite_on(&conn, end_of_year, false, &PriceSources::default(), &rates);
+        match missing {
+            Err(BookError::ExchangeRate(e)) => assert_eq!(e.currency, "GBP"),
+            _ => panic!("Expected a missing exchange rate"),
+        }
+
+        rates.insert(String::from("GBP"), Decimal::new(125, 2));
+        let book =
+            Book::from_sqlite_on(&conn, end_of_year, false, &PriceSources::default(), &rates)
+                .unwrap();
+        let holdings = book.try_classified_holdings(&classifications).unwrap();
+        assert_eq!(holdings[0].value, Decimal::from(1000));
+    }
+}
@@ -0,0 +1,335 @@
+use rust_decimal::Decimal;
+use std::fmt;
+
+use crate::assets::AssetClass;
+use crate::decutil;
+use crate::rebalance::Portfolio;
+
+/// Any single holding above this share of the portfolio merits a warning
+pub const CONCENTRATION_THRESHOLD: Decimal = Decimal::from_parts(50, 0, 0, false, 2);
+
+/// How far one asset class has strayed from its target
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct ClassDrift {
+    pub asset_class: AssetClass,
+    pub current_value: Decimal,
+    pub current_ratio: Decimal,
+    pub target_ratio: Decimal,
+    /// Percentage points above (positive) or below (negative) the target
+    pub drift: Decimal,
+}
+
+/// A machine-readable summary of the portfolio's overall state
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct PortfolioHealth {
+    pub total_value: Decimal,
+    pub stock_ratio: Decimal,
+    pub bond_ratio: Decimal,
+    pub drift: Vec<ClassDrift>,
+    pub stale_prices: usize,
+    pub unclassified: usize,
+    pub concentration_warnings: Vec<String>,
+}
+
+/// Something about the portfolio that merits the user's attention
+#[derive(Debug, PartialEq, Eq)]
+pub enum Warning {
+    /// Uninvested cash exceeds the threshold, dragging down expected returns
+    CashDrag {
+        cash_ratio: Decimal,
+        threshold: Decimal,
+        /// Investing this much would bring cash down to the threshold
+        investable: Decimal,
+    },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Warning::CashDrag {
+                cash_ratio,
+                threshold,
+                investable,
+            } => write!(
+                f,
+                "Cash is {:.2}% of the portfolio (above {:.2}%); consider investing {:}",
+                cash_ratio * Decimal::from(100),
+                threshold * Decimal::from(100),
+                decutil::format_dollars(investable),
+            ),
+        }
+    }
+}
+
+fn is_bond(asset_class: &AssetClass) -> bool {
+    matches!(asset_class, AssetClass::USBonds | AssetClass::IntlBonds)
+}
+
+fn is_stock(asset_class: &AssetClass) -> bool {
+    !is_bond(asset_class) && !matches!(asset_class, AssetClass::Target | AssetClass::Cash)
+}
+
+fn ratio(value: Decimal, total: Decimal) -> Decimal {
+    if total == 0.into() {
+        0.into()
+    } else {
+        value / total
+    }
+}
+
+impl Portfolio {
+    /// Summarize the portfolio in a single pass over its holdings.
+    ///
+    /// Unclassified holdings never make it into a portfolio, so the caller supplies their count.
+    pub fn health(&self, unclassified: usize) -> PortfolioHealth {
+        let total_value = self.current_value();
+
+        let mut stock_value = Decimal::from(0);
+        let mut bond_value = Decimal::from(0);
+        let mut stale_prices = 0;
+        let mut concentration_warnings = Vec::new();
+        let mut drift = Vec::new();
+
+        for allocation in self.allocations() {
+            let current_value = allocation.current_value();
+            if is_bond(&allocation.asset_class) {
+                bond_value += current_value;
+            } else if is_stock(&allocation.asset_class) {
+                stock_value += current_value;
+            }
+
+            for asset in allocation.assets() {
+                if asset.price_is_dated() {
+                    stale_prices += 1;
+                }
+                let share = ratio(asset.value, total_value);
+                if share > CONCENTRATION_THRESHOLD {
+                    concentration_warnings.push(format!(
+                        "{:} is {:.2}% of the portfolio",
+                        asset.symbol.as_ref().unwrap_or(&asset.name),
+                        share * Decimal::from(100),
+                    ));
+                }
+            }
+
+            let current_ratio = ratio(current_value, total_value);
+            drift.push(ClassDrift {
+                asset_class: allocation.asset_class.clone(),
+                current_value,
+                current_ratio,
+                target_ratio: allocation.target_ratio,
+                drift: current_ratio - allocation.target_ratio,
+            });
+        }
+
+        PortfolioHealth {
+            total_value,
+            stock_ratio: ratio(stock_value, total_value),
+            bond_ratio: ratio(bond_value, total_value),
+            drift,
+            stale_prices,
+            unclassified,
+            concentration_warnings,
+        }
+    }
+
+    /// The value-weighted average duration (in years) of bond funds with a known duration
+    ///
+    /// Bond funds without a duration are left out; if none have one, there's nothing to report.
+    pub fn bond_duration(&self) -> Option<Decimal> {
+        let mut weighted = Decimal::from(0);
+        let mut value = Decimal::from(0);
+        for allocation in self.allocations() {
+            if !is_bond(&allocation.asset_class) {
+                continue;
+            }
+            for asset in allocation.assets() {
+                if let Some(duration) = asset.duration {
+                    weighted += asset.value * duration;
+                    value += asset.value;
+                }
+            }
+        }
+        if value == 0.into() {
+            None
+        } else {
+            Some(weighted / value)
+        }
+    }
+
+    /// Warn if uninvested cash (the `Cash` asset class) exceeds `threshold` of the portfolio
+    pub fn cash_drag_warning(&self, threshold: Decimal) -> Option<Warning> {
+        let total_value = self.current_value();
+        let cash: Decimal = self
+            .allocations()
+            .iter()
+            .filter(|allocation| allocation.asset_class == AssetClass::Cash)
+            .map(|allocation| allocation.current_value())
+            .sum();
+
+        let cash_ratio = ratio(cash, total_value);
+        if cash_ratio <= threshold {
+            return None;
+        }
+        Some(Warning::CashDrag {
+            cash_ratio,
+            threshold,
+            investable: cash - threshold * total_value,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::Asset;
+    use crate::rebalance::{AssetAllocation, PortfolioBuilder};
+    use chrono::{Duration, Local};
+    use std::collections::HashMap;
+
+    fn fixture() -> Portfolio {
+        let mut us_stocks = AssetAllocation::new(AssetClass::USTotal, Decimal::new(60, 2));
+        let mut intl_stocks = AssetAllocation::new(AssetClass::IntlStocks, Decimal::new(30, 2));
+        let mut bonds = AssetAllocation::new(AssetClass::USBonds, Decimal::new(10, 2));
+        us_stocks.add_asset(Asset::new(
+            String::from("Vanguard Total Stock Market Index Fund Admiral Shares"),
+            Some(String::from("VTSAX")),
+            660.into(),
+            AssetClass::USTotal,
+            None,
+            None,
+            Some(Local::now()),
+        ));
+        intl_stocks.add_asset(Asset::new(
+            String::from("Vanguard Total International Stock Index Fund Admiral Shares"),
+            Some(String::from("VTIAX")),
+            200.into(),
+            AssetClass::IntlStocks,
+            None,
+            None,
+            Some(Local::now() - Duration::weeks(3)),
+        ));
+        bonds.add_asset(Asset::new(
+            String::from("Vanguard Total Bond Market Index Fund Admiral Shares"),
+            Some(String::from("VBTLX")),
+            140.into(),
+            AssetClass::USBonds,
+            None,
+            None,
+            None,
+        ));
+        Portfolio::new(vec![us_stocks, intl_stocks, bonds])
+    }
+
+    fn fifteen_percent_cash() -> Portfolio {
+        let mut stocks = AssetAllocation::new(AssetClass::USTotal, Decimal::new(90, 2));
+        let mut cash = AssetAllocation::new(AssetClass::Cash, Decimal::new(10, 2));
+        stocks.add_asset(Asset::new(
+            String::from("Vanguard Total Stock Market Index Fund Admiral Shares"),
+            Some(String::from("VTSAX")),
+            850.into(),
+            AssetClass::USTotal,
+            None,
+            None,
+            None,
+        ));
+        cash.add_asset(Asset::new(
+            String::from("Vanguard Federal Money Market Fund"),
+            Some(String::from("VMFXX")),
+            150.into(),
+            AssetClass::Cash,
+            None,
+            None,
+            None,
+        ));
+        Portfolio::new(vec![stocks, cash])
+    }
+
+    #[test]
+    fn test_cash_drag_warning() {
+        let warning = fifteen_percent_cash().cash_drag_warning(Decimal::new(5, 2));
+        assert_eq!(
+            warning,
+            Some(Warning::CashDrag {
+                cash_ratio: Decimal::new(15, 2),
+                threshold: Decimal::new(5, 2),
+                investable: Decimal::from(100),
+            })
+        );
+        assert_eq!(
+            warning.unwrap().to_string(),
+            "Cash is 15.00% of the portfolio (above 5.00%); consider investing $100"
+        );
+    }
+
+    #[test]
+    fn test_no_cash_drag_below_threshold() {
+        let portfolio = fifteen_percent_cash();
+        assert_eq!(portfolio.cash_drag_warning(Decimal::new(20, 2)), None);
+        assert_eq!(fixture().cash_drag_warning(Decimal::new(5, 2)), None);
+    }
+
+    #[test]
+    fn test_empty_portfolio() {
+        let health = Portfolio::new(vec![]).health(0);
+        assert_eq!(health.total_value, 0.into());
+        assert_eq!(health.stock_ratio, 0.into());
+        assert_eq!(health.bond_ratio, 0.into());
+        assert!(health.concentration_warnings.is_empty());
+    }
+
+    #[test]
+    fn test_serialize_health() {
+        let health = fixture().health(2);
+        let json = serde_json::to_value(&health).unwrap();
+
+        assert_eq!(json["total_value"], "1000");
+        assert_eq!(json["stock_ratio"], "0.86");
+        assert_eq!(json["bond_ratio"], "0.14");
+        assert_eq!(json["stale_prices"], 1);
+        assert_eq!(json["unclassified"], 2);
+        assert_eq!(
+            json["concentration_warnings"],
+            serde_json::json!(["VTSAX is 66.00% of the portfolio"])
+        );
+
+        let drift = json["drift"].as_array().unwrap();
+        assert_eq!(drift.len(), 3);
+        assert_eq!(drift[0]["asset_class"], "USTotal");
+        assert_eq!(drift[0]["current_value"], "660");
+        assert_eq!(drift[0]["current_ratio"], "0.66");
+        assert_eq!(drift[0]["target_ratio"], "0.60");
+        assert_eq!(drift[0]["drift"], "0.06");
+        assert_eq!(drift[2]["asset_class"], "USBonds");
+        assert_eq!(drift[2]["drift"], "0.04");
+    }
+
+    #[test]
+    fn test_bond_duration() {
+        let mut portfolio = fifteen_percent_cash();
+        assert_eq!(portfolio.bond_duration(), None);
+
+        portfolio = PortfolioBuilder::new()
+            .class(AssetClass::USTotal, Decimal::new(80, 2))
+            .holding("VTSAX", 800.into())
+            .class(AssetClass::USBonds, Decimal::new(14, 2))
+            .holding("VBTLX", 140.into())
+            .class(AssetClass::IntlBonds, Decimal::new(6, 2))
+            .holding("VTABX", 60.into())
+            .build()
+            .unwrap();
+        assert_eq!(portfolio.bond_duration(), None);
+
+        let durations: HashMap<String, Decimal> = vec![
+            (String::from("VBTLX"), Decimal::new(60, 1)),
+            (String::from("VTABX"), Decimal::new(70, 1)),
+            // Durations for stock funds are meaningless, and ignored
+            (String::from("VTSAX"), Decimal::from(100)),
+        ]
+        .into_iter()
+        .collect();
+        portfolio.set_durations(&durations);
+        // ($140 x 6 years + $60 x 7 years) / $200
+        assert_eq!(portfolio.bond_duration(), Some(Decimal::new(63, 1)));
+    }
+}
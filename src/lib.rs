@@ -0,0 +1,25 @@
+#[macro_use]
+extern crate serde_derive;
+
+pub mod allocation;
+pub mod annual;
+pub mod assets;
+pub mod calendar;
+pub mod cli;
+pub mod compare;
+pub mod compounding;
+pub mod config;
+pub mod currency;
+pub mod dateutil;
+pub mod decutil;
+pub mod exchange;
+pub mod export;
+pub mod failure;
+pub mod gnucash;
+pub mod health;
+pub mod projection;
+pub mod quote;
+pub mod rebalance;
+pub mod receipt;
+pub mod snapshot;
+pub mod stats;
@@ -1,115 +1,590 @@
-#[macro_use]
-extern crate serde_derive;
-
-use chrono::{Datelike, Local, NaiveDate};
+use chrono::Local;
 use rust_decimal::Decimal;
-use std::cmp;
-use std::io;
-
-mod allocation;
-mod assets;
-mod compounding;
-mod config;
-mod dateutil;
-mod decutil;
-mod gnucash;
-mod quote;
-mod rebalance;
-mod stats;
-
-use crate::config::Config;
-use crate::gnucash::Book;
-
-fn get_contribution() -> Decimal {
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::process;
+
+use stay_the_course::allocation::LazyPortfolio;
+use stay_the_course::annual::{self, AnnualReport};
+use stay_the_course::cli::{Args, ArgumentError, OutputFormat};
+use stay_the_course::config::Config;
+use stay_the_course::failure::Failure;
+use stay_the_course::gnucash::{Book, PriceUpdate};
+use stay_the_course::rebalance::{AssetAllocation, Portfolio};
+use stay_the_course::snapshot::Snapshot;
+use stay_the_course::{
+    allocation, assets, compare, compounding, currency, decutil, export, projection, rebalance,
+    receipt, stats,
+};
+
+/// Report the failure (in the requested format), then exit
+fn fail(format: OutputFormat, failure: Failure) -> ! {
+    eprintln!("{:}", failure.render(format));
+    process::exit(1);
+}
+
+fn get_contribution(exchange_rates: &HashMap<String, Decimal>, format: OutputFormat) -> Decimal {
     let mut contribution = String::new();
 
-    println!("How much to contribute or withdraw?");
+    let prompt = "How much to contribute or withdraw? (e.g. 1000, or 1000 GBP)";
+    // Keep stdout free for the JSON report
+    match format {
+        OutputFormat::Text => println!("{:}", prompt),
+        OutputFormat::Json => eprintln!("{:}", prompt),
+    }
     io::stdin()
         .read_line(&mut contribution)
         .expect("Failed to read line");
 
-    contribution.trim().parse().expect("Please type a number!")
+    let amount = currency::parse_amount(&contribution, exchange_rates)
+        .unwrap_or_else(|e| fail(format, Failure::new("invalid_amount", e)));
+    if amount.is_zero() {
+        fail(
+            format,
+            Failure::new("invalid_amount", "Nothing to contribute or withdraw"),
+        );
+    }
+    amount
 }
 
-fn summarize_retirement_prospects(birthday: NaiveDate, portfolio_total: Decimal, real_apy: f64) {
-    println!(
-        "Worth at retirement (Assuming {:.0}% growth):",
-        real_apy * 100.0
-    );
+/// Where to report progress: stdout, unless it's reserved for the JSON report
+fn progress(format: OutputFormat) -> Box<dyn Write> {
+    match format {
+        OutputFormat::Text => Box::new(io::stdout()),
+        OutputFormat::Json => Box::new(io::stderr()),
+    }
+}
 
-    fn summarize(day_of_retirement: NaiveDate, birthday: NaiveDate, future_total: Decimal) {
-        assert!(
-            day_of_retirement > birthday,
-            "Cannot retire before being born..."
-        );
-        // TODO: Correctly calculate age instead of this cheap approximation
-        let retirement_age = day_of_retirement.year() - birthday.year();
-        println!(
-            // Neatly displays net worth up to $25MM
-            // If your assets are that high, why are you running this jank?
-            " - {}: {: >11}  SWR: {: >9}",
-            retirement_age,
-            decutil::format_dollars(&future_total),
-            decutil::format_dollars(&compounding::safe_withdrawal_income(future_total))
-        );
+/// Show the prices that would be written, and ask whether to write them.
+fn confirm_price_updates(updates: &[PriceUpdate], assume_yes: bool, format: OutputFormat) -> bool {
+    let mut out = progress(format);
+    let _ = writeln!(out, "New prices:");
+    for update in updates {
+        let _ = writeln!(out, " - {:}", update);
+    }
+    if assume_yes {
+        return true;
     }
 
-    let today = Local::now().date_naive();
-    summarize(today, birthday, portfolio_total);
-
-    let approx_age = today.year() - birthday.year(); // Could be this age, or one year younger
-    let start_age = cmp::max(50, approx_age + 5);
-
-    let retirement_ages = (start_age)..=(start_age + 15);
-    for age in retirement_ages.step_by(5) {
-        let year = birthday.year() + age;
-        // Subtle bug here -- Feb 29th doesn't exist in some years.
-        // Ignore it for now.
-        let day_of_retirement =
-            NaiveDate::from_ymd_opt(year, birthday.month(), birthday.day()).unwrap();
-        let future_total = compounding::compound(portfolio_total, real_apy, day_of_retirement);
-        summarize(day_of_retirement, birthday, future_total);
-    }
-    println!();
+    let mut answer = String::new();
+    let _ = writeln!(out, "Write these prices to the book? [y/N]");
+    io::stdin()
+        .read_line(&mut answer)
+        .expect("Failed to read line");
+    answer.trim().eq_ignore_ascii_case("y")
 }
 
-fn main() {
-    let conf = Config::from_file("config.toml");
-    let book = Book::from_config(&conf);
-    println!("-----------------------------------------------------------------------");
+/// After-tax income & charitable giving, as recorded in a SQLite book
+fn summarize_giving(conf: &Config) -> rusqlite::Result<(Decimal, Decimal)> {
+    let sql_stats = stats::Stats::try_new(&conf.gnucash.path_to_book, conf.sqlite_open_flags())?;
+    let income = sql_stats.income_before_taxes(conf.gnucash.income_sign)?;
+    if income.is_sign_negative() && income != 0.into() {
+        eprintln!(
+            "Warning: income is negative (-{:}); is income_sign under [gnucash] set correctly?",
+            decutil::format_dollars(&income.abs())
+        );
+    }
+    Ok((
+        sql_stats.after_tax_income(conf.gnucash.income_sign)?,
+        sql_stats.charitable_giving()?,
+    ))
+}
 
-    // Identify our ideal allocations (percentages by asset class, summing to 100%)
-    let birthday = conf.user_birthday();
-    let bond_allocation = allocation::bond_allocation(birthday, 120);
-    let ideal_allocations = allocation::core_four(bond_allocation);
+/// How many of the funds that changed most in value to report
+const TOP_MOVERS: usize = 5;
+
+/// Report the funds whose value changed most since the last run, then save their values
+fn summarize_movers(path: &str, portfolio: &Portfolio) -> Result<(), Box<dyn Error>> {
+    let current = Snapshot::of(portfolio);
+    if let Some(previous) = Snapshot::load(path)? {
+        let movers: Vec<String> = current
+            .movers_since(&previous)
+            .iter()
+            .take(TOP_MOVERS)
+            .map(|mover| mover.to_string())
+            .collect();
+        if !movers.is_empty() {
+            println!("Largest changes since last run: {:}\n", movers.join(", "));
+        }
+    }
+    current.save(path)
+}
+
+/// Realized & unrealized gains on investments, as recorded in a SQLite book
+fn summarize_gains(conf: &Config, portfolio: &Portfolio) -> rusqlite::Result<(Decimal, Decimal)> {
+    let sql_stats = stats::Stats::try_new(&conf.gnucash.path_to_book, conf.sqlite_open_flags())?;
+    let cost_basis = sql_stats.cost_basis_by_symbol()?;
+    Ok((
+        sql_stats.realized_gains()?,
+        portfolio.unrealized_gains(&cost_basis),
+    ))
+}
 
-    let asset_classifications =
-        assets::AssetClassifications::from_csv("data/classified.csv").unwrap();
-    let portfolio = book.portfolio_status(asset_classifications, ideal_allocations);
+/// Compare the portfolio at the end of `year` with how it stood a year before
+fn annual_report(
+    conf: &Config,
+    year: i32,
+    classifications: &assets::AssetClassifications,
+    ideal_allocations: &[AssetAllocation],
+) -> Result<AnnualReport, Box<dyn Error>> {
+    let (start, end) = annual::year_bounds(year, Local::now().date_naive());
+    let rates = conf.exchange_rate_provider();
+    let portfolio_on = |date| -> Result<Portfolio, Box<dyn Error>> {
+        let book = Book::from_sqlite_file_on(
+            &conf.gnucash.path_to_book,
+            conf.sqlite_open_flags(),
+            date,
+            conf.gnucash.reconciled_only,
+            &conf.gnucash.price_sources,
+            rates.as_ref(),
+        )?;
+        let allocations = ideal_allocations
+            .iter()
+            .map(|allocation| {
+                AssetAllocation::new(allocation.asset_class.clone(), allocation.target_ratio)
+            })
+            .collect();
+        Ok(book.try_portfolio_status(classifications, allocations)?)
+    };
+    // Count contributions to the same funds as are valued (those in targeted asset classes)
+    let includes = |symbol: &str| {
+        classifications.classify(symbol).is_ok_and(|asset_class| {
+            ideal_allocations
+                .iter()
+                .any(|allocation| &allocation.asset_class == asset_class)
+        })
+    };
+    let sql_stats = stats::Stats::try_new(&conf.gnucash.path_to_book, conf.sqlite_open_flags())?;
+    Ok(AnnualReport::new(
+        year,
+        &portfolio_on(start)?,
+        &portfolio_on(end)?,
+        sql_stats.net_contributions(start, end, includes)?,
+    ))
+}
 
+/// Everything worth knowing about the portfolio (before deciding what to buy or sell)
+fn describe_portfolio(conf: &Config, args: &Args, portfolio: &Portfolio) {
     println!("{:}\n", portfolio);
 
-    summarize_retirement_prospects(birthday, portfolio.current_value(), 0.07);
+    if let Some(path) = &conf.display.snapshot_path {
+        if let Err(e) = summarize_movers(path, portfolio) {
+            eprintln!("Skipping changes since last run: {:}", e);
+        }
+    }
 
-    if conf.gnucash.file_format == "sqlite3" {
-        let sql_stats = stats::Stats::new(&conf.gnucash.path_to_book);
-        let after_tax = sql_stats.after_tax_income().unwrap();
-        let charity = sql_stats.charitable_giving().unwrap();
-        println!("After-tax income: {:}", decutil::format_dollars(&after_tax));
+    if let Some(threshold) = conf.strategy.overweight_dollar_threshold {
+        let overweights = portfolio.absolute_overweights(threshold);
+        if !overweights.is_empty() {
+            println!(
+                "Rebalancing priorities (over target by more than {:}):",
+                decutil::format_dollars(&threshold)
+            );
+            for (asset_class, excess) in overweights {
+                println!(
+                    " - {:}: {:} over",
+                    asset_class,
+                    decutil::format_dollars(&excess)
+                );
+            }
+            println!();
+        }
+    }
+    if let Some(duration) = portfolio.bond_duration() {
+        println!("Bond duration: {:.1} years\n", duration);
+    }
+    if let Some(threshold) = conf.strategy.cash_drag_threshold {
+        if let Some(warning) = portfolio.cash_drag_warning(threshold) {
+            println!("Warning: {:}\n", warning);
+        }
+    }
+
+    let upgrades = portfolio.share_class_upgrades(&conf.share_class_upgrades);
+    if !upgrades.is_empty() {
+        println!("Share class upgrades available:");
+        for upgrade in upgrades {
+            println!(" - {:}", upgrade);
+        }
+        println!();
+    }
+
+    if !(args.no_projection || conf.display.hide_projection) {
+        let options = projection::ProjectionOptions {
+            growth: conf.growth_model(),
+            contributions: conf.annual_contributions(),
+            ibonds: conf
+                .ibond_rate()
+                .map(|rate| projection::IBondSleeve {
+                    value: portfolio.ibond_value(),
+                    rate,
+                })
+                .filter(|sleeve| sleeve.value > Decimal::ZERO),
+            withdrawal_rate: conf.safe_withdrawal_rate(),
+            fixed_width: conf.display.retirement_column_width,
+            monthly_income: conf.display.monthly_income,
+            minimum_value: conf.display.projection_minimum,
+        };
+        projection::summarize_retirement_prospects(
+            conf.user_birthday(),
+            portfolio.current_value(),
+            &options,
+        );
+        let stock_ratio = portfolio.health(0).stock_ratio;
         println!(
-            "Charitable giving: {:} ({:.0}% of after-tax income)",
-            decutil::format_dollars(&charity),
-            (charity / after_tax) * Decimal::from(100)
+            "Suggested SWR for {:.0}% stocks: {:.2}% (projections assume a flat {:}%)\n",
+            stock_ratio * Decimal::from(100),
+            compounding::suggested_swr(stock_ratio) * Decimal::from(100),
+            (conf.safe_withdrawal_rate() * Decimal::from(100)).normalize(),
         );
     }
 
+    if conf.gnucash.file_format == "sqlite3" {
+        match summarize_giving(conf) {
+            Ok((after_tax, charity)) => {
+                println!("After-tax income: {:}", decutil::format_dollars(&after_tax));
+                println!(
+                    "Charitable giving: {:} ({:.0}% of after-tax income)",
+                    decutil::format_dollars(&charity),
+                    (charity / after_tax) * Decimal::from(100)
+                );
+            }
+            Err(e) => eprintln!("Skipping income statistics: {:}", e),
+        }
+        match summarize_gains(conf, portfolio) {
+            Ok((realized, unrealized)) => println!(
+                "Realized gains: {:}, unrealized gains: {:}",
+                decutil::format_dollars(&realized),
+                decutil::format_dollars(&unrealized)
+            ),
+            Err(e) => eprintln!("Skipping investment gains: {:}", e),
+        }
+    }
+
     println!(
         "Minimum to bring all assets to target: {:}",
         decutil::format_dollars(&portfolio.minimum_addition_to_balance())
     );
-    let contribution = get_contribution();
+}
+
+fn main() {
+    let args = Args::parse(env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{:}", e);
+        process::exit(2);
+    });
+
+    let conf = match Config::read_file("config.toml") {
+        Ok(Some(conf)) => conf,
+        Ok(None) => {
+            let defaults = Config::default();
+            eprintln!("{:}\n", defaults.describe_defaults());
+            defaults
+        }
+        Err(e) => fail(args.format, Failure::new("invalid_config", e)),
+    };
+    if let Some(birthday) = args.glide_path_birthday {
+        if birthday >= Local::now().date_naive() {
+            eprintln!(
+                "{:}",
+                ArgumentError::new(format!(
+                    "Invalid birthday for --glide-path: {:} (must be in the past)",
+                    birthday
+                ))
+            );
+            process::exit(2);
+        }
+        let source = match conf.allocation {
+            Some(_) => String::from("[allocation]"),
+            None => conf.strategy.name.to_string(),
+        };
+        println!(
+            "Target allocation ({:}) for somebody born {:}:",
+            source, birthday
+        );
+        for point in conf.glide_path(birthday, Local::now().date_naive()) {
+            println!(" - {:}", point);
+        }
+        return;
+    }
+    let mut book = Book::from_config(&conf).unwrap_or_else(|e| {
+        fail(
+            args.format,
+            Failure::new(
+                "book_unreadable",
+                format!("Could not read {:}: {:}", conf.gnucash.path_to_book, e),
+            )
+            .with_context("path", &conf.gnucash.path_to_book),
+        )
+    });
+    if args.check_price_precision {
+        let lossy = book.lossy_prices();
+        if lossy.is_empty() {
+            println!("All prices can be written in whole cents");
+            return;
+        }
+        println!("Prices which would lose precision if written in whole cents:");
+        for price in lossy {
+            println!(" - {:}", price);
+        }
+        process::exit(1);
+    }
+    if args.update_prices_only {
+        if conf.gnucash.file_format != "sqlite3" {
+            fail(
+                args.format,
+                Failure::new(
+                    "unsupported_book_format",
+                    "Updating prices is only supported for SQLite books",
+                )
+                .with_context("file_format", &conf.gnucash.file_format),
+            );
+        }
+        let written = book
+            .update_prices(&conf, &mut progress(args.format), |updates| {
+                confirm_price_updates(updates, args.assume_yes, args.format)
+            })
+            .unwrap_or_else(|e| fail(args.format, Failure::new("price_update_failed", e)));
+        let _ = writeln!(
+            progress(args.format),
+            "Wrote {:} new price(s)",
+            written.len()
+        );
+        return;
+    }
+    let fetch_quotes = conf.gnucash.update_prices && conf.gnucash.file_format == "sqlite3";
+    if fetch_quotes {
+        if let Err(e) = book.update_prices(&conf, &mut progress(args.format), |updates| {
+            confirm_price_updates(updates, args.assume_yes, args.format)
+        }) {
+            fail(args.format, Failure::new("price_update_failed", e));
+        }
+    }
+    if let Err(e) = book.check_prices(fetch_quotes) {
+        fail(args.format, Failure::new("no_prices", e));
+    }
+    if let Err(e) = book.convert_prices(conf.exchange_rate_provider().as_ref()) {
+        fail(
+            args.format,
+            Failure::new("missing_exchange_rate", &e).with_context("currency", &e.currency),
+        );
+    }
+    if args.format == OutputFormat::Text {
+        println!("-----------------------------------------------------------------------");
+    }
+
+    // Identify our ideal allocations (percentages by asset class, summing to 100%)
+    let birthday = conf.user_birthday();
+    let today = Local::now().date_naive();
+    let ideal_allocations = conf.target_allocations(birthday, today);
+
+    if let Some(path) = &args.allocation_toml_path {
+        match fs::write(path, allocation::to_toml(&ideal_allocations)) {
+            Ok(()) => println!("Wrote target allocation to {:}", path),
+            Err(e) => fail(
+                args.format,
+                Failure::new(
+                    "export_failed",
+                    format!("Could not write target allocation to {:}: {:}", path, e),
+                )
+                .with_context("path", path),
+            ),
+        }
+        return;
+    }
+
+    let classifications_path = "data/classified.csv";
+    let mut asset_classifications = assets::AssetClassifications::from_csv(classifications_path)
+        .unwrap_or_else(|e| {
+            fail(
+                args.format,
+                Failure::new("classifications_unreadable", e)
+                    .with_context("path", classifications_path),
+            )
+        });
+    if let Some(asset_class) = &conf.default_asset_class {
+        asset_classifications.set_default(asset_class.clone());
+    }
+
+    if let Some(year) = args.annual_report_year {
+        if conf.gnucash.file_format != "sqlite3" {
+            fail(
+                args.format,
+                Failure::new(
+                    "unsupported_book_format",
+                    "Annual reports are only supported for SQLite books",
+                )
+                .with_context("file_format", &conf.gnucash.file_format),
+            );
+        }
+        match annual_report(&conf, year, &asset_classifications, &ideal_allocations) {
+            Ok(report) => println!("{:}", report),
+            Err(e) => fail(args.format, Failure::new("annual_report", e)),
+        }
+        return;
+    }
+
+    // Every holding, including those in asset classes that the strategy doesn't target
+    let holdings = book
+        .try_classified_holdings(&asset_classifications)
+        .unwrap_or_else(|e| {
+            fail(
+                args.format,
+                Failure::new("unclassified_asset", &e).with_context("symbol", e.fund_name()),
+            )
+        });
+    // Warn before the strategy sets aside holdings it doesn't target (a default class may be one)
+    for asset in holdings.iter() {
+        let symbol = asset.symbol.as_deref().unwrap_or(&asset.name);
+        if let Some(warning) = asset_classifications.unclassified_warning(symbol) {
+            eprintln!("Warning: {:}", warning);
+        }
+    }
+    let mut portfolio = Portfolio::from_assets(ideal_allocations, holdings.clone());
+    portfolio.set_display_order(conf.display.asset_class_order.clone());
+    if let Some(share_precision) = conf.display.share_precision {
+        portfolio.set_share_precision(share_precision);
+    }
+    portfolio.annotate(&conf.notes);
+    portfolio.set_durations(&conf.durations);
+    portfolio.lock_classes(&conf.strategy.locked_classes);
+    if let Some(bands) = &conf.bands {
+        portfolio.set_bands(bands);
+    }
+
+    if let Some(path) = &args.aggregator_csv_path {
+        // Export everything in the book, not just what the strategy targets
+        match export::write_aggregator_csv(path, &holdings) {
+            Ok(()) => println!("Wrote holdings to {:}", path),
+            Err(e) => fail(
+                args.format,
+                Failure::new(
+                    "export_failed",
+                    format!("Could not write holdings to {:}: {:}", path, e),
+                )
+                .with_context("path", path),
+            ),
+        }
+        return;
+    }
+
+    if args.format == OutputFormat::Text {
+        describe_portfolio(&conf, &args, &portfolio);
+    }
+    let contribution = match (args.top_up_to, args.contribution) {
+        (Some(target_total), _) => portfolio
+            .contribution_to_reach(target_total)
+            .unwrap_or_else(|e| fail(args.format, Failure::new("target_total", e))),
+        (None, Some(contribution)) => contribution,
+        (None, None) => get_contribution(&conf.exchange_rates, args.format),
+    };
+
+    if let Err(e) = portfolio.check_withdrawal(contribution) {
+        fail(args.format, Failure::new("withdrawal", e));
+    }
+
+    if args.compare_strategies {
+        let comparisons = compare::compare_strategies(
+            &LazyPortfolio::all(),
+            |strategy| conf.strategy_allocations(strategy, birthday, today),
+            &holdings,
+            contribution,
+        );
+        println!("{:}", compare::render_table(&comparisons));
+        return;
+    }
 
     // From those ideal allocations, identify the best way to invest a lump sum
-    let balanced_portfolio = rebalance::optimally_allocate(portfolio, contribution);
-    balanced_portfolio.describe_future_contributions();
+    // (Config validation allows no more than one of these optimizers)
+    let balanced_portfolio = match (
+        conf.strategy.cash_sweep,
+        conf.strategy.max_trades,
+        &conf.strategy.linked_classes,
+    ) {
+        _ if conf.bands.is_some() => {
+            rebalance::optimally_allocate_within_bands(portfolio, contribution)
+        }
+        (Some(sweep_fraction), _, _) if contribution.is_sign_positive() => {
+            rebalance::optimally_allocate_with_cash(portfolio, contribution, sweep_fraction)
+        }
+        (_, Some(max_trades), _) => {
+            rebalance::optimally_allocate_max_trades(portfolio, contribution, max_trades)
+                .unwrap_or_else(|e| fail(args.format, Failure::new("withdrawal", e)))
+        }
+        (_, _, Some(link)) => rebalance::optimally_allocate_linked(portfolio, contribution, link),
+        _ => conf.strategy.objective.allocate(portfolio, contribution),
+    };
+    if let Some(min_fraction) = conf.strategy.withdrawal_floor {
+        if contribution.is_sign_negative() {
+            if let Err(e) = balanced_portfolio.check_withdrawal_floor(min_fraction) {
+                fail(
+                    args.format,
+                    Failure::new("withdrawal_floor", &e)
+                        .with_context("asset_class", &e.asset_class),
+                );
+            }
+        }
+    }
+    let mut plan = balanced_portfolio.contribution_plan();
+    if conf.display.reconcile_pennies {
+        plan.reconcile_pennies();
+    }
+    if args.format == OutputFormat::Json {
+        println!(
+            "{:}",
+            serde_json::to_string_pretty(&balanced_portfolio.report())
+                .expect("Reports can always be serialized")
+        );
+    } else {
+        plan.describe_with_status(
+            &conf.display.drift_thresholds,
+            conf.display.contribution_percentages,
+        );
+        if let Some(totals) = balanced_portfolio.describe_totals() {
+            println!("{:}", totals);
+        }
+        if let Some(band_status) = balanced_portfolio.rebalance_band_status() {
+            println!("{:}", band_status);
+        }
+        if !conf.share_increments.is_empty() && contribution.is_sign_positive() {
+            println!("\nBuy the following:");
+            let plan = balanced_portfolio.buy_instructions(&conf.share_increments);
+            for instruction in plan.instructions {
+                println!(" - {:}", instruction);
+            }
+            if plan.leftover > 0.into() {
+                println!(
+                    "Left over after buying whole increments: {:}",
+                    decutil::format_dollars_and_cents(&plan.leftover)
+                );
+            }
+        }
+    }
+
+    if args.format == OutputFormat::Text
+        && !conf.strategy.sweep_order.is_empty()
+        && contribution.is_sign_positive()
+    {
+        println!("\nContribute to each account:");
+        let plan = balanced_portfolio.sweep(&conf.strategy.sweep_order);
+        for account_contribution in plan.contributions {
+            println!(" - {:}", account_contribution);
+        }
+        for (asset_class, amount) in plan.over_room {
+            println!(
+                "No account has room for {:} of {:}",
+                decutil::format_dollars(&amount),
+                asset_class
+            );
+        }
+    }
+
+    if let Some(path) = &args.receipt_path {
+        match receipt::write_receipt(path, &plan, Local::now().date_naive()) {
+            Ok(()) if args.format == OutputFormat::Text => println!("Wrote receipt to {:}", path),
+            Ok(()) => eprintln!("Wrote receipt to {:}", path),
+            Err(e) => eprintln!("Could not write receipt to {:}: {:}", path, e),
+        }
+    }
 }
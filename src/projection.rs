@@ -0,0 +1,572 @@
+use chrono::{Datelike, Local, NaiveDate};
+use rust_decimal::Decimal;
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::compounding;
+use crate::dateutil;
+use crate::decutil;
+
+/// The projected worth of a portfolio at some age
+#[derive(Debug, PartialEq, Eq)]
+pub struct Projection {
+    pub age: i32,
+    pub total: Decimal,
+}
+
+impl Projection {
+    fn safe_withdrawal_income(&self, rate: Decimal) -> Decimal {
+        compounding::safe_withdrawal_income(self.total, rate)
+    }
+}
+
+/// The (real) returns expected of the portfolio over time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GrowthModel {
+    /// The same return every year
+    Flat(f64),
+    /// A lower (or higher) return for the first several years, then a long-run average
+    TwoRegime {
+        early_apy: f64,
+        early_years: f64,
+        late_apy: f64,
+    },
+}
+
+impl GrowthModel {
+    fn compound(&self, principal: Decimal, end_date: NaiveDate) -> Decimal {
+        match *self {
+            GrowthModel::Flat(apy) => compounding::compound(principal, apy, end_date),
+            GrowthModel::TwoRegime {
+                early_apy,
+                early_years,
+                late_apy,
+            } => compounding::compound_two_regime(
+                principal,
+                early_apy,
+                early_years,
+                late_apy,
+                end_date,
+            ),
+        }
+    }
+
+    fn compound_contributions(&self, annual: Decimal, end_date: NaiveDate) -> Decimal {
+        match *self {
+            GrowthModel::Flat(apy) => compounding::compound_contributions(annual, apy, end_date),
+            GrowthModel::TwoRegime {
+                early_apy,
+                early_years,
+                late_apy,
+            } => compounding::compound_contributions_two_regime(
+                annual,
+                early_apy,
+                early_years,
+                late_apy,
+                end_date,
+            ),
+        }
+    }
+}
+
+/// I Bonds held, which grow at their own (inflation-indexed) rate rather than the portfolio's
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IBondSleeve {
+    pub value: Decimal,
+    /// The composite rate: fixed, plus inflation
+    pub rate: f64,
+}
+
+/// Planned contributions exceeding what the user's accounts may receive each year
+#[derive(Debug, PartialEq, Eq)]
+pub struct ContributionLimitExcess {
+    pub planned: Decimal,
+    pub limit: Decimal,
+    pub accounts: Vec<String>,
+}
+
+impl fmt::Display for ContributionLimitExcess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Planned contributions of {:}/yr are {:} over the annual limit of {:} ({:}); projections assume {:}/yr",
+            decutil::format_dollars(&self.planned),
+            decutil::format_dollars(&(self.planned - self.limit)),
+            decutil::format_dollars(&self.limit),
+            self.accounts.join(", "),
+            decutil::format_dollars(&self.limit),
+        )
+    }
+}
+
+/// Money to be added to the portfolio every year until retirement
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AnnualContributions {
+    pub planned: Decimal,
+    /// The most each type of account (e.g. IRA, 401k) may receive in a year
+    pub limits: BTreeMap<String, Decimal>,
+}
+
+impl AnnualContributions {
+    /// The most that may be contributed in a year, if any limits are known
+    pub fn capacity(&self) -> Option<Decimal> {
+        if self.limits.is_empty() {
+            return None;
+        }
+        Some(self.limits.values().sum())
+    }
+
+    /// What can actually be contributed each year (the planned amount, up to any limits)
+    pub fn effective(&self) -> Decimal {
+        match self.capacity() {
+            Some(capacity) => cmp::min(self.planned, capacity),
+            None => self.planned,
+        }
+    }
+
+    pub fn excess(&self) -> Option<ContributionLimitExcess> {
+        let limit = self.capacity()?;
+        if self.planned <= limit {
+            return None;
+        }
+        Some(ContributionLimitExcess {
+            planned: self.planned,
+            limit,
+            accounts: self.limits.keys().cloned().collect(),
+        })
+    }
+}
+
+impl fmt::Display for GrowthModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GrowthModel::Flat(apy) => write!(f, "{:.0}% growth", apy * 100.0),
+            GrowthModel::TwoRegime {
+                early_apy,
+                early_years,
+                late_apy,
+            } => write!(
+                f,
+                "{:.0}% growth for {:} years, then {:.0}%",
+                early_apy * 100.0,
+                early_years,
+                late_apy * 100.0
+            ),
+        }
+    }
+}
+
+/// Project the portfolio's worth today, and at a few ages in the future
+///
+/// Each year until then, `annual_contribution` is added to the portfolio.
+/// Any I Bonds (part of `portfolio_total`) compound at their own rate instead.
+pub fn project_retirement(
+    birthday: NaiveDate,
+    portfolio_total: Decimal,
+    growth: GrowthModel,
+    annual_contribution: Decimal,
+    ibonds: Option<IBondSleeve>,
+) -> Vec<Projection> {
+    fn age_at(day_of_retirement: NaiveDate, birthday: NaiveDate) -> i32 {
+        assert!(
+            day_of_retirement > birthday,
+            "Cannot retire before being born..."
+        );
+        dateutil::full_years_between(birthday, day_of_retirement) as i32
+    }
+
+    let today = Local::now().date_naive();
+    let mut projections = vec![Projection {
+        age: age_at(today, birthday),
+        total: portfolio_total,
+    }];
+
+    let approx_age = today.year() - birthday.year(); // Could be this age, or one year younger
+    let start_age = cmp::max(50, approx_age + 5);
+
+    let ibond_value = ibonds.map_or_else(Decimal::default, |sleeve| sleeve.value);
+    let retirement_ages = (start_age)..=(start_age + 15);
+    for age in retirement_ages.step_by(5) {
+        let day_of_retirement = dateutil::safe_anniversary(birthday, birthday.year() + age);
+        let ibonds_total = ibonds.map_or_else(Decimal::default, |sleeve| {
+            compounding::compound(sleeve.value, sleeve.rate, day_of_retirement)
+        });
+        projections.push(Projection {
+            age: age_at(day_of_retirement, birthday),
+            total: growth.compound(portfolio_total - ibond_value, day_of_retirement)
+                + growth.compound_contributions(annual_contribution, day_of_retirement)
+                + ibonds_total,
+        });
+    }
+    projections
+}
+
+/// Render one line per projection, with dollar amounts right-aligned in columns.
+///
+/// By default, columns are just wide enough for the largest value.
+/// A fixed width may be given instead (values wider than the column are never truncated).
+pub fn render_table(
+    projections: &[Projection],
+    fixed_width: Option<usize>,
+    monthly_income: bool,
+    withdrawal_rate: Decimal,
+) -> Vec<String> {
+    let rows: Vec<(i32, String, String)> = projections
+        .iter()
+        .map(|projection| {
+            let annual = projection.safe_withdrawal_income(withdrawal_rate);
+            let mut swr = decutil::format_dollars(&annual);
+            if monthly_income {
+                let monthly = compounding::monthly_income(annual);
+                swr = format!(
+                    "{:} ({:}/mo)",
+                    swr,
+                    decutil::format_dollars_and_cents(&monthly)
+                );
+            }
+            (
+                projection.age,
+                decutil::format_dollars(&projection.total),
+                swr,
+            )
+        })
+        .collect();
+
+    let widest = |column: fn(&(i32, String, String)) -> usize| -> usize {
+        fixed_width.unwrap_or_else(|| rows.iter().map(column).max().unwrap_or(0))
+    };
+    let total_width = widest(|row| row.1.len());
+    let swr_width = widest(|row| row.2.len());
+
+    rows.iter()
+        .map(|(age, total, swr)| {
+            format!(
+                " - {:}: {: >total_width$}  SWR: {: >swr_width$}",
+                age,
+                total,
+                swr,
+                total_width = total_width,
+                swr_width = swr_width,
+            )
+        })
+        .collect()
+}
+
+/// What to assume when projecting the portfolio's worth, and how to show the result
+#[derive(Debug, Clone)]
+pub struct ProjectionOptions {
+    pub growth: GrowthModel,
+    pub contributions: AnnualContributions,
+    pub ibonds: Option<IBondSleeve>,
+    pub withdrawal_rate: Decimal,
+    /// Fixed width for dollar columns (default: fit the widest value)
+    pub fixed_width: Option<usize>,
+    pub monthly_income: bool,
+    /// Skip the projection until the portfolio is worth at least this much
+    pub minimum_value: Option<Decimal>,
+}
+
+/// Project the portfolio's worth at retirement, unless it's still below `minimum_value`
+///
+/// (For a tiny balance, a projection like "$47 at age 65" is more noise than insight.)
+pub fn describe_retirement_prospects(
+    birthday: NaiveDate,
+    portfolio_total: Decimal,
+    options: &ProjectionOptions,
+) -> Vec<String> {
+    let ProjectionOptions {
+        growth,
+        ref contributions,
+        ibonds,
+        withdrawal_rate,
+        fixed_width,
+        monthly_income,
+        minimum_value,
+    } = *options;
+    if let Some(minimum_value) = minimum_value {
+        if portfolio_total < minimum_value {
+            return vec![format!(
+                "Projections available once your portfolio exceeds {:}",
+                decutil::format_dollars(&minimum_value)
+            )];
+        }
+    }
+    let annual_contribution = contributions.effective();
+    let projections = project_retirement(
+        birthday,
+        portfolio_total,
+        growth,
+        annual_contribution,
+        ibonds,
+    );
+    let mut lines = Vec::new();
+    if let Some(excess) = contributions.excess() {
+        lines.push(format!("Warning: {:}", excess));
+    }
+    let mut assumptions = vec![growth.to_string()];
+    if let Some(sleeve) = ibonds {
+        assumptions.push(format!("I Bonds at {:.1}%", sleeve.rate * 100.0));
+    }
+    if annual_contribution != 0.into() {
+        assumptions.push(format!(
+            "contributing {:}/yr",
+            decutil::format_dollars(&annual_contribution)
+        ));
+    }
+    lines.push(format!(
+        "Worth at retirement (Assuming {:}):",
+        assumptions.join(", ")
+    ));
+    lines.extend(render_table(
+        &projections,
+        fixed_width,
+        monthly_income,
+        withdrawal_rate,
+    ));
+    lines
+}
+
+pub fn summarize_retirement_prospects(
+    birthday: NaiveDate,
+    portfolio_total: Decimal,
+    options: &ProjectionOptions,
+) {
+    for line in describe_retirement_prospects(birthday, portfolio_total, options) {
+        println!("{:}", line);
+    }
+    println!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compounding::SAFE_WITHDRAWAL_RATE;
+
+    fn flat_growth() -> ProjectionOptions {
+        ProjectionOptions {
+            growth: GrowthModel::Flat(0.07),
+            contributions: AnnualContributions::default(),
+            ibonds: None,
+            withdrawal_rate: SAFE_WITHDRAWAL_RATE,
+            fixed_width: None,
+            monthly_income: false,
+            minimum_value: None,
+        }
+    }
+
+    #[test]
+    fn test_columns_align_above_25_million() {
+        let projections = vec![
+            Projection {
+                age: 39,
+                total: Decimal::from(28_686),
+            },
+            Projection {
+                age: 65,
+                total: Decimal::from(123_456_789),
+            },
+        ];
+        assert_eq!(
+            render_table(&projections, None, false, SAFE_WITHDRAWAL_RATE),
+            vec![
+                " - 39:      $28,686  SWR:     $1,147",
+                " - 65: $123,456,789  SWR: $4,938,272",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fixed_width_never_truncates() {
+        let projections = vec![
+            Projection {
+                age: 50,
+                total: Decimal::from(1_000),
+            },
+            Projection {
+                age: 65,
+                total: Decimal::from(30_000_000),
+            },
+        ];
+        assert_eq!(
+            render_table(&projections, Some(8), false, SAFE_WITHDRAWAL_RATE),
+            vec![
+                " - 50:   $1,000  SWR:      $40",
+                " - 65: $30,000,000  SWR: $1,200,000",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_monthly_income() {
+        // $40,000 a year is $3,333.33 a month (a 4% SWR on $1 million)
+        let projections = vec![Projection {
+            age: 65,
+            total: Decimal::from(1_000_000),
+        }];
+        assert_eq!(
+            render_table(&projections, None, true, SAFE_WITHDRAWAL_RATE),
+            vec![" - 65: $1,000,000  SWR: $40,000 ($3,333.33/mo)"]
+        );
+
+        let annual = projections[0].safe_withdrawal_income(SAFE_WITHDRAWAL_RATE);
+        let monthly = compounding::monthly_income(annual);
+        // Each month is off by at most half a cent
+        let discrepancy = (monthly * Decimal::from(12) - annual).abs();
+        assert!(discrepancy <= Decimal::new(6, 2));
+    }
+
+    #[test]
+    fn test_small_portfolios_skip_projection() {
+        let birthday = NaiveDate::from_ymd_opt(1990, 1, 1).unwrap();
+        let minimum = Some(Decimal::from(1_000));
+        assert_eq!(
+            describe_retirement_prospects(
+                birthday,
+                50.into(),
+                &ProjectionOptions {
+                    minimum_value: minimum,
+                    ..flat_growth()
+                },
+            ),
+            vec!["Projections available once your portfolio exceeds $1,000"]
+        );
+
+        let lines = describe_retirement_prospects(
+            birthday,
+            5_000.into(),
+            &ProjectionOptions {
+                minimum_value: minimum,
+                ..flat_growth()
+            },
+        );
+        assert_eq!(lines[0], "Worth at retirement (Assuming 7% growth):");
+        assert!(lines.len() > 1);
+    }
+
+    #[test]
+    fn test_describe_growth() {
+        assert_eq!(format!("{:}", GrowthModel::Flat(0.07)), "7% growth");
+        let declining = GrowthModel::TwoRegime {
+            early_apy: 0.04,
+            early_years: 10.0,
+            late_apy: 0.07,
+        };
+        assert_eq!(format!("{:}", declining), "4% growth for 10 years, then 7%");
+    }
+
+    #[test]
+    fn test_configured_withdrawal_rate() {
+        let projections = vec![Projection {
+            age: 65,
+            total: Decimal::from(1_000_000),
+        }];
+        assert_eq!(
+            render_table(&projections, None, false, Decimal::new(35, 3)),
+            vec![" - 65: $1,000,000  SWR: $35,000"]
+        );
+    }
+
+    #[test]
+    fn test_contributions_capped_by_limits() {
+        let mut limits = BTreeMap::new();
+        limits.insert(String::from("IRA"), Decimal::from(7_000));
+        let contributions = AnnualContributions {
+            planned: 30_000.into(),
+            limits,
+        };
+        assert_eq!(contributions.effective(), Decimal::from(7_000));
+        let excess = contributions.excess().unwrap();
+        assert_eq!(excess.planned - excess.limit, Decimal::from(23_000));
+        assert_eq!(
+            excess.to_string(),
+            "Planned contributions of $30,000/yr are $23,000 over the annual limit of $7,000 (IRA); projections assume $7,000/yr"
+        );
+
+        let birthday = NaiveDate::from_ymd_opt(1990, 1, 1).unwrap();
+        let lines = describe_retirement_prospects(
+            birthday,
+            100_000.into(),
+            &ProjectionOptions {
+                contributions: contributions.clone(),
+                ..flat_growth()
+            },
+        );
+        assert_eq!(lines[0], format!("Warning: {:}", excess));
+        assert_eq!(
+            lines[1],
+            "Worth at retirement (Assuming 7% growth, contributing $7,000/yr):"
+        );
+
+        // Contributing grows the portfolio beyond what it would reach alone
+        let capped = project_retirement(
+            birthday,
+            100_000.into(),
+            GrowthModel::Flat(0.07),
+            7_000.into(),
+            None,
+        );
+        let alone = project_retirement(
+            birthday,
+            100_000.into(),
+            GrowthModel::Flat(0.07),
+            0.into(),
+            None,
+        );
+        assert_eq!(capped[0], alone[0]);
+        assert!(capped[1].total > alone[1].total);
+
+        // Within the limits (or with none given), everything planned is contributed
+        let unlimited = AnnualContributions {
+            planned: 30_000.into(),
+            limits: BTreeMap::new(),
+        };
+        assert_eq!(unlimited.effective(), Decimal::from(30_000));
+        assert_eq!(unlimited.excess(), None);
+    }
+
+    #[test]
+    fn test_ibonds_grow_at_their_own_rate() {
+        let birthday = NaiveDate::from_ymd_opt(1990, 1, 1).unwrap();
+        let ibonds = IBondSleeve {
+            value: 20_000.into(),
+            rate: 0.03,
+        };
+        let project = |total: i64, apy: f64, ibonds: Option<IBondSleeve>| {
+            project_retirement(
+                birthday,
+                total.into(),
+                GrowthModel::Flat(apy),
+                0.into(),
+                ibonds,
+            )
+        };
+        let mixed = project(100_000, 0.07, Some(ibonds));
+        let equities = project(80_000, 0.07, None);
+        let sleeve = project(20_000, 0.03, None);
+
+        // Today, nothing has grown
+        assert_eq!(mixed[0].total, Decimal::from(100_000));
+        for ((mixed, equities), sleeve) in mixed.iter().zip(&equities).zip(&sleeve).skip(1) {
+            assert_eq!(mixed.age, equities.age);
+            assert_eq!(mixed.total, equities.total + sleeve.total);
+        }
+
+        // I Bonds growing slower than equities hold back the whole portfolio
+        let all_equities = project(100_000, 0.07, None);
+        assert!(mixed[1].total < all_equities[1].total);
+
+        let lines = describe_retirement_prospects(
+            birthday,
+            100_000.into(),
+            &ProjectionOptions {
+                ibonds: Some(ibonds),
+                ..flat_growth()
+            },
+        );
+        assert_eq!(
+            lines[0],
+            "Worth at retirement (Assuming 7% growth, I Bonds at 3.0%):"
+        );
+    }
+}
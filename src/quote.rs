@@ -1,21 +1,64 @@
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Local, NaiveDate, NaiveTime};
+use chrono_tz::Tz;
 use rust_decimal::Decimal;
-use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
 use std::env;
+use std::process::Command;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
 
+use crate::calendar::TradingCalendar;
 use crate::dateutil;
 use crate::gnucash::Commodity;
 
 use std::fmt;
 
+/// Why a quote couldn't be had
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FinanceQuoteErrorKind {
+    /// The provider couldn't be reached
+    Network,
+    /// The provider refused the request, having had too many (AlphaVantage sends a "Note")
+    RateLimited,
+    /// The response wasn't a quote
+    Parse,
+    /// The response held no quote (e.g. for an unknown symbol)
+    EmptyResponse,
+    /// The quote command couldn't be run, or failed
+    Command,
+    /// `ALPHAVANTAGE_API_KEY` isn't set
+    MissingApiKey,
+}
+
+impl fmt::Display for FinanceQuoteErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            FinanceQuoteErrorKind::Network => "could not reach the provider",
+            FinanceQuoteErrorKind::RateLimited => "rate limited by the provider",
+            FinanceQuoteErrorKind::Parse => "could not parse the response",
+            FinanceQuoteErrorKind::EmptyResponse => "no quote in the response",
+            FinanceQuoteErrorKind::Command => "the quote command failed",
+            FinanceQuoteErrorKind::MissingApiKey => "ALPHAVANTAGE_API_KEY is not set",
+        };
+        write!(f, "{:}", description)
+    }
+}
+
 #[derive(Debug)]
 pub struct FinanceQuoteError {
     pub symbol: String,
+    pub kind: FinanceQuoteErrorKind,
 }
 
 impl fmt::Display for FinanceQuoteError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Failed to fetch quote")
+        write!(
+            f,
+            "Failed to fetch quote for {:} ({:})",
+            self.symbol, self.kind
+        )
     }
 }
 
@@ -24,11 +67,8 @@ pub struct JsonQuote {
     #[serde(rename = "01. symbol")]
     pub symbol: String,
 
-    #[serde(
-        rename = "07. latest trading day",
-        deserialize_with = "simple_noon_datetime"
-    )]
-    pub time: DateTime<Local>,
+    #[serde(rename = "07. latest trading day")]
+    pub trading_day: NaiveDate,
 
     #[serde(rename = "05. price")]
     pub last: Decimal,
@@ -49,51 +89,447 @@ pub struct Quote {
     pub currency: String,
 }
 
-// The AlphaVantage-reported quote "datetime" is a naive date, e.g. 2022-12-25
-// We apply the behavior used in the FinanceQuote module -- naively saying it's at noon.
-// This satisfies a GnuCash requirement for storing an actual wall time in the db.
-fn simple_noon_datetime<'de, D>(deserializer: D) -> Result<DateTime<Local>, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let ymd: String = Deserialize::deserialize(deserializer)?;
-    // Probably shouldn't assume that the given YMD is valid, but... :shrug:
-    Ok(dateutil::localize_at_noon(&ymd).unwrap())
+// Long enough to cover a holiday weekend, short enough to catch funds that stopped trading.
+pub const MAX_QUOTE_AGE_DAYS: i64 = 4;
+
+impl Quote {
+    /// Return if the quote's trading day is more than `max_age_days` before `as_of`
+    ///
+    /// Providers will happily return a "latest" quote that's days old (e.g. for thinly traded
+    /// ETFs), so the time of fetching says nothing about how current the price is.
+    pub fn is_stale(&self, as_of: DateTime<Local>, max_age_days: i64) -> bool {
+        (as_of.date_naive() - self.time.date_naive()).num_days() > max_age_days
+    }
 }
 
-pub struct FinanceQuote {}
+/// How often a commodity should have its price refreshed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdateCadence {
+    /// Every trading day (the default, suitable for most funds)
+    #[default]
+    Daily,
+    /// Once a week (suitable for funds that barely move, e.g. money market funds)
+    Weekly,
+}
 
-impl FinanceQuote {
-    pub fn fetch_quote(commodity: &Commodity) -> Result<Quote, FinanceQuoteError> {
-        let api_key: String = env::var("ALPHAVANTAGE_API_KEY").unwrap();
+impl UpdateCadence {
+    /// Return if a price obtained at `last_priced` is old enough to warrant a new quote
+    pub fn is_due(
+        &self,
+        last_priced: DateTime<Local>,
+        now: DateTime<Local>,
+        calendar: &TradingCalendar,
+    ) -> bool {
+        match self {
+            // Settle for the last full trading day's quotes (e.g. Friday's, over the weekend).
+            // (AlphaVantage's free API isn't always the most current)
+            UpdateCadence::Daily => {
+                last_priced.date_naive() < calendar.previous_trading_day(now.date_naive())
+            }
+            UpdateCadence::Weekly => (now - last_priced).num_days().abs() >= 7,
+        }
+    }
+}
 
-        let url: String = format!(
-            "https://www.alphavantage.co/query?function=GLOBAL_QUOTE&symbol={:}&apikey={:}",
-            commodity.id, api_key,
-        );
-        let body = reqwest::blocking::get(url).unwrap().text().unwrap();
-        let json_quote: GlobalJsonQuote = serde_json::from_str(&body).unwrap();
+/// A source of the latest prices for commodities
+///
+/// Providers are shared between threads, so that quotes may be fetched concurrently.
+pub trait QuoteProvider: Sync {
+    /// Fetch the latest quote, treating its trading day as `time_of_day` in `zone` (default: local)
+    fn fetch_quote(
+        &self,
+        commodity: &Commodity,
+        zone: Option<Tz>,
+        time_of_day: NaiveTime,
+    ) -> Result<Quote, FinanceQuoteError>;
+
+    /// The quote source (as recorded for each commodity in GnuCash) served by this provider
+    fn quote_source(&self) -> &str;
+}
+
+/// Quotes fetched at once by default (AlphaVantage limits requests per minute)
+pub const DEFAULT_QUOTE_CONCURRENCY: usize = 4;
+
+/// Fetch a quote for each commodity, with at most `concurrency` requests in flight at once
+///
+/// Results are given in the same order as the commodities.
+pub fn fetch_quotes(
+    provider: &dyn QuoteProvider,
+    commodities: &[Commodity],
+    zone: Option<Tz>,
+    time_of_day: NaiveTime,
+    concurrency: usize,
+) -> Vec<Result<Quote, FinanceQuoteError>> {
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..concurrency.clamp(1, commodities.len().max(1)) {
+            let sender = sender.clone();
+            let next = &next;
+            scope.spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                let commodity = match commodities.get(index) {
+                    Some(commodity) => commodity,
+                    None => break,
+                };
+                let quote = provider.fetch_quote(commodity, zone, time_of_day);
+                sender.send((index, quote)).ok();
+            });
+        }
+    });
+    drop(sender);
+
+    let mut fetched: Vec<(usize, Result<Quote, FinanceQuoteError>)> = receiver.iter().collect();
+    fetched.sort_by_key(|(index, _)| *index);
+    fetched.into_iter().map(|(_, quote)| quote).collect()
+}
+
+/// Quotes from AlphaVantage (requires `ALPHAVANTAGE_API_KEY` to be set)
+///
+/// AlphaVantage doesn't say which currency a quote is in, so it's assumed to be USD
+/// unless the symbol is listed in `currencies` (e.g. a London-listed ETF in GBP).
+#[derive(Default)]
+pub struct AlphaVantage {
+    pub currencies: HashMap<String, String>,
+}
 
+impl AlphaVantage {
+    /// Read AlphaVantage's response to a `GLOBAL_QUOTE` request
+    fn parse_response(
+        &self,
+        body: &str,
+        zone: Option<Tz>,
+        time_of_day: NaiveTime,
+    ) -> Result<Quote, FinanceQuoteErrorKind> {
+        if body.trim().is_empty() {
+            return Err(FinanceQuoteErrorKind::EmptyResponse);
+        }
+        let value: serde_json::Value =
+            serde_json::from_str(body).map_err(|_| FinanceQuoteErrorKind::Parse)?;
+        // When rate limited, a message is given in place of the quote
+        if value.get("Note").is_some() || value.get("Information").is_some() {
+            return Err(FinanceQuoteErrorKind::RateLimited);
+        }
+        // Unknown symbols get an empty quote
+        match value.get("Global Quote") {
+            Some(serde_json::Value::Object(quote)) if quote.is_empty() => {
+                return Err(FinanceQuoteErrorKind::EmptyResponse)
+            }
+            _ => (),
+        }
+        let json_quote: GlobalJsonQuote =
+            serde_json::from_value(value).map_err(|_| FinanceQuoteErrorKind::Parse)?;
+        self.to_quote(json_quote, zone, time_of_day)
+    }
+
+    /// Fails if the time of day doesn't exist on the trading day (i.e. clocks skipped it for DST)
+    fn to_quote(
+        &self,
+        json_quote: GlobalJsonQuote,
+        zone: Option<Tz>,
+        time_of_day: NaiveTime,
+    ) -> Result<Quote, FinanceQuoteErrorKind> {
+        let currency = self
+            .currencies
+            .get(&json_quote.quote.symbol)
+            .cloned()
+            .unwrap_or_else(|| String::from("USD"));
         Ok(Quote {
+            // The AlphaVantage-reported quote "datetime" is a naive date, e.g. 2022-12-25
+            // By default, we apply the behavior used in the FinanceQuote module -- naively saying
+            // it's at noon. This satisfies a GnuCash requirement for storing an actual wall time.
+            time: dateutil::localize_at_time_in(
+                &json_quote.quote.trading_day.to_string(),
+                time_of_day,
+                zone,
+            )
+            .earliest()
+            .ok_or(FinanceQuoteErrorKind::Parse)?,
             symbol: json_quote.quote.symbol,
-            time: json_quote.quote.time,
             last: json_quote.quote.last,
-            currency: String::from("USD"),
+            currency,
         })
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
-struct Person {
-    name: String,
-    age: u8,
+impl QuoteProvider for AlphaVantage {
+    fn fetch_quote(
+        &self,
+        commodity: &Commodity,
+        zone: Option<Tz>,
+        time_of_day: NaiveTime,
+    ) -> Result<Quote, FinanceQuoteError> {
+        let error = |kind| FinanceQuoteError {
+            symbol: commodity.id.clone(),
+            kind,
+        };
+        let api_key: String = env::var("ALPHAVANTAGE_API_KEY")
+            .map_err(|_| error(FinanceQuoteErrorKind::MissingApiKey))?;
+
+        let url: String = format!(
+            "https://www.alphavantage.co/query?function=GLOBAL_QUOTE&symbol={:}&apikey={:}",
+            commodity.id, api_key,
+        );
+        let body = reqwest::blocking::get(url)
+            .and_then(|response| response.text())
+            .map_err(|_| error(FinanceQuoteErrorKind::Network))?;
+        self.parse_response(&body, zone, time_of_day).map_err(error)
+    }
+
+    fn quote_source(&self) -> &str {
+        "alphavantage"
+    }
+}
+
+/// Quotes from a Finance::Quote command (e.g. `gnc-fq-dump yahoo_json`), given symbols as arguments
+///
+/// Finance::Quote is what GnuCash itself uses, and offers dozens of sources.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FinanceQuoteCommand {
+    pub program: String,
+    pub args: Vec<String>,
 }
 
-#[cfg(test)]
+/// A command line without any program to run
+#[derive(Debug, PartialEq, Eq)]
+pub struct EmptyCommandError;
+
+impl fmt::Display for EmptyCommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "quote_command must name a program to run")
+    }
+}
+
+impl std::error::Error for EmptyCommandError {}
+
+impl FinanceQuoteCommand {
+    /// Build from a command line, e.g. `['gnc-fq-dump', 'vanguard']`
+    pub fn new(command: &[String]) -> Result<FinanceQuoteCommand, EmptyCommandError> {
+        match command.split_first() {
+            Some((program, args)) if !program.is_empty() => Ok(FinanceQuoteCommand {
+                program: program.clone(),
+                args: args.to_vec(),
+            }),
+            _ => Err(EmptyCommandError),
+        }
+    }
+}
+
+impl QuoteProvider for FinanceQuoteCommand {
+    fn fetch_quote(
+        &self,
+        commodity: &Commodity,
+        zone: Option<Tz>,
+        time_of_day: NaiveTime,
+    ) -> Result<Quote, FinanceQuoteError> {
+        let error = |kind| FinanceQuoteError {
+            symbol: commodity.id.clone(),
+            kind,
+        };
+        let output = Command::new(&self.program)
+            .args(&self.args)
+            .arg(&commodity.id)
+            .output()
+            .map_err(|_| error(FinanceQuoteErrorKind::Command))?;
+        if !output.status.success() {
+            return Err(error(FinanceQuoteErrorKind::Command));
+        }
+        parse_fq_dump(&String::from_utf8_lossy(&output.stdout), zone, time_of_day)
+            .into_iter()
+            .find(|quote| quote.symbol == commodity.id)
+            .ok_or_else(|| error(FinanceQuoteErrorKind::EmptyResponse))
+    }
+
+    /// The Finance::Quote source, taken to be the command's last argument (e.g. `vanguard`)
+    fn quote_source(&self) -> &str {
+        self.args.last().unwrap_or(&self.program)
+    }
+}
+
+/// Read the quotes printed by `gnc-fq-dump`, one block of `field: value` lines per symbol
+///
+/// Each block starts with the symbol. Values may be followed by hints like `<=== required`.
+/// Blocks lacking a symbol, a date, or any price (`last`, `nav`, or `price`) are skipped.
+pub fn parse_fq_dump(output: &str, zone: Option<Tz>, time_of_day: NaiveTime) -> Vec<Quote> {
+    let mut blocks: Vec<HashMap<&str, &str>> = Vec::new();
+    for line in output.lines() {
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field.trim(), value),
+            None => continue,
+        };
+        let value = value.split("<=").next().unwrap_or_default().trim();
+        if field == "symbol" {
+            blocks.push(HashMap::new());
+        }
+        if let Some(block) = blocks.last_mut() {
+            if !value.is_empty() {
+                block.insert(field, value);
+            }
+        }
+    }
 
+    blocks
+        .into_iter()
+        .filter_map(|block| {
+            let date = block.get("date").and_then(|date| {
+                NaiveDate::parse_from_str(date, "%m/%d/%Y")
+                    .or_else(|_| NaiveDate::parse_from_str(date, "%Y-%m-%d"))
+                    .ok()
+            })?;
+            let last = ["last", "nav", "price"]
+                .iter()
+                .find_map(|field| block.get(field))
+                .and_then(|price| Decimal::from_str(price).ok())?;
+            Some(Quote {
+                symbol: block.get("symbol")?.to_string(),
+                time: dateutil::localize_at_time_in(&date.to_string(), time_of_day, zone)
+                    .single()?,
+                last,
+                currency: block.get("currency").unwrap_or(&"USD").to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
 mod tests {
     use super::*;
-    use chrono::{offset::TimeZone, NaiveDateTime};
+
+    fn quote_on(ymd: &str) -> Quote {
+        Quote {
+            symbol: "FTIAX".into(),
+            time: dateutil::localize_at_noon(ymd).unwrap(),
+            last: Decimal::new(83900, 4),
+            currency: "USD".into(),
+        }
+    }
+
+    /// Hands out a quote for any symbol (after a pause), tracking how many are fetched at once
+    /// Holds the first `barrier`-many fetches until all of them are in flight at once
+    struct SlowQuotes {
+        in_flight: AtomicUsize,
+        most_in_flight: AtomicUsize,
+        started: AtomicUsize,
+        barrier: std::sync::Barrier,
+        overlapping: usize,
+    }
+
+    impl QuoteProvider for SlowQuotes {
+        fn fetch_quote(
+            &self,
+            commodity: &Commodity,
+            _zone: Option<Tz>,
+            _time_of_day: NaiveTime,
+        ) -> Result<Quote, FinanceQuoteError> {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.most_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            if self.started.fetch_add(1, Ordering::SeqCst) < self.overlapping {
+                self.barrier.wait();
+            }
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            if commodity.id == "ZZZZZ" {
+                return Err(FinanceQuoteError {
+                    symbol: commodity.id.clone(),
+                    kind: FinanceQuoteErrorKind::Network,
+                });
+            }
+            Ok(Quote {
+                symbol: commodity.id.clone(),
+                ..quote_on("2024-01-10")
+            })
+        }
+
+        fn quote_source(&self) -> &str {
+            "slow"
+        }
+    }
+
+    #[test]
+    fn test_concurrent_fetches_are_bounded_and_ordered() {
+        let symbols = [
+            "VTSAX", "VTIAX", "ZZZZZ", "VBTLX", "VGSLX", "VSMAX", "VFIAX",
+        ];
+        let commodities: Vec<Commodity> = symbols
+            .iter()
+            .map(|symbol| Commodity {
+                guid: None,
+                id: String::from(*symbol),
+                space: Some(String::from("FUND")),
+                name: String::from(*symbol),
+            })
+            .collect();
+        // With three workers, the first three fetches can only finish once all three overlap
+        let provider = SlowQuotes {
+            in_flight: AtomicUsize::new(0),
+            most_in_flight: AtomicUsize::new(0),
+            started: AtomicUsize::new(0),
+            barrier: std::sync::Barrier::new(3),
+            overlapping: 3,
+        };
+
+        let quotes = fetch_quotes(&provider, &commodities, None, dateutil::noon(), 3);
+        let fetched: Vec<Option<&str>> = quotes
+            .iter()
+            .map(|quote| quote.as_ref().ok().map(|quote| quote.symbol.as_str()))
+            .collect();
+        assert_eq!(
+            fetched,
+            vec![
+                Some("VTSAX"),
+                Some("VTIAX"),
+                None,
+                Some("VBTLX"),
+                Some("VGSLX"),
+                Some("VSMAX"),
+                Some("VFIAX"),
+            ]
+        );
+        assert_eq!(provider.most_in_flight.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_daily_cadence() {
+        let calendar = TradingCalendar::default();
+        let wednesday = dateutil::localize_at_noon("2024-01-10").unwrap();
+        let tuesday = dateutil::localize_at_noon("2024-01-09").unwrap();
+        let monday = dateutil::localize_at_noon("2024-01-08").unwrap();
+        assert!(!UpdateCadence::Daily.is_due(tuesday, wednesday, &calendar));
+        assert!(UpdateCadence::Daily.is_due(monday, wednesday, &calendar));
+
+        // Over the weekend, Friday's price is good enough
+        let friday = dateutil::localize_at_noon("2024-01-12").unwrap();
+        let sunday = dateutil::localize_at_noon("2024-01-14").unwrap();
+        assert!(!UpdateCadence::Daily.is_due(friday, sunday, &calendar));
+
+        // ...as it is the day after a Monday holiday (Martin Luther King Jr. Day)
+        let tuesday_after_holiday = dateutil::localize_at_noon("2024-01-16").unwrap();
+        assert!(!UpdateCadence::Daily.is_due(friday, tuesday_after_holiday, &calendar));
+    }
+
+    #[test]
+    fn test_weekly_cadence() {
+        let calendar = TradingCalendar::default();
+        let wednesday = dateutil::localize_at_noon("2024-01-10").unwrap();
+        let last_friday = dateutil::localize_at_noon("2024-01-05").unwrap();
+        let last_wednesday = dateutil::localize_at_noon("2024-01-03").unwrap();
+        assert!(!UpdateCadence::Weekly.is_due(last_friday, wednesday, &calendar));
+        assert!(UpdateCadence::Weekly.is_due(last_wednesday, wednesday, &calendar));
+    }
+
+    #[test]
+    fn test_week_old_quote_is_stale() {
+        let as_of = dateutil::localize_at_noon("2023-12-28").unwrap();
+        assert!(quote_on("2023-12-21").is_stale(as_of, 2));
+    }
+
+    #[test]
+    fn test_recent_quote_is_not_stale() {
+        let as_of = dateutil::localize_at_noon("2023-12-28").unwrap();
+        assert!(!quote_on("2023-12-28").is_stale(as_of, 2));
+        assert!(!quote_on("2023-12-26").is_stale(as_of, 2));
+        assert!(quote_on("2023-12-25").is_stale(as_of, 2));
+    }
 
     #[test]
     fn test_parse_response() {
@@ -112,9 +548,6 @@ mod tests {
             }
         }"#;
         let parsed: GlobalJsonQuote = serde_json::from_str(data).unwrap();
-        let naive =
-            NaiveDateTime::parse_from_str("2023-12-28T12:00:00", "%Y-%m-%dT%H:%M:%S").unwrap();
-        let local: DateTime<Local> = Local.from_local_datetime(&naive).unwrap();
 
         assert_eq!(
             parsed,
@@ -122,9 +555,172 @@ mod tests {
                 quote: JsonQuote {
                     symbol: "FTIAX".into(),
                     last: Decimal::new(83900, 4),
-                    time: local,
+                    trading_day: NaiveDate::from_ymd_opt(2023, 12, 28).unwrap(),
                 }
             }
         )
     }
+
+    #[test]
+    fn test_failed_responses() {
+        let provider = AlphaVantage::default();
+        let parse = |body| provider.parse_response(body, None, dateutil::noon());
+
+        let rate_limited = r#"{"Note": "Thank you for using Alpha Vantage! Our standard API call frequency is 5 calls per minute."}"#;
+        assert_eq!(parse(rate_limited), Err(FinanceQuoteErrorKind::RateLimited));
+        let information = r#"{"Information": "You have reached the daily limit."}"#;
+        assert_eq!(parse(information), Err(FinanceQuoteErrorKind::RateLimited));
+
+        assert_eq!(
+            parse(r#"{"Global Quote": {}}"#),
+            Err(FinanceQuoteErrorKind::EmptyResponse)
+        );
+        assert_eq!(parse(""), Err(FinanceQuoteErrorKind::EmptyResponse));
+        assert_eq!(
+            parse("<html>502 Bad Gateway</html>"),
+            Err(FinanceQuoteErrorKind::Parse)
+        );
+        assert_eq!(
+            parse(r#"{"Global Quote": {"01. symbol": "VTSAX"}}"#),
+            Err(FinanceQuoteErrorKind::Parse)
+        );
+
+        let quote = parse(
+            r#"{"Global Quote": {"01. symbol": "VTSAX", "05. price": "111.41", "07. latest trading day": "2023-12-28"}}"#,
+        )
+        .unwrap();
+        assert_eq!(quote.last, Decimal::new(11141, 2));
+
+        // 2:30am Eastern was skipped when DST began
+        let spring_forward = r#"{"Global Quote": {"01. symbol": "VTSAX", "05. price": "111.41", "07. latest trading day": "2024-03-10"}}"#;
+        let half_past_two = NaiveTime::from_hms_opt(2, 30, 0).unwrap();
+        assert_eq!(
+            provider.parse_response(spring_forward, Some(Tz::US__Eastern), half_past_two),
+            Err(FinanceQuoteErrorKind::Parse)
+        );
+
+        let error = FinanceQuoteError {
+            symbol: String::from("VTSAX"),
+            kind: FinanceQuoteErrorKind::RateLimited,
+        };
+        assert_eq!(
+            error.to_string(),
+            "Failed to fetch quote for VTSAX (rate limited by the provider)"
+        );
+
+        let error = FinanceQuoteError {
+            symbol: String::from("VTSAX"),
+            kind: FinanceQuoteErrorKind::MissingApiKey,
+        };
+        assert_eq!(
+            error.to_string(),
+            "Failed to fetch quote for VTSAX (ALPHAVANTAGE_API_KEY is not set)"
+        );
+    }
+
+    #[test]
+    fn test_configured_listing_currency() {
+        let json_quote = |symbol: &str| GlobalJsonQuote {
+            quote: JsonQuote {
+                symbol: symbol.into(),
+                last: Decimal::new(10234, 2),
+                trading_day: NaiveDate::from_ymd_opt(2023, 12, 28).unwrap(),
+            },
+        };
+        let mut currencies = HashMap::new();
+        currencies.insert(String::from("VUSA.LON"), String::from("GBP"));
+        let provider = AlphaVantage { currencies };
+
+        let london = provider
+            .to_quote(json_quote("VUSA.LON"), None, dateutil::noon())
+            .unwrap();
+        assert_eq!(london.currency, "GBP");
+        assert_eq!(london.last, Decimal::new(10234, 2));
+
+        // Anything not listed is assumed to be in US dollars
+        let us = provider
+            .to_quote(json_quote("VTI"), None, dateutil::noon())
+            .unwrap();
+        assert_eq!(us.currency, "USD");
+    }
+
+    const FQ_DUMP: &str = "\
+Finance::Quote fields Gnucash uses:
+    symbol: VTSAX          <=== required
+      date: 12/28/2023     <=== recommended
+  currency: USD            <=== required
+      last:                <=\\
+       nav: 111.4100       <=== one of these
+     price:                <=/
+  timezone:                <=== optional
+Finance::Quote fields Gnucash uses:
+    symbol: NOPRICE        <=== required
+      date: 12/28/2023     <=== recommended
+  currency: USD            <=== required
+";
+
+    #[test]
+    fn test_parse_fq_dump() {
+        let quotes = parse_fq_dump(FQ_DUMP, None, dateutil::noon());
+        assert_eq!(
+            quotes,
+            vec![Quote {
+                symbol: "VTSAX".into(),
+                time: dateutil::localize_at_noon("2023-12-28").unwrap(),
+                last: Decimal::new(1114100, 4),
+                currency: "USD".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_finance_quote_command() {
+        // Stand in for `gnc-fq-dump`, echoing back whichever symbol is requested
+        let stub = FinanceQuoteCommand::new(&[
+            String::from("sh"),
+            String::from("-c"),
+            String::from(
+                "printf 'Finance::Quote fields Gnucash uses:\n    symbol: %s\n      date: 12/28/2023\n  currency: USD\n      last: 8.39\n' \"$1\"",
+            ),
+            String::from("gnc-fq-dump"),
+        ])
+        .unwrap();
+        let commodity = Commodity {
+            guid: None,
+            id: String::from("FTIAX"),
+            space: Some(String::from("FUND")),
+            name: String::from("FTIAX"),
+        };
+        let quote = stub
+            .fetch_quote(&commodity, None, dateutil::noon())
+            .unwrap();
+        assert_eq!(quote, quote_on("2023-12-28"));
+
+        let failing = FinanceQuoteCommand::new(&[String::from("false")]).unwrap();
+        assert_eq!(
+            failing
+                .fetch_quote(&commodity, None, dateutil::noon())
+                .unwrap_err()
+                .symbol,
+            "FTIAX"
+        );
+    }
+
+    #[test]
+    fn test_quote_sources() {
+        assert_eq!(AlphaVantage::default().quote_source(), "alphavantage");
+        let command =
+            FinanceQuoteCommand::new(&[String::from("gnc-fq-dump"), String::from("vanguard")])
+                .unwrap();
+        assert_eq!(command.quote_source(), "vanguard");
+    }
+
+    #[test]
+    fn test_finance_quote_command_needs_a_program() {
+        assert_eq!(FinanceQuoteCommand::new(&[]), Err(EmptyCommandError));
+        assert_eq!(
+            FinanceQuoteCommand::new(&[String::new(), String::from("vanguard")]),
+            Err(EmptyCommandError)
+        );
+    }
 }
@@ -1,15 +1,250 @@
-use crate::assets::{Asset, AssetClass};
+use crate::assets::{
+    self, Asset, AssetClass, AssetClassifications, Holding, HoldingError, ShareClassUpgrade,
+};
 use crate::decutil;
 use rust_decimal::Decimal;
 use std::cmp;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
 use std::fmt;
 
+/// A target total that the portfolio already meets (or exceeds)
+#[derive(Debug, PartialEq, Eq)]
+pub struct TargetTotalError {
+    target_total: Decimal,
+    current_value: Decimal,
+}
+
+impl fmt::Display for TargetTotalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Target total of {:} must exceed the current value of {:}",
+            decutil::format_dollars(&self.target_total),
+            decutil::format_dollars(&self.current_value),
+        )
+    }
+}
+
+impl Error for TargetTotalError {}
+
+/// Target ratios which don't account for the whole portfolio
+#[derive(Debug, PartialEq, Eq)]
+pub struct TargetRatioError {
+    total: Decimal,
+}
+
+impl fmt::Display for TargetRatioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Target ratios must sum to 100%, not {:}%",
+            (self.total * Decimal::from(100)).normalize()
+        )
+    }
+}
+
+impl Error for TargetRatioError {}
+
+/// New targets which can't take over a portfolio's current holdings
+#[derive(Debug, PartialEq, Eq)]
+pub enum MigrationError {
+    /// An asset class is held, but has no new target
+    MissingTarget(AssetClass),
+    TargetRatio(TargetRatioError),
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MigrationError::MissingTarget(asset_class) => {
+                write!(f, "No target given for {:}", asset_class)
+            }
+            MigrationError::TargetRatio(e) => write!(f, "{:}", e),
+        }
+    }
+}
+
+impl Error for MigrationError {}
+
+impl From<TargetRatioError> for MigrationError {
+    fn from(e: TargetRatioError) -> Self {
+        MigrationError::TargetRatio(e)
+    }
+}
+
+/// A portfolio declared out of order, or with targets that don't add up
+#[derive(Debug, PartialEq, Eq)]
+pub enum BuildError {
+    /// A holding was given before any asset class
+    HoldingWithoutClass,
+    TargetRatio(TargetRatioError),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::HoldingWithoutClass => {
+                write!(f, "Declare an asset class before its holdings")
+            }
+            BuildError::TargetRatio(e) => write!(f, "{:}", e),
+        }
+    }
+}
+
+impl Error for BuildError {}
+
+impl From<TargetRatioError> for BuildError {
+    fn from(e: TargetRatioError) -> Self {
+        BuildError::TargetRatio(e)
+    }
+}
+
+/// A withdrawal which would leave an asset class too far below its target
+#[derive(Debug, PartialEq, Eq)]
+pub struct WithdrawalFloorError {
+    pub asset_class: AssetClass,
+    /// What the asset class would be worth after the withdrawal
+    remaining: Decimal,
+    /// The least the asset class may be worth (some fraction of its target value)
+    floor: Decimal,
+}
+
+impl fmt::Display for WithdrawalFloorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Withdrawal would leave {:} at {:}, below its minimum of {:}",
+            self.asset_class,
+            decutil::format_dollars(&self.remaining),
+            decutil::format_dollars(&self.floor),
+        )
+    }
+}
+
+impl Error for WithdrawalFloorError {}
+
+/// A withdrawal larger than the asset classes we may sell from can cover
+#[derive(Debug, PartialEq, Eq)]
+pub enum WithdrawalError {
+    /// The asset classes chosen under a cap on trades hold too little
+    TooFewTrades {
+        withdrawal: Decimal,
+        available: Decimal,
+        max_trades: usize,
+    },
+    /// The whole portfolio is worth no more than the withdrawal
+    ExceedsPortfolio {
+        withdrawal: Decimal,
+        available: Decimal,
+    },
+    /// Asset classes other than the locked ones hold too little
+    MostlyLocked {
+        withdrawal: Decimal,
+        available: Decimal,
+    },
+}
+
+impl fmt::Display for WithdrawalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WithdrawalError::TooFewTrades {
+                withdrawal,
+                available,
+                max_trades,
+            } => write!(
+                f,
+                "Cannot withdraw {:} in only {:} trade(s); those asset classes hold {:}",
+                decutil::format_dollars(withdrawal),
+                max_trades,
+                decutil::format_dollars(available),
+            ),
+            WithdrawalError::ExceedsPortfolio {
+                withdrawal,
+                available,
+            } => write!(
+                f,
+                "Cannot withdraw {:} from a portfolio worth {:}",
+                decutil::format_dollars(withdrawal),
+                decutil::format_dollars(available),
+            ),
+            WithdrawalError::MostlyLocked {
+                withdrawal,
+                available,
+            } => write!(
+                f,
+                "Cannot withdraw {:}; unlocked asset classes hold only {:}",
+                decutil::format_dollars(withdrawal),
+                decutil::format_dollars(available),
+            ),
+        }
+    }
+}
+
+impl Error for WithdrawalError {}
+
+/// Decimal places kept in ratios & deviations
+///
+/// Division can otherwise yield up to 28 decimal places, which slows comparisons (and is
+/// noise when displayed). Twelve places is still sub-penny precision on a billion dollars.
+pub const RATIO_SCALE: u32 = 12;
+
+/// The range of ratios an asset class may drift within before it's rebalanced
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct Band {
+    pub min: Decimal,
+    pub max: Decimal,
+}
+
+impl Band {
+    /// The change in value which brings an asset class to the nearer edge of the band
+    ///
+    /// Classes already within the band need no change at all.
+    fn correction(&self, value: Decimal, new_total: Decimal) -> Decimal {
+        let (lowest, highest) = (self.min * new_total, self.max * new_total);
+        if value < lowest {
+            lowest - value
+        } else if value > highest {
+            highest - value
+        } else {
+            0.into()
+        }
+    }
+
+    fn contains(&self, value: Decimal, total: Decimal) -> bool {
+        self.correction(value, total) == 0.into()
+    }
+}
+
+/// How many asset classes are within their bands, before & after a contribution
+#[derive(Debug, PartialEq, Eq)]
+pub struct BandStatus {
+    /// Asset classes with a band at all (the rest are never in or out of band)
+    pub banded: usize,
+    pub in_band_before: usize,
+    pub in_band_after: usize,
+}
+
+impl fmt::Display for BandStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Within bands: {:} of {:} asset classes before, {:} after",
+            self.in_band_before, self.banded, self.in_band_after
+        )
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct AssetAllocation {
     pub asset_class: AssetClass,
     pub target_ratio: Decimal,
     underlying_assets: Vec<Asset>,
     future_contribution: Decimal,
+    /// Locked asset classes are never sold to fund a withdrawal
+    locked: bool,
+    /// If set, the class is only rebalanced once it drifts outside this band
+    band: Option<Band>,
 }
 
 impl Ord for AssetAllocation {
@@ -36,39 +271,105 @@ impl AssetAllocation {
             underlying_assets,
             target_ratio,
             future_contribution,
+            locked: false,
+            band: None,
         }
     }
 
+    /// Leave this asset class alone unless its ratio falls outside of the band
+    pub fn set_band(&mut self, band: Band) {
+        self.band = Some(band);
+    }
+
+    /// Exclude this asset class from withdrawals (contributions are unaffected)
+    pub fn lock(&mut self) {
+        self.locked = true;
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
     pub fn add_contribution(&mut self, contribution: Decimal) {
         self.future_contribution += contribution;
     }
 
-    fn current_value(&self) -> Decimal {
+    pub fn current_value(&self) -> Decimal {
         self.underlying_assets.iter().map(|asset| asset.value).sum()
     }
 
+    pub fn assets(&self) -> &[Asset] {
+        &self.underlying_assets
+    }
+
     fn future_value(&self) -> Decimal {
         self.current_value() + self.future_contribution
     }
 
+    /// Each underlying asset, with its share of this asset class's current value
+    ///
+    /// Shares always sum to exactly 1 (the last asset absorbs any rounding).
+    /// If the asset class is worth nothing, it's split evenly between its assets.
+    pub fn holding_shares(&self) -> Vec<(&Asset, Decimal)> {
+        let total = self.current_value();
+        let count = self.underlying_assets.len();
+        let mut assigned = Decimal::from(0);
+        self.underlying_assets
+            .iter()
+            .enumerate()
+            .map(|(i, asset)| {
+                let share = if i + 1 == count {
+                    Decimal::from(1) - assigned
+                } else if total == 0.into() {
+                    (Decimal::from(1) / Decimal::from(count as u64)).round_dp(RATIO_SCALE)
+                } else {
+                    (asset.value / total).round_dp(RATIO_SCALE)
+                };
+                assigned += share;
+                (asset, share)
+            })
+            .collect()
+    }
+
     pub fn add_asset(&mut self, asset: Asset) {
         if asset.asset_class != self.asset_class {
             panic!("Asset types must match");
         }
-        self.underlying_assets.push(asset);
-        // TODO: Could use a BinaryHeap instead for better efficiency
-        self.underlying_assets.sort();
+        // Keep assets sorted as they're added, rather than re-sorting each time
+        let index = self
+            .underlying_assets
+            .binary_search(&asset)
+            .unwrap_or_else(|index| index);
+        self.underlying_assets.insert(index, asset);
     }
 
     fn percent_holdings(&self, portfolio_total: Decimal) -> Decimal {
-        self.future_value() / portfolio_total
+        (self.future_value() / portfolio_total).round_dp(RATIO_SCALE)
     }
 
     fn deviation(&self, new_total: Decimal) -> Decimal {
         // Identify the percentage of total holdings that this asset will hold
         // (Assesses current value, pending contributions over the eventual total portfolio value)
         let actual = self.percent_holdings(new_total);
-        (actual / self.target_ratio) - Decimal::from(1)
+        if self.target_ratio == 0.into() {
+            // With a target of 0%, any holdings at all are entirely overweight
+            // (Such classes are sold off before solving for deviations; see `optimally_allocate`)
+            return if actual == 0.into() {
+                0.into()
+            } else {
+                Decimal::MAX
+            };
+        }
+        ((actual / self.target_ratio) - Decimal::from(1)).round_dp(RATIO_SCALE)
+    }
+
+    /// Deposit or withdraw just enough that this asset deviates from its target by the given amount
+    fn contribute_to_deviation(&mut self, new_total: Decimal, deviation_target: Decimal) {
+        // Equivalent to `target_value * (deviation_target - deviation)`, but avoids dividing by
+        // (then multiplying by) the target ratio, which would introduce rounding errors.
+        let target_value = new_total * self.target_ratio;
+        let delta = target_value * (Decimal::from(1) + deviation_target) - self.future_value();
+        self.add_contribution(delta);
     }
 }
 
@@ -82,485 +383,3224 @@ impl fmt::Display for AssetAllocation {
             self.target_ratio * Decimal::from(100)
         )?;
 
-        for asset in &self.underlying_assets {
+        // A lone fund is trivially 100% of its asset class; only report shares when split
+        let show_shares = self.underlying_assets.len() > 1;
+        for (asset, share) in self.holding_shares() {
             write!(f, "\n  - {:}", asset)?;
+            if show_shares {
+                write!(f, " {{{:.1}% of class}}", share * Decimal::from(100))?;
+            }
         }
         Ok(())
     }
 }
 
-pub struct Portfolio {
-    allocations: Vec<AssetAllocation>,
+/// The order in which asset classes are displayed
+///
+/// This affects only display! Internally, allocations are sorted as needed by the optimizer.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DisplayOrder {
+    /// Largest allocations first
+    #[default]
+    Value,
+    /// Alphabetically, by the asset class's name
+    Alphabetical,
+    /// In the given order (any other asset classes follow, largest first)
+    Custom(Vec<AssetClass>),
 }
 
-impl fmt::Display for Portfolio {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        // Display allocations in order, starting from the largest
-        for allocation in (&self.allocations).iter() {
-            writeln!(f, "{:}", allocation)?;
+/// Relative deviations from target which separate "on target" from minor & major drift
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct DriftThresholds {
+    pub minor: Decimal,
+    pub major: Decimal,
+}
+
+impl Default for DriftThresholds {
+    fn default() -> DriftThresholds {
+        DriftThresholds {
+            minor: Decimal::new(2, 2),
+            major: Decimal::new(10, 2),
+        }
+    }
+}
+
+/// An at-a-glance read of how far an asset class is from its target
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DriftStatus {
+    OnTarget,
+    MinorDrift,
+    MajorDrift,
+}
+
+impl DriftStatus {
+    /// Classify a relative deviation from target (e.g. -0.05 for 5% below target)
+    pub fn classify(deviation: Decimal, thresholds: &DriftThresholds) -> DriftStatus {
+        let deviation = deviation.abs();
+        if deviation < thresholds.minor {
+            DriftStatus::OnTarget
+        } else if deviation < thresholds.major {
+            DriftStatus::MinorDrift
+        } else {
+            DriftStatus::MajorDrift
         }
+    }
+}
+
+impl fmt::Display for DriftStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let marker = match self {
+            DriftStatus::OnTarget => "on target",
+            DriftStatus::MinorDrift => "minor drift",
+            DriftStatus::MajorDrift => "major drift",
+        };
+        write!(f, "{:}", marker)
+    }
+}
+
+/// An account to fill with contributions, in priority order (see `Portfolio::sweep`)
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SweepAccount {
+    /// Account name, as given to each holding (e.g. "401k")
+    pub account: String,
+    /// How much more this account may receive (e.g. remaining annual room), if limited
+    pub room: Option<Decimal>,
+}
+
+/// Two asset classes to hold in a fixed ratio, e.g. $2 of US Total for every $1 of US Small/Mid
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct LinkedClasses {
+    pub primary: AssetClass,
+    pub secondary: AssetClass,
+    /// Dollars of the primary class per dollar of the secondary
+    pub ratio: Decimal,
+}
+
+/// Money to put into one account, for one asset class
+#[derive(Debug, PartialEq, Eq)]
+pub struct AccountContribution {
+    pub account: String,
+    pub asset_class: AssetClass,
+    pub amount: Decimal,
+}
+
+impl fmt::Display for AccountContribution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "Portfolio total: {:}",
-            decutil::format_dollars(&self.current_value())
+            "{:}: {:} into {:}",
+            self.account,
+            decutil::format_dollars(&self.amount),
+            self.asset_class
         )
     }
 }
 
-impl Portfolio {
-    pub fn new(mut allocations: Vec<AssetAllocation>) -> Portfolio {
-        allocations.sort();
-        Portfolio { allocations }
+/// Which accounts receive each asset class's contribution, and what no account has room for
+#[derive(Debug, PartialEq, Eq)]
+pub struct SweepPlan {
+    pub contributions: Vec<AccountContribution>,
+    /// Contributions (by asset class) exceeding the room of every account holding the class
+    pub over_room: BTreeMap<AssetClass, Decimal>,
+}
+
+/// What to buy of one fund, in whole increments of shares where the fund trades that way
+#[derive(Debug, PartialEq, Eq)]
+pub struct BuyInstruction {
+    /// Ticker (or name, if there's no ticker)
+    pub fund: String,
+    pub amount: Decimal,
+    /// Shares to buy, if the fund is bought in share increments (otherwise, in dollars)
+    pub shares: Option<Decimal>,
+}
+
+impl fmt::Display for BuyInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.shares {
+            Some(shares) => write!(
+                f,
+                "{:}: {:} shares (${:.2})",
+                self.fund,
+                shares.normalize(),
+                self.amount
+            ),
+            None => write!(f, "{:}: ${:.2}", self.fund, self.amount),
+        }
     }
+}
 
-    pub fn current_value(&self) -> Decimal {
-        self.allocations
-            .iter()
-            .map(|allocation| allocation.current_value())
-            .sum()
+/// What to buy of each fund, and whatever couldn't be spent in whole increments
+#[derive(Debug, PartialEq, Eq)]
+pub struct BuyPlan {
+    pub instructions: Vec<BuyInstruction>,
+    /// Money planned for contributions, but left over once purchases were rounded down
+    ///
+    /// Rounded to the cent, so this is zero unless at least a penny is left.
+    pub leftover: Decimal,
+}
+
+/// One fund within an asset class, and its part in the class's contribution
+#[derive(Debug, PartialEq, Eq)]
+pub struct PlannedFund {
+    /// Ticker (or name, if there's no ticker)
+    pub fund: String,
+    pub current_value: Decimal,
+    /// Fraction of the asset class's contribution (or withdrawal) made in this fund
+    pub share: Decimal,
+}
+
+/// The amount to deposit into (or withdraw from) one asset class
+#[derive(Debug, PartialEq, Eq)]
+pub struct PlannedContribution {
+    pub asset_class: AssetClass,
+    /// Positive for a purchase, negative for a sale
+    pub amount: Decimal,
+    pub start_ratio: Decimal,
+    pub end_ratio: Decimal,
+    pub target_ratio: Decimal,
+    /// Funds splitting the amount, in proportion to their value (empty if none are held yet)
+    pub funds: Vec<PlannedFund>,
+}
+
+impl PlannedContribution {
+    /// How far a ratio falls short of the target, relative to the target (negative if over)
+    ///
+    /// When targeting 0%, any holdings at all are wholly in excess (-100%).
+    fn relative_deviation(&self, ratio: Decimal) -> Decimal {
+        if self.target_ratio != 0.into() {
+            Decimal::from(1) - (ratio / self.target_ratio)
+        } else if ratio == 0.into() {
+            0.into()
+        } else {
+            Decimal::from(-1)
+        }
     }
 
-    /// Identify the minimum amount to bring the portfolio into perfect balance.
-    pub fn minimum_addition_to_balance(&self) -> Decimal {
-        let total = self.current_value();
-        if total == 0.into() {
+    /// The fraction of the whole contribution (or withdrawal) going to this asset class
+    pub fn share_of(&self, contribution: Decimal) -> Decimal {
+        if contribution == 0.into() {
             return 0.into();
         }
+        self.amount.abs() / contribution.abs()
+    }
+}
 
-        // First, find the most overallocated fund.
-        let most_overallocated = self
-            .allocations
-            .iter()
-            .max_by(|a, b| a.deviation(total).cmp(&b.deviation(total)))
-            .expect("Can't find most overallocated asset; no allocations found!");
-
-        // We will contribute to other funds *first* until this fund reaches its target ratio.
-        // Once that minimum amount is contributed, we'll be in balance.
-        let min_new_portfolio_value =
-            most_overallocated.current_value() / most_overallocated.target_ratio;
+/// The outcome of allocating a contribution (or withdrawal) across a portfolio
+#[derive(Debug, PartialEq, Eq)]
+pub struct ContributionPlan {
+    pub starting_total: Decimal,
+    /// Net amount deposited (negative if withdrawing)
+    pub contribution: Decimal,
+    pub contributions: Vec<PlannedContribution>,
+}
 
-        min_new_portfolio_value - total
+impl ContributionPlan {
+    pub fn ending_total(&self) -> Decimal {
+        self.starting_total + self.contribution
     }
 
-    fn future_value(&self) -> Decimal {
-        self.allocations
-            .iter()
-            .map(|allocation| allocation.future_value())
-            .sum()
-    }
+    /// Round each amount to whole cents, such that they sum exactly to the (rounded) contribution
+    ///
+    /// Any rounding residual (generally a penny or two) goes to the largest amount.
+    /// Ending ratios are recomputed to reflect the rounded amounts.
+    pub fn reconcile_pennies(&mut self) {
+        for planned in self.contributions.iter_mut() {
+            planned.amount = planned.amount.round_dp(2);
+        }
+        self.contribution = self.contribution.round_dp(2);
+        let rounded_sum: Decimal = self.contributions.iter().map(|p| p.amount).sum();
+        let residual = self.contribution - rounded_sum;
+
+        if let Some(largest) = self
+            .contributions
+            .iter_mut()
+            .max_by_key(|planned| planned.amount.abs())
+        {
+            largest.amount += residual;
+        }
 
-    fn sum_target_ratios(&self) -> Decimal {
-        self.allocations
-            .iter()
-            .map(|allocation| allocation.target_ratio)
-            .sum()
+        let ending_total = self.ending_total();
+        for planned in self.contributions.iter_mut() {
+            planned.end_ratio = if ending_total == 0.into() {
+                0.into()
+            } else {
+                let ending_value = planned.start_ratio * self.starting_total + planned.amount;
+                (ending_value / ending_total).round_dp(RATIO_SCALE)
+            };
+        }
     }
 
-    fn num_asset_classes(&self) -> usize {
-        self.allocations.len()
+    pub fn describe(&self) {
+        self.describe_with_status(&DriftThresholds::default(), false);
     }
 
-    pub fn describe_future_contributions(&self) {
-        let portfolio_total = self.current_value();
-        let new_total = self.future_value();
-        let verb = if new_total < portfolio_total {
+    /// Describe each contribution, marking how close each asset class ends up to its target
+    ///
+    /// Optionally, show each amount's share of the whole contribution as well.
+    pub fn describe_with_status(&self, thresholds: &DriftThresholds, show_shares: bool) {
+        let verb = if self.contribution.is_sign_negative() {
             "Withdraw"
         } else {
             "Contribute"
         };
         println!("{:} the following amounts:", verb);
 
-        let zero: Decimal = 0.into();
-        for asset in self.allocations.iter() {
-            let start_ratio: Decimal = if portfolio_total == zero {
-                // If our starting portfolio was empty, we don't want to divide by zero
-                // Treat an asset class as holding 0% of an empty portfolio
-                zero
-            } else {
-                asset.current_value() / portfolio_total
-            };
-            let end_ratio = asset.percent_holdings(new_total);
-
-            println!(
-                " - {:}: ${:.2}",
-                asset.asset_class,
-                asset.future_contribution.abs()
-            );
+        for planned in self.contributions.iter() {
+            print!(" - {:}: ${:.2}", planned.asset_class, planned.amount.abs());
+            if show_shares {
+                let share = planned.share_of(self.contribution) * Decimal::from(100);
+                print!(" ({:}%)", share.round_dp(1).normalize());
+            }
+            println!();
             print!(
                 "   {:.2}% -> {:.2}% (🎯 {:.2}%)",
-                start_ratio * Decimal::from(100),
-                end_ratio * Decimal::from(100),
-                asset.target_ratio * Decimal::from(100),
+                planned.start_ratio * Decimal::from(100),
+                planned.end_ratio * Decimal::from(100),
+                planned.target_ratio * Decimal::from(100),
             );
 
             // How much the resulting ratio deviates *relative* to the target
             // Small deviations are to be expected, but high deviations may call for rebalancing
             // (Absolute deviation should be obvious by just reporting current & target ratios)
-            let start_deviation = Decimal::from(1) - (start_ratio / asset.target_ratio);
-            let end_deviation = Decimal::from(1) - (end_ratio / asset.target_ratio);
+            let start_deviation = planned.relative_deviation(planned.start_ratio);
+            let end_deviation = planned.relative_deviation(planned.end_ratio);
 
             // For sufficiently high deviations, report the starting & ending deviation
             if cmp::max(start_deviation.abs(), end_deviation.abs()) > Decimal::new(2, 2) {
-                println!(
+                print!(
                     " Δ [{:.1}% -> {:.1}%]",
                     start_deviation * Decimal::from(100),
                     end_deviation * Decimal::from(100),
                 );
-            } else {
-                println!();
             }
+            println!(" [{:}]", DriftStatus::classify(end_deviation, thresholds));
         }
     }
 }
 
-fn proportionally_allocate(mut portfolio: Portfolio, contribution: Decimal) -> Portfolio {
-    for asset in portfolio.allocations.iter_mut() {
-        let amount = asset.target_ratio * contribution;
-        asset.add_contribution(amount);
+/// One asset class in a `PortfolioReport`
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct AllocationReport {
+    pub asset_class: AssetClass,
+    pub target_ratio: Decimal,
+    pub current_value: Decimal,
+    /// Positive for a purchase, negative for a sale
+    pub future_contribution: Decimal,
+}
+
+/// A machine-readable view of the portfolio, along with any pending contributions
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct PortfolioReport {
+    pub total_value: Decimal,
+    pub minimum_to_balance: Decimal,
+    /// Net amount to be deposited (negative if withdrawing)
+    pub contribution: Decimal,
+    pub allocations: Vec<AllocationReport>,
+}
+
+pub struct Portfolio {
+    allocations: Vec<AssetAllocation>,
+    display_order: DisplayOrder,
+}
+
+impl fmt::Display for Portfolio {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for allocation in self.allocations_for_display() {
+            writeln!(f, "{:}", allocation)?;
+        }
+        write!(
+            f,
+            "Portfolio total: {:}",
+            decutil::format_dollars(&self.current_value())
+        )?;
+        if let Some(totals) = self.describe_totals() {
+            write!(f, "\n{:}", totals)?;
+        }
+        Ok(())
     }
-    portfolio
 }
 
-pub fn optimally_allocate(mut portfolio: Portfolio, contribution: Decimal) -> Portfolio {
-    if contribution == 0.into() {
-        panic!("Must deposit or withdraw in order to rebalance");
+impl Portfolio {
+    pub fn new(mut allocations: Vec<AssetAllocation>) -> Portfolio {
+        allocations.sort();
+        Portfolio {
+            allocations,
+            display_order: DisplayOrder::default(),
+        }
     }
 
-    if portfolio.sum_target_ratios() != 1.into() {
-        panic!("Cannot rebalance unless total is 100%");
+    pub fn allocations(&self) -> &[AssetAllocation] {
+        &self.allocations
     }
 
-    let current_value = portfolio.current_value();
-    if contribution.is_sign_negative() {
-        assert!(
-            contribution.abs() < current_value,
-            "Cannot withdraw more than portfolio!"
-        );
+    /// Attach user-supplied notes (keyed by symbol or account name) to matching assets
+    pub fn annotate(&mut self, notes: &HashMap<String, String>) {
+        for allocation in self.allocations.iter_mut() {
+            for asset in allocation.underlying_assets.iter_mut() {
+                asset.annotate(notes);
+            }
+        }
     }
-    if current_value == 0.into() {
-        return proportionally_allocate(portfolio, contribution);
+
+    /// Show this many decimal places of shares held
+    pub fn set_share_precision(&mut self, share_precision: u32) {
+        for allocation in self.allocations.iter_mut() {
+            for asset in allocation.underlying_assets.iter_mut() {
+                asset.set_share_precision(share_precision);
+            }
+        }
     }
 
-    assert!(
-        !current_value.is_sign_negative(),
-        "Can't handle a portfolio with a negative balance"
-    );
+    /// Attach durations (keyed by symbol) to matching bond funds
+    pub fn set_durations(&mut self, durations: &HashMap<String, Decimal>) {
+        for allocation in self.allocations.iter_mut() {
+            for asset in allocation.underlying_assets.iter_mut() {
+                asset.set_duration(durations);
+            }
+        }
+    }
 
-    // The amount left for contribution begins as the total amount we have available
-    // (We will portion this money out sequentially to each fund, eventually exhausting it)
-    let mut amount_left_to_contribute = contribution;
+    /// Mark the given asset classes as "do not withdraw"
+    pub fn lock_classes(&mut self, locked: &[AssetClass]) {
+        for allocation in self.allocations.iter_mut() {
+            if locked.contains(&allocation.asset_class) {
+                allocation.lock();
+            }
+        }
+    }
 
-    // The new total is our portfolio's current value, plus the amount we'll contribute
-    // In other words, this will be the denomenator for calculating final percent allocation
-    let new_total = current_value + contribution;
+    pub fn set_bands(&mut self, bands: &BTreeMap<AssetClass, Band>) {
+        for allocation in self.allocations.iter_mut() {
+            if let Some(band) = bands.get(&allocation.asset_class) {
+                allocation.set_band(*band);
+            }
+        }
+    }
 
-    // We sort our asset allocations by how much they've deviated from their target
-    // If contributing: underallocated funds come first. Overallocated funds come last.
-    // If withdrawing: overallocated funds come first. Underallocated funds come last.
-    portfolio
-        .allocations
-        .sort_by(|a, b| a.deviation(new_total).cmp(&b.deviation(new_total)));
-    if contribution.is_sign_negative() {
-        portfolio.allocations.reverse();
+    pub fn set_display_order(&mut self, display_order: DisplayOrder) {
+        self.display_order = display_order;
     }
 
-    let num_assets = portfolio.num_asset_classes();
+    fn allocations_for_display(&self) -> Vec<&AssetAllocation> {
+        // Start from the largest allocations, so that ties (if any) are broken by value
+        let mut allocations: Vec<&AssetAllocation> = self.allocations.iter().collect();
+        allocations.sort();
 
-    let (deviation_target, index_to_stop): (Decimal, usize) = {
-        // As we loop through assets, we track the sum of all ideal fund values
-        let mut summed_targets_of_affected_assets: Decimal = 0.into();
+        match &self.display_order {
+            DisplayOrder::Value => (),
+            DisplayOrder::Alphabetical => {
+                allocations.sort_by_key(|allocation| allocation.asset_class.to_string())
+            }
+            DisplayOrder::Custom(order) => allocations.sort_by_key(|allocation| {
+                order
+                    .iter()
+                    .position(|asset_class| asset_class == &allocation.asset_class)
+                    .unwrap_or(order.len())
+            }),
+        }
+        allocations
+    }
 
-        // We iterate through assets based on which need alteration first (to minimize variation)
-        // We may not end up depositing/withdrawing from all accounts.
-        //
-        // As we loop through, we keep track of:
-        // 1. Which assets receive deposits/withdrawals (first through `last_known_index`)
-        // 2. The fractional deviation used to calculate the magnitude of deposits/withdrawals
-        //    `deviation_target` will be the deviation (or "approximation error") of the last
-        //    asset class we're optimizing.
-        let mut deviation_target = 0.into();
-        let mut last_known_index = 0;
-
-        for (index, asset) in portfolio.allocations.iter().enumerate() {
-            assert!(amount_left_to_contribute.abs() > 0.into());
-
-            // Because we have money left to distribute, we know the asset at portfolio.allocations[index] will
-            // be affected (receiving deposits if amount > 0, or withdrawn if amount < 0)
-            last_known_index = index;
-
-            // Identify how much this asset's allocation deviates from its target
-            // On the last loop iteration, this target is used to calculate final asset deltas
-            deviation_target = asset.deviation(new_total);
-
-            // Identify the total value of this asset that brings it in line with our target ratio
-            // Importantly, this is the total value _with the new contribution included_
-            // (We can use this value to calculate required deposits/withdrawals)
-            let target_value = new_total * asset.target_ratio;
-
-            summed_targets_of_affected_assets += target_value;
-
-            // Peek ahead in the vector to get the asset which is the second-most underallocated
-            // (We will contribute proportionally until all assets are at least that close to their target)
-            let next_lowest_deviation = if index >= (num_assets - 1) {
-                0.into()
+    /// Place each asset into its matching allocation.
+    ///
+    /// Assets of an asset class not included in the ideal allocations are ignored.
+    pub fn from_assets(ideal_allocations: Vec<AssetAllocation>, assets: Vec<Asset>) -> Portfolio {
+        let mut by_asset_class: HashMap<AssetClass, AssetAllocation> = HashMap::new();
+        for allocation in ideal_allocations.into_iter() {
+            by_asset_class.insert(allocation.asset_class.clone(), allocation);
+        }
+
+        for asset in assets {
+            if let Some(allocation) = by_asset_class.get_mut(&asset.asset_class) {
+                allocation.add_asset(asset);
+            }
+        }
+        Portfolio::new(by_asset_class.into_values().collect())
+    }
+
+    /// Build a portfolio entirely from in-memory data (no GnuCash book or CSV needed)
+    pub fn from_holdings(
+        ideal_allocations: Vec<AssetAllocation>,
+        holdings: &[Holding],
+        classifications: &AssetClassifications,
+        prices: &HashMap<String, Decimal>,
+    ) -> Result<Portfolio, HoldingError> {
+        let assets = assets::value_holdings(holdings, classifications, prices)?;
+        Ok(Portfolio::from_assets(ideal_allocations, assets))
+    }
+
+    pub fn current_value(&self) -> Decimal {
+        self.allocations
+            .iter()
+            .map(|allocation| allocation.current_value())
+            .sum()
+    }
+
+    /// The current value of all I Bonds held
+    pub fn ibond_value(&self) -> Decimal {
+        self.allocations
+            .iter()
+            .flat_map(|allocation| allocation.assets())
+            .filter(|asset| asset.symbol.as_deref().is_some_and(assets::is_series_i))
+            .map(|asset| asset.value)
+            .sum()
+    }
+
+    /// Identify holdings which could be consolidated into a cheaper share class
+    pub fn share_class_upgrades(
+        &self,
+        upgrades: &HashMap<String, String>,
+    ) -> Vec<ShareClassUpgrade> {
+        self.allocations
+            .iter()
+            .flat_map(|allocation| allocation.underlying_assets.iter())
+            .filter_map(|asset| asset.share_class_upgrade(upgrades))
+            .collect()
+    }
+
+    /// Migrate current holdings to a new target allocation, buying & selling as needed.
+    ///
+    /// Unlike `optimally_allocate`, no money enters or leaves the portfolio. Instead, every asset
+    /// class is brought exactly to its new target. Asset classes not yet held start out empty,
+    /// but every asset class currently held must be given a (non-zero) target.
+    pub fn rebalance_into(
+        self,
+        new_targets: Vec<AssetAllocation>,
+    ) -> Result<Portfolio, MigrationError> {
+        let new_classes: HashSet<AssetClass> = new_targets
+            .iter()
+            .map(|allocation| allocation.asset_class.clone())
+            .collect();
+
+        let Portfolio {
+            allocations,
+            display_order,
+        } = self;
+        let assets: Vec<Asset> = allocations
+            .into_iter()
+            .flat_map(|allocation| allocation.underlying_assets)
+            .collect();
+        if let Some(asset) = assets
+            .iter()
+            .find(|asset| !new_classes.contains(&asset.asset_class))
+        {
+            return Err(MigrationError::MissingTarget(asset.asset_class.clone()));
+        }
+
+        let mut portfolio = Portfolio::from_assets(new_targets, assets);
+        portfolio.set_display_order(display_order);
+        let total_ratio = portfolio.sum_target_ratios();
+        if total_ratio != 1.into() {
+            return Err(TargetRatioError { total: total_ratio }.into());
+        }
+
+        let total = portfolio.current_value();
+        for allocation in portfolio.allocations.iter_mut() {
+            allocation.contribute_to_deviation(total, 0.into());
+        }
+        Ok(portfolio)
+    }
+
+    /// Identify the contribution which brings the portfolio to a (presumably round) total
+    pub fn contribution_to_reach(
+        &self,
+        target_total: Decimal,
+    ) -> Result<Decimal, TargetTotalError> {
+        let current_value = self.current_value();
+        if target_total <= current_value {
+            return Err(TargetTotalError {
+                target_total,
+                current_value,
+            });
+        }
+        Ok(target_total - current_value)
+    }
+
+    /// Identify how much to put into just one asset class to bring it to its target.
+    ///
+    /// All other asset classes are held fixed, but the portfolio's total changes with the
+    /// contribution. A negative amount means the asset class is overweight, and should be sold.
+    /// Returns `None` if no single trade can bring the asset class to target (i.e. it targets
+    /// 100%, yet other asset classes hold value).
+    pub fn contribution_to_target(&self, asset_class: &AssetClass) -> Option<Decimal> {
+        let (current, target_ratio) = self
+            .allocations
+            .iter()
+            .find(|allocation| &allocation.asset_class == asset_class)
+            .map(|allocation| (allocation.current_value(), allocation.target_ratio))
+            .unwrap_or_else(|| (0.into(), 0.into()));
+        let total = self.current_value();
+        if target_ratio == 1.into() {
+            // Only by selling everything else could this class ever be "on target"
+            return if current == total {
+                Some(0.into())
             } else {
-                portfolio.allocations[index + 1].deviation(new_total)
+                None
             };
+        }
 
-            // Solve for the amount that brings this asset as close to its target as the next closest
-            let delta: Decimal =
-                summed_targets_of_affected_assets * (next_lowest_deviation - deviation_target);
+        // Solve for x: (current + x) / (total + x) = target_ratio
+        Some((target_ratio * total - current) / (Decimal::from(1) - target_ratio))
+    }
 
-            if delta.abs() > amount_left_to_contribute.abs() {
-                // If we don't have enough money left to contribute the full amount, then we'll
-                // dedicate what's left to the given fund, and exit.
-                deviation_target += amount_left_to_contribute / summed_targets_of_affected_assets;
-                amount_left_to_contribute = 0.into();
-            } else {
-                // Otherwise, this asset is now as close to its target as the next worst asset(s)
-                // We continue by bringing these assets closer to their targets
-                amount_left_to_contribute -= delta;
-                deviation_target = next_lowest_deviation;
+    /// Identify the minimum amount to bring the portfolio into perfect balance.
+    pub fn minimum_addition_to_balance(&self) -> Decimal {
+        let total = self.current_value();
+        if total == 0.into() {
+            return 0.into();
+        }
+
+        // First, find the most overallocated fund.
+        // (No contribution brings a class targeting 0% to target; only selling can)
+        let most_overallocated = self
+            .allocations
+            .iter()
+            .filter(|allocation| allocation.target_ratio != 0.into())
+            .max_by(|a, b| a.deviation(total).cmp(&b.deviation(total)))
+            .expect("Can't find most overallocated asset; no allocations found!");
+
+        // We will contribute to other funds *first* until this fund reaches its target ratio.
+        // Once that minimum amount is contributed, we'll be in balance.
+        let min_new_portfolio_value =
+            most_overallocated.current_value() / most_overallocated.target_ratio;
+
+        // (With holdings targeting 0% set aside, the rest may already be under their targets)
+        cmp::max(min_new_portfolio_value - total, 0.into())
+    }
+
+    /// Sum the gains (or losses) on holdings not yet sold, given the cost basis of each symbol
+    ///
+    /// Holdings without a known basis (or without a symbol) are skipped.
+    pub fn unrealized_gains(&self, cost_basis: &HashMap<String, Decimal>) -> Decimal {
+        let mut value_by_symbol: HashMap<&str, Decimal> = HashMap::new();
+        for asset in self
+            .allocations
+            .iter()
+            .flat_map(|allocation| allocation.underlying_assets.iter())
+        {
+            if let Some(symbol) = &asset.symbol {
+                *value_by_symbol.entry(symbol).or_default() += asset.value;
+            }
+        }
+        value_by_symbol
+            .into_iter()
+            .filter_map(|(symbol, value)| cost_basis.get(symbol).map(|basis| value - basis))
+            .sum()
+    }
+
+    /// Identify asset classes holding more than `dollar_threshold` above their target value
+    ///
+    /// In a large portfolio, a small relative deviation may still be a lot of money.
+    /// Results are sorted by the amount over target, largest first.
+    pub fn absolute_overweights(&self, dollar_threshold: Decimal) -> Vec<(AssetClass, Decimal)> {
+        let total = self.current_value();
+        let mut overweights: Vec<(AssetClass, Decimal)> = self
+            .allocations
+            .iter()
+            .map(|allocation| {
+                let excess = allocation.current_value() - total * allocation.target_ratio;
+                (allocation.asset_class.clone(), excess)
+            })
+            .filter(|(_, excess)| *excess > dollar_threshold)
+            .collect();
+        overweights.sort_by_key(|(_, excess)| cmp::Reverse(*excess));
+        overweights
+    }
+
+    fn future_value(&self) -> Decimal {
+        self.allocations
+            .iter()
+            .map(|allocation| allocation.future_value())
+            .sum()
+    }
+
+    /// Compare the total now with the total once planned trades are made (if any are planned)
+    pub fn describe_totals(&self) -> Option<String> {
+        let (current, future) = (self.current_value(), self.future_value());
+        if current == future {
+            return None;
+        }
+        let label = if future < current {
+            "After withdrawal"
+        } else {
+            "After contribution"
+        };
+        Some(format!(
+            "Current: {:} → {:}: {:}",
+            decutil::format_dollars(&current),
+            label,
+            decutil::format_dollars(&future)
+        ))
+    }
+
+    /// Confirm that, after planned trades, each class sold holds at least `min_fraction` of its target
+    ///
+    /// A withdrawal can otherwise wipe out a small asset class (e.g. when others are locked).
+    /// Classes which aren't being sold are never refused, however far below target they are.
+    pub fn check_withdrawal_floor(
+        &self,
+        min_fraction: Decimal,
+    ) -> Result<(), WithdrawalFloorError> {
+        let new_total = self.future_value();
+        for allocation in self
+            .allocations
+            .iter()
+            .filter(|allocation| allocation.future_contribution < 0.into())
+        {
+            let floor = new_total * allocation.target_ratio * min_fraction;
+            let remaining = allocation.future_value();
+            if remaining < floor {
+                return Err(WithdrawalFloorError {
+                    asset_class: allocation.asset_class.clone(),
+                    remaining,
+                    floor,
+                });
             }
+        }
+        Ok(())
+    }
+
+    /// Confirm that a withdrawal leaves something behind, without selling from locked classes
+    ///
+    /// Contributions are never refused.
+    pub fn check_withdrawal(&self, contribution: Decimal) -> Result<(), WithdrawalError> {
+        if !contribution.is_sign_negative() {
+            return Ok(());
+        }
+        let current_value = self.current_value();
+        if contribution.abs() >= current_value {
+            return Err(WithdrawalError::ExceedsPortfolio {
+                withdrawal: contribution.abs(),
+                available: current_value,
+            });
+        }
+        if !self.allocations.iter().any(|a| a.locked) {
+            return Ok(());
+        }
+        let unlocked_value: Decimal = self
+            .allocations
+            .iter()
+            .filter(|allocation| !allocation.locked)
+            .map(|allocation| allocation.current_value())
+            .sum();
+        if contribution.abs() > unlocked_value {
+            return Err(WithdrawalError::MostlyLocked {
+                withdrawal: contribution.abs(),
+                available: unlocked_value,
+            });
+        }
+        Ok(())
+    }
+
+    /// Decide which accounts receive each asset class's planned contribution
+    ///
+    /// Of the accounts holding an asset class, those earlier in `order` are filled first (up to
+    /// their room, which is shared across asset classes). Accounts not in `order` come last.
+    /// Whatever exceeds an account's room carries over to the next; whatever no account has
+    /// room for is left out of the contributions. Withdrawals are left to the user.
+    pub fn sweep(&self, order: &[SweepAccount]) -> SweepPlan {
+        let mut room: HashMap<&str, Option<Decimal>> = order
+            .iter()
+            .map(|sweep| (sweep.account.as_str(), sweep.room))
+            .collect();
+        let priority = |account: &str| {
+            order
+                .iter()
+                .position(|sweep| sweep.account == account)
+                .unwrap_or(order.len())
+        };
 
-            // Two cases bring us to an exit:
-            // 1. We contributed the exact amount to bring the asset as close to its target as the
-            //    next worst (rare, but possible)
-            // 2. We were not able to contribute the full amount, so we contributed what was left
-            if amount_left_to_contribute == 0.into() {
-                break;
+        let mut swept = Vec::new();
+        let mut over_room = BTreeMap::new();
+        for allocation in self.allocations.iter() {
+            if allocation.future_contribution <= 0.into() {
+                continue;
+            }
+            let mut accounts: Vec<&str> = allocation
+                .underlying_assets
+                .iter()
+                .map(|asset| asset.name.as_str())
+                .collect();
+            accounts.sort_by_key(|account| (priority(account), *account));
+            accounts.dedup();
+
+            let mut amount_left = allocation.future_contribution;
+            for account in accounts.iter() {
+                let amount = match room.get(account).copied().flatten() {
+                    Some(available) => cmp::min(available, amount_left),
+                    None => amount_left,
+                };
+                if amount <= 0.into() {
+                    continue;
+                }
+                if let Some(Some(available)) = room.get_mut(account) {
+                    *available -= amount;
+                }
+                swept.push(AccountContribution {
+                    account: account.to_string(),
+                    asset_class: allocation.asset_class.clone(),
+                    amount,
+                });
+                amount_left -= amount;
+                if amount_left == 0.into() {
+                    break;
+                }
+            }
+            if amount_left > 0.into() {
+                over_room.insert(allocation.asset_class.clone(), amount_left);
             }
         }
+        SweepPlan {
+            contributions: swept,
+            over_room,
+        }
+    }
 
-        let index_to_stop = last_known_index + 1;
-        (deviation_target, index_to_stop)
-    };
+    /// Split each asset class's contribution across its funds (in proportion to their value)
+    ///
+    /// Funds with an increment (keyed by symbol, e.g. 1 for whole shares) are bought in
+    /// multiples of that increment, never exceeding the amount planned; others are bought
+    /// in dollars. Asset classes without any fund yet are named in place of a fund.
+    /// Withdrawals are left out.
+    pub fn buy_instructions(&self, increments: &HashMap<String, Decimal>) -> BuyPlan {
+        let mut instructions = Vec::new();
+        let mut planned = Decimal::from(0);
+        for allocation in self.allocations.iter() {
+            if allocation.future_contribution <= 0.into() {
+                continue;
+            }
+            planned += allocation.future_contribution;
+            if allocation.underlying_assets.is_empty() {
+                instructions.push(BuyInstruction {
+                    fund: format!("{:} (no fund held yet)", allocation.asset_class),
+                    amount: allocation.future_contribution,
+                    shares: None,
+                });
+                continue;
+            }
+            for (asset, share) in allocation.holding_shares() {
+                let amount = allocation.future_contribution * share;
+                let fund = asset.symbol.clone().unwrap_or_else(|| asset.name.clone());
+                let increment = asset
+                    .symbol
+                    .as_ref()
+                    .and_then(|symbol| increments.get(symbol));
+                let instruction = match (increment, asset.last_price()) {
+                    (Some(increment), Some(price)) if price > 0.into() => {
+                        let shares = (amount / price / increment).floor() * increment;
+                        BuyInstruction {
+                            fund,
+                            amount: shares * price,
+                            shares: Some(shares),
+                        }
+                    }
+                    _ => BuyInstruction {
+                        fund,
+                        amount,
+                        shares: None,
+                    },
+                };
+                instructions.push(instruction);
+            }
+        }
+        let spent: Decimal = instructions
+            .iter()
+            .map(|instruction| instruction.amount)
+            .sum();
+        BuyPlan {
+            instructions,
+            leftover: (planned - spent).round_dp(2),
+        }
+    }
 
-    for (index, asset) in portfolio.allocations.iter_mut().enumerate() {
-        if index == index_to_stop {
-            break;
+    fn sum_target_ratios(&self) -> Decimal {
+        self.allocations
+            .iter()
+            .map(|allocation| allocation.target_ratio)
+            .sum()
+    }
+
+    /// Summarize the pending contributions (or withdrawals) to each asset class
+    pub fn contribution_plan(&self) -> ContributionPlan {
+        let starting_total = self.current_value();
+        let new_total = self.future_value();
+
+        let zero: Decimal = 0.into();
+        let contributions = self
+            .allocations
+            .iter()
+            .map(|asset| PlannedContribution {
+                asset_class: asset.asset_class.clone(),
+                amount: asset.future_contribution,
+                start_ratio: if starting_total == zero {
+                    // If our starting portfolio was empty, we don't want to divide by zero
+                    // Treat an asset class as holding 0% of an empty portfolio
+                    zero
+                } else {
+                    asset.current_value() / starting_total
+                },
+                end_ratio: asset.percent_holdings(new_total),
+                target_ratio: asset.target_ratio,
+                funds: asset
+                    .holding_shares()
+                    .into_iter()
+                    .map(|(fund, share)| PlannedFund {
+                        fund: fund.symbol.clone().unwrap_or_else(|| fund.name.clone()),
+                        current_value: fund.value,
+                        share,
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        ContributionPlan {
+            starting_total,
+            contribution: new_total - starting_total,
+            contributions,
+        }
+    }
+
+    /// Count asset classes within their bands, now & once the contribution is made
+    ///
+    /// Returns `None` if no asset class has a band.
+    pub fn rebalance_band_status(&self) -> Option<BandStatus> {
+        let (current_total, future_total) = (self.current_value(), self.future_value());
+        let banded: Vec<(&AssetAllocation, Band)> = self
+            .allocations
+            .iter()
+            .filter_map(|allocation| allocation.band.map(|band| (allocation, band)))
+            .collect();
+        if banded.is_empty() {
+            return None;
+        }
+        Some(BandStatus {
+            banded: banded.len(),
+            in_band_before: banded
+                .iter()
+                .filter(|(allocation, band)| {
+                    band.contains(allocation.current_value(), current_total)
+                })
+                .count(),
+            in_band_after: banded
+                .iter()
+                .filter(|(allocation, band)| band.contains(allocation.future_value(), future_total))
+                .count(),
+        })
+    }
+
+    /// Summarize the portfolio (and what's to be bought or sold) for `--format json`
+    ///
+    /// Dollar amounts are rounded to whole cents.
+    pub fn report(&self) -> PortfolioReport {
+        PortfolioReport {
+            total_value: self.current_value().round_dp(2),
+            minimum_to_balance: self.minimum_addition_to_balance().round_dp(2),
+            contribution: (self.future_value() - self.current_value()).round_dp(2),
+            allocations: self
+                .allocations_for_display()
+                .into_iter()
+                .map(|allocation| AllocationReport {
+                    asset_class: allocation.asset_class.clone(),
+                    target_ratio: allocation.target_ratio,
+                    current_value: allocation.current_value().round_dp(2),
+                    future_contribution: allocation.future_contribution.round_dp(2),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Declare a portfolio one asset class at a time, each followed by its holdings
+///
+/// e.g. `PortfolioBuilder::new().class(AssetClass::USTotal, 1.into()).holding("VTSAX", 100.into())`
+///
+/// Mistakes (like a holding without an asset class) are reported by `build()`.
+#[derive(Default)]
+pub struct PortfolioBuilder {
+    allocations: Vec<AssetAllocation>,
+    holding_without_class: bool,
+}
+
+impl PortfolioBuilder {
+    pub fn new() -> PortfolioBuilder {
+        PortfolioBuilder::default()
+    }
+
+    /// Target this ratio of the portfolio for an asset class
+    pub fn class(mut self, asset_class: AssetClass, target_ratio: Decimal) -> PortfolioBuilder {
+        self.allocations
+            .push(AssetAllocation::new(asset_class, target_ratio));
+        self
+    }
+
+    /// Hold some value of a fund (named by its symbol) in the last asset class declared
+    pub fn holding(mut self, symbol: &str, value: Decimal) -> PortfolioBuilder {
+        let asset_class = match self.allocations.last() {
+            Some(allocation) => allocation.asset_class.clone(),
+            None => {
+                self.holding_without_class = true;
+                return self;
+            }
+        };
+        self.asset(Asset::new(
+            String::from(symbol),
+            Some(String::from(symbol)),
+            value,
+            asset_class,
+            None,
+            None,
+            None,
+        ))
+    }
+
+    /// Hold an asset in the last asset class declared (which it must belong to)
+    pub fn asset(mut self, asset: Asset) -> PortfolioBuilder {
+        match self.allocations.last_mut() {
+            Some(allocation) => allocation.add_asset(asset),
+            None => self.holding_without_class = true,
         }
+        self
+    }
+
+    pub fn build(self) -> Result<Portfolio, BuildError> {
+        if self.holding_without_class {
+            return Err(BuildError::HoldingWithoutClass);
+        }
+        let portfolio = Portfolio::new(self.allocations);
+        let total = portfolio.sum_target_ratios();
+        if total != 1.into() {
+            return Err(TargetRatioError { total }.into());
+        }
+        Ok(portfolio)
+    }
+}
+
+/// 60% US stocks (in VTSAX) and 40% US bonds (in VBTLX)
+#[cfg(test)]
+pub(crate) fn sixty_forty_portfolio(stocks_value: Decimal, bonds_value: Decimal) -> Portfolio {
+    PortfolioBuilder::new()
+        .class(AssetClass::USTotal, Decimal::new(60, 2))
+        .holding("VTSAX", stocks_value)
+        .class(AssetClass::USBonds, Decimal::new(40, 2))
+        .holding("VBTLX", bonds_value)
+        .build()
+        .unwrap()
+}
+
+fn proportionally_allocate(mut portfolio: Portfolio, contribution: Decimal) -> Portfolio {
+    for asset in portfolio.allocations.iter_mut() {
+        let amount = asset.target_ratio * contribution;
+        asset.add_contribution(amount);
+    }
+    portfolio
+}
+
+/// Identify how many allocations (from the front) receive money, and the deviation they end at.
+///
+/// Allocations must be sorted by deviation: most underallocated first if contributing,
+/// most overallocated first if withdrawing. Returns the deviation from target that every
+/// affected allocation should end up at, and the index of the first unaffected allocation.
+fn compute_deviation_target(
+    sorted_allocations: &[AssetAllocation],
+    new_total: Decimal,
+    amount: Decimal,
+) -> (Decimal, usize) {
+    // The amount left for contribution begins as the total amount we have available
+    // (We will portion this money out sequentially to each fund, eventually exhausting it)
+    let mut amount_left_to_contribute = amount;
+
+    // As we loop through assets, we track the sum of all ideal fund values
+    let mut summed_targets_of_affected_assets: Decimal = 0.into();
+
+    // We iterate through assets based on which need alteration first (to minimize variation)
+    // We may not end up depositing/withdrawing from all accounts.
+    //
+    // As we loop through, we keep track of:
+    // 1. Which assets receive deposits/withdrawals (first through `last_known_index`)
+    // 2. The fractional deviation used to calculate the magnitude of deposits/withdrawals
+    //    `deviation_target` will be the deviation (or "approximation error") of the last
+    //    asset class we're optimizing.
+    let mut deviation_target = 0.into();
+    let mut last_known_index = 0;
+
+    for (index, asset) in sorted_allocations.iter().enumerate() {
+        assert!(amount_left_to_contribute.abs() > 0.into());
+
+        // Because we have money left to distribute, we know the asset at `index` will
+        // be affected (receiving deposits if amount > 0, or withdrawn if amount < 0)
+        last_known_index = index;
+
+        // Identify how much this asset's allocation deviates from its target
+        // On the last loop iteration, this target is used to calculate final asset deltas
+        deviation_target = asset.deviation(new_total);
+
+        // Identify the total value of this asset that brings it in line with our target ratio
+        // Importantly, this is the total value _with the new contribution included_
+        // (We can use this value to calculate required deposits/withdrawals)
         let target_value = new_total * asset.target_ratio;
-        let deviation = asset.deviation(new_total);
 
-        let delta = target_value * (deviation_target - deviation);
+        summed_targets_of_affected_assets += target_value;
+
+        // Peek ahead in the vector to get the asset which is the second-most underallocated
+        // (We will contribute proportionally until all assets are at least that close to their target)
+        // (The last asset is brought all the way to its target, since none are closer)
+        let next_lowest_deviation = sorted_allocations
+            .get(index + 1)
+            .map_or_else(|| 0.into(), |next| next.deviation(new_total));
+
+        // Solve for the amount that brings this asset as close to its target as the next closest
+        let delta: Decimal =
+            summed_targets_of_affected_assets * (next_lowest_deviation - deviation_target);
+
+        if delta.abs() > amount_left_to_contribute.abs() {
+            // If we don't have enough money left to contribute the full amount, then we'll
+            // dedicate what's left to the given fund, and exit.
+            deviation_target += amount_left_to_contribute / summed_targets_of_affected_assets;
+            amount_left_to_contribute = 0.into();
+        } else {
+            // Otherwise, this asset is now as close to its target as the next worst asset(s)
+            // We continue by bringing these assets closer to their targets
+            amount_left_to_contribute -= delta;
+            deviation_target = next_lowest_deviation;
+        }
+
+        // Two cases bring us to an exit:
+        // 1. We contributed the exact amount to bring the asset as close to its target as the
+        //    next worst (rare, but possible)
+        // 2. We were not able to contribute the full amount, so we contributed what was left
+        if amount_left_to_contribute == 0.into() {
+            break;
+        }
+    }
+
+    let index_to_stop = last_known_index + 1;
+    (deviation_target, index_to_stop)
+}
+
+/// Withdraw only from unlocked asset classes, leaving locked classes untouched.
+///
+/// Withdrawing exactly what the unlocked classes hold sells them off entirely.
+/// (Withdrawals beyond that are refused up front; see `Portfolio::check_withdrawal`)
+fn withdraw_from_unlocked(mut portfolio: Portfolio, withdrawal: Decimal) -> Portfolio {
+    let unlocked_value: Decimal = portfolio
+        .allocations
+        .iter()
+        .filter(|allocation| !allocation.locked)
+        .map(|allocation| allocation.current_value())
+        .sum();
+    assert!(
+        withdrawal.abs() <= unlocked_value,
+        "Unlocked asset classes cannot cover the withdrawal!"
+    );
+    if withdrawal.abs() == unlocked_value {
+        for allocation in portfolio.allocations.iter_mut() {
+            if !allocation.locked {
+                allocation.add_contribution(-allocation.current_value());
+            }
+        }
+        return portfolio;
+    }
+    allocate_among(portfolio, withdrawal, |allocation| !allocation.locked)
+}
+
+/// Allocate money to (or from) just the chosen asset classes, leaving all others untouched.
+///
+/// The chosen classes are balanced amongst themselves, as if they made up the whole
+/// portfolio (their targets are scaled up to sum to 100%, preserving their relative sizes).
+/// If none of the chosen classes has a target, they're weighed equally.
+fn allocate_among<F>(portfolio: Portfolio, contribution: Decimal, chosen: F) -> Portfolio
+where
+    F: Fn(&AssetAllocation) -> bool,
+{
+    let Portfolio {
+        allocations,
+        display_order,
+    } = portfolio;
+    let (mut included, excluded): (Vec<AssetAllocation>, Vec<AssetAllocation>) = allocations
+        .into_iter()
+        .partition(|allocation| chosen(allocation));
+    assert!(!included.is_empty(), "No asset classes to allocate among!");
+
+    let original_targets: HashMap<AssetClass, Decimal> = included
+        .iter()
+        .map(|a| (a.asset_class.clone(), a.target_ratio))
+        .collect();
+    if included.iter().all(|a| a.target_ratio == 0.into()) {
+        for allocation in included.iter_mut() {
+            allocation.target_ratio = 1.into();
+        }
+    }
+    let included_target: Decimal = included.iter().map(|a| a.target_ratio).sum();
+
+    // Scale targets such that they sum to exactly 100% (the last absorbs any rounding)
+    let mut remaining: Decimal = 1.into();
+    let num_included = included.len();
+    for (index, allocation) in included.iter_mut().enumerate() {
+        allocation.target_ratio = if index == num_included - 1 {
+            remaining
+        } else {
+            allocation.target_ratio / included_target
+        };
+        remaining -= allocation.target_ratio;
+    }
 
-        asset.add_contribution(delta);
+    let mut allocations = optimally_allocate(Portfolio::new(included), contribution).allocations;
+    for allocation in allocations.iter_mut() {
+        allocation.target_ratio = original_targets[&allocation.asset_class];
     }
+    allocations.extend(excluded);
 
+    let mut portfolio = Portfolio::new(allocations);
+    portfolio.set_display_order(display_order);
     portfolio
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Sell off all holdings in asset classes targeting 0%, investing the proceeds in the others
+fn liquidate_zero_targets(portfolio: Portfolio, contribution: Decimal) -> Portfolio {
+    let is_zero_target = |allocation: &AssetAllocation| allocation.target_ratio == 0.into();
+    let proceeds: Decimal = portfolio
+        .allocations
+        .iter()
+        .filter(|allocation| is_zero_target(allocation))
+        .map(|allocation| allocation.current_value())
+        .sum();
+    let mut portfolio = if contribution + proceeds == 0.into() {
+        portfolio
+    } else {
+        allocate_among(portfolio, contribution + proceeds, |allocation| {
+            !is_zero_target(allocation)
+        })
+    };
+    for allocation in portfolio.allocations.iter_mut() {
+        if is_zero_target(allocation) {
+            allocation.add_contribution(-allocation.current_value());
+        }
+    }
+    portfolio
+}
+
+pub fn optimally_allocate(mut portfolio: Portfolio, contribution: Decimal) -> Portfolio {
+    if contribution == 0.into() {
+        panic!("Must deposit or withdraw in order to rebalance");
+    }
+
+    if portfolio.sum_target_ratios() != 1.into() {
+        panic!("Cannot rebalance unless total is 100%");
+    }
+
+    let current_value = portfolio.current_value();
+    if contribution.is_sign_negative() {
+        assert!(
+            contribution.abs() < current_value,
+            "Cannot withdraw more than portfolio!"
+        );
+    }
+    // An empty portfolio is split by target ratio, and a lone asset class takes everything
+    // (Solving for deviations would only introduce rounding error)
+    if current_value == 0.into() || portfolio.allocations.len() == 1 {
+        return proportionally_allocate(portfolio, contribution);
+    }
+    if contribution.is_sign_negative() && portfolio.allocations.iter().any(|a| a.locked) {
+        return withdraw_from_unlocked(portfolio, contribution);
+    }
+    if portfolio
+        .allocations
+        .iter()
+        .any(|a| a.target_ratio == 0.into() && a.current_value() != 0.into())
+    {
+        return liquidate_zero_targets(portfolio, contribution);
+    }
+
+    assert!(
+        !current_value.is_sign_negative(),
+        "Can't handle a portfolio with a negative balance"
+    );
+
+    // The new total is our portfolio's current value, plus the amount we'll contribute
+    // In other words, this will be the denomenator for calculating final percent allocation
+    let new_total = current_value + contribution;
+
+    // We sort our asset allocations by how much they've deviated from their target
+    // If contributing: underallocated funds come first. Overallocated funds come last.
+    // If withdrawing: overallocated funds come first. Underallocated funds come last.
+    portfolio
+        .allocations
+        .sort_by_key(|allocation| allocation.deviation(new_total));
+    if contribution.is_sign_negative() {
+        portfolio.allocations.reverse();
+    }
+
+    let (deviation_target, index_to_stop) =
+        compute_deviation_target(&portfolio.allocations, new_total, contribution);
+
+    for (index, asset) in portfolio.allocations.iter_mut().enumerate() {
+        if index == index_to_stop {
+            break;
+        }
+        asset.contribute_to_deviation(new_total, deviation_target);
+    }
+
+    portfolio
+}
+
+/// What the optimizer should prioritize when allocating a contribution (or withdrawal)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Objective {
+    /// Spread money across asset classes, so that all end equally close to their targets
+    #[default]
+    MinDeviation,
+    /// Trade in as few asset classes as possible (filling the largest gaps first)
+    MinTrades,
+}
+
+impl Objective {
+    pub fn allocate(&self, portfolio: Portfolio, contribution: Decimal) -> Portfolio {
+        match self {
+            Objective::MinDeviation => optimally_allocate(portfolio, contribution),
+            Objective::MinTrades => optimally_allocate_min_trades(portfolio, contribution),
+        }
+    }
+}
+
+/// Allocate a contribution (or withdrawal) while touching as few asset classes as possible.
+///
+/// Each asset class has a dollar "gap" between its current value and its target value (at the
+/// new total). We fill the largest gap first, then the next largest, until the money runs out.
+/// Every trade moves an asset class towards its target, but the end result is generally less
+/// balanced than with `optimally_allocate`.
+pub fn optimally_allocate_min_trades(mut portfolio: Portfolio, contribution: Decimal) -> Portfolio {
+    if contribution == 0.into() {
+        panic!("Must deposit or withdraw in order to rebalance");
+    }
+    if portfolio.sum_target_ratios() != 1.into() {
+        panic!("Cannot rebalance unless total is 100%");
+    }
+
+    let withdrawing = contribution.is_sign_negative();
+    let current_value = portfolio.current_value();
+    if withdrawing {
+        let unlocked_value: Decimal = portfolio
+            .allocations
+            .iter()
+            .filter(|allocation| !allocation.locked)
+            .map(|allocation| allocation.current_value())
+            .sum();
+        assert!(
+            contribution.abs() < current_value,
+            "Cannot withdraw more than portfolio!"
+        );
+        assert!(
+            contribution.abs() <= unlocked_value,
+            "Unlocked asset classes cannot cover the withdrawal!"
+        );
+    }
+
+    let new_total = current_value + contribution;
+    // Positive if the asset class should receive money, negative if it should shed money
+    let gap = |allocation: &AssetAllocation| {
+        new_total * allocation.target_ratio - allocation.future_value()
+    };
+    let eligible = |allocation: &AssetAllocation| !(withdrawing && allocation.locked);
+
+    // Largest gaps (in the direction we're moving money) come first
+    portfolio.allocations.sort_by_key(|allocation| {
+        let magnitude = if withdrawing {
+            -gap(allocation)
+        } else {
+            gap(allocation)
+        };
+        cmp::Reverse(magnitude)
+    });
+
+    let mut amount_left = contribution;
+    for allocation in portfolio.allocations.iter_mut() {
+        let gap = gap(allocation);
+        if !eligible(allocation) || gap.is_sign_negative() != withdrawing || gap == 0.into() {
+            continue;
+        }
+        let amount = if gap.abs() > amount_left.abs() {
+            amount_left
+        } else {
+            gap
+        };
+        allocation.add_contribution(amount);
+        amount_left -= amount;
+        if amount_left == 0.into() {
+            return portfolio;
+        }
+    }
+
+    // Only locked asset classes can leave money unplaced: sell what else we must, largest first
+    portfolio
+        .allocations
+        .sort_by_key(|allocation| cmp::Reverse(allocation.future_value()));
+    for allocation in portfolio.allocations.iter_mut() {
+        if !eligible(allocation) {
+            continue;
+        }
+        let amount = cmp::max(amount_left, -allocation.future_value());
+        allocation.add_contribution(amount);
+        amount_left -= amount;
+        if amount_left == 0.into() {
+            break;
+        }
+    }
+    portfolio
+}
+
+/// Allocate a contribution (or withdrawal) in no more than `max_trades` asset classes.
+///
+/// The asset classes furthest from their targets (in dollars, in the direction we're moving
+/// money) are the ones where a trade most reduces overall deviation. We pick up to
+/// `max_trades` of those, then balance the money amongst just them.
+///
+/// Fails if withdrawing more than those asset classes hold.
+pub fn optimally_allocate_max_trades(
+    portfolio: Portfolio,
+    contribution: Decimal,
+    max_trades: usize,
+) -> Result<Portfolio, WithdrawalError> {
+    assert!(max_trades > 0, "Must allow at least one trade");
+    if contribution == 0.into() {
+        panic!("Must deposit or withdraw in order to rebalance");
+    }
+    if portfolio.sum_target_ratios() != 1.into() {
+        panic!("Cannot rebalance unless total is 100%");
+    }
+    if max_trades >= portfolio.allocations.len() {
+        return Ok(optimally_allocate(portfolio, contribution));
+    }
+
+    let withdrawing = contribution.is_sign_negative();
+    let new_total = portfolio.current_value() + contribution;
+    let mut gaps: Vec<(AssetClass, Decimal)> = portfolio
+        .allocations
+        .iter()
+        .filter(|allocation| !(withdrawing && allocation.locked))
+        .map(|allocation| {
+            let gap = new_total * allocation.target_ratio - allocation.future_value();
+            let magnitude = if withdrawing { -gap } else { gap };
+            (allocation.asset_class.clone(), magnitude)
+        })
+        .collect();
+    gaps.sort_by_key(|(_, magnitude)| cmp::Reverse(*magnitude));
+    let chosen: Vec<AssetClass> = gaps
+        .into_iter()
+        .take(max_trades)
+        .map(|(asset_class, _)| asset_class)
+        .collect();
+
+    if withdrawing {
+        let chosen_value: Decimal = portfolio
+            .allocations
+            .iter()
+            .filter(|allocation| chosen.contains(&allocation.asset_class))
+            .map(|allocation| allocation.current_value())
+            .sum();
+        if contribution.abs() >= chosen_value {
+            return Err(WithdrawalError::TooFewTrades {
+                withdrawal: contribution.abs(),
+                available: chosen_value,
+                max_trades,
+            });
+        }
+    }
+    Ok(allocate_among(portfolio, contribution, |allocation| {
+        chosen.contains(&allocation.asset_class)
+    }))
+}
+
+/// Invest new money together with some of the uninvested cash already held.
+///
+/// `sweep_fraction` (0 to 1) of the `Cash` asset class is added to the contribution. The
+/// combined sum is allocated among all other asset classes, and taken out of cash.
+pub fn optimally_allocate_with_cash(
+    portfolio: Portfolio,
+    contribution: Decimal,
+    sweep_fraction: Decimal,
+) -> Portfolio {
+    assert!(
+        sweep_fraction >= 0.into() && sweep_fraction <= 1.into(),
+        "Can only sweep between 0% and 100% of cash"
+    );
+    let cash: Decimal = portfolio
+        .allocations
+        .iter()
+        .filter(|allocation| allocation.asset_class == AssetClass::Cash)
+        .map(|allocation| allocation.current_value())
+        .sum();
+    let swept = cash * sweep_fraction;
+    if swept == 0.into() {
+        return optimally_allocate(portfolio, contribution);
+    }
+
+    let mut portfolio = allocate_among(portfolio, contribution + swept, |allocation| {
+        allocation.asset_class != AssetClass::Cash
+    });
+    for allocation in portfolio.allocations.iter_mut() {
+        if allocation.asset_class == AssetClass::Cash {
+            allocation.add_contribution(-allocation.current_value() * sweep_fraction);
+        }
+    }
+    portfolio
+}
+
+/// Allocate a contribution while holding two asset classes in a fixed ratio to each other.
+///
+/// The linked pair is balanced against other classes as if it were just one (targeting the sum
+/// of the pair's targets). Its share of the contribution is then divided so that afterwards, the
+/// pair holds exactly the linked ratio (which may mean selling one to buy the other).
+pub fn optimally_allocate_linked(
+    portfolio: Portfolio,
+    contribution: Decimal,
+    link: &LinkedClasses,
+) -> Portfolio {
+    let is_linked = |allocation: &AssetAllocation| {
+        allocation.asset_class == link.primary || allocation.asset_class == link.secondary
+    };
+    let Portfolio {
+        allocations,
+        display_order,
+    } = portfolio;
+    let (mut pair, mut others): (Vec<AssetAllocation>, Vec<AssetAllocation>) =
+        allocations.into_iter().partition(is_linked);
+    if pair.len() != 2 {
+        others.extend(pair);
+        let mut portfolio = optimally_allocate(Portfolio::new(others), contribution);
+        portfolio.set_display_order(display_order);
+        return portfolio;
+    }
+
+    // Stand in for the pair with a single allocation, holding their combined value
+    let pair_value: Decimal = pair.iter().map(|a| a.current_value()).sum();
+    let mut combined = AssetAllocation::new(
+        link.primary.clone(),
+        pair.iter().map(|a| a.target_ratio).sum(),
+    );
+    combined.add_asset(Asset::new(
+        link.primary.to_string(),
+        None,
+        pair_value,
+        link.primary.clone(),
+        None,
+        None,
+        None,
+    ));
+    others.push(combined);
+    let (combined, mut allocations): (Vec<AssetAllocation>, Vec<AssetAllocation>) =
+        optimally_allocate(Portfolio::new(others), contribution)
+            .allocations
+            .into_iter()
+            .partition(|allocation| allocation.asset_class == link.primary);
+    let pair_contribution = combined[0].future_contribution;
+
+    // Divide the pair's contribution such that it ends at the linked ratio
+    let primary_value: Decimal = pair
+        .iter()
+        .filter(|allocation| allocation.asset_class == link.primary)
+        .map(|allocation| allocation.current_value())
+        .sum();
+    let primary_contribution = (pair_value + pair_contribution) * link.ratio
+        / (link.ratio + Decimal::from(1))
+        - primary_value;
+    for allocation in pair.iter_mut() {
+        if allocation.asset_class == link.primary {
+            allocation.add_contribution(primary_contribution);
+        } else {
+            allocation.add_contribution(pair_contribution - primary_contribution);
+        }
+    }
+    allocations.extend(pair);
+
+    let mut portfolio = Portfolio::new(allocations);
+    portfolio.set_display_order(display_order);
+    portfolio
+}
+
+/// Rebalance only asset classes which have drifted outside of their bands.
+///
+/// Each such class is brought just to the nearer edge of its band (not to its target). Whatever
+/// money remains is then balanced among the other classes, as with `optimally_allocate`. Unlike
+/// other strategies, there's no need to contribute: with no contribution, classes outside their
+/// bands are simply traded back in.
+pub fn optimally_allocate_within_bands(
+    mut portfolio: Portfolio,
+    contribution: Decimal,
+) -> Portfolio {
+    let new_total = portfolio.current_value() + contribution;
+    let mut remaining = contribution;
+    let mut corrected: HashSet<AssetClass> = HashSet::new();
+    for allocation in portfolio.allocations.iter_mut() {
+        let correction = match allocation.band {
+            Some(band) => band.correction(allocation.current_value(), new_total),
+            None => 0.into(),
+        };
+        if correction != 0.into() {
+            allocation.add_contribution(correction);
+            remaining -= correction;
+            corrected.insert(allocation.asset_class.clone());
+        }
+    }
+
+    if remaining == 0.into() {
+        portfolio
+    } else if corrected.len() == portfolio.allocations.len() {
+        optimally_allocate(portfolio, remaining)
+    } else {
+        allocate_among(portfolio, remaining, |allocation| {
+            !corrected.contains(&allocation.asset_class)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "Asset types must match")]
+    fn test_asset_types_must_match() {
+        let mut stocks = AssetAllocation::new(AssetClass::USTotal, 1.into());
+
+        stocks.add_asset(Asset::new(
+            String::from("Vanguard Total Intl Bd Idx Admiral"),
+            Some(String::from("VTABX")),
+            1234.into(),
+            AssetClass::IntlBonds,
+            None,
+            None,
+            None,
+        ));
+    }
+
+    #[test]
+    fn test_current_value_is_summed_assets() {
+        let mut stocks = AssetAllocation::new(AssetClass::USTotal, 1.into());
+        assert_eq!(stocks.current_value(), 0.into());
+
+        stocks.add_asset(Asset::new(
+            String::from("Vanguard Total Stock Market Index Fund Admiral Shares"),
+            Some(String::from("VTSAX")),
+            8675.into(),
+            AssetClass::USTotal,
+            None,
+            None,
+            None,
+        ));
+
+        assert_eq!(stocks.current_value(), Decimal::from(8675));
+
+        stocks.add_asset(Asset::new(
+            String::from("Fidelity ZERO Total Market Index Fund"),
+            Some(String::from("FZROX")),
+            10000.into(),
+            AssetClass::USTotal,
+            None,
+            None,
+            None,
+        ));
+
+        assert_eq!(stocks.current_value(), Decimal::from(18675));
+    }
+    #[test]
+    fn test_add_contribution() {
+        let mut bonds = AssetAllocation::new(AssetClass::USBonds, 1.into());
+
+        // Object starts with no known assets, no future contribution
+        assert_eq!(bonds.current_value(), 0.into());
+        assert_eq!(bonds.future_value(), 0.into());
+
+        // We add $37.20 as a future contribution
+        bonds.add_contribution(Decimal::new(3720, 2));
+        assert_eq!(bonds.current_value(), 0.into());
+        assert_eq!(bonds.future_value(), Decimal::new(3720, 2));
+
+        // We add another future contribution ($14.67)
+        bonds.add_contribution(Decimal::new(1467, 2));
+        assert_eq!(bonds.current_value(), 0.into());
+        assert_eq!(bonds.future_value(), Decimal::new(5187, 2));
+    }
+
+    #[test]
+    fn test_minimum_to_balance_two_fund_portfolio() {
+        let mut stocks = AssetAllocation::new(AssetClass::USTotal, Decimal::new(50, 2));
+        let bonds = AssetAllocation::new(AssetClass::USBonds, Decimal::new(50, 2));
+        stocks.add_asset(Asset::new(
+            String::from("Vanguard Total Stock Market Index Fund Admiral Shares"),
+            Some(String::from("VTSAX")),
+            8675.into(),
+            AssetClass::USTotal,
+            None,
+            None,
+            None,
+        ));
+
+        let allocations = vec![stocks, bonds];
+        let portfolio = Portfolio::new(allocations);
+
+        // With $8,675 in stocks and 0 in bonds, you need $8,675 in bonds to get 50/50
+        assert_eq!(portfolio.minimum_addition_to_balance(), 8675.into());
+    }
+
+    /// Two allocations (50% target each), currently holding $0 and $100, sorted by deviation
+    fn empty_and_hundred(new_total: Decimal) -> Vec<AssetAllocation> {
+        let empty = AssetAllocation::new(AssetClass::USTotal, Decimal::new(50, 2));
+        let mut full = AssetAllocation::new(AssetClass::USBonds, Decimal::new(50, 2));
+        full.add_asset(Asset::new(
+            String::from("Vanguard Total Bond Market Index Fund Admiral Shares"),
+            None,
+            100.into(),
+            AssetClass::USBonds,
+            None,
+            None,
+            None,
+        ));
+        let allocations = vec![empty, full];
+        assert!(allocations[0].deviation(new_total) < allocations[1].deviation(new_total));
+        allocations
+    }
+
+    #[test]
+    fn test_deviation_target_money_runs_out_mid_class() {
+        // Targets are $75 each: the empty class is at -100%, the other at +33%.
+        // Bringing the empty class up to +33% would take $100, but we only have $50.
+        let new_total = Decimal::from(150);
+        let (deviation_target, index_to_stop) =
+            compute_deviation_target(&empty_and_hundred(new_total), new_total, 50.into());
+        assert_eq!(index_to_stop, 1);
+        assert_eq!(
+            deviation_target,
+            Decimal::from(-1) + Decimal::from(50) / Decimal::from(75)
+        );
+    }
+
+    #[test]
+    fn test_deviation_target_exact_boundary() {
+        // Targets are $100 each: the empty class is at -100%, the other exactly on target.
+        // It takes exactly $100 to bring the empty class to 0% deviation.
+        let new_total = Decimal::from(200);
+        let (deviation_target, index_to_stop) =
+            compute_deviation_target(&empty_and_hundred(new_total), new_total, 100.into());
+        assert_eq!(index_to_stop, 1);
+        assert_eq!(deviation_target, 0.into());
+    }
+
+    #[test]
+    fn test_deviation_target_spans_all_classes() {
+        // Targets are $200 each: deviations are -100% and -50%.
+        // $100 brings the empty class to -50%, then $200 more brings both to target.
+        let new_total = Decimal::from(400);
+        let (deviation_target, index_to_stop) =
+            compute_deviation_target(&empty_and_hundred(new_total), new_total, 300.into());
+        assert_eq!(index_to_stop, 2);
+        assert_eq!(deviation_target, 0.into());
+    }
+
+    #[test]
+    fn test_deviation_target_withdrawal() {
+        // Withdrawing, the overallocated class comes first.
+        // Targets are $40 each: the full class is at +150%, so withdrawing all $20 comes from it.
+        let new_total = Decimal::from(80);
+        let mut allocations = empty_and_hundred(new_total);
+        allocations.reverse();
+        let (deviation_target, index_to_stop) =
+            compute_deviation_target(&allocations, new_total, Decimal::from(-20));
+        assert_eq!(index_to_stop, 1);
+        assert_eq!(deviation_target, Decimal::new(1, 0));
+    }
+
+    #[test]
+    fn test_top_up_to_round_total() {
+        let portfolio = sixty_forty_portfolio(300_000.into(), 180_000.into());
+        let contribution = portfolio.contribution_to_reach(500_000.into()).unwrap();
+        assert_eq!(contribution, Decimal::from(20_000));
+
+        let balanced = optimally_allocate(portfolio, contribution);
+        assert_eq!(balanced.future_value(), Decimal::from(500_000));
+        let trades = trades(&balanced);
+        assert_eq!(trades[&AssetClass::USBonds], Decimal::from(20_000));
+        assert_eq!(trades[&AssetClass::USTotal], Decimal::from(0));
+
+        let plan = balanced.contribution_plan();
+        assert_eq!(plan.starting_total, Decimal::from(480_000));
+        assert_eq!(plan.contribution, Decimal::from(20_000));
+        assert_eq!(plan.ending_total(), Decimal::from(500_000));
+        let bonds = plan
+            .contributions
+            .iter()
+            .find(|planned| planned.asset_class == AssetClass::USBonds)
+            .unwrap();
+        assert_eq!(bonds.start_ratio, Decimal::new(375, 3));
+        assert_eq!(bonds.end_ratio, Decimal::new(4, 1));
+    }
+
+    #[test]
+    fn test_top_up_to_total_below_current_value() {
+        let portfolio = sixty_forty_portfolio(300_000.into(), 180_000.into());
+        let error = portfolio.contribution_to_reach(450_000.into()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Target total of $450,000 must exceed the current value of $480,000"
+        );
+    }
+
+    fn planned(asset_class: AssetClass, amount: Decimal) -> PlannedContribution {
+        PlannedContribution {
+            asset_class,
+            amount,
+            start_ratio: 0.into(),
+            end_ratio: 0.into(),
+            target_ratio: Decimal::from(1) / Decimal::from(3),
+            funds: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_reconcile_pennies() {
+        let third = Decimal::from(100) / Decimal::from(3);
+        let mut plan = ContributionPlan {
+            starting_total: 0.into(),
+            contribution: 100.into(),
+            contributions: vec![
+                planned(AssetClass::USTotal, third),
+                planned(AssetClass::IntlStocks, third),
+                planned(AssetClass::USBonds, third),
+            ],
+        };
+        // Naively, each rounds to $33.33, which sums to $99.99
+        let naive: Decimal = plan
+            .contributions
+            .iter()
+            .map(|p| p.amount.round_dp(2))
+            .sum();
+        assert_eq!(naive, Decimal::new(9999, 2));
+
+        plan.reconcile_pennies();
+        let amounts: Vec<Decimal> = plan.contributions.iter().map(|p| p.amount).collect();
+        assert_eq!(amounts.iter().sum::<Decimal>(), Decimal::from(100));
+        let pennies: Vec<Decimal> = amounts.iter().map(|a| a.round_dp(2)).collect();
+        assert_eq!(amounts, pennies);
+        assert_eq!(
+            amounts
+                .iter()
+                .filter(|&&a| a == Decimal::new(3334, 2))
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_reconcile_pennies_on_withdrawal() {
+        let third = Decimal::from(-200) / Decimal::from(3);
+        let mut plan = ContributionPlan {
+            starting_total: 1000.into(),
+            contribution: Decimal::from(-200),
+            contributions: vec![
+                planned(AssetClass::USTotal, third),
+                planned(AssetClass::IntlStocks, third),
+                planned(AssetClass::USBonds, third),
+            ],
+        };
+        plan.reconcile_pennies();
+        let total: Decimal = plan.contributions.iter().map(|p| p.amount).sum();
+        assert_eq!(total, Decimal::from(-200));
+    }
+
+    #[test]
+    fn test_reconcile_pennies_updates_end_ratios() {
+        let mut portfolio = PortfolioBuilder::new()
+            .class(AssetClass::USTotal, Decimal::new(50, 2))
+            .holding("VTSAX", 100.into())
+            .class(AssetClass::USBonds, Decimal::new(50, 2))
+            .holding("VBTLX", 100.into())
+            .build()
+            .unwrap();
+        portfolio.allocations[0].add_contribution(Decimal::new(33333, 3));
+        portfolio.allocations[1].add_contribution(Decimal::new(66667, 3));
+
+        let mut plan = portfolio.contribution_plan();
+        plan.reconcile_pennies();
+        let amounts: Vec<Decimal> = plan.contributions.iter().map(|p| p.amount).collect();
+        assert_eq!(amounts, vec![Decimal::new(3333, 2), Decimal::new(6667, 2)]);
+
+        // $133.33 of $300 & $166.67 of $300
+        let end_ratios: Vec<Decimal> = plan.contributions.iter().map(|p| p.end_ratio).collect();
+        assert_eq!(
+            end_ratios,
+            vec![
+                (Decimal::new(13333, 2) / Decimal::from(300)).round_dp(RATIO_SCALE),
+                (Decimal::new(16667, 2) / Decimal::from(300)).round_dp(RATIO_SCALE),
+            ]
+        );
+    }
+
+    fn ninety_ten_portfolio() -> Portfolio {
+        PortfolioBuilder::new()
+            .class(AssetClass::USTotal, Decimal::new(90, 2))
+            .holding("VTSAX", 900.into())
+            .class(AssetClass::USBonds, Decimal::new(10, 2))
+            .holding("VBTLX", 100.into())
+            .build()
+            .unwrap()
+    }
+
+    fn trades(portfolio: &Portfolio) -> HashMap<AssetClass, Decimal> {
+        portfolio
+            .allocations
+            .iter()
+            .map(|allocation| {
+                (
+                    allocation.asset_class.clone(),
+                    allocation.future_contribution,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rebalance_into_new_target() {
+        let migrated = ninety_ten_portfolio()
+            .rebalance_into(vec![
+                AssetAllocation::new(AssetClass::USTotal, Decimal::new(60, 2)),
+                AssetAllocation::new(AssetClass::USBonds, Decimal::new(40, 2)),
+            ])
+            .unwrap();
+        let trades = trades(&migrated);
+        assert_eq!(trades[&AssetClass::USTotal], Decimal::from(-300));
+        assert_eq!(trades[&AssetClass::USBonds], Decimal::from(300));
+        assert_eq!(migrated.future_value(), Decimal::from(1000));
+    }
+
+    #[test]
+    fn test_rebalance_into_unheld_asset_class() {
+        let migrated = ninety_ten_portfolio()
+            .rebalance_into(vec![
+                AssetAllocation::new(AssetClass::USTotal, Decimal::new(50, 2)),
+                AssetAllocation::new(AssetClass::IntlStocks, Decimal::new(20, 2)),
+                AssetAllocation::new(AssetClass::USBonds, Decimal::new(30, 2)),
+            ])
+            .unwrap();
+        let trades = trades(&migrated);
+        assert_eq!(trades[&AssetClass::USTotal], Decimal::from(-400));
+        assert_eq!(trades[&AssetClass::IntlStocks], Decimal::from(200));
+        assert_eq!(trades[&AssetClass::USBonds], Decimal::from(200));
+    }
+
+    #[test]
+    fn test_rebalance_into_must_cover_held_classes() {
+        let error = ninety_ten_portfolio()
+            .rebalance_into(vec![AssetAllocation::new(AssetClass::USTotal, 1.into())])
+            .err()
+            .unwrap();
+        assert_eq!(error, MigrationError::MissingTarget(AssetClass::USBonds));
+        assert_eq!(error.to_string(), "No target given for US bonds");
+    }
+
+    #[test]
+    fn test_rebalance_into_targets_must_sum_to_one() {
+        let error = ninety_ten_portfolio()
+            .rebalance_into(vec![
+                AssetAllocation::new(AssetClass::USTotal, Decimal::new(50, 2)),
+                AssetAllocation::new(AssetClass::USBonds, Decimal::new(30, 2)),
+            ])
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "Target ratios must sum to 100%, not 80%");
+    }
+
+    #[test]
+    fn test_minimum_to_balance_three_fund_portfolio() {
+        let mut us_stocks = AssetAllocation::new(AssetClass::USTotal, Decimal::new(60, 2));
+        let mut intl_stocks = AssetAllocation::new(AssetClass::IntlStocks, Decimal::new(30, 2));
+        let mut bonds = AssetAllocation::new(AssetClass::USBonds, Decimal::new(10, 2));
+
+        // Portfolio is $1000 total.
+        // Bonds *and* stocks are over-allocated, but in an interesting way:
+        // - Stocks are overallocated *absolutely* ($60 over target, vs $40 over for bonds)
+        // - Bonds are overallocated from a target ratio standpoint.
+        //
+        // The ideal result is for bonds to be at 10% the total, still at $140.
+        // To do that, we need to add $400: $180 into US stocks, $220 international
+        us_stocks.add_asset(Asset::new(
+            String::from("Vanguard Total Stock Market Index Fund Admiral Shares"),
+            Some(String::from("VTSAX")),
+            660.into(),
+            AssetClass::USTotal,
+            None,
+            None,
+            None,
+        ));
+        intl_stocks.add_asset(Asset::new(
+            String::from("Vanguard Total International Stock Index Fund Admiral Shares"),
+            Some(String::from("VTIAX")),
+            200.into(),
+            AssetClass::IntlStocks,
+            None,
+            None,
+            None,
+        ));
+        bonds.add_asset(Asset::new(
+            String::from("Vanguard Total Bond Market Index Fund Admiral Shares"),
+            Some(String::from("VBTLX")),
+            140.into(),
+            AssetClass::USBonds,
+            None,
+            None,
+            None,
+        ));
+
+        let allocations = vec![us_stocks, intl_stocks, bonds];
+        let portfolio = Portfolio::new(allocations);
+
+        assert_eq!(portfolio.minimum_addition_to_balance(), 400.into());
+
+        // The recommendations for allocating money match what we'd expect:
+        // - $220 into Intl stocks, total $220
+        // - $180 into US stocks, total $840
+        // - $0 to bonds, remaining at $140
+        let balanced_portfolio = optimally_allocate(portfolio, 400.into());
+        assert_eq!(balanced_portfolio.future_value(), 1400.into());
+        let future_values: Vec<Decimal> = balanced_portfolio
+            .allocations
+            .iter()
+            .map(|allocation| allocation.future_value().round_dp(2))
+            .collect();
+        let tickers: Vec<&AssetClass> = balanced_portfolio
+            .allocations
+            .iter()
+            .map(|allocation| &allocation.asset_class)
+            .collect();
+
+        assert_eq!(
+            tickers,
+            vec![
+                &AssetClass::IntlStocks,
+                &AssetClass::USTotal,
+                &AssetClass::USBonds,
+            ]
+        );
+        assert_eq!(future_values, vec![420.into(), 840.into(), 140.into()]);
+    }
+
+    #[test]
+    fn test_minimum_to_balance_single_fund_portfolio() {
+        let terrible_allocation = AssetAllocation::new(AssetClass::Cash, 1.into());
+        let portfolio = Portfolio::new(vec![terrible_allocation]);
+        // Obviously, you never need to add money to get a 100% allocation
+        assert_eq!(portfolio.minimum_addition_to_balance(), 0.into());
+    }
+
+    #[test]
+    fn test_allocations_sum_to_1() {
+        let terrible_allocation = AssetAllocation::new(AssetClass::Cash, 1.into());
+        let portfolio = Portfolio::new(vec![terrible_allocation]);
+        let balanced_portfolio = optimally_allocate(portfolio, 1_000.into());
+        assert_eq!(
+            balanced_portfolio.allocations[0].future_contribution,
+            1_000.into()
+        );
+        assert_eq!(balanced_portfolio.future_value(), 1_000.into());
+    }
+
+    #[test]
+    fn test_single_class_receives_entire_contribution() {
+        for contribution in [
+            Decimal::new(100_000, 2),
+            Decimal::new(33_333, 2),
+            Decimal::new(-125_050, 2),
+        ] {
+            let mut bonds = AssetAllocation::new(AssetClass::USBonds, 1.into());
+            bonds.add_asset(held_in("IRA", "VBTLX", AssetClass::USBonds, 3_333));
+            let balanced_portfolio = optimally_allocate(Portfolio::new(vec![bonds]), contribution);
+
+            let allocation = &balanced_portfolio.allocations[0];
+            assert_eq!(allocation.future_contribution, contribution);
+            assert_eq!(
+                balanced_portfolio.future_value(),
+                Decimal::from(3_333) + contribution
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot rebalance unless total is 100%")]
+    fn test_allocations_do_not_sum() {
+        let does_not_sum = vec![
+            AssetAllocation::new(AssetClass::USTotal, Decimal::new(3, 1)),
+            AssetAllocation::new(AssetClass::USBonds, Decimal::new(3, 1)),
+        ];
+        let portfolio = Portfolio::new(does_not_sum);
+
+        optimally_allocate(portfolio, 1_000.into());
+    }
+
+    #[test]
+    fn test_portfolio_from_in_memory_holdings() {
+        let classifications: AssetClassifications = vec![
+            (String::from("VTSAX"), AssetClass::USTotal),
+            (String::from("VBTLX"), AssetClass::USBonds),
+        ]
+        .into_iter()
+        .collect();
+        let mut prices = HashMap::new();
+        prices.insert(String::from("VTSAX"), Decimal::from(100));
+        prices.insert(String::from("VBTLX"), Decimal::from(10));
+
+        let holdings = vec![
+            Holding::new("Total Stock Market", "VTSAX", 7.into()),
+            Holding::new("Total Bond Market", "VBTLX", 30.into()),
+        ];
+        let portfolio = Portfolio::from_holdings(
+            vec![
+                AssetAllocation::new(AssetClass::USTotal, Decimal::new(50, 2)),
+                AssetAllocation::new(AssetClass::USBonds, Decimal::new(50, 2)),
+            ],
+            &holdings,
+            &classifications,
+            &prices,
+        )
+        .unwrap();
+        assert_eq!(portfolio.current_value(), 1000.into());
+
+        // $700 in stocks, $300 in bonds: $600 brings us to 50/50 at $1,600
+        let balanced = optimally_allocate(portfolio, 600.into());
+        let contributions: Vec<(&AssetClass, Decimal)> = balanced
+            .allocations
+            .iter()
+            .map(|allocation| {
+                (
+                    &allocation.asset_class,
+                    allocation.future_contribution.round_dp(2),
+                )
+            })
+            .collect();
+        assert_eq!(
+            contributions,
+            vec![
+                (&AssetClass::USBonds, 500.into()),
+                (&AssetClass::USTotal, 100.into()),
+            ]
+        );
+    }
+
+    fn three_fund_portfolio() -> Portfolio {
+        PortfolioBuilder::new()
+            .class(AssetClass::USTotal, Decimal::new(60, 2))
+            .holding("VTSAX", 660.into())
+            .class(AssetClass::IntlStocks, Decimal::new(30, 2))
+            .holding("VTIAX", 200.into())
+            .class(AssetClass::USBonds, Decimal::new(10, 2))
+            .holding("VBTLX", 140.into())
+            .build()
+            .unwrap()
+    }
+
+    fn displayed_classes(portfolio: &Portfolio) -> Vec<String> {
+        format!("{}", portfolio)
+            .lines()
+            .filter(|line| {
+                !line.starts_with(' ')
+                    && !line.starts_with("Portfolio total")
+                    && !line.starts_with("Current:")
+            })
+            .map(|line| line.split(':').next().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_display_order() {
+        let mut portfolio = three_fund_portfolio();
+        assert_eq!(
+            displayed_classes(&portfolio),
+            vec!["US total market", "International stocks", "US bonds"]
+        );
+
+        portfolio.set_display_order(DisplayOrder::Alphabetical);
+        assert_eq!(
+            displayed_classes(&portfolio),
+            vec!["International stocks", "US bonds", "US total market"]
+        );
+
+        portfolio.set_display_order(DisplayOrder::Custom(vec![
+            AssetClass::USBonds,
+            AssetClass::USTotal,
+        ]));
+        assert_eq!(
+            displayed_classes(&portfolio),
+            vec!["US bonds", "US total market", "International stocks"]
+        );
+
+        // The optimizer still works from the most underallocated asset class
+        let balanced_portfolio = optimally_allocate(portfolio, 400.into());
+        let internal_order: Vec<&AssetClass> = balanced_portfolio
+            .allocations
+            .iter()
+            .map(|allocation| &allocation.asset_class)
+            .collect();
+        assert_eq!(
+            internal_order,
+            vec![
+                &AssetClass::IntlStocks,
+                &AssetClass::USTotal,
+                &AssetClass::USBonds,
+            ]
+        );
+        assert_eq!(
+            displayed_classes(&balanced_portfolio),
+            vec!["US bonds", "US total market", "International stocks"]
+        );
+    }
+
+    #[test]
+    fn test_share_class_upgrades() {
+        let mut stocks = AssetAllocation::new(AssetClass::USTotal, 1.into());
+        stocks.add_asset(Asset::new(
+            String::from("Vanguard Total Stock Market Index Fund Investor Shares"),
+            Some(String::from("VTSMX")),
+            3000.into(),
+            AssetClass::USTotal,
+            None,
+            None,
+            None,
+        ));
+        stocks.add_asset(Asset::new(
+            String::from("Vanguard Total Stock Market Index Fund Admiral Shares"),
+            Some(String::from("VTSAX")),
+            5000.into(),
+            AssetClass::USTotal,
+            None,
+            None,
+            None,
+        ));
+        let portfolio = Portfolio::new(vec![stocks]);
+
+        let mut upgrades = HashMap::new();
+        upgrades.insert(String::from("VTSMX"), String::from("VTSAX"));
+        assert_eq!(
+            portfolio.share_class_upgrades(&upgrades),
+            vec![ShareClassUpgrade {
+                from_symbol: String::from("VTSMX"),
+                to_symbol: String::from("VTSAX"),
+                value: 3000.into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_should_sort_by_current_allocation_value() {
+        let mut stocks = AssetAllocation::new(AssetClass::USTotal, Decimal::new(50, 2));
+        let mut bonds = AssetAllocation::new(AssetClass::USBonds, Decimal::new(50, 2));
+
+        // We keep $10 in bonds, but plan to contribute nearly $1 million in stocks
+        bonds.add_asset(Asset::new(
+            String::from("Vanguard Total Intl Bd Idx Admiral"),
+            Some(String::from("VBTLX")),
+            10.into(),
+            AssetClass::USBonds,
+            None,
+            None,
+            None,
+        ));
+        stocks.add_contribution(999_999.into());
+
+        // Ordering is done by current value.
+        let mut allocations = vec![&stocks, &bonds];
+        allocations.sort();
+        assert_eq!(allocations, vec![&bonds, &stocks]);
+    }
+
+    #[test]
+    fn test_withdraw_around_locked_class() {
+        // Bonds are overallocated, so would ordinarily be sold first
+        let mut portfolio = sixty_forty_portfolio(500.into(), 500.into());
+        portfolio.lock_classes(&[AssetClass::USBonds]);
+
+        let balanced = optimally_allocate(portfolio, Decimal::from(-100));
+        let trades = trades(&balanced);
+        assert_eq!(trades[&AssetClass::USBonds], Decimal::from(0));
+        assert_eq!(trades[&AssetClass::USTotal], Decimal::from(-100));
+
+        // Original targets are reported, not the ones used to balance unlocked classes
+        let bonds = balanced
+            .allocations()
+            .iter()
+            .find(|allocation| allocation.asset_class == AssetClass::USBonds)
+            .unwrap();
+        assert!(bonds.is_locked());
+        assert_eq!(bonds.target_ratio, Decimal::new(40, 2));
+    }
+
+    #[test]
+    fn test_withdraw_everything_unlocked() {
+        let mut portfolio = sixty_forty_portfolio(500.into(), 500.into());
+        portfolio.lock_classes(&[AssetClass::USBonds]);
+
+        let balanced = optimally_allocate(portfolio, Decimal::from(-500));
+        let trades = trades(&balanced);
+        assert_eq!(trades[&AssetClass::USTotal], Decimal::from(-500));
+        assert_eq!(trades[&AssetClass::USBonds], Decimal::from(0));
+    }
+
+    #[test]
+    fn test_withdrawal_beyond_unlocked_classes() {
+        let mut portfolio = sixty_forty_portfolio(500.into(), 500.into());
+        assert_eq!(portfolio.check_withdrawal(Decimal::from(-600)), Ok(()));
+        assert_eq!(
+            portfolio
+                .check_withdrawal(Decimal::from(-1000))
+                .unwrap_err()
+                .to_string(),
+            "Cannot withdraw $1,000 from a portfolio worth $1,000"
+        );
+
+        portfolio.lock_classes(&[AssetClass::USBonds]);
+        assert_eq!(portfolio.check_withdrawal(Decimal::from(-500)), Ok(()));
+        let error = portfolio.check_withdrawal(Decimal::from(-600)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Cannot withdraw $600; unlocked asset classes hold only $500"
+        );
+    }
+
+    #[test]
+    fn test_withdraw_from_unlocked_classes_without_targets() {
+        // Only the locked class has a target, so there are no relative sizes to preserve
+        let mut portfolio = PortfolioBuilder::new()
+            .class(AssetClass::USTotal, 0.into())
+            .holding("VTSAX", 300.into())
+            .class(AssetClass::IntlStocks, 0.into())
+            .holding("VTIAX", 100.into())
+            .class(AssetClass::USBonds, 1.into())
+            .holding("VBTLX", 600.into())
+            .build()
+            .unwrap();
+        portfolio.lock_classes(&[AssetClass::USBonds]);
+
+        let balanced = optimally_allocate(portfolio, Decimal::from(-100));
+        let trades = trades(&balanced);
+        assert_eq!(trades[&AssetClass::USTotal], Decimal::from(-100));
+        assert_eq!(trades[&AssetClass::IntlStocks], Decimal::from(0));
+        assert_eq!(trades[&AssetClass::USBonds], Decimal::from(0));
+        assert!(balanced
+            .allocations()
+            .iter()
+            .all(|allocation| allocation.asset_class == AssetClass::USBonds
+                || allocation.target_ratio == 0.into()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unlocked asset classes cannot cover the withdrawal!")]
+    fn test_locked_classes_cannot_cover_withdrawal() {
+        let mut portfolio = sixty_forty_portfolio(500.into(), 500.into());
+        portfolio.lock_classes(&[AssetClass::USBonds]);
+        optimally_allocate(portfolio, Decimal::from(-600));
+    }
+
+    #[test]
+    fn test_absolute_overweights() {
+        // Stocks are $10,000 over their 60% target (and bonds $10,000 under)
+        let portfolio = sixty_forty_portfolio(70_000.into(), 30_000.into());
+        assert_eq!(
+            portfolio.absolute_overweights(5_000.into()),
+            vec![(AssetClass::USTotal, Decimal::from(10_000))]
+        );
+        assert!(portfolio.absolute_overweights(10_000.into()).is_empty());
+    }
+
+    fn four_class_portfolio() -> Portfolio {
+        PortfolioBuilder::new()
+            .class(AssetClass::USTotal, Decimal::new(40, 2))
+            .holding("VTSAX", 300.into())
+            .class(AssetClass::IntlStocks, Decimal::new(30, 2))
+            .holding("VTIAX", 250.into())
+            .class(AssetClass::USBonds, Decimal::new(20, 2))
+            .holding("VBTLX", 150.into())
+            .class(AssetClass::REIT, Decimal::new(10, 2))
+            .holding("VGSLX", 100.into())
+            .build()
+            .unwrap()
+    }
+
+    fn classes_traded(portfolio: &Portfolio) -> usize {
+        trades(portfolio)
+            .values()
+            .filter(|&&amount| amount != 0.into())
+            .count()
+    }
+
+    #[test]
+    fn test_min_trades_touches_fewer_classes() {
+        // At $830, we'd want $332 / $249 / $166 / $83: stocks & bonds are equally far behind
+        let min_deviation = Objective::MinDeviation.allocate(four_class_portfolio(), 30.into());
+        let min_trades = Objective::MinTrades.allocate(four_class_portfolio(), 30.into());
+
+        assert_eq!(classes_traded(&min_deviation), 2);
+        assert_eq!(classes_traded(&min_trades), 1);
+        let trades = trades(&min_trades);
+        assert_eq!(trades[&AssetClass::USTotal], Decimal::from(30));
+        assert_eq!(min_trades.future_value(), Decimal::from(830));
+    }
+
+    #[test]
+    fn test_min_trades_withdrawal() {
+        // At $770, we'd want $308 / $231 / $154 / $77: REIT is furthest ahead, then intl
+        let min_trades = Objective::MinTrades.allocate(four_class_portfolio(), Decimal::from(-30));
+        let trades = trades(&min_trades);
+        assert_eq!(trades[&AssetClass::REIT], Decimal::from(-23));
+        assert_eq!(trades[&AssetClass::IntlStocks], Decimal::from(-7));
+        assert_eq!(classes_traded(&min_trades), 2);
+    }
+
+    #[test]
+    fn test_holding_shares_within_class() {
+        let mut stocks = AssetAllocation::new(AssetClass::USTotal, 1.into());
+        for (symbol, value) in [("VTSAX", 750), ("FZROX", 250)] {
+            stocks.add_asset(Asset::new(
+                String::from(symbol),
+                Some(String::from(symbol)),
+                Decimal::from(value),
+                AssetClass::USTotal,
+                None,
+                None,
+                None,
+            ));
+        }
+
+        let shares: Vec<(String, Decimal)> = stocks
+            .holding_shares()
+            .into_iter()
+            .map(|(asset, share)| (asset.name.clone(), share))
+            .collect();
+        assert_eq!(
+            shares,
+            vec![
+                (String::from("FZROX"), Decimal::new(25, 2)),
+                (String::from("VTSAX"), Decimal::new(75, 2)),
+            ]
+        );
+        let total: Decimal = shares.iter().map(|(_, share)| *share).sum();
+        assert_eq!(total, 1.into());
+
+        let displayed = stocks.to_string();
+        assert!(displayed.contains("{75.0% of class}"));
+        assert!(displayed.contains("{25.0% of class}"));
+    }
+
+    #[test]
+    fn test_drift_status_bands() {
+        let thresholds = DriftThresholds {
+            minor: Decimal::new(3, 2),
+            major: Decimal::new(10, 2),
+        };
+        let status = |percent: i64| DriftStatus::classify(Decimal::new(percent, 2), &thresholds);
+        assert_eq!(status(1), DriftStatus::OnTarget);
+        assert_eq!(status(4), DriftStatus::MinorDrift);
+        assert_eq!(status(12), DriftStatus::MajorDrift);
+        // Being under target is just as much drift as being over it
+        assert_eq!(status(-12), DriftStatus::MajorDrift);
+        assert_eq!(status(-1), DriftStatus::OnTarget);
+    }
+
+    fn five_class_drifted_portfolio() -> Portfolio {
+        // At $1,300, targets are $390 / $260 / $260 / $195 / $195 (all behind, by varying amounts)
+        PortfolioBuilder::new()
+            .class(AssetClass::USTotal, Decimal::new(30, 2))
+            .holding("VTSAX", 300.into())
+            .class(AssetClass::IntlStocks, Decimal::new(20, 2))
+            .holding("VTIAX", 180.into())
+            .class(AssetClass::USBonds, Decimal::new(20, 2))
+            .holding("VBTLX", 200.into())
+            .class(AssetClass::REIT, Decimal::new(15, 2))
+            .holding("VGSLX", 170.into())
+            .class(AssetClass::USSmall, Decimal::new(15, 2))
+            .holding("VSMAX", 150.into())
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_max_trades_picks_most_impactful_classes() {
+        // Unconstrained, $300 exactly closes every gap (so all five classes are traded)
+        let unconstrained = optimally_allocate(five_class_drifted_portfolio(), 300.into());
+        assert_eq!(classes_traded(&unconstrained), 5);
+
+        let capped =
+            optimally_allocate_max_trades(five_class_drifted_portfolio(), 300.into(), 3).unwrap();
+        assert_eq!(classes_traded(&capped), 3);
+        let trades = trades(&capped);
+        // The three largest gaps ($90, $80, $60) are filled; REIT & small cap are left alone
+        assert_eq!(trades[&AssetClass::REIT], 0.into());
+        assert_eq!(trades[&AssetClass::USSmall], 0.into());
+        assert_eq!(trades[&AssetClass::USTotal].round_dp(2), Decimal::from(120));
+        assert_eq!(
+            trades[&AssetClass::IntlStocks].round_dp(2),
+            Decimal::from(100)
+        );
+        assert_eq!(trades[&AssetClass::USBonds].round_dp(2), Decimal::from(80));
+        assert_eq!(
+            trades.values().sum::<Decimal>().round_dp(2),
+            Decimal::from(300)
+        );
+    }
+
+    #[test]
+    fn test_max_trades_withdrawal() {
+        // At $780, VGSLX is $22 over target & VTIAX is $16 over: sell from VGSLX alone
+        let withdrawn =
+            optimally_allocate_max_trades(four_class_portfolio(), (-20).into(), 1).unwrap();
+        let trades = trades(&withdrawn);
+        assert_eq!(classes_traded(&withdrawn), 1);
+        assert_eq!(trades[&AssetClass::REIT], (-20).into());
+    }
+
+    #[test]
+    fn test_max_trades_withdrawal_too_large() {
+        // At $480, VTSAX is the class furthest over target, but holds only $300
+        let error = optimally_allocate_max_trades(four_class_portfolio(), (-300).into(), 1)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Cannot withdraw $300 in only 1 trade(s); those asset classes hold $300"
+        );
+    }
+
+    #[test]
+    fn test_max_trades_beyond_class_count() {
+        let capped = optimally_allocate_max_trades(four_class_portfolio(), 30.into(), 10).unwrap();
+        let unconstrained = optimally_allocate(four_class_portfolio(), 30.into());
+        assert_eq!(trades(&capped), trades(&unconstrained));
+    }
+
+    #[test]
+    fn test_deviation_scale_is_bounded() {
+        // Thirds never terminate, so unbounded division would use every available decimal place
+        let portfolio = PortfolioBuilder::new()
+            .class(AssetClass::USTotal, Decimal::new(333_333, 6))
+            .holding("VTSAX", 100.into())
+            .class(AssetClass::IntlStocks, Decimal::new(333_333, 6))
+            .holding("VTIAX", 200.into())
+            .class(AssetClass::USBonds, Decimal::new(333_334, 6))
+            .holding("VBTLX", 400.into())
+            .build()
+            .unwrap();
+        for allocation in portfolio.allocations.iter() {
+            assert!(allocation.deviation(700.into()).scale() <= RATIO_SCALE);
+            assert!(allocation.percent_holdings(700.into()).scale() <= RATIO_SCALE);
+        }
+
+        // Ordering is unaffected: the most underallocated class still gets the most money
+        let balanced = optimally_allocate(portfolio, 300.into());
+        let trades = trades(&balanced);
+        assert!(trades[&AssetClass::USTotal] > trades[&AssetClass::IntlStocks]);
+        assert_eq!(trades[&AssetClass::USBonds], 0.into());
+        assert_eq!(
+            trades.values().sum::<Decimal>().round_dp(2),
+            Decimal::from(300)
+        );
+        for allocation in balanced.allocations.iter() {
+            assert!(allocation.deviation(1000.into()).scale() <= RATIO_SCALE);
+        }
+    }
+
+    #[test]
+    fn test_withdrawal_floor() {
+        let half = Decimal::new(50, 2);
+
+        // With stocks locked, a modest withdrawal from bonds is fine...
+        let mut portfolio = sixty_forty_portfolio(600.into(), 400.into());
+        portfolio.lock_classes(&[AssetClass::USTotal]);
+        let balanced = optimally_allocate(portfolio, Decimal::from(-100));
+        assert_eq!(balanced.check_withdrawal_floor(half), Ok(()));
+
+        // ...but a large one would nearly wipe them out ($50 left, vs. a $260 target at $650)
+        let mut portfolio = sixty_forty_portfolio(600.into(), 400.into());
+        portfolio.lock_classes(&[AssetClass::USTotal]);
+        let balanced = optimally_allocate(portfolio, Decimal::from(-350));
+        let err = balanced.check_withdrawal_floor(half).unwrap_err();
+        assert_eq!(err.asset_class, AssetClass::USBonds);
+        assert_eq!(
+            err.to_string(),
+            "Withdrawal would leave US bonds at $50, below its minimum of $130"
+        );
+
+        // Classes already below their floor are fine, so long as they're not being sold
+        let balanced = optimally_allocate(
+            sixty_forty_portfolio(600.into(), 10.into()),
+            Decimal::from(-100),
+        );
+        assert_eq!(
+            balanced.check_withdrawal_floor(half),
+            Ok(()),
+            "{:?}",
+            trades(&balanced)
+        );
+    }
+
+    fn held_in(account: &str, symbol: &str, asset_class: AssetClass, value: i64) -> Asset {
+        Asset::new(
+            String::from(account),
+            Some(String::from(symbol)),
+            Decimal::from(value),
+            asset_class,
+            None,
+            None,
+            None,
+        )
+    }
+
+    #[test]
+    fn test_sweep_fills_higher_priority_account_first() {
+        // Both accounts hold US stocks; only the taxable account holds bonds
+        let mut stocks = AssetAllocation::new(AssetClass::USTotal, Decimal::new(60, 2));
+        stocks.add_asset(held_in("Taxable", "VTSAX", AssetClass::USTotal, 400));
+        stocks.add_asset(held_in("401k", "FXAIX", AssetClass::USTotal, 200));
+        let mut bonds = AssetAllocation::new(AssetClass::USBonds, Decimal::new(40, 2));
+        bonds.add_asset(held_in("Taxable", "VBTLX", AssetClass::USBonds, 400));
+        let portfolio = Portfolio::new(vec![stocks, bonds]);
+
+        // $1,000 at 60/40 means $600 into stocks, $400 into bonds
+        let balanced = optimally_allocate(portfolio, 1000.into());
+        let order = vec![
+            SweepAccount {
+                account: String::from("401k"),
+                room: Some(500.into()),
+            },
+            SweepAccount {
+                account: String::from("Taxable"),
+                room: None,
+            },
+        ];
+        let mut swept: Vec<String> = balanced
+            .sweep(&order)
+            .contributions
+            .iter()
+            .map(|contribution| contribution.to_string())
+            .collect();
+        swept.sort();
+        // The 401k gets all it has room for; the rest of the stock contribution spills over
+        assert_eq!(
+            swept,
+            vec![
+                "401k: $500 into US total market",
+                "Taxable: $100 into US total market",
+                "Taxable: $400 into US bonds",
+            ]
+        );
+
+        // Without any order, the class's contribution all goes to one account
+        assert_eq!(balanced.sweep(&[]).contributions.len(), 2);
+    }
+
+    #[test]
+    fn test_sweep_never_exceeds_room() {
+        let mut stocks = AssetAllocation::new(AssetClass::USTotal, Decimal::new(60, 2));
+        stocks.add_asset(held_in("Taxable", "VTSAX", AssetClass::USTotal, 400));
+        stocks.add_asset(held_in("401k", "FXAIX", AssetClass::USTotal, 200));
+        let mut bonds = AssetAllocation::new(AssetClass::USBonds, Decimal::new(40, 2));
+        bonds.add_asset(held_in("Taxable", "VBTLX", AssetClass::USBonds, 400));
+        let balanced = optimally_allocate(Portfolio::new(vec![stocks, bonds]), 1000.into());
+
+        // $600 into stocks, but only $450 of room between the two accounts holding them
+        let order = vec![
+            SweepAccount {
+                account: String::from("401k"),
+                room: Some(200.into()),
+            },
+            SweepAccount {
+                account: String::from("Taxable"),
+                room: Some(250.into()),
+            },
+        ];
+        let plan = balanced.sweep(&order);
+        let swept: Vec<String> = plan
+            .contributions
+            .iter()
+            .map(|contribution| contribution.to_string())
+            .collect();
+        // Stocks fill both accounts' room first, leaving bonds only what's left in Taxable
+        assert_eq!(
+            swept,
+            vec![
+                "401k: $200 into US total market",
+                "Taxable: $250 into US total market",
+            ]
+        );
+        let mut over_room = BTreeMap::new();
+        over_room.insert(AssetClass::USTotal, Decimal::from(150));
+        over_room.insert(AssetClass::USBonds, Decimal::from(400));
+        assert_eq!(plan.over_room, over_room);
+    }
+
+    #[test]
+    fn test_new_money_and_swept_cash_invested_together() {
+        let mut allocations: Vec<AssetAllocation> = four_class_portfolio().allocations;
+        for allocation in allocations.iter_mut() {
+            allocation.target_ratio *= Decimal::new(95, 2);
+        }
+        let mut cash = AssetAllocation::new(AssetClass::Cash, Decimal::new(5, 2));
+        cash.add_asset(Asset::new(
+            String::from("Brokerage cash"),
+            None,
+            500.into(),
+            AssetClass::Cash,
+            None,
+            None,
+            None,
+        ));
+        allocations.push(cash);
+
+        let portfolio =
+            optimally_allocate_with_cash(Portfolio::new(allocations), 1_000.into(), 1.into());
+        let trades = trades(&portfolio);
+        let invested: Decimal = trades
+            .iter()
+            .filter(|(asset_class, _)| **asset_class != AssetClass::Cash)
+            .map(|(_, amount)| *amount)
+            .sum();
+        assert_eq!(invested.round_dp(2), Decimal::from(1_500));
+        assert_eq!(trades[&AssetClass::Cash], Decimal::from(-500));
+
+        let cash = portfolio
+            .allocations()
+            .iter()
+            .find(|allocation| allocation.asset_class == AssetClass::Cash)
+            .unwrap();
+        assert_eq!(cash.future_value(), 0.into());
+        // Only the new money changes the portfolio's total
+        assert_eq!(
+            portfolio.future_value().round_dp(2),
+            Decimal::from(800 + 500 + 1_000)
+        );
+    }
+
+    #[test]
+    fn test_contribution_to_target_for_one_class() {
+        let portfolio = three_fund_portfolio();
+
+        // Bonds are $140 of $1,000; selling $44.44 leaves $95.56 of $955.56 (10%)
+        let bonds = portfolio
+            .contribution_to_target(&AssetClass::USBonds)
+            .unwrap();
+        assert_eq!(bonds.round_dp(2), Decimal::new(-4444, 2));
+        assert_eq!(
+            ((Decimal::from(140) + bonds) / (Decimal::from(1_000) + bonds)).round_dp(10),
+            Decimal::new(10, 2)
+        );
+
+        // International is $200 of $1,000; buying $142.86 brings it to $342.86 of $1,142.86 (30%)
+        let intl = portfolio
+            .contribution_to_target(&AssetClass::IntlStocks)
+            .unwrap();
+        assert_eq!(intl.round_dp(2), Decimal::new(14286, 2));
+        assert_eq!(
+            ((Decimal::from(200) + intl) / (Decimal::from(1_000) + intl)).round_dp(10),
+            Decimal::new(30, 2)
+        );
+
+        // Asset classes not in the portfolio have nothing to reach
+        assert_eq!(
+            portfolio.contribution_to_target(&AssetClass::REIT),
+            Some(0.into())
+        );
+    }
+
+    #[test]
+    fn test_contribution_to_target_unreachable() {
+        let mut portfolio = three_fund_portfolio();
+        for allocation in portfolio.allocations.iter_mut() {
+            allocation.target_ratio = if allocation.asset_class == AssetClass::USTotal {
+                1.into()
+            } else {
+                0.into()
+            };
+        }
+        // Other classes still hold value, so no purchase brings US stocks to 100%
+        assert_eq!(portfolio.contribution_to_target(&AssetClass::USTotal), None);
+    }
 
-    #[test]
-    #[should_panic(expected = "Asset types must match")]
-    fn test_asset_types_must_match() {
-        let mut stocks = AssetAllocation::new(AssetClass::USTotal, 1.into());
+    fn banded_sixty_five_thirty_five() -> Portfolio {
+        let mut portfolio = PortfolioBuilder::new()
+            .class(AssetClass::USTotal, Decimal::new(55, 2))
+            .holding("VTSAX", 6_500.into())
+            .class(AssetClass::USBonds, Decimal::new(45, 2))
+            .holding("VBTLX", 3_500.into())
+            .build()
+            .unwrap();
+        let mut bands = BTreeMap::new();
+        bands.insert(
+            AssetClass::USTotal,
+            Band {
+                min: Decimal::new(50, 2),
+                max: Decimal::new(60, 2),
+            },
+        );
+        portfolio.set_bands(&bands);
+        portfolio
+    }
 
-        stocks.add_asset(Asset::new(
-            String::from("Vanguard Total Intl Bd Idx Admiral"),
-            Some(String::from("VTABX")),
-            1234.into(),
-            AssetClass::IntlBonds,
-            None,
-            None,
-            None,
-        ));
+    fn future_value_of(portfolio: &Portfolio, asset_class: AssetClass) -> Decimal {
+        portfolio
+            .allocations
+            .iter()
+            .find(|allocation| allocation.asset_class == asset_class)
+            .unwrap()
+            .future_value()
+            .round_dp(2)
     }
 
     #[test]
-    fn test_current_value_is_summed_assets() {
-        let mut stocks = AssetAllocation::new(AssetClass::USTotal, 1.into());
-        assert_eq!(stocks.current_value(), 0.into());
+    fn test_out_of_band_trimmed_to_edge() {
+        // At 65%, stocks are trimmed to the top of their band (60%), not to the 55% target
+        let balanced = optimally_allocate_within_bands(banded_sixty_five_thirty_five(), 0.into());
+        assert_eq!(
+            future_value_of(&balanced, AssetClass::USTotal),
+            6_000.into()
+        );
+        assert_eq!(
+            future_value_of(&balanced, AssetClass::USBonds),
+            4_000.into()
+        );
+    }
 
-        stocks.add_asset(Asset::new(
-            String::from("Vanguard Total Stock Market Index Fund Admiral Shares"),
-            Some(String::from("VTSAX")),
-            8675.into(),
-            AssetClass::USTotal,
-            None,
-            None,
-            None,
-        ));
+    #[test]
+    fn test_in_band_left_alone() {
+        // $1,000 more brings the portfolio to $11,000: stocks are back in band at 59%
+        let balanced =
+            optimally_allocate_within_bands(banded_sixty_five_thirty_five(), 1_000.into());
+        assert_eq!(
+            future_value_of(&balanced, AssetClass::USTotal),
+            6_500.into()
+        );
+        assert_eq!(
+            future_value_of(&balanced, AssetClass::USBonds),
+            4_500.into()
+        );
+    }
 
-        assert_eq!(stocks.current_value(), Decimal::from(8675));
+    #[test]
+    fn test_bands_set_by_asset_class() {
+        let mut portfolio = three_fund_portfolio();
+        let mut bands = BTreeMap::new();
+        let band = Band {
+            min: Decimal::new(20, 2),
+            max: Decimal::new(40, 2),
+        };
+        bands.insert(AssetClass::USBonds, band);
+        portfolio.set_bands(&bands);
+        for allocation in portfolio.allocations.iter() {
+            let expected = match allocation.asset_class {
+                AssetClass::USBonds => Some(band),
+                _ => None,
+            };
+            assert_eq!(allocation.band, expected);
+        }
+    }
 
-        stocks.add_asset(Asset::new(
-            String::from("Fidelity ZERO Total Market Index Fund"),
-            Some(String::from("FZROX")),
-            10000.into(),
-            AssetClass::USTotal,
-            None,
-            None,
-            None,
-        ));
+    #[test]
+    fn test_linked_classes_keep_their_ratio() {
+        let mut us_total = AssetAllocation::new(AssetClass::USTotal, Decimal::new(45, 2));
+        us_total.add_asset(held_in("IRA", "VTSAX", AssetClass::USTotal, 4_000));
+        let mut us_small = AssetAllocation::new(AssetClass::USSmall, Decimal::new(15, 2));
+        us_small.add_asset(held_in("IRA", "VSMAX", AssetClass::USSmall, 1_000));
+        let mut bonds = AssetAllocation::new(AssetClass::USBonds, Decimal::new(40, 2));
+        bonds.add_asset(held_in("IRA", "VBTLX", AssetClass::USBonds, 4_000));
+        let portfolio = Portfolio::new(vec![us_total, us_small, bonds]);
+
+        let link = LinkedClasses {
+            primary: AssetClass::USTotal,
+            secondary: AssetClass::USSmall,
+            ratio: 2.into(),
+        };
+        let balanced = optimally_allocate_linked(portfolio, 1_000.into(), &link);
 
-        assert_eq!(stocks.current_value(), Decimal::from(18675));
+        // Bonds are already on target, so the pair (targeting 60% together) receives all $1,000.
+        // Rather than following their individual targets (3:1), the pair ends at exactly 2:1.
+        assert_eq!(
+            future_value_of(&balanced, AssetClass::USBonds),
+            4_000.into()
+        );
+        assert_eq!(
+            future_value_of(&balanced, AssetClass::USTotal),
+            4_000.into()
+        );
+        assert_eq!(
+            future_value_of(&balanced, AssetClass::USSmall),
+            2_000.into()
+        );
+        assert_eq!(balanced.future_value().round_dp(2), 10_000.into());
     }
+
     #[test]
-    fn test_add_contribution() {
+    fn test_zero_target_class_is_liquidated() {
+        // An all-bonds strategy (e.g. `core_four(1.0)`) zeroes the stock classes
+        let mut stocks = AssetAllocation::new(AssetClass::USTotal, 0.into());
+        stocks.add_asset(held_in("IRA", "VTSAX", AssetClass::USTotal, 1_000));
         let mut bonds = AssetAllocation::new(AssetClass::USBonds, 1.into());
+        bonds.add_asset(held_in("IRA", "VBTLX", AssetClass::USBonds, 1_000));
+        let portfolio = Portfolio::new(vec![stocks, bonds]);
 
-        // Object starts with no known assets, no future contribution
-        assert_eq!(bonds.current_value(), 0.into());
-        assert_eq!(bonds.future_value(), 0.into());
+        assert_eq!(
+            portfolio.allocations[0].deviation(2_000.into()),
+            Decimal::MAX
+        );
+        // Contributing can never bring stocks to 0%, so it's not counted towards balancing
+        assert_eq!(portfolio.minimum_addition_to_balance(), 0.into());
 
-        // We add $37.20 as a future contribution
-        bonds.add_contribution(Decimal::new(3720, 2));
-        assert_eq!(bonds.current_value(), 0.into());
-        assert_eq!(bonds.future_value(), Decimal::new(3720, 2));
+        let plan = optimally_allocate(portfolio, 500.into()).contribution_plan();
+        let amounts: HashMap<AssetClass, Decimal> = plan
+            .contributions
+            .iter()
+            .map(|planned| (planned.asset_class.clone(), planned.amount))
+            .collect();
+        assert_eq!(amounts[&AssetClass::USTotal], Decimal::from(-1_000));
+        assert_eq!(amounts[&AssetClass::USBonds], Decimal::from(1_500));
 
-        // We add another future contribution ($14.67)
-        bonds.add_contribution(Decimal::new(1467, 2));
-        assert_eq!(bonds.current_value(), 0.into());
-        assert_eq!(bonds.future_value(), Decimal::new(5187, 2));
+        let stock_plan = plan
+            .contributions
+            .iter()
+            .find(|planned| planned.asset_class == AssetClass::USTotal)
+            .unwrap();
+        assert_eq!(
+            stock_plan.relative_deviation(stock_plan.start_ratio),
+            Decimal::from(-1)
+        );
+        assert_eq!(
+            stock_plan.relative_deviation(stock_plan.end_ratio),
+            0.into()
+        );
     }
 
     #[test]
-    fn test_minimum_to_balance_two_fund_portfolio() {
-        let mut stocks = AssetAllocation::new(AssetClass::USTotal, Decimal::new(50, 2));
-        let bonds = AssetAllocation::new(AssetClass::USBonds, Decimal::new(50, 2));
-        stocks.add_asset(Asset::new(
-            String::from("Vanguard Total Stock Market Index Fund Admiral Shares"),
-            Some(String::from("VTSAX")),
-            8675.into(),
-            AssetClass::USTotal,
-            None,
-            None,
-            None,
-        ));
+    fn test_assets_ordered_by_name_then_descending_value() {
+        let portfolio = [
+            ("VTSAX", 500),
+            ("FSKAX", 100),
+            ("VTSAX", 2_000),
+            ("SWTSX", 300),
+            ("VTSAX", 1_000),
+        ]
+        .iter()
+        .fold(
+            PortfolioBuilder::new().class(AssetClass::USTotal, 1.into()),
+            |builder, (name, value)| builder.holding(name, Decimal::from(*value)),
+        )
+        .build()
+        .unwrap();
+        let ordered: Vec<(&str, Decimal)> = portfolio.allocations()[0]
+            .assets()
+            .iter()
+            .map(|asset| (asset.name.as_str(), asset.value))
+            .collect();
+        assert_eq!(
+            ordered,
+            vec![
+                ("FSKAX", Decimal::from(100)),
+                ("SWTSX", Decimal::from(300)),
+                ("VTSAX", Decimal::from(2_000)),
+                ("VTSAX", Decimal::from(1_000)),
+                ("VTSAX", Decimal::from(500)),
+            ]
+        );
+    }
 
-        let allocations = vec![stocks, bonds];
-        let portfolio = Portfolio::new(allocations);
+    #[test]
+    fn test_shares_of_contribution_sum_to_100_percent() {
+        let plan = optimally_allocate(three_fund_portfolio(), 400.into()).contribution_plan();
+        let shares: Vec<Decimal> = plan
+            .contributions
+            .iter()
+            .map(|planned| planned.share_of(plan.contribution))
+            .collect();
+        assert!(shares.iter().all(|share| *share >= 0.into()));
+        assert_eq!(shares.iter().sum::<Decimal>().round_dp(6), Decimal::from(1));
 
-        // With $8,675 in stocks and 0 in bonds, you need $8,675 in bonds to get 50/50
-        assert_eq!(portfolio.minimum_addition_to_balance(), 8675.into());
+        // Withdrawals are shares of the (absolute) amount withdrawn
+        let plan = optimally_allocate(three_fund_portfolio(), (-400).into()).contribution_plan();
+        let total: Decimal = plan
+            .contributions
+            .iter()
+            .map(|planned| planned.share_of(plan.contribution))
+            .sum();
+        assert_eq!(total.round_dp(6), Decimal::from(1));
     }
 
     #[test]
-    fn test_minimum_to_balance_three_fund_portfolio() {
-        let mut us_stocks = AssetAllocation::new(AssetClass::USTotal, Decimal::new(60, 2));
-        let mut intl_stocks = AssetAllocation::new(AssetClass::IntlStocks, Decimal::new(30, 2));
-        let mut bonds = AssetAllocation::new(AssetClass::USBonds, Decimal::new(10, 2));
+    fn test_rebalance_band_status() {
+        assert_eq!(three_fund_portfolio().rebalance_band_status(), None);
 
-        // Portfolio is $1000 total.
-        // Bonds *and* stocks are over-allocated, but in an interesting way:
-        // - Stocks are overallocated *absolutely* ($60 over target, vs $40 over for bonds)
-        // - Bonds are overallocated from a target ratio standpoint.
-        //
-        // The ideal result is for bonds to be at 10% the total, still at $140.
-        // To do that, we need to add $400: $180 into US stocks, $220 international
-        us_stocks.add_asset(Asset::new(
-            String::from("Vanguard Total Stock Market Index Fund Admiral Shares"),
-            Some(String::from("VTSAX")),
-            660.into(),
-            AssetClass::USTotal,
-            None,
-            None,
-            None,
-        ));
-        intl_stocks.add_asset(Asset::new(
-            String::from("Vanguard Total International Stock Index Fund Admiral Shares"),
-            Some(String::from("VTIAX")),
-            200.into(),
-            AssetClass::IntlStocks,
-            None,
-            None,
-            None,
-        ));
-        bonds.add_asset(Asset::new(
-            String::from("Vanguard Total Bond Market Index Fund Admiral Shares"),
-            Some(String::from("VBTLX")),
-            140.into(),
+        // Stocks (65%) and bonds (35%) have both drifted out of their bands
+        let mut portfolio = banded_sixty_five_thirty_five();
+        let mut bands = BTreeMap::new();
+        bands.insert(
             AssetClass::USBonds,
-            None,
-            None,
-            None,
-        ));
-
-        let allocations = vec![us_stocks, intl_stocks, bonds];
-        let portfolio = Portfolio::new(allocations);
+            Band {
+                min: Decimal::new(40, 2),
+                max: Decimal::new(50, 2),
+            },
+        );
+        portfolio.set_bands(&bands);
+        let balanced = optimally_allocate_within_bands(portfolio, 0.into());
+        let status = balanced.rebalance_band_status().unwrap();
+        assert_eq!(
+            status,
+            BandStatus {
+                banded: 2,
+                in_band_before: 0,
+                in_band_after: 2,
+            }
+        );
+        assert_eq!(
+            status.to_string(),
+            "Within bands: 0 of 2 asset classes before, 2 after"
+        );
 
-        assert_eq!(portfolio.minimum_addition_to_balance(), 400.into());
+        // Contributing to bonds alone brings stocks back within their band (59%)
+        let balanced =
+            optimally_allocate_within_bands(banded_sixty_five_thirty_five(), 1_000.into());
+        assert_eq!(
+            balanced.rebalance_band_status(),
+            Some(BandStatus {
+                banded: 1,
+                in_band_before: 0,
+                in_band_after: 1,
+            })
+        );
+    }
 
-        // The recommendations for allocating money match what we'd expect:
-        // - $220 into Intl stocks, total $220
-        // - $180 into US stocks, total $840
-        // - $0 to bonds, remaining at $140
-        let balanced_portfolio = optimally_allocate(portfolio, 400.into());
-        assert_eq!(balanced_portfolio.future_value(), 1400.into());
-        let future_values: Vec<Decimal> = balanced_portfolio
-            .allocations
-            .iter()
-            .map(|allocation| allocation.future_value().round_dp(2))
-            .collect();
-        let tickers: Vec<&AssetClass> = balanced_portfolio
+    #[test]
+    fn test_report() {
+        let report = optimally_allocate(three_fund_portfolio(), 400.into()).report();
+        assert_eq!(report.total_value, Decimal::from(1000));
+        // Bonds are at 14% (targeting 10%), so the portfolio must grow to $1,400
+        assert_eq!(report.minimum_to_balance, Decimal::from(400));
+        assert_eq!(report.contribution, Decimal::from(400));
+
+        let contributions: Vec<(AssetClass, Decimal)> = report
             .allocations
             .iter()
-            .map(|allocation| &allocation.asset_class)
+            .map(|a| (a.asset_class.clone(), a.future_contribution))
             .collect();
+        assert_eq!(
+            contributions,
+            vec![
+                (AssetClass::USTotal, Decimal::from(180)),
+                (AssetClass::IntlStocks, Decimal::from(220)),
+                (AssetClass::USBonds, Decimal::from(0)),
+            ]
+        );
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["total_value"], "1000");
+        assert_eq!(json["allocations"][0]["asset_class"], "USTotal");
+        assert_eq!(json["allocations"][0]["target_ratio"], "0.60");
+        assert_eq!(json["allocations"][0]["current_value"], "660");
+    }
 
+    #[test]
+    fn test_buy_instructions_respect_increments() {
+        // Increments are in shares, so these holdings need a quantity & price
+        let mut portfolio = PortfolioBuilder::new()
+            .class(AssetClass::USTotal, Decimal::new(50, 2))
+            .asset(Asset::new(
+                String::from("Vanguard Total Stock Market ETF"),
+                Some(String::from("VTI")),
+                2000.into(),
+                AssetClass::USTotal,
+                Some(10.into()),
+                Some(200.into()),
+                None,
+            ))
+            .class(AssetClass::IntlStocks, Decimal::new(50, 2))
+            .asset(Asset::new(
+                String::from("Vanguard Total International Stock Index Fund Admiral Shares"),
+                Some(String::from("VTIAX")),
+                2000.into(),
+                AssetClass::IntlStocks,
+                Some(Decimal::new(63755, 3)),
+                Some(Decimal::new(3137, 2)),
+                None,
+            ))
+            .build()
+            .unwrap();
+        for allocation in portfolio.allocations.iter_mut() {
+            allocation.add_contribution(Decimal::from(650));
+        }
+
+        let mut increments = HashMap::new();
+        increments.insert(String::from("VTI"), Decimal::from(1));
+        increments.insert(String::from("VTIAX"), Decimal::new(1, 3));
+        let plan = portfolio.buy_instructions(&increments);
+        // What 3 whole shares of VTI & 20.720 shares of VTIAX don't spend ($50.0136)
+        assert_eq!(plan.leftover, Decimal::new(5001, 2));
+        let mut instructions = plan.instructions;
+        instructions.sort_by(|a, b| a.fund.cmp(&b.fund));
         assert_eq!(
-            tickers,
+            instructions,
             vec![
-                &AssetClass::IntlStocks,
-                &AssetClass::USTotal,
-                &AssetClass::USBonds,
+                // $650 buys only 3 whole shares at $200
+                BuyInstruction {
+                    fund: String::from("VTI"),
+                    amount: Decimal::from(600),
+                    shares: Some(Decimal::from(3)),
+                },
+                // $650 would buy 20.7204... shares, but only thousandths may be bought
+                BuyInstruction {
+                    fund: String::from("VTIAX"),
+                    amount: Decimal::new(6499864, 4),
+                    shares: Some(Decimal::new(20720, 3)),
+                },
             ]
         );
-        assert_eq!(future_values, vec![420.into(), 840.into(), 140.into()]);
+        assert_eq!(instructions[0].to_string(), "VTI: 3 shares ($600.00)");
+        assert_eq!(instructions[1].to_string(), "VTIAX: 20.72 shares ($649.98)");
+
+        // Without an increment, funds are bought in dollars
+        let dollars = portfolio.buy_instructions(&HashMap::new());
+        assert_eq!(dollars.leftover, Decimal::from(0));
+        assert!(dollars
+            .instructions
+            .iter()
+            .all(|instruction| instruction.shares.is_none()));
+        assert!(dollars
+            .instructions
+            .iter()
+            .all(|instruction| instruction.amount == Decimal::from(650)));
     }
 
     #[test]
-    fn test_minimum_to_balance_single_fund_portfolio() {
-        let terrible_allocation = AssetAllocation::new(AssetClass::Cash, 1.into());
-        let portfolio = Portfolio::new(vec![terrible_allocation]);
-        // Obviously, you never need to add money to get a 100% allocation
-        assert_eq!(portfolio.minimum_addition_to_balance(), 0.into());
+    fn test_buy_instructions_spend_everything_in_dollars() {
+        // Thirds can't be represented exactly, but nothing should be left over
+        let mut us_stocks = AssetAllocation::new(AssetClass::USTotal, Decimal::from(1));
+        for symbol in &["VTSAX", "VTI", "ITOT"] {
+            us_stocks.add_asset(Asset::new(
+                symbol.to_string(),
+                Some(symbol.to_string()),
+                100.into(),
+                AssetClass::USTotal,
+                None,
+                None,
+                None,
+            ));
+        }
+        us_stocks.add_contribution(Decimal::from(100));
+        let portfolio = Portfolio::new(vec![us_stocks]);
+
+        let plan = portfolio.buy_instructions(&HashMap::new());
+        assert_eq!(plan.leftover, Decimal::from(0));
+        let spent: Decimal = plan.instructions.iter().map(|i| i.amount).sum();
+        assert_eq!(spent, Decimal::from(100));
     }
 
     #[test]
-    fn test_allocations_sum_to_1() {
-        let terrible_allocation = AssetAllocation::new(AssetClass::Cash, 1.into());
-        let portfolio = Portfolio::new(vec![terrible_allocation]);
-        optimally_allocate(portfolio, 1_000.into());
+    fn test_buy_instructions_split_worthless_classes_evenly() {
+        let mut us_bonds = AssetAllocation::new(AssetClass::USBonds, Decimal::from(1));
+        for symbol in &["VBTLX", "BND"] {
+            us_bonds.add_asset(Asset::new(
+                symbol.to_string(),
+                Some(symbol.to_string()),
+                0.into(),
+                AssetClass::USBonds,
+                None,
+                None,
+                None,
+            ));
+        }
+        us_bonds.add_contribution(Decimal::from(500));
+        let portfolio = Portfolio::new(vec![us_bonds]);
+
+        let plan = portfolio.buy_instructions(&HashMap::new());
+        let amounts: Vec<Decimal> = plan.instructions.iter().map(|i| i.amount).collect();
+        assert_eq!(amounts, vec![Decimal::from(250), Decimal::from(250)]);
+        assert_eq!(plan.leftover, Decimal::from(0));
     }
 
     #[test]
-    #[should_panic(expected = "Cannot rebalance unless total is 100%")]
-    fn test_allocations_do_not_sum() {
-        let does_not_sum = vec![
-            AssetAllocation::new(AssetClass::USTotal, Decimal::new(3, 1)),
-            AssetAllocation::new(AssetClass::USBonds, Decimal::new(3, 1)),
-        ];
-        let portfolio = Portfolio::new(does_not_sum);
+    fn test_buy_instructions_name_classes_without_funds() {
+        let mut us_bonds = AssetAllocation::new(AssetClass::USBonds, Decimal::new(50, 2));
+        let us_stocks = AssetAllocation::new(AssetClass::USTotal, Decimal::new(50, 2));
+        us_bonds.add_contribution(Decimal::from(500));
+        let portfolio = Portfolio::new(vec![us_stocks, us_bonds]);
 
-        optimally_allocate(portfolio, 1_000.into());
+        let plan = portfolio.buy_instructions(&HashMap::new());
+        assert_eq!(
+            plan.instructions,
+            vec![BuyInstruction {
+                fund: String::from("US bonds (no fund held yet)"),
+                amount: Decimal::from(500),
+                shares: None,
+            }]
+        );
+        assert_eq!(plan.leftover, Decimal::from(0));
     }
 
     #[test]
-    fn test_should_sort_by_current_allocation_value() {
-        let mut stocks = AssetAllocation::new(AssetClass::USTotal, Decimal::new(50, 2));
-        let mut bonds = AssetAllocation::new(AssetClass::USBonds, Decimal::new(50, 2));
+    fn test_builder_requires_ratios_summing_to_one() {
+        let err = PortfolioBuilder::new()
+            .class(AssetClass::USTotal, Decimal::new(60, 2))
+            .holding("VTSAX", 600.into())
+            .class(AssetClass::USBonds, Decimal::new(30, 2))
+            .build()
+            .err()
+            .unwrap();
+        assert_eq!(
+            err,
+            BuildError::TargetRatio(TargetRatioError {
+                total: Decimal::new(90, 2)
+            })
+        );
+        assert_eq!(err.to_string(), "Target ratios must sum to 100%, not 90%");
+    }
 
-        // We keep $10 in bonds, but plan to contribute nearly $1 million in stocks
-        bonds.add_asset(Asset::new(
-            String::from("Vanguard Total Intl Bd Idx Admiral"),
-            Some(String::from("VBTLX")),
-            10.into(),
-            AssetClass::USBonds,
-            None,
-            None,
-            None,
-        ));
-        stocks.add_contribution(999_999.into());
+    #[test]
+    fn test_builder_holdings_need_a_class() {
+        let err = PortfolioBuilder::new()
+            .holding("VTSAX", 600.into())
+            .class(AssetClass::USTotal, Decimal::from(1))
+            .build()
+            .err()
+            .unwrap();
+        assert_eq!(err, BuildError::HoldingWithoutClass);
+        assert_eq!(
+            err.to_string(),
+            "Declare an asset class before its holdings"
+        );
+    }
 
-        // Ordering is done by current value.
-        let mut allocations = vec![&stocks, &bonds];
-        allocations.sort();
-        assert_eq!(allocations, vec![&bonds, &stocks]);
+    #[test]
+    fn test_totals_after_contribution() {
+        assert_eq!(three_fund_portfolio().describe_totals(), None);
+
+        let contributed = optimally_allocate(three_fund_portfolio(), 1_000.into());
+        assert_eq!(
+            contributed.describe_totals(),
+            Some(String::from("Current: $1,000 → After contribution: $2,000"))
+        );
+        assert!(contributed
+            .to_string()
+            .ends_with("Portfolio total: $1,000\nCurrent: $1,000 → After contribution: $2,000"));
+
+        let withdrawn = optimally_allocate(three_fund_portfolio(), (-100).into());
+        assert_eq!(
+            withdrawn.describe_totals(),
+            Some(String::from("Current: $1,000 → After withdrawal: $900"))
+        );
+    }
+
+    #[test]
+    fn test_ibond_value() {
+        let portfolio = PortfolioBuilder::new()
+            .class(AssetClass::USTotal, Decimal::new(80, 2))
+            .holding("VTSAX", 800.into())
+            .class(AssetClass::USBonds, Decimal::new(20, 2))
+            .holding("VBTLX", 50.into())
+            .holding("Series I 2022-05", 100.into())
+            .holding("Series I 2023-11", 50.into())
+            .build()
+            .unwrap();
+        assert_eq!(portfolio.ibond_value(), Decimal::from(150));
+        assert_eq!(ninety_ten_portfolio().ibond_value(), Decimal::ZERO);
     }
 }
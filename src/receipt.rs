@@ -0,0 +1,208 @@
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use std::fs;
+use std::io;
+
+use crate::decutil;
+use crate::rebalance::ContributionPlan;
+
+fn percent(ratio: Decimal) -> String {
+    format!("{:.2}%", (ratio * Decimal::from(100)).round_dp(2))
+}
+
+/// The fraction of `total` made up by `value` (nothing, if the total is zero)
+fn ratio_of(value: Decimal, total: Decimal) -> Decimal {
+    if total == 0.into() {
+        0.into()
+    } else {
+        value / total
+    }
+}
+
+/// Render a shareable Markdown record of a rebalance, with a row for each fund bought or sold
+///
+/// Asset classes without any fund yet are listed by name in place of a fund.
+pub fn render_markdown(plan: &ContributionPlan, date: NaiveDate) -> String {
+    let label = if plan.contribution.is_sign_negative() {
+        "Withdrawal"
+    } else {
+        "Contribution"
+    };
+    let ending_total = plan.ending_total();
+    let mut lines = vec![
+        format!("# Rebalance receipt: {:}", date.format("%Y-%m-%d")),
+        String::new(),
+        format!(
+            "- Starting total: {:}",
+            decutil::format_dollars(&plan.starting_total)
+        ),
+        format!("- {:}: ${:.2}", label, plan.contribution.abs()),
+        format!(
+            "- Ending total: {:}",
+            decutil::format_dollars(&ending_total)
+        ),
+        String::new(),
+        String::from("| Fund | Asset class | Action | Amount | Before | After | Class target |"),
+        String::from("|---|---|---|---:|---:|---:|---:|"),
+    ];
+
+    for planned in plan.contributions.iter() {
+        let action = if planned.amount.is_sign_negative() {
+            "Sell"
+        } else {
+            "Buy"
+        };
+        let mut rows: Vec<(String, Decimal, Decimal)> = planned
+            .funds
+            .iter()
+            .map(|fund| {
+                (
+                    fund.fund.clone(),
+                    fund.current_value,
+                    planned.amount * fund.share,
+                )
+            })
+            .collect();
+        if rows.is_empty() {
+            rows.push((
+                format!("{:} (no fund held yet)", planned.asset_class),
+                0.into(),
+                planned.amount,
+            ));
+        }
+        for (fund, current_value, amount) in rows {
+            lines.push(format!(
+                "| {:} | {:} | {:} | ${:.2} | {:} | {:} | {:} |",
+                fund,
+                planned.asset_class,
+                action,
+                amount.abs(),
+                percent(ratio_of(current_value, plan.starting_total)),
+                percent(ratio_of(current_value + amount, ending_total)),
+                percent(planned.target_ratio),
+            ));
+        }
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+pub fn write_receipt(path: &str, plan: &ContributionPlan, date: NaiveDate) -> io::Result<()> {
+    fs::write(path, render_markdown(plan, date))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::{Asset, AssetClass};
+    use crate::rebalance::{AssetAllocation, ContributionPlan, Portfolio};
+
+    /// An asset class holding each (symbol, value), with a planned contribution
+    fn planned_class(
+        asset_class: AssetClass,
+        target_ratio: Decimal,
+        holdings: &[(&str, i64)],
+        contribution: i64,
+    ) -> AssetAllocation {
+        let mut allocation = AssetAllocation::new(asset_class.clone(), target_ratio);
+        for (symbol, value) in holdings {
+            allocation.add_asset(Asset::new(
+                symbol.to_string(),
+                Some(symbol.to_string()),
+                Decimal::from(*value),
+                asset_class.clone(),
+                None,
+                None,
+                None,
+            ));
+        }
+        allocation.add_contribution(contribution.into());
+        allocation
+    }
+
+    /// $500 each in stocks & bonds (targeting 70/30), then buying or selling stocks
+    fn two_fund_plan(stock_contribution: i64) -> ContributionPlan {
+        Portfolio::new(vec![
+            planned_class(
+                AssetClass::USTotal,
+                Decimal::new(70, 2),
+                &[("VTSAX", 500)],
+                stock_contribution,
+            ),
+            planned_class(
+                AssetClass::USBonds,
+                Decimal::new(30, 2),
+                &[("VBTLX", 500)],
+                0,
+            ),
+        ])
+        .contribution_plan()
+    }
+
+    #[test]
+    fn test_render_two_fund_receipt() {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 10).unwrap();
+        let markdown = render_markdown(&two_fund_plan(500), date);
+        let lines: Vec<&str> = markdown.lines().collect();
+
+        assert_eq!(lines[0], "# Rebalance receipt: 2024-01-10");
+        assert!(lines.contains(&"- Starting total: $1,000"));
+        assert!(lines.contains(&"- Contribution: $500.00"));
+        assert!(lines.contains(&"- Ending total: $1,500"));
+        assert!(lines
+            .contains(&"| VTSAX | US total market | Buy | $500.00 | 50.00% | 66.67% | 70.00% |"));
+        assert!(lines.contains(&"| VBTLX | US bonds | Buy | $0.00 | 50.00% | 33.33% | 30.00% |"));
+    }
+
+    #[test]
+    fn test_funds_split_their_class_contribution() {
+        let plan = Portfolio::new(vec![
+            planned_class(
+                AssetClass::USTotal,
+                Decimal::new(60, 2),
+                &[("VTSAX", 300), ("VTI", 100)],
+                200,
+            ),
+            planned_class(
+                AssetClass::USBonds,
+                Decimal::new(40, 2),
+                &[("VBTLX", 600)],
+                0,
+            ),
+        ])
+        .contribution_plan();
+        let date = NaiveDate::from_ymd_opt(2024, 1, 10).unwrap();
+        let markdown = render_markdown(&plan, date);
+
+        // $200 into US stocks is split 3:1 between its funds ($450 & $150 of $1,200)
+        assert!(markdown.contains("| VTSAX | US total market | Buy | $150.00 | 30.00% | 37.50% |"));
+        assert!(markdown.contains("| VTI | US total market | Buy | $50.00 | 10.00% | 12.50% |"));
+    }
+
+    #[test]
+    fn test_classes_without_funds_are_named() {
+        let plan = Portfolio::new(vec![
+            planned_class(
+                AssetClass::USTotal,
+                Decimal::new(90, 2),
+                &[("VTSAX", 900)],
+                0,
+            ),
+            planned_class(AssetClass::USBonds, Decimal::new(10, 2), &[], 100),
+        ])
+        .contribution_plan();
+        let date = NaiveDate::from_ymd_opt(2024, 1, 10).unwrap();
+        let markdown = render_markdown(&plan, date);
+        assert!(markdown.contains(
+            "| US bonds (no fund held yet) | US bonds | Buy | $100.00 | 0.00% | 10.00% | 10.00% |"
+        ));
+    }
+
+    #[test]
+    fn test_withdrawals_are_sales() {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 10).unwrap();
+        let markdown = render_markdown(&two_fund_plan(-100), date);
+        assert!(markdown.contains("- Withdrawal: $100.00"));
+        assert!(markdown.contains("| VTSAX | US total market | Sell | $100.00 |"));
+    }
+}
@@ -0,0 +1,158 @@
+use rust_decimal::Decimal;
+use std::cmp;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+
+use crate::decutil;
+use crate::rebalance::Portfolio;
+
+/// The value of each fund as of some run, for comparison against the next run
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Total value of each fund, keyed by symbol (or account name, for holdings without one)
+    pub values: BTreeMap<String, Decimal>,
+}
+
+/// The change in one fund's value between two snapshots
+#[derive(Debug, PartialEq, Eq)]
+pub struct Mover {
+    pub fund: String,
+    pub change: Decimal,
+}
+
+impl fmt::Display for Mover {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.change.is_sign_negative() {
+            "-"
+        } else {
+            "+"
+        };
+        write!(
+            f,
+            "{:} {:}{:}",
+            self.fund,
+            sign,
+            decutil::format_dollars(&self.change.abs())
+        )
+    }
+}
+
+impl Snapshot {
+    /// Record the value of every fund in the portfolio (funds held in several accounts are summed)
+    pub fn of(portfolio: &Portfolio) -> Snapshot {
+        let mut values = BTreeMap::new();
+        for allocation in portfolio.allocations() {
+            for asset in allocation.assets() {
+                let fund = asset.symbol.clone().unwrap_or_else(|| asset.name.clone());
+                *values.entry(fund).or_default() += asset.value;
+            }
+        }
+        Snapshot { values }
+    }
+
+    /// Read a snapshot saved by a previous run (if there is one)
+    pub fn load(path: &str) -> Result<Option<Snapshot>, Box<dyn Error>> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Ok(Some(serde_json::from_str(&contents)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Every fund whose value changed since the previous snapshot, largest change first
+    ///
+    /// Funds bought or sold off entirely count as changing from (or to) nothing.
+    pub fn movers_since(&self, previous: &Snapshot) -> Vec<Mover> {
+        let zero = Decimal::from(0);
+        let mut funds: Vec<&String> = self.values.keys().collect();
+        funds.extend(
+            previous
+                .values
+                .keys()
+                .filter(|fund| !self.values.contains_key(*fund)),
+        );
+
+        let mut movers: Vec<Mover> = funds
+            .into_iter()
+            .map(|fund| Mover {
+                fund: fund.clone(),
+                change: self.values.get(fund).unwrap_or(&zero)
+                    - previous.values.get(fund).unwrap_or(&zero),
+            })
+            .filter(|mover| mover.change != zero)
+            .collect();
+        movers.sort_by_key(|mover| cmp::Reverse(mover.change.abs()));
+        movers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(values: &[(&str, i64)]) -> Snapshot {
+        Snapshot {
+            values: values
+                .iter()
+                .map(|(fund, value)| (String::from(*fund), Decimal::from(*value)))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_movers_ranked_by_absolute_change() {
+        let previous = snapshot(&[("VTSAX", 50_000), ("VBTLX", 20_000), ("VTIAX", 30_000)]);
+        let current = snapshot(&[
+            ("VTSAX", 53_210),
+            ("VBTLX", 19_860),
+            ("VTIAX", 30_000),
+            ("VGSLX", 500),
+        ]);
+        let movers: Vec<String> = current
+            .movers_since(&previous)
+            .iter()
+            .map(|mover| mover.to_string())
+            .collect();
+        // Unchanged funds are left out; gains & losses alike are ranked by size (a new fund too)
+        assert_eq!(movers, vec!["VTSAX +$3,210", "VGSLX +$500", "VBTLX -$140"]);
+    }
+
+    #[test]
+    fn test_funds_sold_off_entirely() {
+        let previous = snapshot(&[("VTSAX", 1_000), ("VFIAX", 2_000)]);
+        let current = snapshot(&[("VTSAX", 1_100)]);
+        assert_eq!(
+            current.movers_since(&previous),
+            vec![
+                Mover {
+                    fund: String::from("VFIAX"),
+                    change: Decimal::from(-2_000),
+                },
+                Mover {
+                    fund: String::from("VTSAX"),
+                    change: Decimal::from(100),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let path = std::env::temp_dir().join(format!("snapshot_{:}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        assert_eq!(Snapshot::load(path).unwrap(), None);
+
+        let saved = snapshot(&[("VTSAX", 53_210), ("VBTLX", 19_860)]);
+        saved.save(path).unwrap();
+        assert_eq!(Snapshot::load(path).unwrap(), Some(saved));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,5 +1,81 @@
-use rusqlite::{Connection, NO_PARAMS};
+use crate::gnucash::fraction_from_row;
+use chrono::NaiveDate;
+use rusqlite::{params, Connection, OpenFlags, NO_PARAMS};
 use rust_decimal::Decimal;
+use std::collections::{HashMap, VecDeque};
+
+/// A purchase (positive quantity) or sale (negative quantity) of some commodity
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trade {
+    pub quantity: Decimal,
+    /// Amount paid (positive) or received (negative), as recorded in the split
+    pub value: Decimal,
+}
+
+/// The outcome of matching sales against purchases, first in first out
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct LotGains {
+    /// Gains (or losses) on shares which were sold
+    pub realized: Decimal,
+    /// What was paid for the shares still held
+    pub cost_basis: Decimal,
+}
+
+/// Match each sale against the oldest purchases still held (FIFO), in chronological order
+///
+/// A sale may consume part of a lot, leaving the rest (at a proportional basis) for later.
+/// Splits which move no shares (e.g. a fee or price adjustment) are neither purchases nor sales.
+pub fn fifo_gains(trades: &[Trade]) -> LotGains {
+    // Open lots, as (quantity, basis)
+    let mut lots: VecDeque<(Decimal, Decimal)> = VecDeque::new();
+    let mut realized: Decimal = 0.into();
+
+    for trade in trades {
+        if trade.quantity == 0.into() {
+            continue;
+        }
+        if trade.quantity.is_sign_positive() {
+            lots.push_back((trade.quantity, trade.value));
+            continue;
+        }
+
+        let proceeds = -trade.value;
+        let mut to_sell = -trade.quantity;
+        let mut basis_sold: Decimal = 0.into();
+        while to_sell > 0.into() {
+            let (quantity, basis) = match lots.pop_front() {
+                Some(lot) => lot,
+                None => break, // Selling shares we never bought; count the rest as pure gain
+            };
+            if quantity <= to_sell {
+                to_sell -= quantity;
+                basis_sold += basis;
+            } else {
+                let partial_basis = basis * to_sell / quantity;
+                basis_sold += partial_basis;
+                lots.push_front((quantity - to_sell, basis - partial_basis));
+                to_sell = 0.into();
+            }
+        }
+        realized += proceeds - basis_sold;
+    }
+
+    LotGains {
+        realized,
+        cost_basis: lots.iter().map(|(_, basis)| *basis).sum(),
+    }
+}
+
+/// How income is signed in a book's splits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IncomeSign {
+    /// Income is negative, as is conventional in double-entry accounting (and GnuCash)
+    #[default]
+    Negative,
+    /// Income is positive (as in some imported books)
+    Positive,
+}
 
 pub struct Stats {
     conn: Connection,
@@ -7,9 +83,17 @@ pub struct Stats {
 
 impl Stats {
     /// Open a connection to a SQLite accounting file, provide statistics!
-    pub fn new(filename: &str) -> Stats {
-        let conn = Connection::open(filename).expect("Could not open file");
-        Stats { conn }
+    pub fn new(filename: &str, flags: OpenFlags) -> Stats {
+        Stats::try_new(filename, flags).expect("Could not open file")
+    }
+
+    /// Open an existing SQLite accounting file (a missing file is an error, not created)
+    ///
+    /// Pass `OpenFlags::SQLITE_OPEN_READ_ONLY` to guarantee the book is left untouched.
+    pub fn try_new(filename: &str, flags: OpenFlags) -> rusqlite::Result<Stats> {
+        let flags = flags - OpenFlags::SQLITE_OPEN_CREATE;
+        let conn = Connection::open_with_flags(filename, flags)?;
+        Ok(Stats { conn })
     }
 
     /// Retrieve the guid of an account under Root -> Expenses
@@ -35,7 +119,7 @@ impl Stats {
             ",
             name = name
         );
-        let mut stmt = (&self.conn).prepare(&sql)?;
+        let mut stmt = self.conn.prepare(&sql)?;
         let mut guids = stmt.query_map(NO_PARAMS, |row| {
             let taxes_guid: String = row.get(0)?;
             Ok(taxes_guid)
@@ -59,12 +143,8 @@ impl Stats {
             where_clause = where_clause
         );
 
-        let mut stmt = (&self.conn).prepare(&sql)?;
-        let rows = stmt.query_map(NO_PARAMS, |row| {
-            let value_num: i64 = row.get(0)?;
-            let value_denom: i64 = row.get(1)?;
-            Ok(Decimal::from(value_num) / Decimal::from(value_denom))
-        })?;
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map(NO_PARAMS, |row| fraction_from_row(row, 0, 1))?;
 
         rows.sum()
     }
@@ -90,12 +170,14 @@ impl Stats {
     ///
     /// Note that income will be _positive_, despite the fact that dual-entry
     /// accounting typically regards income as negatively signed.
-    fn income_before_taxes(&self) -> rusqlite::Result<Decimal> {
-        match self.sum_splits("", "account_type='INCOME'") {
+    /// (If it's negative anyway, the book likely uses the other sign convention)
+    pub fn income_before_taxes(&self, sign: IncomeSign) -> rusqlite::Result<Decimal> {
+        let total = self.sum_splits("", "account_type='INCOME'")?;
+        Ok(match sign {
             // Income is recorded as negative, but we want to consider it positive!
-            Ok(total) => Ok(-total),
-            x => x,
-        }
+            IncomeSign::Negative => -total,
+            IncomeSign::Positive => total,
+        })
     }
 
     /// Sum all taxes paid out of income
@@ -114,8 +196,118 @@ impl Stats {
     ///
     /// Note that the return value is expected to be _positive_ (unless the amount
     /// paid in taxes somehow exceeds total income).
-    pub fn after_tax_income(&self) -> rusqlite::Result<Decimal> {
-        Ok(self.income_before_taxes()? - self.taxes_paid()?)
+    pub fn after_tax_income(&self, sign: IncomeSign) -> rusqlite::Result<Decimal> {
+        Ok(self.income_before_taxes(sign)? - self.taxes_paid()?)
+    }
+
+    /// Read every trade in investment accounts, grouped by account, in the order they were posted
+    ///
+    /// Returns a map from each account to its commodity's symbol & trades.
+    fn investment_trades(&self) -> rusqlite::Result<HashMap<String, (String, Vec<Trade>)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT a.guid, c.mnemonic,
+                    s.quantity_num, s.quantity_denom,
+                    s.value_num, s.value_denom
+               FROM splits s
+                    JOIN accounts a     ON s.account_guid = a.guid
+                    JOIN commodities c  ON a.commodity_guid = c.guid
+                    JOIN transactions t ON s.tx_guid = t.guid
+              WHERE c.namespace = 'FUND'
+              ORDER BY t.post_date, s.guid",
+        )?;
+        let rows = stmt.query_map(NO_PARAMS, |row| {
+            let account_guid: String = row.get(0)?;
+            let symbol: String = row.get(1)?;
+            let trade = Trade {
+                quantity: fraction_from_row(row, 2, 3)?,
+                value: fraction_from_row(row, 4, 5)?,
+            };
+            Ok((account_guid, symbol, trade))
+        })?;
+
+        let mut by_account: HashMap<String, (String, Vec<Trade>)> = HashMap::new();
+        for row in rows {
+            let (account_guid, symbol, trade) = row?;
+            by_account
+                .entry(account_guid)
+                .or_insert_with(|| (symbol, Vec::new()))
+                .1
+                .push(trade);
+        }
+        Ok(by_account)
+    }
+
+    /// Sum gains (or losses) on all shares sold, matching sales to purchases FIFO within each account
+    pub fn realized_gains(&self) -> rusqlite::Result<Decimal> {
+        Ok(self
+            .investment_trades()?
+            .values()
+            .map(|(_, trades)| fifo_gains(trades).realized)
+            .sum())
+    }
+
+    /// What was paid for shares still held, keyed by symbol (see `Portfolio::unrealized_gains`)
+    pub fn cost_basis_by_symbol(&self) -> rusqlite::Result<HashMap<String, Decimal>> {
+        let mut cost_basis: HashMap<String, Decimal> = HashMap::new();
+        for (symbol, trades) in self.investment_trades()?.values() {
+            *cost_basis.entry(symbol.clone()).or_default() += fifo_gains(trades).cost_basis;
+        }
+        Ok(cost_basis)
+    }
+
+    /// Sum money put into investments (less money taken out) after `start`, through `end`
+    ///
+    /// Only funds for which `includes` holds are counted (e.g. those in a portfolio's targets).
+    /// Purchases funded entirely from income (i.e. reinvested dividends) aren't contributions:
+    /// that money was earned by the investments themselves.
+    pub fn net_contributions<F>(
+        &self,
+        start: NaiveDate,
+        end: NaiveDate,
+        includes: F,
+    ) -> rusqlite::Result<Decimal>
+    where
+        F: Fn(&str) -> bool,
+    {
+        let mut stmt = self.conn.prepare(
+            "WITH from_income AS (
+               SELECT s.tx_guid
+                 FROM splits s
+                      JOIN accounts a ON s.account_guid = a.guid
+                WHERE a.account_type = 'INCOME'
+             ), from_elsewhere AS (
+               SELECT s.tx_guid
+                 FROM splits s
+                      JOIN accounts a    ON s.account_guid = a.guid
+                      JOIN commodities c ON a.commodity_guid = c.guid
+                WHERE a.account_type != 'INCOME'
+                  AND c.namespace != 'FUND'
+             )
+             SELECT c.mnemonic, s.value_num, s.value_denom
+               FROM splits s
+                    JOIN accounts a     ON s.account_guid = a.guid
+                    JOIN commodities c  ON a.commodity_guid = c.guid
+                    JOIN transactions t ON s.tx_guid = t.guid
+              WHERE c.namespace = 'FUND'
+                AND date(t.post_date) > ?1
+                AND date(t.post_date) <= ?2
+                AND NOT (
+                  s.tx_guid IN (SELECT tx_guid FROM from_income)
+                  AND s.tx_guid NOT IN (SELECT tx_guid FROM from_elsewhere)
+                )",
+        )?;
+        let rows = stmt.query_map(params![start.to_string(), end.to_string()], |row| {
+            let symbol: String = row.get(0)?;
+            Ok((symbol, fraction_from_row(row, 1, 2)?))
+        })?;
+        let mut total = Decimal::from(0);
+        for row in rows {
+            let (symbol, value) = row?;
+            if includes(&symbol) {
+                total += value;
+            }
+        }
+        Ok(total)
     }
 
     /// Sum value of all contributions to charity
@@ -124,3 +316,277 @@ impl Stats {
         self.sum_all_transactions_in(&charity_guid)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_nonexistent_file() {
+        assert!(Stats::try_new(
+            "/tmp/definitely_does_not_exist.gnucash",
+            OpenFlags::SQLITE_OPEN_READ_WRITE
+        )
+        .is_err());
+    }
+
+    fn trade(quantity: i64, value: i64) -> Trade {
+        Trade {
+            quantity: Decimal::from(quantity),
+            value: Decimal::from(value),
+        }
+    }
+
+    #[test]
+    fn test_fifo_partial_sale() {
+        // Buy 10 @ $100, buy 10 @ $120, then sell 15 @ $140
+        let gains = fifo_gains(&[trade(10, 1000), trade(10, 1200), trade(-15, -2100)]);
+        // The sale consumes all of the first lot ($1,000) & half the second ($600)
+        assert_eq!(gains.realized, Decimal::from(500));
+        assert_eq!(gains.cost_basis, Decimal::from(600));
+    }
+
+    #[test]
+    fn test_fifo_skips_zero_quantity_splits() {
+        let gains = fifo_gains(&[trade(10, 1000), trade(0, -25), trade(-5, -600)]);
+        assert_eq!(gains.realized, Decimal::from(100));
+        assert_eq!(gains.cost_basis, Decimal::from(500));
+    }
+
+    #[test]
+    fn test_fifo_without_sales() {
+        let gains = fifo_gains(&[trade(10, 1000), trade(5, 450)]);
+        assert_eq!(gains.realized, Decimal::from(0));
+        assert_eq!(gains.cost_basis, Decimal::from(1450));
+    }
+
+    /// A book with a single fund, bought twice and then partially sold
+    fn book_with_partial_sale() -> Stats {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE commodities(guid text, namespace text, mnemonic text);
+             CREATE TABLE accounts(guid text, name text, account_type text, commodity_guid text);
+             CREATE TABLE transactions(guid text, post_date text);
+             CREATE TABLE splits(
+                guid text, tx_guid text, account_guid text,
+                value_num bigint, value_denom bigint,
+                quantity_num bigint, quantity_denom bigint
+             );
+             INSERT INTO commodities VALUES ('vtsax', 'FUND', 'VTSAX'), ('usd', 'CURRENCY', 'USD');
+             INSERT INTO accounts VALUES
+                ('roth', 'Roth IRA', 'MUTUAL', 'vtsax'),
+                ('cash', 'Cash', 'BANK', 'usd');
+             INSERT INTO transactions VALUES
+                ('buy1', '2021-01-04 10:59:00'),
+                ('buy2', '2022-01-04 10:59:00'),
+                ('sell', '2023-01-04 10:59:00');
+             INSERT INTO splits VALUES
+                ('s1', 'buy1', 'roth', 100000, 100, 10, 1),
+                ('s2', 'buy1', 'cash', -100000, 100, -100000, 100),
+                ('s3', 'buy2', 'roth', 120000, 100, 100, 10),
+                ('s4', 'buy2', 'cash', -120000, 100, -120000, 100),
+                ('s5', 'sell', 'roth', -210000, 100, -15, 1),
+                ('s6', 'sell', 'cash', 210000, 100, 210000, 100);",
+        )
+        .unwrap();
+        Stats { conn }
+    }
+
+    #[test]
+    fn test_fractions_overflowing_i64() {
+        let conn = Connection::open_in_memory().unwrap();
+        // Untyped columns keep oversized integers as text (rather than lossy floats)
+        conn.execute_batch(
+            "CREATE TABLE commodities(guid, namespace, mnemonic);
+             CREATE TABLE accounts(guid, name, account_type, commodity_guid);
+             CREATE TABLE transactions(guid, post_date);
+             CREATE TABLE splits(
+                guid, tx_guid, account_guid, value_num, value_denom, quantity_num, quantity_denom
+             );
+             INSERT INTO commodities VALUES ('vtsax', 'FUND', 'VTSAX'), ('usd', 'CURRENCY', 'USD');
+             INSERT INTO accounts VALUES
+                ('roth', 'Roth IRA', 'MUTUAL', 'vtsax'),
+                ('cash', 'Cash', 'BANK', 'usd');
+             INSERT INTO transactions VALUES
+                ('buy', '2021-01-04 10:59:00'),
+                ('sell', '2022-01-04 10:59:00');
+             INSERT INTO splits VALUES
+                ('s1', 'buy', 'roth', '10000000000000000000000', '10000000000000000000',
+                 '100000000000000000000', '10000000000000000000'),
+                ('s2', 'buy', 'cash', -100000, 100, -100000, 100),
+                ('s3', 'sell', 'roth', -60000, 100, -5, 1),
+                ('s4', 'sell', 'cash', 60000, 100, 60000, 100);",
+        )
+        .unwrap();
+        let stats = Stats { conn };
+        let ymd = |year, month, day| NaiveDate::from_ymd_opt(year, month, day).unwrap();
+
+        // Bought 10 shares for $1,000, then sold half for $600
+        assert_eq!(stats.realized_gains().unwrap(), Decimal::from(100));
+        assert_eq!(
+            stats
+                .net_contributions(ymd(2020, 12, 31), ymd(2021, 12, 31), |_| true)
+                .unwrap(),
+            Decimal::from(1000)
+        );
+    }
+
+    #[test]
+    fn test_net_contributions() {
+        let stats = book_with_partial_sale();
+        let ymd = |year, month, day| NaiveDate::from_ymd_opt(year, month, day).unwrap();
+        let all = |_: &str| true;
+        assert_eq!(
+            stats
+                .net_contributions(ymd(2021, 12, 31), ymd(2022, 12, 31), all)
+                .unwrap(),
+            Decimal::from(1200)
+        );
+        // Sales count against contributions
+        assert_eq!(
+            stats
+                .net_contributions(ymd(2020, 12, 31), ymd(2023, 12, 31), all)
+                .unwrap(),
+            Decimal::from(1000 + 1200 - 2100)
+        );
+        // The start is exclusive, the end inclusive
+        assert_eq!(
+            stats
+                .net_contributions(ymd(2021, 1, 4), ymd(2022, 1, 4), all)
+                .unwrap(),
+            Decimal::from(1200)
+        );
+    }
+
+    #[test]
+    fn test_net_contributions_exclude_reinvestment_and_other_funds() {
+        let stats = book_with_partial_sale();
+        stats
+            .conn
+            .execute_batch(
+                "INSERT INTO commodities VALUES ('vbtlx', 'FUND', 'VBTLX');
+                 INSERT INTO accounts VALUES
+                    ('bonds', 'Bonds', 'MUTUAL', 'vbtlx'),
+                    ('dividends', 'Dividend Income', 'INCOME', 'usd'),
+                    ('salary', 'Salary', 'INCOME', 'usd');
+                 INSERT INTO transactions VALUES
+                    ('reinvest', '2022-06-30 10:59:00'),
+                    ('bond_buy', '2022-07-01 10:59:00'),
+                    ('paycheck', '2022-07-15 10:59:00');
+                 INSERT INTO splits VALUES
+                    ('s7', 'reinvest', 'roth', 5000, 100, 4, 10),
+                    ('s8', 'reinvest', 'dividends', -5000, 100, -5000, 100),
+                    ('s9', 'bond_buy', 'bonds', 50000, 100, 50, 1),
+                    ('s10', 'bond_buy', 'cash', -50000, 100, -50000, 100),
+                    ('s11', 'paycheck', 'roth', 30000, 100, 2, 1),
+                    ('s12', 'paycheck', 'cash', 70000, 100, 70000, 100),
+                    ('s13', 'paycheck', 'salary', -100000, 100, -100000, 100);",
+            )
+            .unwrap();
+        let ymd = |year, month, day| NaiveDate::from_ymd_opt(year, month, day).unwrap();
+
+        // The reinvested dividend isn't counted, but part of a paycheck invested directly is
+        assert_eq!(
+            stats
+                .net_contributions(ymd(2021, 12, 31), ymd(2022, 12, 31), |_| true)
+                .unwrap(),
+            Decimal::from(1200 + 500 + 300)
+        );
+        assert_eq!(
+            stats
+                .net_contributions(ymd(2021, 12, 31), ymd(2022, 12, 31), |symbol| {
+                    symbol == "VTSAX"
+                })
+                .unwrap(),
+            Decimal::from(1200 + 300)
+        );
+    }
+
+    #[test]
+    fn test_realized_and_unrealized_gains() {
+        use crate::assets::{Asset, AssetClass};
+        use crate::rebalance::{AssetAllocation, Portfolio};
+
+        let stats = book_with_partial_sale();
+        assert_eq!(stats.realized_gains().unwrap(), Decimal::from(500));
+        let cost_basis = stats.cost_basis_by_symbol().unwrap();
+        assert_eq!(cost_basis.get("VTSAX"), Some(&Decimal::from(600)));
+
+        // The 5 remaining shares are now worth $150 each
+        let mut stocks = AssetAllocation::new(AssetClass::USTotal, 1.into());
+        stocks.add_asset(Asset::new(
+            String::from("Roth IRA"),
+            Some(String::from("VTSAX")),
+            750.into(),
+            AssetClass::USTotal,
+            Some(5.into()),
+            Some(150.into()),
+            None,
+        ));
+        let portfolio = Portfolio::new(vec![stocks]);
+        assert_eq!(portfolio.unrealized_gains(&cost_basis), Decimal::from(150));
+    }
+
+    #[test]
+    fn test_open_example_book() {
+        let stats =
+            Stats::try_new("example/sqlite3.gnucash", OpenFlags::SQLITE_OPEN_READ_ONLY).unwrap();
+        assert!(stats.after_tax_income(IncomeSign::Negative).is_ok());
+    }
+
+    #[test]
+    fn test_read_only_connection_rejects_writes() {
+        // Work on a copy, so that a regression can't clobber the example book
+        let path = std::env::temp_dir().join(format!("read_only_{:}.gnucash", std::process::id()));
+        std::fs::copy("example/sqlite3.gnucash", &path).unwrap();
+
+        let stats =
+            Stats::try_new(path.to_str().unwrap(), OpenFlags::SQLITE_OPEN_READ_ONLY).unwrap();
+        assert!(stats.after_tax_income(IncomeSign::Negative).is_ok());
+        let written = stats
+            .conn
+            .execute("UPDATE accounts SET name = 'Renamed'", NO_PARAMS);
+        assert!(written.is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// A book with a single paycheck, deposited to checking
+    fn book_with_income(income_num: i64) -> Stats {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE accounts(guid text, name text, account_type text);
+             CREATE TABLE splits(guid text, account_guid text, value_num bigint, value_denom bigint);
+             INSERT INTO accounts VALUES
+                ('salary', 'Salary', 'INCOME'),
+                ('checking', 'Checking', 'BANK');",
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO splits VALUES ('s1', 'salary', ?1, 100), ('s2', 'checking', ?2, 100)",
+            params![income_num, -income_num],
+        )
+        .unwrap();
+        Stats { conn }
+    }
+
+    #[test]
+    fn test_income_sign_convention() {
+        let conventional = book_with_income(-500_000);
+        assert_eq!(
+            conventional.income_before_taxes(IncomeSign::Negative),
+            Ok(Decimal::from(5000))
+        );
+
+        let positive = book_with_income(500_000);
+        assert_eq!(
+            positive.income_before_taxes(IncomeSign::Positive),
+            Ok(Decimal::from(5000))
+        );
+        // Assuming the wrong convention yields "negative" income
+        assert_eq!(
+            positive.income_before_taxes(IncomeSign::Negative),
+            Ok(Decimal::from(-5000))
+        );
+    }
+}
@@ -0,0 +1,323 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+/// Settings for the example book (as `Config::default()` would use, but written out)
+const EXAMPLE_CONFIG: &str = "[user]\nbirthday = '1985-01-01'\n\n[gnucash]\npath_to_book = 'example/sqlite3.gnucash'\nfile_format = 'sqlite3'\nupdate_prices = false\n";
+
+/// A scratch directory holding a copy of the example book, removed once dropped
+///
+/// (Running elsewhere keeps tests from picking up a developer's own `config.toml`.)
+struct Sandbox {
+    dir: PathBuf,
+}
+
+impl Sandbox {
+    fn new(name: &str) -> Sandbox {
+        let dir =
+            std::env::temp_dir().join(format!("stay_the_course_{:}_{:}", name, std::process::id()));
+        fs::create_dir_all(dir.join("data")).unwrap();
+        fs::create_dir_all(dir.join("example")).unwrap();
+        fs::copy("data/classified.csv", dir.join("data/classified.csv")).unwrap();
+        fs::copy(
+            "example/sqlite3.gnucash",
+            dir.join("example/sqlite3.gnucash"),
+        )
+        .unwrap();
+        Sandbox { dir }
+    }
+
+    fn with_config(name: &str, config: &str) -> Sandbox {
+        let sandbox = Sandbox::new(name);
+        fs::write(sandbox.dir.join("config.toml"), config).unwrap();
+        sandbox
+    }
+
+    fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_stay_the_course"));
+        command.args(args).current_dir(&self.dir);
+        command
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Run the program against the example book, contributing $1,000
+fn run_example(sandbox: &Sandbox, args: &[&str]) -> Output {
+    let mut child = sandbox
+        .command(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Could not start program");
+    child.stdin.take().unwrap().write_all(b"1000\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    output
+}
+
+fn run_with_args(name: &str, args: &[&str]) -> String {
+    let sandbox = Sandbox::with_config(name, EXAMPLE_CONFIG);
+    String::from_utf8(run_example(&sandbox, args).stdout).unwrap()
+}
+
+#[test]
+fn test_warns_when_using_default_config() {
+    let sandbox = Sandbox::new("default_config");
+    let stderr = String::from_utf8(run_example(&sandbox, &["--no-projection"]).stderr).unwrap();
+    assert!(stderr.contains("WARNING: No config.toml found"));
+    assert!(stderr.contains("birthday: 1985-01-01"));
+}
+
+#[test]
+fn test_projection_is_shown_by_default() {
+    let stdout = run_with_args("projection", &[]);
+    assert!(stdout.contains("Worth at retirement"));
+    assert!(stdout.contains("Contribute the following amounts:"));
+}
+
+#[test]
+fn test_no_projection() {
+    let stdout = run_with_args("no_projection", &["--no-projection"]);
+    assert!(!stdout.contains("Worth at retirement"));
+    assert!(!stdout.contains("SWR:"));
+
+    // Everything else is still shown
+    assert!(stdout.contains("Portfolio total:"));
+    assert!(stdout.contains("After-tax income:"));
+    assert!(stdout.contains("Contribute the following amounts:"));
+}
+
+#[test]
+fn test_update_prices_only_skips_the_portfolio() {
+    let book = std::env::current_dir().unwrap().join("example/xml.gnucash");
+    let sandbox = Sandbox::with_config(
+        "update_prices_only",
+        &format!(
+            "[user]\nbirthday = '1985-01-01'\n\n[gnucash]\npath_to_book = '{:}'\nfile_format = 'xml'\nupdate_prices = true\n",
+            book.display()
+        ),
+    );
+
+    let output = sandbox.command(&["--update-prices-only"]).output().unwrap();
+
+    // Prices can't be written to XML books, so we stop before doing anything else
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Updating prices is only supported for SQLite books"));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.contains("Portfolio total:"));
+    assert!(!stdout.contains("Worth at retirement"));
+}
+
+#[test]
+fn test_update_prices_only_writes_prices_to_sqlite_books() {
+    // Stand in for `gnc-fq-dump`, quoting every requested symbol at $8.39 today
+    // (The last argument names the quote source, which the example book's funds use)
+    let quote_command = r#"['sh', '-c', 'printf "Finance::Quote fields Gnucash uses:\n    symbol: %s\n      date: %s\n  currency: USD\n      last: 8.39\n" "$1" "$(date +%m/%d/%Y)"', 'alphavantage']"#;
+    let sandbox = Sandbox::with_config(
+        "update_prices_only_sqlite",
+        &format!(
+            "[user]\nbirthday = '1985-01-01'\n\n[gnucash]\npath_to_book = 'example/sqlite3.gnucash'\nfile_format = 'sqlite3'\nupdate_prices = true\nquote_command = {:}\n",
+            quote_command
+        ),
+    );
+
+    let output = sandbox
+        .command(&["--update-prices-only", "--yes"])
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(" - VTSAX: 130.636046 -> 8.39"));
+    assert!(stdout.contains("Wrote 5 new price(s)"));
+    // Nothing beyond the price update is shown
+    assert!(!stdout.contains("Portfolio total:"));
+    assert!(!stdout.contains("Worth at retirement"));
+}
+
+#[test]
+fn test_book_without_prices_exits_cleanly() {
+    let sandbox = Sandbox::with_config("no_prices", EXAMPLE_CONFIG);
+    let book = rusqlite::Connection::open(sandbox.path().join("example/sqlite3.gnucash")).unwrap();
+    book.execute("DELETE FROM prices", rusqlite::NO_PARAMS)
+        .unwrap();
+
+    let output = sandbox.command(&[]).output().unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("No prices available; enable update_prices or seed prices"));
+    assert!(!stderr.contains("panicked"));
+}
+
+#[test]
+fn test_json_errors() {
+    let sandbox = Sandbox::with_config(
+        "json_errors",
+        "[user]\nbirthday = '1985-01-01'\n\n[gnucash]\npath_to_book = 'holdings.csv'\nfile_format = 'csv'\nupdate_prices = false\n",
+    );
+    let dir = sandbox.path();
+    fs::write(
+        dir.join("holdings.csv"),
+        "account,symbol,quantity,price\nTaxable,ZZZZZ,1,10\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("data/classified.csv"),
+        "ticker_name,asset_class,name\nVTSAX,USTotal,\"Vanguard Total Stock Market Index Fund Admiral Shares\"\n",
+    )
+    .unwrap();
+
+    let output = sandbox.command(&["--format", "json"]).output().unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    let error: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(error["error"]["kind"], "unclassified_asset");
+    assert_eq!(error["error"]["context"]["symbol"], "ZZZZZ");
+    assert!(error["error"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("ZZZZZ"));
+}
+
+#[test]
+fn test_unreadable_book_is_a_json_error() {
+    let sandbox = Sandbox::with_config(
+        "unreadable_book",
+        "[user]\nbirthday = '1985-01-01'\n\n[gnucash]\npath_to_book = 'missing.csv'\nfile_format = 'csv'\nupdate_prices = false\n",
+    );
+    let output = sandbox.command(&["--format", "json"]).output().unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    let error: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(error["error"]["kind"], "book_unreadable");
+    assert_eq!(error["error"]["context"]["path"], "missing.csv");
+}
+
+#[test]
+fn test_contribution_given_as_an_argument() {
+    let sandbox = Sandbox::with_config("contribution_argument", EXAMPLE_CONFIG);
+    // With nothing on stdin, there's nothing to wait on
+    let output = sandbox
+        .command(&["--no-projection", "--withdraw", "100"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.contains("How much to contribute or withdraw?"));
+    assert!(stdout.contains("Withdraw the following amounts:"));
+
+    let output = sandbox
+        .command(&["--contribute", "lots"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Invalid amount for --contribute: Not a number: 'lots'"));
+
+    // The example book is worth far less than this
+    let output = sandbox
+        .command(&["--no-projection", "--withdraw", "10000000"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Cannot withdraw $10,000,000"),
+        "{:}",
+        stderr
+    );
+}
+
+#[test]
+fn test_glide_path_birthday_must_be_in_the_past() {
+    let sandbox = Sandbox::with_config("glide_path", EXAMPLE_CONFIG);
+    let output = sandbox
+        .command(&["--glide-path", "2999-01-01"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Invalid birthday for --glide-path: 2999-01-01 (must be in the past)"));
+}
+
+#[test]
+fn test_annual_report_counts_only_new_money_in_targeted_classes() {
+    let sandbox = Sandbox::with_config(
+        "annual_report",
+        &format!(
+            "{:}\n[allocation]\nUSTotal = 0.6\nUSBonds = 0.4\n",
+            EXAMPLE_CONFIG
+        ),
+    );
+
+    // Reinvest a $100 dividend in VTSAX (in the sandbox's copy of the book)
+    let conn = rusqlite::Connection::open(sandbox.path().join("example/sqlite3.gnucash")).unwrap();
+    let guid = |sql: &str| -> String {
+        conn.query_row(sql, rusqlite::NO_PARAMS, |row| row.get(0))
+            .unwrap()
+    };
+    let usd = guid("SELECT guid FROM commodities WHERE mnemonic = 'USD'");
+    let vtsax = guid("SELECT guid FROM accounts WHERE name = 'VTSAX'");
+    let dividends = guid("SELECT guid FROM accounts WHERE name = 'Dividend Income'");
+    conn.execute(
+        "INSERT INTO transactions
+             VALUES ('reinvested', ?1, '', '2019-06-28 10:59:00', '2019-06-28 10:59:00', 'Dividend')",
+        [&usd],
+    )
+    .unwrap();
+    conn.execute(
+        "INSERT INTO splits VALUES
+             ('reinvested1', 'reinvested', ?1, '', '', 'n', NULL, 10000, 100, 1, 1, NULL),
+             ('reinvested2', 'reinvested', ?2, '', '', 'n', NULL, -10000, 100, -10000, 100, NULL)",
+        [&vtsax, &dividends],
+    )
+    .unwrap();
+    drop(conn);
+
+    let output = sandbox
+        .command(&["--annual-report", "2019"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    // 2019 purchases of FZROX, VTSAX & VBTLX, but not VTIAX (untargeted) or the dividend
+    assert!(stdout.contains("Net contributions: $3,639"), "{:}", stdout);
+}
+
+#[test]
+fn test_json_report() {
+    let sandbox = Sandbox::with_config("json_report", EXAMPLE_CONFIG);
+    let output = run_example(&sandbox, &["--format", "json", "--contribute", "1000"]);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["contribution"], "1000.00");
+    assert!(report["total_value"].is_string());
+    assert!(report["minimum_to_balance"].is_string());
+
+    let allocations = report["allocations"].as_array().unwrap();
+    assert!(!allocations.is_empty());
+    for allocation in allocations {
+        assert!(allocation["asset_class"].is_string());
+        assert!(allocation["target_ratio"].is_string());
+        assert!(allocation["current_value"].is_string());
+        assert!(allocation["future_contribution"].is_string());
+    }
+}